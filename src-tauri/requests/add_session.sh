@@ -0,0 +1,6 @@
+# usage: ./add_session.sh <game id> [user]
+# records a session of the last hour
+curl -H 'Content-Type: application/json' \
+      -d "{ \"game_id\": \"$1\", \"client_id\": \"curl\", \"user\": \"${2:-$USER}\", \"started_at\": \"$(date -u -d '-1 hour' +%FT%TZ)\", \"ended_at\": \"$(date -u +%FT%TZ)\", \"exit_status\": 0 }" \
+      -X POST \
+      http://127.0.0.1:53317/sessions
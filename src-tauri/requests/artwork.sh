@@ -0,0 +1 @@
+curl http://127.0.0.1:53317/artwork
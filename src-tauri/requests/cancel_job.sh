@@ -0,0 +1,2 @@
+# usage: NAS_GAME_TOKEN=<admin token> ./cancel_job.sh <job id>
+curl -X POST -H "Authorization: Bearer $NAS_GAME_TOKEN" http://127.0.0.1:53317/jobs/$1/cancel
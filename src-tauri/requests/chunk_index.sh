@@ -0,0 +1,2 @@
+# usage: ./chunk_index.sh <game id>
+curl http://127.0.0.1:53317/games/$1/chunks
@@ -0,0 +1,3 @@
+# usage: ./delete_game.sh <game id>
+curl -X DELETE \
+      http://127.0.0.1:53317/games/$1
@@ -0,0 +1,3 @@
+# lists the registered devices, approve them on the server with
+# `nas-game server devices approve <id>`
+curl http://127.0.0.1:53317/devices
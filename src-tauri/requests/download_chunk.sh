@@ -0,0 +1,2 @@
+# usage: ./download_chunk.sh <game id> <sha256 of the chunk>
+curl -o "$2" http://127.0.0.1:53317/games/$1/chunks/$2
@@ -0,0 +1,2 @@
+# usage: ./download_file.sh <game id> <path inside of the game> [first byte]
+curl -o "$(basename "$2")" -r "${3:-0}-" "http://127.0.0.1:53317/games/$1/files/$2"
@@ -0,0 +1,6 @@
+# usage: ./edit_game.sh <game id>
+curl http://127.0.0.1:53317/games/$1
+curl -H 'Content-Type: application/json' \
+      -d '{ "name": "Hades", "steam_grid_id": null }' \
+      -X PATCH \
+      http://127.0.0.1:53317/games/$1
@@ -0,0 +1,6 @@
+# usage: NAS_GAME_TOKEN=<token> ./favourite_game.sh <game id> [true|false]
+curl -H 'Content-Type: application/json' \
+      -H "Authorization: Bearer $NAS_GAME_TOKEN" \
+      -d "{ \"favourite\": ${2:-true} }" \
+      -X PATCH \
+      http://127.0.0.1:53317/me/games/$1
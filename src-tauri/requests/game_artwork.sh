@@ -0,0 +1,2 @@
+# usage: ./game_artwork.sh <game id> <grids|heroes|logos|icons|screenshots> [index] > artwork.png
+curl "http://127.0.0.1:53317/games/$1/artwork/$2?index=${3:-0}"
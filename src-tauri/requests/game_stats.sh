@@ -0,0 +1,2 @@
+# usage: ./game_stats.sh <game id> [weeks]
+curl "http://127.0.0.1:53317/stats/games/$1?weeks=${2:-12}"
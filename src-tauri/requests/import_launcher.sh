@@ -0,0 +1,3 @@
+# usage: ./import_launcher.sh <launcher name, e.g. epic>
+curl -X POST \
+      http://127.0.0.1:53317/launchers/$1/import
@@ -0,0 +1,2 @@
+# usage: ./job.sh <job id>
+curl http://127.0.0.1:53317/jobs/$1
@@ -0,0 +1,2 @@
+# usage: ./job_events.sh [job id], without an id every job is followed
+curl -N http://127.0.0.1:53317/jobs/${1:-events}${1:+/events}
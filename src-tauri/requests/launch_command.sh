@@ -0,0 +1,2 @@
+# usage: ./launch_command.sh <game id>
+curl http://127.0.0.1:53317/games/$1/launch_command
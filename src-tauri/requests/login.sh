@@ -0,0 +1,6 @@
+# usage: ./login.sh <name> <password>
+# prints the token, pass it to the other scripts as NAS_GAME_TOKEN
+curl -H 'Content-Type: application/json' \
+      -d "{ \"name\": \"$1\", \"password\": \"$2\" }" \
+      -X POST \
+      http://127.0.0.1:53317/login
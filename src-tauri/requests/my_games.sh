@@ -0,0 +1,3 @@
+# usage: NAS_GAME_TOKEN=<token> ./my_games.sh [hidden]
+# the library as the logged in user sees it, with the hidden games if asked for
+curl -H "Authorization: Bearer $NAS_GAME_TOKEN" "http://127.0.0.1:53317/me/games?hidden=${1:-false}"
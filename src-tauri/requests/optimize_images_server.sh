@@ -1,2 +1,3 @@
+# usage: ./optimize_images_server.sh [force]
 curl -X POST \
-http://127.0.0.1:53317/optimize_images_server
+"http://127.0.0.1:53317/optimize_images_server${1:+?force=true}"
@@ -0,0 +1,2 @@
+# usage: ./optimized_artwork.sh <game id> <grids|heroes|logos|icons|screenshots> [width] [height] > artwork.webp
+curl "http://127.0.0.1:53317/games/$1/artwork/$2/optimized?${3:+w=$3&}${4:+h=$4}"
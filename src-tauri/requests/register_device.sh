@@ -0,0 +1,6 @@
+# usage: ./register_device.sh <name> <base64 ed25519 public key>
+# `nas-game client register` creates the key and does this for you
+curl -H 'Content-Type: application/json' \
+      -d "{ \"name\": \"$1\", \"public_key\": \"$2\" }" \
+      -X POST \
+      http://127.0.0.1:53317/devices/register
@@ -0,0 +1,6 @@
+# usage: NAS_GAME_TOKEN=<admin token> ./restrict_user.sh <name> <minutes per day>
+curl -H 'Content-Type: application/json' \
+      -H "Authorization: Bearer $NAS_GAME_TOKEN" \
+      -d "{ \"role\": \"kid\", \"restrictions\": { \"daily_minutes\": $2 } }" \
+      -X PATCH \
+      http://127.0.0.1:53317/users/$1
@@ -0,0 +1,2 @@
+curl -X POST \
+      http://127.0.0.1:53317/scan
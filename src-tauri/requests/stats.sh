@@ -0,0 +1,2 @@
+# usage: ./stats.sh [weeks] [limit]
+curl "http://127.0.0.1:53317/stats?weeks=${1:-12}&limit=${2:-10}"
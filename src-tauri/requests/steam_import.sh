@@ -0,0 +1,6 @@
+# usage: ./steam_import.sh <64-bit steam id>
+# the server needs STEAM_API_KEY to be set
+curl -H 'Content-Type: application/json' \
+      -d "{ \"steam_id\": \"$1\", \"achievements\": true }" \
+      -X POST \
+      http://127.0.0.1:53317/steam/import
@@ -0,0 +1,2 @@
+# usage: ./steam_profile.sh <64-bit steam id>
+curl http://127.0.0.1:53317/steam/profile/$1
@@ -0,0 +1,2 @@
+# usage: ./user_stats.sh <user> [weeks] [limit]
+curl "http://127.0.0.1:53317/stats/users/$1?weeks=${2:-12}&limit=${3:-10}"
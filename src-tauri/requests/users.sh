@@ -0,0 +1,2 @@
+# usage: NAS_GAME_TOKEN=<admin token> ./users.sh
+curl -H "Authorization: Bearer $NAS_GAME_TOKEN" http://127.0.0.1:53317/users
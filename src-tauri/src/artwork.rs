@@ -0,0 +1,526 @@
+//! This crate is for the artwork of the games. Every kind of artwork
+//! has a directory of its own in `images_dir`, in which images are
+//! saved as `{game id}.{extension}`, so that renaming a game or a
+//! SteamGridDB result that is named differently than the library entry
+//! doesn't lose its artwork. Games have several screenshots, the ones
+//! after the first are saved as `{game id}-{index}.{extension}`.
+//!
+//! Every image is listed in `artwork.json` next to the images, together
+//! with the `steam_grid_id` it belongs to, where it was fetched from,
+//! its hash, its size and when it was fetched. A game without artwork
+//! of its own uses that of a game with the same `steam_grid_id`.
+//!
+//! Optimized copies are kept in `optimized_images_dir`, in the size of
+//! the `ImageProfile` of their kind, or in `{width}x{height}/` for the
+//! sizes that clients asked for with `?w=&h=`. Those are rounded up to
+//! one of `SIZE_BUCKETS` and only `MAX_SIZED_COPIES` of them are kept
+//! per image, so that clients can't fill the disk. Every copy has a
+//! `.profile` file next to it with the profile it was optimized with,
+//! a copy of another profile is optimized again.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{info, warn, logging::LoggingLevel, logging::logging_function};
+use crate::types::{ArtworkKind, Game, ImageProfile, OutputFormat, ResizePolicy, ServerSettings};
+use crate::files::write_atomic;
+
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const MANIFEST_FILE: &str = "artwork.json";
+/// What images used to be saved as, by name instead of by id.
+const LEGACY_EXTENSIONS: &[&str] = &["webp", "jpg", "jpeg", "png"];
+/// The largest width or height that can be asked for.
+pub const MAX_SIZE: u32 = 4096;
+/// The sides `?w=&h=` are rounded up to.
+pub const SIZE_BUCKETS: [u32; 7] = [64, 128, 256, 512, 1024, 2048, MAX_SIZE];
+/// How many copies in sizes of their own are kept of every image, the
+/// ones that were written the longest ago are removed first.
+pub const MAX_SIZED_COPIES: usize = 8;
+/// Artwork rarely changes, clients check the ETag after a day.
+pub const ARTWORK_CACHE_CONTROL: &str = "public, max-age=86400";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArtworkEntry {
+    pub game_id: String,
+    #[serde(default)]
+    pub kind: ArtworkKind,
+    /// Which of the images of the kind this is, only screenshots have
+    /// more than one.
+    #[serde(default, skip_serializing_if = "is_first")]
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub steam_grid_id: Option<String>,
+    /// The path of the image in `images_dir`.
+    pub file: String,
+    /// Where the image was fetched from, unknown for images that were
+    /// there before the manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    pub sha256: String,
+    pub width: u32,
+    pub height: u32,
+    pub fetched_at: DateTime<Utc>,
+}
+
+fn is_first(index: &usize) -> bool { *index == 0 }
+
+impl ArtworkEntry {
+    /// Where the image is served.
+    pub fn url(&self) -> String {
+        match self.index {
+            0 => format!("/games/{}/artwork/{}", self.game_id, self.kind.as_str()),
+            index => format!("/games/{}/artwork/{}?index={}", self.game_id, self.kind.as_str(), index),
+        }
+    }
+}
+
+/// An entry of the manifest as it is sent to clients.
+#[derive(Serialize, Debug)]
+pub struct ArtworkInfo {
+    #[serde(flatten)]
+    pub entry: ArtworkEntry,
+    pub url: String,
+}
+
+impl From<ArtworkEntry> for ArtworkInfo {
+    fn from(entry: ArtworkEntry) -> Self { Self { url: entry.url(), entry } }
+}
+
+/// The query of `GET /games/{id}/artwork/{kind}`.
+#[derive(Deserialize, Debug, Default)]
+pub struct ArtworkQuery {
+    /// Which screenshot, the first one without it.
+    pub index: Option<usize>,
+    /// The size the optimized image has to fit into, see `size()`.
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+}
+
+impl ArtworkQuery {
+    /// The asked for size, each side rounded up to the next of
+    /// `SIZE_BUCKETS`.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::BadRequest` for a side of 0 or above
+    /// `MAX_SIZE`.
+    pub fn bounds(&self) -> Result<(Option<u32>, Option<u32>), NasError> {
+        let bucket = |side: Option<u32>| match side {
+            Some(side) if side > 0 => SIZE_BUCKETS.into_iter().find(|bucket| *bucket >= side)
+                .map(Some)
+                .ok_or_else(|| NasError::new(ErrorKind::BadRequest, format!("Images can be 1 to {} pixels wide and high, not {}", MAX_SIZE, side))),
+            Some(side) => Err(NasError::new(ErrorKind::BadRequest, format!("Images can be 1 to {} pixels wide and high, not {}", MAX_SIZE, side))),
+            None => Ok(None),
+        };
+        Ok((bucket(self.w)?, bucket(self.h)?))
+    }
+}
+
+/// An image as it was downloaded, see `server::fetch_artwork()`.
+pub struct FetchedImage {
+    /// The SteamGridDB game the image belongs to, screenshots don't
+    /// come from SteamGridDB.
+    pub steam_grid_id: Option<String>,
+    pub source_url: String,
+    pub extension: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Scales `size` down until it fits into `bounds`, keeping its aspect
+/// ratio.
+pub fn fit((width, height): (u32, u32), (max_width, max_height): (u32, u32)) -> (u32, u32) {
+    let scale = f64::min(1.0, f64::min(max_width as f64 / width.max(1) as f64, max_height as f64 / height.max(1) as f64));
+    (((width as f64 * scale).round() as u32).max(1), ((height as f64 * scale).round() as u32).max(1))
+}
+
+/// The `ImageProfile` of `entry`, with the size it is optimized to so
+/// that it fits into `bounds` or else the size of the profile. With
+/// `ResizePolicy::Fit` that is the size of the result, which keeps the
+/// aspect ratio of the image and is never larger than it. The other
+/// policies take the size as it is, a missing side follows the aspect
+/// ratio of the profile.
+pub fn optimized_profile(entry: &ArtworkEntry, settings: &ServerSettings, bounds: (Option<u32>, Option<u32>)) -> ImageProfile {
+    let profile = settings.image_profile(entry.kind);
+    let scale = |side: u32, to: u32, from: u32| ((side as u64 * to as u64 / from.max(1) as u64) as u32).max(1);
+    let (width, height) = match profile.resize {
+        ResizePolicy::Fit => match bounds {
+            (None, None) => fit((entry.width, entry.height), (profile.width, profile.height)),
+            (w, h) => fit((entry.width, entry.height), (w.unwrap_or(u32::MAX), h.unwrap_or(u32::MAX))),
+        },
+        ResizePolicy::Fill | ResizePolicy::Pad => match bounds {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, scale(w, profile.height, profile.width)),
+            (None, Some(h)) => (scale(h, profile.width, profile.height), h),
+            (None, None) => (profile.width, profile.height),
+        },
+    };
+    ImageProfile { width, height, ..profile }
+}
+
+/// Where the optimized copy of `entry` is kept in `dir_out`, `size` is
+/// that of a copy with a size of its own.
+pub fn optimized_path(entry: &ArtworkEntry, dir_out: &Path, size: Option<(u32, u32)>, format: OutputFormat) -> PathBuf {
+    let dir = dir_out.join(entry.kind.as_str());
+    let dir = match size {
+        Some((width, height)) => dir.join(format!("{}x{}", width, height)),
+        None => dir,
+    };
+    let stem = Path::new(&entry.file).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    dir.join(format!("{}.{}", stem, format.extension()))
+}
+
+/// The profile and the path of the optimized copy of `entry` that fits
+/// into `bounds`, see `optimized_profile()` and `optimized_path()`.
+pub fn optimized_target(entry: &ArtworkEntry, settings: &ServerSettings, dir_out: &Path, bounds: (Option<u32>, Option<u32>)) -> (ImageProfile, PathBuf) {
+    let profile = optimized_profile(entry, settings, bounds);
+    let path = optimized_path(entry, dir_out, (bounds != (None, None)).then_some((profile.width, profile.height)), profile.format);
+    (profile, path)
+}
+
+/// Removes the copies of `entry` in sizes of their own beyond
+/// `MAX_SIZED_COPIES`, the ones that were written first go first.
+pub fn prune_sized_copies(entry: &ArtworkEntry, dir_out: &Path) {
+    let stem = Path::new(&entry.file).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let Ok(dirs) = fs::read_dir(dir_out.join(entry.kind.as_str())) else { return; };
+    let mut copies: Vec<(std::time::SystemTime, PathBuf)> = dirs.flatten()
+        .filter(|dir| dir.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|dir| fs::read_dir(dir.path()).ok())
+        .flat_map(|files| files.flatten())
+        .map(|file| file.path())
+        .filter(|path| path.file_stem().is_some_and(|s| s.to_string_lossy() == stem))
+        .filter_map(|path| Some((fs::metadata(&path).and_then(|m| m.modified()).ok()?, path)))
+        .collect();
+    if copies.len() <= MAX_SIZED_COPIES { return; }
+    copies.sort();
+    for (_, path) in &copies[..copies.len() - MAX_SIZED_COPIES] {
+        if let Err(e) = fs::remove_file(path) { warn!("Failed to remove the sized copy {:?} with {}", path, e); }
+        let _ = fs::remove_file(fingerprint_path(path));
+        // the size is gone once no image has a copy in it anymore
+        if let Some(dir) = path.parent() { let _ = fs::remove_dir(dir); }
+    }
+}
+
+/// Where the profile that `optimized` was optimized with is kept.
+pub fn fingerprint_path(optimized: &Path) -> PathBuf {
+    let mut name = optimized.as_os_str().to_owned();
+    name.push(".profile");
+    PathBuf::from(name)
+}
+
+fn fingerprint(profile: &ImageProfile) -> String {
+    serde_json::to_string(profile).unwrap_or_default()
+}
+
+/// Remembers that `optimized` was optimized with `profile`, see
+/// `is_outdated()`.
+pub fn write_fingerprint(optimized: &Path, profile: &ImageProfile) -> Result<(), NasError> {
+    write_atomic(&fingerprint_path(optimized), fingerprint(profile).as_bytes())
+}
+
+/// Whether `optimized` is missing, older than `original` or was
+/// optimized with another profile than `profile`, i.e. has to be
+/// optimized (again).
+pub fn is_outdated(original: &Path, optimized: &Path, profile: &ImageProfile) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let same_profile = fs::read_to_string(fingerprint_path(optimized)).is_ok_and(|written| written == fingerprint(profile));
+    match (modified(optimized), modified(original)) {
+        (Some(optimized), Some(original)) => optimized < original || !same_profile,
+        _ => true,
+    }
+}
+
+/// The artwork in `images_dir` and its manifest.
+pub struct ArtworkStore {
+    dir: PathBuf,
+    entries: Mutex<Vec<ArtworkEntry>>,
+}
+
+impl ArtworkStore {
+    /// Opens the artwork in `dir`, a missing manifest is an empty one.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::FailedToParse` if the manifest is broken.
+    pub fn open(dir: PathBuf) -> Result<Self, NasError> {
+        let path = dir.join(MANIFEST_FILE);
+        let entries = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s).context(ErrorKind::FailedToParse, format!("Failed to parse {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(NasError::new(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path)).with_source(e)),
+        };
+        Ok(Self { dir, entries: Mutex::new(entries) })
+    }
+
+    fn write(&self, entries: &[ArtworkEntry]) -> Result<(), NasError> {
+        fs::create_dir_all(&self.dir).context(ErrorKind::FailedToCreateFolder, format!("Failed to create {:?}", self.dir))?;
+        let json = serde_json::to_string_pretty(entries).context(ErrorKind::FailedToSerialize, "Failed to serialize the artwork manifest")?;
+        write_atomic(&self.dir.join(MANIFEST_FILE), json.as_bytes())
+    }
+
+    pub fn entries(&self) -> Result<Vec<ArtworkEntry>, NasError> {
+        Ok(self.entries.lock().map_err(|_| ErrorKind::FailedToAquireLock)?.clone())
+    }
+
+    pub fn path(&self, entry: &ArtworkEntry) -> PathBuf { self.dir.join(&entry.file) }
+
+    /// Every image of `kind` of `game`, or else those of a game with the
+    /// same `steam_grid_id`. Entries whose image has been deleted don't
+    /// count.
+    pub fn get_all(&self, game: &Game, kind: ArtworkKind) -> Result<Vec<ArtworkEntry>, NasError> {
+        let entries = self.entries.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let of_kind = || entries.iter().filter(|entry| entry.kind == kind && self.path(entry).is_file());
+        let mut found: Vec<ArtworkEntry> = of_kind().filter(|entry| entry.game_id == game.id()).cloned().collect();
+        if found.is_empty() {
+            if let Some(id) = game.steam_grid_id() {
+                // the entries of a single game, not a mix of every game that shares the id
+                let shared = of_kind().find(|entry| entry.steam_grid_id.as_deref() == Some(id)).map(|entry| entry.game_id.clone());
+                found = of_kind().filter(|entry| Some(&entry.game_id) == shared.as_ref()).cloned().collect();
+            }
+        }
+        found.sort_by_key(|entry| entry.index);
+        Ok(found)
+    }
+
+    /// The first image of `kind` of `game`, see `get_all()`.
+    pub fn get(&self, game: &Game, kind: ArtworkKind) -> Result<Option<ArtworkEntry>, NasError> {
+        Ok(self.get_all(game, kind)?.into_iter().next())
+    }
+
+    /// Saves the images of `kind` of `game_id`, replacing the ones it had.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::InvalidPath` for ids that aren't a plain
+    /// file name, see `check_file_name()`.
+    pub fn save(&self, game_id: &str, kind: ArtworkKind, images: Vec<FetchedImage>) -> Result<Vec<ArtworkEntry>, NasError> {
+        check_file_name(game_id)?;
+        let dir = self.dir.join(kind.as_str());
+        fs::create_dir_all(&dir).context(ErrorKind::FailedToCreateFolder, format!("Failed to create {:?}", dir))?;
+        let mut saved = Vec::new();
+        for (index, image) in images.into_iter().enumerate() {
+            let (width, height) = image::ImageReader::new(Cursor::new(&image.bytes)).with_guessed_format()
+                .context(ErrorKind::FailedToReadFile, format!("Failed to read the image {:?}", image.source_url))?
+                .into_dimensions()
+                .context(ErrorKind::FailedToParse, format!("{:?} is not an image", image.source_url))?;
+            let name = match index {
+                0 => format!("{}.{}", game_id, image.extension.to_lowercase()),
+                index => format!("{}-{}.{}", game_id, index, image.extension.to_lowercase()),
+            };
+            write_atomic(&dir.join(&name), &image.bytes)?;
+            saved.push(ArtworkEntry {
+                game_id: game_id.to_owned(),
+                kind,
+                index,
+                steam_grid_id: image.steam_grid_id,
+                file: format!("{}/{}", kind.as_str(), name),
+                source_url: Some(image.source_url),
+                sha256: format!("{:x}", Sha256::digest(&image.bytes)),
+                width,
+                height,
+                fetched_at: Utc::now(),
+            });
+        }
+        self.replace(game_id, kind, saved.clone())?;
+        Ok(saved)
+    }
+
+    /// Puts `new` in place of the entries of `kind` of `game_id` and
+    /// removes the images that aren't used anymore.
+    fn replace(&self, game_id: &str, kind: ArtworkKind, new: Vec<ArtworkEntry>) -> Result<(), NasError> {
+        let mut entries = self.entries.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let (old, mut kept): (Vec<ArtworkEntry>, Vec<ArtworkEntry>) = entries.drain(..).partition(|entry| entry.game_id == game_id && entry.kind == kind);
+        for old in old.iter().filter(|old| !new.iter().any(|entry| entry.file == old.file)) {
+            let _ = fs::remove_file(self.path(old)).map_err(|e| { warn!("Failed to remove the old artwork {:?} with {}", old.file, e); });
+        }
+        kept.extend(new);
+        *entries = kept;
+        self.write(&entries)
+    }
+
+    /// Takes over a grid that was saved under the name of `game` before
+    /// images were saved by id.
+    pub fn adopt_legacy(&self, game: &Game) -> Result<Option<ArtworkEntry>, NasError> {
+        check_file_name(game.id())?;
+        // a name like that can't have been saved by name either
+        if check_file_name(game.name()).is_err() { return Ok(None); }
+        let Some(legacy) = LEGACY_EXTENSIONS.iter().map(|ext| self.dir.join(format!("{}.{}", game.name(), ext))).find(|path| path.is_file()) else {
+            return Ok(None);
+        };
+        let bytes = fs::read(&legacy).context(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", legacy))?;
+        let (width, height) = image::image_dimensions(&legacy).context(ErrorKind::FailedToParse, format!("{:?} is not an image", legacy))?;
+        // the closest there is to when it was fetched
+        let fetched_at = fs::metadata(&legacy).and_then(|m| m.modified()).map(DateTime::from).unwrap_or_else(|_| Utc::now());
+        let extension = legacy.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let kind = ArtworkKind::Grids;
+        let dir = self.dir.join(kind.as_str());
+        fs::create_dir_all(&dir).context(ErrorKind::FailedToCreateFolder, format!("Failed to create {:?}", dir))?;
+        let file = format!("{}/{}.{}", kind.as_str(), game.id(), extension);
+        fs::rename(&legacy, self.dir.join(&file)).context(ErrorKind::FailedToWrite, format!("Failed to rename {:?}", legacy))?;
+        let entry = ArtworkEntry {
+            game_id: game.id().to_owned(),
+            kind,
+            index: 0,
+            steam_grid_id: game.steam_grid_id().map(str::to_owned),
+            file,
+            source_url: None,
+            sha256: format!("{:x}", Sha256::digest(&bytes)),
+            width,
+            height,
+            fetched_at,
+        };
+        info!("Moved the artwork of {:?} from {:?} to {:?}", game.name(), legacy, entry.file);
+        self.replace(game.id(), kind, vec![entry.clone()])?;
+        Ok(Some(entry))
+    }
+}
+
+/// Game ids and names end up in the names of the images, so they must
+/// not leave the directory of their kind.
+///
+/// # Errors
+/// Fails with `ErrorKind::InvalidPath` for empty values and values
+/// with a path separator or `..`.
+fn check_file_name(value: &str) -> Result<(), NasError> {
+    if value.is_empty() || value.contains(['/', '\\']) || value.contains("..") {
+        return Err(NasError::new(ErrorKind::InvalidPath, format!("{:?} can't be part of the name of an image", value)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        image::RgbImage::new(width, height).write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    fn fetched(steam_grid_id: &str, extension: &str, bytes: Vec<u8>) -> FetchedImage {
+        let source_url = format!("https://cdn2.steamgriddb.com/grid/{}.{}", steam_grid_id, extension);
+        FetchedImage { steam_grid_id: Some(steam_grid_id.to_owned()), source_url, extension: extension.to_owned(), bytes }
+    }
+
+    #[test]
+    fn artwork_follows_the_game_not_its_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let artwork = ArtworkStore::open(dir.path().to_path_buf()).unwrap();
+        let mut fez = Game::with_id("1".to_owned(), "FEZ".to_owned());
+        fez.set_steam_grid_id(Some("4425".to_owned()));
+        assert_eq!(artwork.get(&fez, ArtworkKind::Grids).unwrap(), None);
+
+        let entry = artwork.save(fez.id(), ArtworkKind::Grids, vec![fetched("4425", "PNG", png(6, 9))]).unwrap().remove(0);
+        assert_eq!((entry.file.as_str(), entry.width, entry.height), ("grids/1.png", 6, 9));
+        assert_eq!(entry.sha256, format!("{:x}", Sha256::digest(png(6, 9))));
+        // renaming the game keeps its artwork, and another entry of the same game shares it
+        fez.set_name("Fez: Anniversary Edition".to_owned());
+        assert_eq!(artwork.get(&fez, ArtworkKind::Grids).unwrap(), Some(entry.clone()));
+        let mut gog_fez = Game::with_id("2".to_owned(), "Fez".to_owned());
+        gog_fez.set_steam_grid_id(Some("4425".to_owned()));
+        assert_eq!(artwork.get(&gog_fez, ArtworkKind::Grids).unwrap(), Some(entry));
+        assert_eq!(artwork.get(&fez, ArtworkKind::Logos).unwrap(), None);
+
+        // new images replace the old files, kinds are kept apart
+        artwork.save(fez.id(), ArtworkKind::Grids, vec![fetched("4425", "jpg", png(3, 3))]).unwrap();
+        assert!(!dir.path().join("grids/1.png").exists());
+        let screenshots = (0..3).map(|_| fetched("4425", "jpg", png(16, 9))).collect();
+        artwork.save(fez.id(), ArtworkKind::Screenshots, screenshots).unwrap();
+        assert_eq!(artwork.get_all(&gog_fez, ArtworkKind::Screenshots).unwrap().last().unwrap().url(), "/games/1/artwork/screenshots?index=2");
+        artwork.save(fez.id(), ArtworkKind::Screenshots, vec![fetched("4425", "jpg", png(16, 9))]).unwrap();
+        assert_eq!(artwork.get_all(&fez, ArtworkKind::Screenshots).unwrap().len(), 1);
+        assert!(!dir.path().join("screenshots/1-1.jpg").exists());
+
+        // images that were saved by name are taken over
+        let celeste = Game::with_id("3".to_owned(), "Celeste".to_owned());
+        fs::write(dir.path().join("Celeste.png"), png(2, 2)).unwrap();
+        let adopted = artwork.adopt_legacy(&celeste).unwrap().unwrap();
+        assert_eq!((adopted.file.as_str(), adopted.source_url), ("grids/3.png", None));
+        assert!(!dir.path().join("Celeste.png").exists());
+
+        // ids that would leave the directory of their kind are refused
+        for id in ["../1", "a/b", "..", ""] {
+            assert_eq!(artwork.save(id, ArtworkKind::Grids, vec![fetched("4425", "png", png(2, 2))]).unwrap_err().kind(), ErrorKind::InvalidPath);
+        }
+        fs::write(dir.path().join("grids").join("x.png"), png(2, 2)).unwrap();
+        assert_eq!(artwork.adopt_legacy(&Game::with_id("4".to_owned(), "grids/x".to_owned())).unwrap(), None);
+        assert!(dir.path().join("grids").join("x.png").exists());
+
+        let reopened = ArtworkStore::open(dir.path().to_path_buf()).unwrap();
+        assert_eq!(reopened.entries().unwrap(), artwork.entries().unwrap());
+        assert_eq!(reopened.get(&fez, ArtworkKind::Grids).unwrap().unwrap().file, "grids/1.jpg");
+        assert_eq!(fit((1920, 1080), (640, 360)), (640, 360));
+        assert_eq!(fit((400, 100), (640, 360)), (400, 100));
+    }
+
+    #[test]
+    fn optimized_sizes_keep_logos_unstretched() {
+        let settings = ServerSettings::default();
+        let entry = |kind, width, height| ArtworkEntry {
+            game_id: "1".to_owned(), kind, index: 0, steam_grid_id: None, file: format!("{}/1.png", kind.as_str()),
+            source_url: None, sha256: String::new(), width, height, fetched_at: Utc::now(),
+        };
+        let size = |entry: &ArtworkEntry, bounds| { let profile = optimized_profile(entry, &settings, bounds); (profile.width, profile.height) };
+        let grid = entry(ArtworkKind::Grids, 600, 900);
+        assert_eq!(size(&grid, (None, None)), settings.image_size());
+        assert_eq!(size(&grid, (Some(616), None)), (616, 616 * settings.image_size().1 / settings.image_size().0));
+        let logo = entry(ArtworkKind::Logos, 1000, 250);
+        assert_eq!(size(&logo, (None, None)), (640, 160));
+        assert_eq!(size(&logo, (None, Some(50))), (200, 50));
+        assert_eq!(size(&logo, (Some(4000), None)), (1000, 250));
+
+        let dir = Path::new("/optimized");
+        assert_eq!(optimized_path(&logo, dir, None, OutputFormat::Webp), Path::new("/optimized/logos/1.webp"));
+        assert_eq!(optimized_path(&logo, dir, Some((200, 50)), OutputFormat::Jpeg), Path::new("/optimized/logos/200x50/1.jpg"));
+        let too_wide = ArtworkQuery { index: None, w: Some(MAX_SIZE + 1), h: None };
+        assert_eq!(too_wide.bounds().unwrap_err().kind(), ErrorKind::BadRequest);
+        // sizes are rounded up, so there are only so many of them
+        assert_eq!(ArtworkQuery { index: None, w: Some(300), h: Some(64) }.bounds().unwrap(), (Some(512), Some(64)));
+        assert_eq!(ArtworkQuery { index: None, w: None, h: Some(1) }.bounds().unwrap(), (None, Some(64)));
+    }
+
+    #[test]
+    fn optimizing_follows_the_profile_and_skips_fresh_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let (artwork, dir_out) = (ArtworkStore::open(dir.path().join("images")).unwrap(), dir.path().join("optimized"));
+        let logo = artwork.save("1", ArtworkKind::Logos, vec![fetched("4425", "png", png(200, 50))]).unwrap().remove(0);
+        let mut settings = ServerSettings::default();
+
+        let fitted = crate::server::optimize_artwork(&artwork, &logo, &settings, &dir_out, (None, None), false).unwrap();
+        assert_eq!(image::image_dimensions(&fitted).unwrap(), (200, 50));
+        let pad = ImageProfile { width: 100, height: 100, quality: 80.0, resize: ResizePolicy::Pad, format: OutputFormat::Png };
+        settings.image_profiles.insert(ArtworkKind::Logos, pad);
+        let padded = crate::server::optimize_artwork(&artwork, &logo, &settings, &dir_out, (None, None), false).unwrap();
+        assert_eq!((padded.extension().unwrap().to_str(), image::image_dimensions(&padded).unwrap()), (Some("png"), (100, 100)));
+        assert_eq!(image::open(&padded).unwrap().to_rgba8().get_pixel(50, 10)[3], 0);
+
+        // an image that is newer than its original isn't optimized again
+        let modified = || fs::metadata(&padded).unwrap().modified().unwrap();
+        let age = |path: &Path, secs| fs::File::options().write(true).open(path).unwrap()
+            .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(secs)).unwrap();
+        age(&artwork.path(&logo), 3600);
+        age(&padded, 60);
+        let before = modified();
+        crate::server::optimize_artwork(&artwork, &logo, &settings, &dir_out, (None, None), false).unwrap();
+        assert_eq!(modified(), before);
+        crate::server::optimize_artwork(&artwork, &logo, &settings, &dir_out, (None, None), true).unwrap();
+        assert!(modified() > before);
+
+        // unless the profile changed in a way the path doesn't show
+        age(&padded, 60);
+        let before = modified();
+        settings.image_profiles.insert(ArtworkKind::Logos, ImageProfile { resize: ResizePolicy::Fill, ..pad });
+        crate::server::optimize_artwork(&artwork, &logo, &settings, &dir_out, (None, None), false).unwrap();
+        assert!(modified() > before);
+
+        // only so many sizes of their own are kept
+        for height in [64, 128, 256] {
+            for width in [64, 128, 256] {
+                crate::server::optimize_artwork(&artwork, &logo, &settings, &dir_out, (Some(width), Some(height)), false).unwrap();
+            }
+        }
+        let sized = fs::read_dir(dir_out.join("logos")).unwrap().flatten().filter(|dir| dir.path().is_dir()).count();
+        assert_eq!(sized, MAX_SIZED_COPIES);
+    }
+}
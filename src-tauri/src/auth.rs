@@ -0,0 +1,35 @@
+//! This crate is for what the client and the server have to agree on
+//! to authenticate requests. Every client has its own Ed25519 key,
+//! the "device key", and signs every request that changes something
+//! on the server. The signature covers
+//!
+//! ```text
+//! METHOD\npath?query\ntimestamp\nsha256(body)
+//! ```
+//!
+//! and is sent base64 encoded together with the device id and the
+//! timestamp in the `X-Nas-*` headers.
+use ed25519_dalek::VerifyingKey;
+use sha2::{Digest, Sha256};
+
+/// The id of the device that signed the request, see `device_id()`.
+pub const DEVICE_HEADER: &str = "x-nas-device";
+/// When the request was signed, in seconds since the unix epoch.
+pub const TIMESTAMP_HEADER: &str = "x-nas-timestamp";
+/// The base64 encoded Ed25519 signature of `message()`.
+pub const SIGNATURE_HEADER: &str = "x-nas-signature";
+
+/// How far the clocks of the client and the server may drift apart
+/// before signed requests are rejected.
+pub const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// The id of a device, the first 16 hex digits of the SHA-256 of its
+/// public key.
+pub fn device_id(key: &VerifyingKey) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))[..16].to_owned()
+}
+
+/// The bytes that are signed for a request.
+pub fn message(method: &str, path_and_query: &str, timestamp: i64, body: &[u8]) -> Vec<u8> {
+    format!("{}\n{}\n{}\n{:x}", method.to_uppercase(), path_and_query, timestamp, Sha256::digest(body)).into_bytes()
+}
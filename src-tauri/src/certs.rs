@@ -0,0 +1,174 @@
+//! This crate is for serving https without a public CA.
+//! `nas-game server cert init` creates a local CA and a server
+//! certificate signed by it, which the server loads when `tls` is on
+//! in the server settings. Clients pin the CA the first time they
+//! connect, see the `pinning` crate of the client.
+//!
+//! Running `cert init` again only renews the server certificate, the
+//! CA is kept so that the pinned clients keep trusting the server.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{info, logging::LoggingLevel, logging::logging_function};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use rcgen::{BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose};
+use rustls::ServerConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+
+pub const DEFAULT_TLS_DIR: &str = "tls";
+pub const CA_CERT_FILE: &str = "ca.crt";
+pub const CA_KEY_FILE: &str = "ca.key";
+pub const SERVER_CERT_FILE: &str = "server.crt";
+pub const SERVER_KEY_FILE: &str = "server.key";
+
+/// What `init()` created.
+#[derive(Debug)]
+pub struct CertReport {
+    pub ca_cert: PathBuf,
+    /// The fingerprint clients show when they pin the CA, see
+    /// `pinning::fingerprint()`.
+    pub ca_sha256: String,
+    pub new_ca: bool,
+    pub server_cert: PathBuf,
+    pub server_key: PathBuf,
+    pub names: Vec<String>,
+}
+
+fn rcgen_error(what: &str) -> impl FnOnce(rcgen::Error) -> NasError + '_ {
+    move |e| NasError::new(ErrorKind::FailedToEncode, format!("Failed to create the {}: {}", what, e))
+}
+
+/// Writes a file only the current user may read.
+fn write_private(path: &Path, contents: &str) -> Result<(), NasError> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path).context(ErrorKind::FailedToWrite, format!("Failed to create {:?}", path))?;
+    std::io::Write::write_all(&mut file, contents.as_bytes()).context(ErrorKind::FailedToWrite, format!("Failed to write {:?}", path))
+}
+
+/// Creates the CA in `dir` unless there already is one (or `new_ca` is
+/// set) and a server certificate for `names`, which always include
+/// `localhost`, `127.0.0.1` and `pinning::TLS_NAME`.
+///
+/// The server certificate is written together with the CA, so that
+/// the clients get to see the CA during the handshake.
+pub fn init(dir: &Path, names: &[String], new_ca: bool) -> Result<CertReport, NasError> {
+    fs::create_dir_all(dir).context(ErrorKind::FailedToCreateFolder, format!("Failed to create {:?}", dir))?;
+    let (ca_cert_path, ca_key_path) = (dir.join(CA_CERT_FILE), dir.join(CA_KEY_FILE));
+
+    let reuse_ca = !new_ca && ca_cert_path.exists() && ca_key_path.exists();
+    let (ca_pem, ca_key) = if reuse_ca {
+        let pem = fs::read_to_string(&ca_cert_path).context(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", ca_cert_path))?;
+        let key = fs::read_to_string(&ca_key_path).context(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", ca_key_path))?;
+        (pem, KeyPair::from_pem(&key).map_err(rcgen_error("CA key"))?)
+    } else {
+        let key = KeyPair::generate().map_err(rcgen_error("CA key"))?;
+        let mut params = CertificateParams::new(Vec::<String>::new()).map_err(rcgen_error("CA"))?;
+        params.distinguished_name.push(DnType::CommonName, "nas-game local CA");
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign, KeyUsagePurpose::DigitalSignature];
+        let cert = params.self_signed(&key).map_err(rcgen_error("CA"))?;
+        write_private(&ca_key_path, &key.serialize_pem())?;
+        fs::write(&ca_cert_path, cert.pem()).context(ErrorKind::FailedToWrite, format!("Failed to write {:?}", ca_cert_path))?;
+        info!("Created a new CA at {:?}", ca_cert_path);
+        (cert.pem(), key)
+    };
+    // only used to sign, the certificate that is handed out stays the one on disk
+    let issuer = CertificateParams::from_ca_cert_pem(&ca_pem).map_err(rcgen_error("CA"))?
+        .self_signed(&ca_key).map_err(rcgen_error("CA"))?;
+    let ca_der = CertificateDer::from_pem_slice(ca_pem.as_bytes()).context(ErrorKind::FailedToParse, format!("Failed to parse {:?}", ca_cert_path))?;
+
+    let mut all_names = vec!["localhost".to_owned(), "127.0.0.1".to_owned(), nas_game_lib::pinning::TLS_NAME.to_owned()];
+    for name in names {
+        if !name.is_empty() && !all_names.contains(name) { all_names.push(name.clone()); }
+    }
+    let key = KeyPair::generate().map_err(rcgen_error("server key"))?;
+    let mut params = CertificateParams::new(all_names.clone()).map_err(rcgen_error("server certificate"))?;
+    params.distinguished_name.push(DnType::CommonName, all_names[0].clone());
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.use_authority_key_identifier_extension = true;
+    let cert = params.signed_by(&key, &issuer, &ca_key).map_err(rcgen_error("server certificate"))?;
+
+    let (server_cert, server_key) = (dir.join(SERVER_CERT_FILE), dir.join(SERVER_KEY_FILE));
+    write_private(&server_key, &key.serialize_pem())?;
+    fs::write(&server_cert, format!("{}{}", cert.pem(), ca_pem)).context(ErrorKind::FailedToWrite, format!("Failed to write {:?}", server_cert))?;
+    info!("Created the server certificate at {:?} for {:?}", server_cert, all_names);
+    Ok(CertReport {
+        ca_cert: ca_cert_path,
+        ca_sha256: nas_game_lib::pinning::fingerprint(&ca_der),
+        new_ca: !reuse_ca,
+        server_cert,
+        server_key,
+        names: all_names,
+    })
+}
+
+/// Loads the certificate chain and the key the server speaks https
+/// with.
+///
+/// # Errors
+/// Fails with `ErrorKind::FailedToReadFile` if either is missing, run
+/// `nas-game server cert init` to create them.
+pub fn server_config(cert: &Path, key: &Path) -> Result<ServerConfig, NasError> {
+    let missing = |path: &Path| format!("Failed to read {:?}, create it with `nas-game server cert init`", path);
+    let chain = CertificateDer::pem_file_iter(cert).context(ErrorKind::FailedToReadFile, missing(cert))?
+        .collect::<Result<Vec<_>, _>>().context(ErrorKind::FailedToParse, format!("Failed to parse {:?}", cert))?;
+    let private_key = PrivateKeyDer::from_pem_file(key).context(ErrorKind::FailedToReadFile, missing(key))?;
+    ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .context(ErrorKind::Ignore, "Failed to configure tls")?
+        .with_no_client_auth()
+        .with_single_cert(chain, private_key)
+        .context(ErrorKind::FailedToParse, format!("The key {:?} doesn't match the certificate", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpResponse, HttpServer, dev::ServerHandle};
+
+    fn serve(report: &CertReport) -> (String, ServerHandle) {
+        let config = server_config(&report.server_cert, &report.server_key).unwrap();
+        let server = HttpServer::new(|| App::new().route("/", web::get().to(HttpResponse::Ok)))
+            .workers(1)
+            .bind_rustls_0_23(("127.0.0.1", 0), config).unwrap();
+        let url = format!("https://127.0.0.1:{}/", server.addrs()[0].port());
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        (url, handle)
+    }
+
+    #[actix_web::test]
+    async fn clients_trust_the_first_ca_they_see() {
+        let dir = tempfile::tempdir().unwrap();
+        let tls = dir.path().join("tls");
+        let first = init(&tls, &[], false).unwrap();
+        let renewed = init(&tls, &["nas.local".to_owned()], false).unwrap();
+        assert!(first.new_ca && !renewed.new_ca);
+        assert_eq!(renewed.ca_sha256, first.ca_sha256);
+        assert!(renewed.names.contains(&"nas.local".to_owned()));
+
+        let known_servers = dir.path().join("known_servers.json");
+        let http = nas_game_lib::pinning::http_client(known_servers.clone()).unwrap();
+        let (url, first_server) = serve(&renewed);
+        assert!(http.get(&url).send().await.unwrap().status().is_success());
+        let pinned = nas_game_lib::pinning::known_servers(&known_servers).unwrap();
+        assert_eq!((pinned[0].host.as_str(), &pinned[0].sha256), ("127.0.0.1", &first.ca_sha256));
+
+        // a renewed server certificate is still trusted
+        let (url, renewed_server) = serve(&init(&tls, &[], false).unwrap());
+        assert!(http.get(&url).send().await.unwrap().status().is_success());
+
+        // a new CA is refused until its pin is forgotten
+        let (url, new_ca_server) = serve(&init(&tls, &[], true).unwrap());
+        assert!(http.get(&url).send().await.is_err());
+        assert!(nas_game_lib::pinning::forget(&known_servers, "127.0.0.1").unwrap());
+        assert!(http.get(&url).send().await.unwrap().status().is_success());
+
+        for server in [first_server, renewed_server, new_ca_server] { server.stop(false).await; }
+    }
+}
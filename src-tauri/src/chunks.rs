@@ -0,0 +1,291 @@
+//! This crate is for transferring game updates in chunks instead of
+//! whole files. Every file is split with content defined chunking
+//! (FastCDC), so a patch that changes a few bytes in the middle of a
+//! huge archive only changes the chunks around those bytes.
+//!
+//! The server keeps a `ChunkIndex` of every game, the client builds
+//! the same index of its local install and only downloads the chunks
+//! it doesn't have yet. Chunks are addressed by their SHA-256, so a
+//! chunk can be reused no matter which file or offset it came from.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, logging::LoggingLevel, logging::logging_function};
+use crate::manifest;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use fastcdc::v2020::StreamCDC;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const MIN_CHUNK_SIZE: u32 = 256 * 1024;
+pub const AVG_CHUNK_SIZE: u32 = 1024 * 1024;
+pub const MAX_CHUNK_SIZE: u32 = 4 * 1024 * 1024;
+
+/// Requests for chunks the index doesn't know rebuild the index at
+/// most this often, anyone can ask for made up hashes.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A piece of a file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// The lower case hex SHA-256 of the data of the chunk.
+    pub sha256: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// A file of a game and the chunks it consists of, in order.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChunkedFile {
+    /// The path relative to the install directory, always separated
+    /// by `/`.
+    pub path: String,
+    pub size: u64,
+    /// The SHA-256 of the whole file.
+    pub sha256: String,
+    /// Only used to tell whether the file has to be chunked again.
+    #[serde(default)]
+    pub modified: u64,
+    pub chunks: Vec<Chunk>,
+}
+
+/// Every file of a game split into chunks.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChunkIndex {
+    pub game_id: String,
+    pub total_size: u64,
+    pub files: Vec<ChunkedFile>,
+}
+
+impl ChunkIndex {
+    pub fn file(&self, path: &str) -> Option<&ChunkedFile> {
+        self.files.iter().find(|f| f.path == path)
+    }
+
+    /// Every chunk by its hash together with the file it is part of.
+    /// Chunks that occur more than once point to their first
+    /// occurrence.
+    pub fn locations(&self) -> HashMap<&str, (&ChunkedFile, &Chunk)> {
+        let mut locations = HashMap::new();
+        for file in &self.files {
+            for chunk in &file.chunks {
+                locations.entry(chunk.sha256.as_str()).or_insert((file, chunk));
+            }
+        }
+        locations
+    }
+}
+
+pub fn hash_bytes(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Splits a file into chunks and hashes the whole file on the way.
+pub fn chunk_file(path: &Path) -> Result<(String, Vec<Chunk>), NasError> {
+    let file = fs::File::open(path).context(ErrorKind::FailedToReadFile, format!("Failed to open {:?}", path))?;
+    let mut hasher = Sha256::new();
+    let mut chunks = Vec::new();
+    for chunk in StreamCDC::new(BufReader::new(file), MIN_CHUNK_SIZE, AVG_CHUNK_SIZE, MAX_CHUNK_SIZE) {
+        let chunk = chunk.context(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path))?;
+        hasher.update(&chunk.data);
+        chunks.push(Chunk { sha256: hash_bytes(&chunk.data), offset: chunk.offset, size: chunk.length as u64 });
+    }
+    Ok((format!("{:x}", hasher.finalize()), chunks))
+}
+
+/// Reads a chunk of a file and checks that it still has the expected
+/// hash.
+///
+/// # Errors
+/// Fails with `ErrorKind::NotFound` if the file has changed since it
+/// was chunked.
+pub fn read_chunk(path: &Path, chunk: &Chunk) -> Result<Vec<u8>, NasError> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(chunk.offset))?;
+    let mut data = Vec::with_capacity(chunk.size as usize);
+    file.take(chunk.size).read_to_end(&mut data)?;
+    if data.len() as u64 != chunk.size || hash_bytes(&data) != chunk.sha256 {
+        return Err(NasError::new(ErrorKind::NotFound, format!("{:?} no longer contains the chunk {}", path, chunk.sha256)));
+    }
+    Ok(data)
+}
+
+/// Builds the chunk index of every file below `root`. Files whose
+/// size and modification time match the `previous` index keep their
+/// chunks, every other file is chunked again.
+///
+/// Unfinished downloads (`*.part`) are left out.
+pub fn build_index(game_id: &str, root: &Path, previous: Option<&ChunkIndex>) -> Result<ChunkIndex, NasError> {
+    let mut paths = Vec::new();
+    manifest::collect_files(root, root, &mut paths)?;
+    paths.retain(|(relative, _)| !relative.ends_with(".part"));
+    paths.sort();
+
+    let mut files = Vec::with_capacity(paths.len());
+    let mut chunked = 0;
+    for (relative, path) in paths {
+        let metadata = fs::metadata(&path)?;
+        let (size, modified) = (metadata.len(), manifest::modified(&metadata));
+        let known = previous.and_then(|p| p.file(&relative)).filter(|f| f.size == size && f.modified == modified);
+        let file = match known {
+            Some(file) => file.clone(),
+            None => {
+                chunked += 1;
+                let (sha256, chunks) = chunk_file(&path)?;
+                ChunkedFile { path: relative, size, sha256, modified, chunks }
+            },
+        };
+        files.push(file);
+    }
+    trace!("Chunked {} of the {} files of {}", chunked, files.len(), game_id);
+    Ok(ChunkIndex { game_id: game_id.to_owned(), total_size: files.iter().map(|f| f.size).sum(), files })
+}
+
+/// Builds the chunk index of a game, reusing the index that was cached
+/// in `cache_dir` the last time.
+pub fn load_or_build_index(cache_dir: &Path, game_id: &str, root: &Path) -> Result<ChunkIndex, NasError> {
+    let cache = manifest::join(cache_dir, &format!("{}.json", game_id))?;
+    let previous: Option<ChunkIndex> = fs::read_to_string(&cache).ok().and_then(|s| serde_json::from_str(&s).ok());
+    let index = build_index(game_id, root, previous.as_ref())?;
+    if previous.as_ref() != Some(&index) {
+        info!("The chunk index of {} has changed, {} files with {} bytes", game_id, index.files.len(), index.total_size);
+        let json = serde_json::to_vec(&index).context(ErrorKind::FailedToSerialize, "Failed to serialize the chunk index")?;
+        if let Err(e) = fs::create_dir_all(cache_dir).and_then(|_| fs::write(&cache, json)) {
+            warn!("Failed to cache the chunk index of {} at {:?} with {}", game_id, cache, e);
+        }
+    }
+    Ok(index)
+}
+
+/// The chunk index of a game together with where its files are, so
+/// that chunks can be looked up by their hash.
+pub struct ChunkSource {
+    pub root: PathBuf,
+    pub index: ChunkIndex,
+    // hash -> (file, chunk) as positions in `index`
+    locations: HashMap<String, (usize, usize)>,
+    built_at: Instant,
+}
+
+impl ChunkSource {
+    pub fn new(root: PathBuf, index: ChunkIndex) -> Self {
+        let mut locations = HashMap::new();
+        for (i, file) in index.files.iter().enumerate() {
+            for (j, chunk) in file.chunks.iter().enumerate() {
+                locations.entry(chunk.sha256.clone()).or_insert((i, j));
+            }
+        }
+        Self { root, index, locations, built_at: Instant::now() }
+    }
+
+    /// Reads a chunk by its hash.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::NotFound` for unknown chunks and chunks
+    /// whose file has changed since the index was built, and with
+    /// `ErrorKind::InvalidPath` for files that became a symlink out
+    /// of the game.
+    pub fn read(&self, sha256: &str) -> Result<Vec<u8>, NasError> {
+        let &(i, j) = self.locations.get(sha256)
+            .ok_or_else(|| NasError::new(ErrorKind::NotFound, format!("There is no chunk {} in {}", sha256, self.index.game_id)))?;
+        let file = &self.index.files[i];
+        read_chunk(&manifest::resolve(&self.root, &file.path)?, &file.chunks[j])
+    }
+}
+
+/// The chunk sources of every game the server was asked about, so
+/// that chunk requests don't have to load the index every time.
+pub struct ChunkCache {
+    cache_dir: PathBuf,
+    sources: Mutex<HashMap<String, Arc<ChunkSource>>>,
+}
+
+impl ChunkCache {
+    /// `cache_dir` is where the indices are kept between restarts.
+    pub fn new(cache_dir: PathBuf) -> Self {
+        Self { cache_dir, sources: Mutex::new(HashMap::new()) }
+    }
+
+    /// Builds the index of a game again, see `load_or_build_index()`.
+    pub fn refresh(&self, game_id: &str, root: &Path) -> Result<Arc<ChunkSource>, NasError> {
+        let index = load_or_build_index(&self.cache_dir, game_id, root)?;
+        let source = Arc::new(ChunkSource::new(root.to_path_buf(), index));
+        self.sources.lock().map_err(|_| ErrorKind::FailedToAquireLock)?.insert(game_id.to_owned(), source.clone());
+        Ok(source)
+    }
+
+    /// Returns the known source of a game, or builds it should the
+    /// game be unknown or have moved.
+    pub fn source(&self, game_id: &str, root: &Path) -> Result<Arc<ChunkSource>, NasError> {
+        let known = self.sources.lock().map_err(|_| ErrorKind::FailedToAquireLock)?.get(game_id).cloned();
+        match known {
+            Some(source) if source.root == root => Ok(source),
+            _ => self.refresh(game_id, root),
+        }
+    }
+
+    /// Reads a chunk of a game. Should the files have changed since
+    /// the index was built then the index is built again once, but
+    /// not more often than every `MIN_REFRESH_INTERVAL`.
+    pub fn read(&self, game_id: &str, root: &Path, sha256: &str) -> Result<Vec<u8>, NasError> {
+        let source = self.source(game_id, root)?;
+        match source.read(sha256) {
+            Err(e) if e.kind() == ErrorKind::NotFound && source.built_at.elapsed() >= MIN_REFRESH_INTERVAL => {
+                trace!("Chunk {} of {} is missing, rebuilding the index", sha256, game_id);
+                self.refresh(game_id, root)?.read(sha256)
+            },
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Data that doesn't repeat, so that the chunk boundaries depend on
+    /// the content like they would for real game files.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect()
+    }
+
+    #[test]
+    fn edits_only_change_nearby_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = noise(8 * 1024 * 1024, 42);
+        fs::write(dir.path().join("level.pak"), &data).unwrap();
+        fs::write(dir.path().join("empty"), "").unwrap();
+        fs::write(dir.path().join("level.pak.part"), "unfinished").unwrap();
+
+        let before = build_index("1", dir.path(), None).unwrap();
+        assert_eq!(before.files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), ["empty", "level.pak"]);
+        let chunks = &before.file("level.pak").unwrap().chunks;
+        assert!(chunks.len() > 2);
+        assert_eq!(chunks.iter().map(|c| c.size).sum::<u64>(), data.len() as u64);
+        assert_eq!(before.file("level.pak").unwrap().sha256, hash_bytes(&data));
+
+        // insert a few bytes in the middle of the file
+        let mut patched = data.clone();
+        patched.splice(4 * 1024 * 1024..4 * 1024 * 1024, *b"patch");
+        fs::write(dir.path().join("level.pak"), &patched).unwrap();
+        let after = build_index("1", dir.path(), None).unwrap();
+        let known = before.locations();
+        let new = after.file("level.pak").unwrap().chunks.iter().filter(|c| !known.contains_key(c.sha256.as_str())).count();
+        assert!(new <= 2, "{} of {} chunks changed", new, chunks.len());
+
+        let source = ChunkSource::new(dir.path().to_path_buf(), after.clone());
+        let chunk = &after.file("level.pak").unwrap().chunks[1];
+        assert_eq!(source.read(&chunk.sha256).unwrap().len() as u64, chunk.size);
+        assert_eq!(source.read("unknown").unwrap_err().kind(), ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,305 @@
+//! This crate is for the client side code of the application that
+//! isn't tied to the Tauri window, i.e. where the client keeps its
+//! files and how it reaches the server.
+//!
+//! Requests that change something on the server are signed with the
+//! device key of the client, see the `auth` crate. The key is created
+//! by `nas-game client register` and has to be approved on the server
+//! before the signatures are accepted.
+//!
+//! Servers that speak https are trusted on first use, see the
+//! `pinning` crate, so every request goes through the client of
+//! `http()`.
+//!
+//! Once the server has accounts, the client also sends the token of
+//! `nas-game client login` with every request, see the `users` crate
+//! of the server.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{info, warn, logging::LoggingLevel, logging::logging_function};
+use crate::auth;
+use crate::pinning;
+
+use std::{env, fs};
+use std::path::{Path, PathBuf};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use ed25519_dalek::{Signer, SigningKey};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_SERVER_URL: &str = "http://127.0.0.1:53317";
+const CLIENT_SETTINGS_PATH: &str = "client_settings.json";
+const DEVICE_KEY_PATH: &str = "device_key";
+
+/// The settings of the client, see `get_client_settings()`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientSettings {
+    /// Where the server can be reached, e.g. `http://192.168.1.10:53317`.
+    #[serde(default = "default_server_url")]
+    pub server_url: String,
+    /// Tells the clients apart in the play sessions, generated the
+    /// first time the settings are read.
+    #[serde(default)]
+    pub client_id: String,
+    /// Who is playing on this client, defaults to the name of the
+    /// logged in user.
+    #[serde(default = "default_user")]
+    pub user: String,
+    /// The token of the last login, see `login()`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+fn default_server_url() -> String { DEFAULT_SERVER_URL.to_owned() }
+
+fn default_user() -> String {
+    env::var("USER").or_else(|_| env::var("USERNAME")).unwrap_or_else(|_| "default".to_owned())
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self { Self { server_url: default_server_url(), client_id: String::new(), user: default_user(), token: None } }
+}
+
+/// The directory the client keeps its files in, which is created
+/// should it be missing.
+pub fn client_dir() -> PathBuf {
+    let path = match env::var("HOME") {
+        Ok(home) => PathBuf::from(home).join(".local/share/nas-game/client"),
+        Err(_) => PathBuf::from("nas-game-client"),
+    };
+    if !path.exists() {
+        match fs::create_dir_all(&path) {
+            Ok(()) => { info!("Folder {:?} was created since it was missing", &path); },
+            Err(e) => { warn!("Failed to create the client directory {:?} with {:?}", &path, e); },
+        }
+    }
+    path
+}
+
+/// Reads `client_settings.json` from the client directory. The server
+/// url can be overridden with `NAS_GAME_SERVER`.
+///
+/// The `client_id` stays empty until the settings are saved for the
+/// first time, see `save_client_settings()`.
+///
+/// # Errors
+/// A missing file results in the default settings, only a file that
+/// can't be parsed errors with `ErrorKind::FailedToParse`.
+pub fn get_client_settings() -> Result<ClientSettings, NasError> {
+    let path = client_dir().join(CLIENT_SETTINGS_PATH);
+    let mut settings = match fs::read_to_string(&path) {
+        Ok(s) => serde_json::from_str(&s).context(ErrorKind::FailedToParse, format!("Failed to parse {:?}", path))?,
+        Err(_) => ClientSettings::default(),
+    };
+    if let Ok(url) = env::var("NAS_GAME_SERVER") {
+        settings.server_url = url;
+    }
+    Ok(settings)
+}
+
+/// Writes `client_settings.json` to the client directory. Settings
+/// without a `client_id` are given one first, which then stays the
+/// same.
+pub fn save_client_settings(settings: &mut ClientSettings) -> Result<(), NasError> {
+    if settings.client_id.is_empty() {
+        settings.client_id = uuid::Uuid::new_v4().to_string();
+        info!("This client is now known as {}", settings.client_id);
+    }
+    let path = client_dir().join(CLIENT_SETTINGS_PATH);
+    let json = serde_json::to_string_pretty(settings).context(ErrorKind::FailedToSerialize, "Failed to serialize the client settings")?;
+    // the settings hold the token, so they are only readable by the current user
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&path).context(ErrorKind::FailedToWrite, format!("Failed to open {:?}", path))?;
+    // older versions created the file readable by everyone
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600)).context(ErrorKind::FailedToWrite, format!("Failed to protect {:?}", path))?;
+    std::io::Write::write_all(&mut file, json.as_bytes()).context(ErrorKind::FailedToWrite, format!("Failed to write {:?}", path))
+}
+
+/// The http client every request to the server is made with, which
+/// pins the certificate of https servers.
+pub fn http() -> Result<reqwest::Client, NasError> {
+    pinning::http_client(client_dir().join(pinning::KNOWN_SERVERS_PATH))
+}
+
+/// Whether the client has never been started before, i.e. it doesn't
+/// know which server to talk to yet, since the settings have never
+/// been saved.
+pub fn is_first_run() -> bool {
+    !client_dir().join(CLIENT_SETTINGS_PATH).exists()
+}
+
+/// Makes `url` the server of the client, e.g. one that was found with
+/// `discovery::discover()`.
+pub fn select_server(url: &str) -> Result<ClientSettings, NasError> {
+    reqwest::Url::parse(url).context(ErrorKind::BadRequest, format!("{:?} is not a valid url", url))?;
+    let mut settings = get_client_settings()?;
+    settings.server_url = url.trim_end_matches('/').to_owned();
+    save_client_settings(&mut settings)?;
+    info!("The server of this client is now {}", settings.server_url);
+    Ok(settings)
+}
+
+fn read_device_key(path: &Path) -> Result<Option<SigningKey>, NasError> {
+    let encoded = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(NasError::new(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path)).with_source(e)),
+    };
+    let seed: [u8; 32] = BASE64.decode(encoded.trim()).ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| NasError::new(ErrorKind::FailedToParse, format!("{:?} is not a device key", path)))?;
+    Ok(Some(SigningKey::from_bytes(&seed)))
+}
+
+/// Reads the key this client signs its requests with, `None` if the
+/// client has never been registered.
+pub fn load_device_key() -> Result<Option<SigningKey>, NasError> {
+    read_device_key(&client_dir().join(DEVICE_KEY_PATH))
+}
+
+/// Returns the device key, generating it should there be none yet.
+/// The key is only readable by the current user.
+pub fn create_device_key() -> Result<SigningKey, NasError> {
+    let path = client_dir().join(DEVICE_KEY_PATH);
+    if let Some(key) = read_device_key(&path)? { return Ok(key); }
+    let mut seed = [0; 32];
+    getrandom::getrandom(&mut seed).map_err(|e| NasError::new(ErrorKind::Ignore, format!("Failed to generate a device key: {}", e)))?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&path).context(ErrorKind::FailedToWrite, format!("Failed to create {:?}", path))?;
+    std::io::Write::write_all(&mut file, BASE64.encode(seed).as_bytes()).context(ErrorKind::FailedToWrite, format!("Failed to write {:?}", path))?;
+    info!("Created a new device key at {:?}", path);
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Adds the signature headers of `auth` for the request to `builder`.
+pub fn sign(builder: reqwest::RequestBuilder, key: &SigningKey, method: &reqwest::Method, url: &reqwest::Url, body: &[u8]) -> reqwest::RequestBuilder {
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_owned(),
+    };
+    let timestamp = chrono::Utc::now().timestamp();
+    let signature = key.sign(&auth::message(method.as_str(), &path, timestamp, body));
+    builder
+        .header(auth::DEVICE_HEADER, auth::device_id(&key.verifying_key()))
+        .header(auth::TIMESTAMP_HEADER, timestamp.to_string())
+        .header(auth::SIGNATURE_HEADER, BASE64.encode(signature.to_bytes()))
+}
+
+/// What the requests of this client are made with, read once for
+/// everything that sends many requests, e.g. `install::install()`.
+#[derive(Clone, Default)]
+pub struct Credentials {
+    /// The token of the logged in user.
+    pub token: Option<String>,
+    /// The key of the device, if it has been registered.
+    pub device_key: Option<SigningKey>,
+}
+
+impl Credentials {
+    /// Reads the token of the logged in user and the device key.
+    pub fn load() -> Result<Self, NasError> {
+        Ok(Self { token: get_client_settings()?.token, device_key: load_device_key()? })
+    }
+
+    /// Sends `builder` as the logged in user and signs it with the
+    /// device key, for whichever of them there is.
+    pub fn apply(&self, mut builder: reqwest::RequestBuilder, method: &reqwest::Method, url: &reqwest::Url, body: &[u8]) -> reqwest::RequestBuilder {
+        if let Some(token) = &self.token {
+            builder = builder.bearer_auth(token);
+        }
+        if let Some(key) = &self.device_key {
+            builder = sign(builder, key, method, url, body);
+        }
+        builder
+    }
+}
+
+/// Builds a request with a json body, signed with the device key if
+/// the client has one and sent as the logged in user if there is one.
+pub fn request(http: &reqwest::Client, method: reqwest::Method, url: &str, body: Vec<u8>) -> Result<reqwest::RequestBuilder, NasError> {
+    let url = reqwest::Url::parse(url).context(ErrorKind::BadRequest, format!("{:?} is not a valid url", url))?;
+    let builder = http.request(method.clone(), url.clone());
+    let mut builder = Credentials::load()?.apply(builder, &method, &url, &body);
+    if !body.is_empty() {
+        builder = builder.header(reqwest::header::CONTENT_TYPE, "application/json");
+    }
+    Ok(builder.body(body))
+}
+
+/// Sends a request to `path` on the server and returns the status and
+/// the body of the response, which is what `nas-game client request`
+/// does.
+pub async fn send(server_url: &str, method: &str, path: &str, body: Vec<u8>) -> Result<(reqwest::StatusCode, String), NasError> {
+    let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes())
+        .map_err(|_| NasError::new(ErrorKind::BadRequest, format!("{:?} is not a http method", method)))?;
+    let url = format!("{}/{}", server_url.trim_end_matches('/'), path.trim_start_matches('/'));
+    let res = request(&http()?, method, &url, body)?.send().await
+        .context(ErrorKind::FailedToFetch, format!("Failed to request {}", url))?;
+    let status = res.status();
+    let text = res.text().await.context(ErrorKind::FailedToFetch, format!("Failed to read the response of {}", url))?;
+    Ok((status, text))
+}
+
+/// What the server answers to a registration.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RegisteredDevice {
+    pub id: String,
+    pub name: String,
+    pub status: String,
+}
+
+/// Creates the device key if necessary and registers it with the
+/// server as `name`.
+pub async fn register_device(server_url: &str, name: &str) -> Result<RegisteredDevice, NasError> {
+    let key = create_device_key()?;
+    let url = format!("{}/devices/register", server_url.trim_end_matches('/'));
+    let body = serde_json::json!({ "name": name, "public_key": BASE64.encode(key.verifying_key().as_bytes()) });
+    let res = http()?.post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send().await
+        .context(ErrorKind::FailedToFetch, format!("Failed to request {}", url))?;
+    let status = res.status();
+    let text = res.text().await.context(ErrorKind::FailedToFetch, format!("Failed to read the response of {}", url))?;
+    if !status.is_success() {
+        return Err(NasError::new(ErrorKind::FailedToFetch, format!("The server refused the registration with {}: {}", status, text)));
+    }
+    serde_json::from_str(&text).context(ErrorKind::FailedToParse, format!("Failed to parse the response of {}", url))
+}
+
+/// What the server answers to a login.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LoggedIn {
+    pub token: String,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Logs in as `name` and keeps the token in the client settings, so
+/// that every following request is made as that user.
+pub async fn login(name: &str, password: &str) -> Result<LoggedIn, NasError> {
+    let mut settings = get_client_settings()?;
+    let url = format!("{}/login", settings.server_url.trim_end_matches('/'));
+    let body = serde_json::json!({ "name": name, "password": password });
+    let res = http()?.post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send().await
+        .context(ErrorKind::FailedToFetch, format!("Failed to request {}", url))?;
+    let status = res.status();
+    let text = res.text().await.context(ErrorKind::FailedToFetch, format!("Failed to read the response of {}", url))?;
+    if !status.is_success() {
+        let kind = if status == reqwest::StatusCode::UNAUTHORIZED { ErrorKind::Unauthorized } else { ErrorKind::FailedToFetch };
+        return Err(NasError::new(kind, format!("The server refused the login with {}: {}", status, text)));
+    }
+    let logged_in: LoggedIn = serde_json::from_str(&text).context(ErrorKind::FailedToParse, format!("Failed to parse the response of {}", url))?;
+    settings.user = name.to_owned();
+    settings.token = Some(logged_in.token.clone());
+    save_client_settings(&mut settings)?;
+    info!("Logged in as {}", name);
+    Ok(logged_in)
+}
@@ -0,0 +1,317 @@
+//! This crate is for loading the `ServerSettings` in layers. Every
+//! layer overrides the one before it:
+//!
+//! 1. the defaults of `ServerSettings`
+//! 2. the settings file, `server_settings.json` in the data directory
+//! 3. environment variables, `NAS_GAME_` and the key in upper case,
+//!    e.g. `NAS_GAME_PORT=8080` or `NAS_GAME_DATA_DIR=/mnt/pool/nas-game`
+//! 4. the flags of `nas-game server`, e.g. `--port 8080` or
+//!    `--set webp_quality=90`
+//!
+//! Values are json, except for keys whose default is a string, so
+//! `NAS_GAME_NAME=attic` needs no quotes. Lists may also be given
+//! like `$PATH`, e.g. `NAS_GAME_LIBRARY_ROOTS=/mnt/pool/games:/mnt/pool/gog`.
+//!
+//! The settings file is found before it is read, so it can't move
+//! itself: it is `--config`, `NAS_GAME_CONFIG` or the settings file
+//! of the data directory that `--data-dir`, `NAS_GAME_DATA_DIR` or the
+//! default point to, see the `data_dir` crate.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, warn, logging::LoggingLevel, logging::logging_function};
+use crate::types::ServerSettings;
+use crate::data_dir::{self, DataDir};
+use crate::files::write_atomic;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use serde_json::{Map, Value};
+
+pub const ENV_PREFIX: &str = "NAS_GAME_";
+pub const CONFIG_ENV: &str = "NAS_GAME_CONFIG";
+
+/// Where the value of a setting came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File,
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File => write!(f, "file"),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Cli => write!(f, "flag"),
+        }
+    }
+}
+
+/// The settings with every layer applied.
+#[derive(Debug, Clone)]
+pub struct LoadedSettings {
+    pub settings: ServerSettings,
+    /// The settings file, which doesn't have to exist.
+    pub path: PathBuf,
+    /// The source of every key, see `Source`.
+    pub sources: BTreeMap<String, Source>,
+}
+
+/// The layers before they are merged, the environment is passed in so
+/// that it can be faked.
+#[derive(Debug, Clone, Default)]
+pub struct Layers {
+    /// `--config`
+    pub config: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    /// The flags as key and raw value, in the order they were given.
+    pub cli: Vec<(String, String)>,
+}
+
+impl Layers {
+    /// The layers of the current process.
+    pub fn from_process(config: Option<PathBuf>, cli: Vec<(String, String)>) -> Self {
+        let env = std::env::vars().filter(|(var, _)| var.starts_with(ENV_PREFIX)).collect();
+        Self { config, env, cli }
+    }
+
+    fn env_var(&self, var: &str) -> Option<&str> {
+        self.env.iter().rev().find(|(v, _)| v == var).map(|(_, value)| value.as_str())
+    }
+
+    /// Where the settings file is, see the crate docs.
+    pub fn settings_path(&self) -> PathBuf {
+        if let Some(path) = self.config.clone().or_else(|| self.env_var(CONFIG_ENV).map(PathBuf::from)) {
+            return crate::server::expand_tilde(&path.to_string_lossy());
+        }
+        let data_dir = self.cli.iter().rev().find(|(key, _)| key == "data_dir").map(|(_, value)| value.as_str())
+            .or_else(|| self.env_var(&env_var("data_dir")))
+            .map(PathBuf::from)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(data_dir::default_root);
+        crate::server::expand_tilde(&data_dir.to_string_lossy()).join(data_dir::SETTINGS_FILE)
+    }
+}
+
+/// The environment variable that overrides `key`.
+pub fn env_var(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase())
+}
+
+fn defaults() -> Map<String, Value> {
+    match serde_json::to_value(ServerSettings::default()) {
+        Ok(Value::Object(map)) => map,
+        _ => unreachable!("ServerSettings serializes to an object"),
+    }
+}
+
+/// Turns the raw value of an environment variable or flag into json,
+/// guided by the type of the default of `key`.
+///
+/// # Errors
+/// Fails with `ErrorKind::BadRequest` for keys that don't exist and
+/// values that are neither json nor a string where one is expected.
+pub fn parse_value(key: &str, raw: &str) -> Result<Value, NasError> {
+    let defaults = defaults();
+    let default = defaults.get(key)
+        .ok_or_else(|| NasError::new(ErrorKind::BadRequest, format!("There is no setting called {:?}, see `nas-game server config show`", key)))?;
+    match default {
+        Value::String(_) => Ok(Value::String(raw.to_owned())),
+        Value::Array(_) if !raw.trim_start().starts_with('[') => {
+            Ok(Value::Array(raw.split(':').filter(|part| !part.is_empty()).map(|part| Value::String(part.to_owned())).collect()))
+        },
+        _ => serde_json::from_str(raw).context(ErrorKind::BadRequest, format!("{:?} is not a valid value for {:?}", raw, key)),
+    }
+}
+
+fn read_file(path: &Path) -> Result<Option<Map<String, Value>>, NasError> {
+    let file = match fs::read_to_string(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(NasError::new(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path)).with_source(e)),
+    };
+    match serde_json::from_str(&file).context(ErrorKind::FailedToParse, format!("Failed to parse {:?}", path))? {
+        Value::Object(map) => Ok(Some(map)),
+        _ => Err(NasError::new(ErrorKind::FailedToParse, format!("{:?} is not a json object", path))),
+    }
+}
+
+fn into_settings(map: Map<String, Value>) -> Result<ServerSettings, NasError> {
+    serde_json::from_value(Value::Object(map)).context(ErrorKind::BadRequest, "The server settings are invalid")
+}
+
+/// Merges every layer without validating the result.
+///
+/// # Errors
+/// Fails if the settings file can't be read, or if a layer has a value
+/// of the wrong type or a key that doesn't exist. The file may have
+/// unknown keys, which are only warned of.
+pub fn merge(layers: &Layers) -> Result<LoadedSettings, NasError> {
+    let path = layers.settings_path();
+    let mut merged = defaults();
+    let mut sources: BTreeMap<String, Source> = merged.keys().map(|key| (key.clone(), Source::Default)).collect();
+
+    match read_file(&path)? {
+        Some(file) => for (key, value) in file {
+            if !merged.contains_key(&key) {
+                warn!("Ignoring the unknown setting {:?} in {:?}", key, path);
+                continue;
+            }
+            merged.insert(key.clone(), value);
+            sources.insert(key, Source::File);
+        },
+        None => { trace!("There are no server settings at {:?}, using the defaults", path); },
+    };
+    for key in sources.keys().cloned().collect::<Vec<_>>() {
+        let var = env_var(&key);
+        if let Some(raw) = layers.env_var(&var) {
+            merged.insert(key.clone(), parse_value(&key, raw)?);
+            sources.insert(key, Source::Env(var));
+        }
+    }
+    for (key, raw) in &layers.cli {
+        merged.insert(key.clone(), parse_value(key, raw)?);
+        sources.insert(key.clone(), Source::Cli);
+    }
+
+    Ok(LoadedSettings { settings: into_settings(merged)?, path, sources })
+}
+
+/// Merges every layer and validates the result.
+///
+/// # Errors
+/// Fails like `merge()`, or with `ErrorKind::BadRequest` if
+/// `validate()` finds problems.
+pub fn load(layers: &Layers) -> Result<LoadedSettings, NasError> {
+    let loaded = merge(layers)?;
+    let problems = validate(&loaded.settings);
+    if !problems.is_empty() {
+        return Err(NasError::new(ErrorKind::BadRequest, format!("The server settings are invalid: {}", problems.join(", "))));
+    }
+    Ok(loaded)
+}
+
+/// Lists everything that is wrong with `settings`, nothing if they are
+/// fine.
+pub fn validate(settings: &ServerSettings) -> Vec<String> {
+    let mut problems = Vec::new();
+    if settings.ip.parse::<IpAddr>().is_err() { problems.push(format!("ip {:?} is not an ip address", settings.ip)); }
+    if settings.port == 0 { problems.push("port must not be 0".to_owned()); }
+    if settings.image_width == 0 || settings.image_height == 0 {
+        problems.push(format!("image_width and image_height must not be 0, they are {}x{}", settings.image_width, settings.image_height));
+    }
+    if !(0.0..=100.0).contains(&settings.webp_quality) { problems.push(format!("webp_quality must be between 0 and 100, it is {}", settings.webp_quality)); }
+    for (kind, profile) in &settings.image_profiles {
+        if profile.width == 0 || profile.height == 0 { problems.push(format!("the image profile of {} must not be 0 in size, it is {}x{}", kind.as_str(), profile.width, profile.height)); }
+        if !(0.0..=100.0).contains(&profile.quality) { problems.push(format!("the quality of {} must be between 0 and 100, it is {}", kind.as_str(), profile.quality)); }
+    }
+    if settings.download_concurrency == 0 { problems.push("download_concurrency must be at least 1".to_owned()); }
+    if settings.tls {
+        for (key, path) in [("tls_cert", &settings.tls_cert), ("tls_key", &settings.tls_key)] {
+            let path = DataDir::from_settings(settings).resolve(path);
+            if !path.is_file() { problems.push(format!("{} {:?} doesn't exist, create it with `nas-game server cert init`", key, path)); }
+        }
+    }
+    problems
+}
+
+/// Sets `key` in the settings file, leaving the other keys of the file
+/// alone. Returns the settings of the defaults and the file.
+///
+/// # Errors
+/// Fails like `parse_value()`, if the new settings don't pass
+/// `validate()`, or if the file can't be read or written.
+pub fn set(path: &Path, key: &str, raw: &str) -> Result<ServerSettings, NasError> {
+    let value = parse_value(key, raw)?;
+    let mut file = read_file(path)?.unwrap_or_default();
+    file.insert(key.to_owned(), value);
+
+    let mut merged = defaults();
+    for (key, value) in &file {
+        if merged.contains_key(key) { merged.insert(key.clone(), value.clone()); }
+    }
+    let settings = into_settings(merged)?;
+    let problems = validate(&settings);
+    if !problems.is_empty() {
+        return Err(NasError::new(ErrorKind::BadRequest, problems.join(", ")));
+    }
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).context(ErrorKind::FailedToCreateFolder, format!("Failed to create {:?}", dir))?;
+    }
+    let json = serde_json::to_string_pretty(&file).context(ErrorKind::FailedToSerialize, "Failed to serialize the server settings")?;
+    write_atomic(path, json.as_bytes())?;
+    Ok(settings)
+}
+
+/// Every key of the settings with its value as json and its source,
+/// for `nas-game server config show`.
+pub fn describe(loaded: &LoadedSettings) -> Vec<(String, String, Source)> {
+    let values = match serde_json::to_value(&loaded.settings) {
+        Ok(Value::Object(map)) => map,
+        _ => unreachable!("ServerSettings serializes to an object"),
+    };
+    values.into_iter()
+        .map(|(key, value)| {
+            let source = loaded.sources.get(&key).cloned().unwrap_or(Source::Default);
+            (key, value.to_string(), source)
+        })
+        .collect()
+}
+
+/// The keys whose value differs, with the old and the new value as
+/// json.
+pub fn diff(old: &ServerSettings, new: &ServerSettings) -> Vec<(String, String, String)> {
+    let (Ok(Value::Object(old)), Ok(Value::Object(mut new))) = (serde_json::to_value(old), serde_json::to_value(new)) else {
+        unreachable!("ServerSettings serializes to an object")
+    };
+    old.into_iter()
+        .filter_map(|(key, old)| {
+            let new = new.remove(&key).unwrap_or(Value::Null);
+            (old != new).then(|| (key, old.to_string(), new.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("pool");
+        let layers = |env: &[(&str, &str)], cli: &[(&str, &str)]| Layers {
+            config: None,
+            env: env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            cli: cli.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        let data_dir_env = ("NAS_GAME_DATA_DIR", data_dir.to_str().unwrap());
+
+        // the file is found in the data directory of the environment
+        let path = layers(&[data_dir_env], &[]).settings_path();
+        assert_eq!(path, data_dir.join(data_dir::SETTINGS_FILE));
+        set(&path, "port", "8080").unwrap();
+        set(&path, "name", "attic").unwrap();
+        assert!(set(&path, "webp_quality", "120").is_err());
+        assert!(set(&path, "no_such_key", "1").is_err());
+
+        let loaded = load(&layers(&[data_dir_env, ("NAS_GAME_PORT", "9090"), ("NAS_GAME_LIBRARY_ROOTS", "/mnt/pool/games:/mnt/pool/gog")], &[("port", "9191")])).unwrap();
+        assert_eq!(loaded.settings.port, 9191);
+        assert_eq!(loaded.settings.name, "attic");
+        assert_eq!(loaded.settings.library_roots, [PathBuf::from("/mnt/pool/games"), PathBuf::from("/mnt/pool/gog")]);
+        assert_eq!(loaded.settings.image_size(), (308, 461));
+        assert_eq!(loaded.sources["port"], Source::Cli);
+        assert_eq!(loaded.sources["name"], Source::File);
+        assert_eq!(loaded.sources["library_roots"], Source::Env("NAS_GAME_LIBRARY_ROOTS".to_owned()));
+        assert_eq!(loaded.sources["webp_quality"], Source::Default);
+
+        assert!(load(&layers(&[data_dir_env, ("NAS_GAME_DOWNLOAD_CONCURRENCY", "0")], &[])).is_err());
+        assert!(load(&layers(&[data_dir_env], &[("log_level", "loud")])).is_err());
+    }
+}
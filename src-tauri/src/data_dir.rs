@@ -0,0 +1,164 @@
+//! This crate is for the files of the server. Every path the server
+//! reads or writes comes from a `DataDir`, nothing is relative to the
+//! working directory of the process, so that several servers can run
+//! side by side and tests can use a temporary directory.
+//!
+//! The data directory is `data_dir` of the settings, which defaults
+//! to `$XDG_DATA_HOME/nas-game/server` and can be moved with
+//! `--data-dir` or `NAS_GAME_DATA_DIR`. Caches that can be rebuilt go
+//! to `cache_dir`, which defaults to `$XDG_CACHE_HOME/nas-game/server`.
+//! The defaults are only resolved here, the settings leave them empty
+//! so that a settings file doesn't pin the home of whoever wrote it.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{info, logging::LoggingLevel, logging::logging_function};
+use crate::types::ServerSettings;
+use crate::server::expand_tilde;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const SETTINGS_FILE: &str = "server_settings.json";
+pub const GAME_LIB_FILE: &str = "game_library.json";
+#[cfg(feature = "sqlite")]
+pub const GAME_DB_FILE: &str = "game_library.db";
+pub const DEVICES_FILE: &str = "devices.json";
+pub const USERS_FILE: &str = "users.json";
+pub const JOBS_FILE: &str = "jobs.json";
+pub const LOGS_DIR: &str = "logs";
+pub const LOG_FILE: &str = "server.log";
+
+/// A XDG base directory, e.g. `xdg_home("XDG_DATA_HOME", ".local/share")`.
+///
+/// Like the spec says relative values are ignored.
+pub fn xdg_home(var: &str, fallback: &str) -> PathBuf {
+    env::var_os(var).map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| expand_tilde(&format!("~/{}", fallback)))
+}
+
+/// Where the data directory is unless the settings say otherwise.
+pub fn default_root() -> PathBuf {
+    xdg_home("XDG_DATA_HOME", ".local/share").join("nas-game").join("server")
+}
+
+/// Where the caches are unless the settings say otherwise.
+pub fn default_cache() -> PathBuf {
+    xdg_home("XDG_CACHE_HOME", ".cache").join("nas-game").join("server")
+}
+
+/// The files and directories of a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataDir {
+    root: PathBuf,
+    settings: PathBuf,
+    cache: PathBuf,
+    images: PathBuf,
+    optimized_images: PathBuf,
+    logs: PathBuf,
+}
+
+impl DataDir {
+    /// The directories of the settings, paths that are relative are
+    /// relative to `data_dir`. Empty directories are the defaults.
+    pub fn from_settings(settings: &ServerSettings) -> Self {
+        let root = match settings.data_dir.as_os_str().is_empty() {
+            true => default_root(),
+            false => expand_tilde(&settings.data_dir.to_string_lossy()),
+        };
+        let resolve = |path: &Path| root.join(expand_tilde(&path.to_string_lossy()));
+        Self {
+            settings: root.join(SETTINGS_FILE),
+            cache: match settings.cache_dir.as_os_str().is_empty() {
+                true => default_cache(),
+                false => resolve(&settings.cache_dir),
+            },
+            logs: root.join(LOGS_DIR),
+            images: resolve(&settings.images_dir),
+            optimized_images: resolve(&settings.optimized_images_dir),
+            root,
+        }
+    }
+
+    /// Uses another settings file, e.g. the one of `--config`.
+    pub fn with_settings_file(mut self, path: PathBuf) -> Self {
+        self.settings = path;
+        self
+    }
+
+    pub fn root(&self) -> &Path { &self.root }
+    pub fn settings(&self) -> &Path { &self.settings }
+    pub fn cache(&self) -> &Path { &self.cache }
+    /// Where downloaded artwork is saved.
+    pub fn images(&self) -> &Path { &self.images }
+    pub fn optimized_images(&self) -> &Path { &self.optimized_images }
+    /// Where the server appends its messages to.
+    pub fn log_file(&self) -> PathBuf { self.logs.join(LOG_FILE) }
+
+    /// A path of the settings, relative to the data directory.
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        self.root.join(expand_tilde(&path.to_string_lossy()))
+    }
+
+    pub fn library(&self) -> PathBuf { self.root.join(GAME_LIB_FILE) }
+    #[cfg(feature = "sqlite")]
+    pub fn library_db(&self) -> PathBuf { self.root.join(GAME_DB_FILE) }
+    pub fn devices(&self) -> PathBuf { self.root.join(DEVICES_FILE) }
+    pub fn users(&self) -> PathBuf { self.root.join(USERS_FILE) }
+    /// The queue of the `jobs` crate.
+    pub fn jobs(&self) -> PathBuf { self.root.join(JOBS_FILE) }
+    /// Where `nas-game server cert init` puts the certificates.
+    pub fn tls(&self) -> PathBuf { self.root.join(crate::certs::DEFAULT_TLS_DIR) }
+    /// The hashes of the game files, see the `manifest` crate.
+    pub fn manifests(&self) -> PathBuf { self.cache.join("manifests") }
+    /// The chunk indices of the games, see the `chunks` crate.
+    pub fn chunks(&self) -> PathBuf { self.cache.join("chunks") }
+
+    /// Creates the data, cache and log directories, should they be
+    /// missing.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::FailedToCreateFolder`.
+    pub fn create(&self) -> Result<(), NasError> {
+        for dir in [&self.root, &self.cache, &self.logs] {
+            if dir.is_dir() { continue; }
+            fs::create_dir_all(dir).context(ErrorKind::FailedToCreateFolder, format!("Failed to create {:?}", dir))?;
+            info!("Folder {:?} was created since it was missing", dir);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_relative_paths_against_the_data_dir() {
+        let settings = ServerSettings {
+            data_dir: PathBuf::from("/mnt/pool/nas-game"),
+            cache_dir: PathBuf::from("/var/cache/nas-game"),
+            images_dir: PathBuf::from("artwork"),
+            ..ServerSettings::default()
+        };
+        let dirs = DataDir::from_settings(&settings);
+        assert_eq!(dirs.settings(), Path::new("/mnt/pool/nas-game/server_settings.json"));
+        assert_eq!(dirs.library(), Path::new("/mnt/pool/nas-game/game_library.json"));
+        assert_eq!(dirs.images(), Path::new("/mnt/pool/nas-game/artwork"));
+        assert_eq!(dirs.optimized_images(), Path::new("/mnt/pool/nas-game/images/optimized"));
+        assert_eq!(dirs.manifests(), Path::new("/var/cache/nas-game/manifests"));
+        assert_eq!(dirs.resolve(Path::new("tls/server.crt")), Path::new("/mnt/pool/nas-game/tls/server.crt"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = DataDir::from_settings(&ServerSettings { data_dir: dir.path().join("server"), cache_dir: PathBuf::from("cache"), ..ServerSettings::default() });
+        dirs.create().unwrap();
+        assert!(dirs.root().is_dir() && dirs.cache().is_dir() && dirs.log_file().parent().unwrap().is_dir());
+        assert_eq!(dirs.cache(), dir.path().join("server").join("cache"));
+        assert_eq!(dirs.log_file(), dir.path().join("server").join("logs").join("server.log"));
+
+        // the defaults are left to the data directory
+        let defaults = DataDir::from_settings(&ServerSettings::default());
+        assert_eq!(ServerSettings::default().data_dir, PathBuf::new());
+        assert_eq!((defaults.root(), defaults.cache()), (default_root().as_path(), default_cache().as_path()));
+    }
+}
@@ -0,0 +1,356 @@
+//! This crate is for the devices that are allowed to change things on
+//! the server. A client registers its public key with
+//! `POST /devices/register`, after which an admin approves it with
+//! `nas-game server devices approve <id>`. From then on every request
+//! that changes something has to be signed with that key, which
+//! `require_signature()` enforces for the whole app.
+//!
+//! The devices are kept in `devices.json` next to the game library.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, logging::LoggingLevel, logging::logging_function};
+use crate::auth;
+use crate::files::write_atomic;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use actix_web::{web, body::MessageBody, dev::{Payload, ServiceRequest, ServiceResponse}, http::Method, middleware::Next};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+/// Requests with a larger body are rejected before they are checked.
+/// Every signed route takes json, which actix limits to the same.
+const MAX_SIGNED_BODY: usize = 2 * 1024 * 1024;
+
+/// `POST /devices/register` is open to everyone, so the number of
+/// devices that wait for approval is limited.
+const MAX_PENDING_DEVICES: usize = 16;
+/// How long the name of a device may be, in characters.
+const MAX_DEVICE_NAME: usize = 64;
+
+/// Routes that are open to everyone even though their method changes
+/// something.
+const OPEN_ROUTES: &[(Method, &str)] = &[
+    (Method::POST, "/devices/register"),
+    // the password or the token are what authenticate these
+    (Method::POST, "/login"),
+    (Method::POST, "/logout"),
+];
+/// Routes that change something even though they are `GET` requests.
+const MUTATING_GETS: &[&str] = &["/add_dummy"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceStatus {
+    /// Registered but not yet approved by an admin.
+    Pending,
+    Approved,
+    Revoked,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    /// See `auth::device_id()`.
+    pub id: String,
+    pub name: String,
+    /// The base64 encoded Ed25519 public key.
+    pub public_key: String,
+    pub status: DeviceStatus,
+    pub registered_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approved_at: Option<DateTime<Utc>>,
+}
+
+/// What a client sends to `POST /devices/register`.
+#[derive(Deserialize, Debug)]
+pub struct RegisterRequest {
+    pub name: String,
+    pub public_key: String,
+}
+
+fn decode_public_key(public_key: &str) -> Result<VerifyingKey, NasError> {
+    let bytes = BASE64.decode(public_key.trim()).context(ErrorKind::BadRequest, "The public key is not valid base64")?;
+    let bytes: [u8; 32] = bytes.try_into().map_err(|_| NasError::new(ErrorKind::BadRequest, "The public key has to be 32 bytes long"))?;
+    VerifyingKey::from_bytes(&bytes).context(ErrorKind::BadRequest, "The public key is not a valid Ed25519 key")
+}
+
+/// Every known device, mirrored into a json file.
+pub struct DeviceRegistry {
+    path: PathBuf,
+    devices: Mutex<Vec<Device>>,
+    /// Signatures of the last `MAX_CLOCK_SKEW_SECS`, so that a request
+    /// can't be replayed, with their timestamp.
+    seen: Mutex<HashMap<String, i64>>,
+}
+
+impl DeviceRegistry {
+    /// Opens the registry at `path`, a missing file is an empty
+    /// registry.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::FailedToParse` if the file is broken,
+    /// since silently forgetting every device would lock every client
+    /// out.
+    pub fn open(path: PathBuf) -> Result<Self, NasError> {
+        let devices = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s).context(ErrorKind::FailedToParse, format!("Failed to parse {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(NasError::new(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path)).with_source(e)),
+        };
+        Ok(Self { path, devices: Mutex::new(devices), seen: Mutex::new(HashMap::new()) })
+    }
+
+    fn write(&self, devices: &[Device]) -> Result<(), NasError> {
+        let json = serde_json::to_string_pretty(devices).context(ErrorKind::FailedToSerialize, "Failed to serialize the devices")?;
+        write_atomic(&self.path, json.as_bytes())
+    }
+
+    pub fn devices(&self) -> Result<Vec<Device>, NasError> {
+        Ok(self.devices.lock().map_err(|_| ErrorKind::FailedToAquireLock)?.clone())
+    }
+
+    /// Adds a device that still has to be approved. Registering the
+    /// same key again only updates the name.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::BadRequest` if the key is not a valid
+    /// Ed25519 public key or the name is too long, and with
+    /// `ErrorKind::Forbidden` once `MAX_PENDING_DEVICES` devices wait
+    /// for approval.
+    pub fn register(&self, name: &str, public_key: &str) -> Result<Device, NasError> {
+        let id = auth::device_id(&decode_public_key(public_key)?);
+        if name.chars().count() > MAX_DEVICE_NAME {
+            return Err(NasError::new(ErrorKind::BadRequest, format!("The name of a device can't be longer than {} characters", MAX_DEVICE_NAME)));
+        }
+        let mut devices = self.devices.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let pending = devices.iter().filter(|d| d.status == DeviceStatus::Pending).count();
+        let device = match devices.iter_mut().find(|d| d.id == id) {
+            Some(device) => {
+                device.name = name.to_owned();
+                device.clone()
+            },
+            None if pending >= MAX_PENDING_DEVICES => {
+                warn!("Refused to register the device {:?}, {} devices wait for approval already", name, MAX_PENDING_DEVICES);
+                return Err(NasError::new(ErrorKind::Forbidden, "Too many devices wait for approval, approve or revoke them first"));
+            },
+            None => {
+                let device = Device {
+                    id,
+                    name: name.to_owned(),
+                    public_key: public_key.trim().to_owned(),
+                    status: DeviceStatus::Pending,
+                    registered_at: Utc::now(),
+                    approved_at: None,
+                };
+                info!("The device {:?} ({}) has been registered and waits for approval", device.name, device.id);
+                devices.push(device.clone());
+                device
+            },
+        };
+        self.write(&devices)?;
+        Ok(device)
+    }
+
+    /// Approves or revokes a device.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::NotFound` for unknown devices.
+    pub fn set_status(&self, id: &str, status: DeviceStatus) -> Result<Device, NasError> {
+        let mut devices = self.devices.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let device = devices.iter_mut().find(|d| d.id == id)
+            .ok_or_else(|| NasError::new(ErrorKind::NotFound, format!("There is no device with the id {}", id)))?;
+        device.status = status;
+        if status == DeviceStatus::Approved { device.approved_at = Some(Utc::now()); }
+        let device = device.clone();
+        self.write(&devices)?;
+        info!("The device {:?} ({}) is now {:?}", device.name, device.id, device.status);
+        Ok(device)
+    }
+
+    /// Checks everything about a signed request that doesn't need its
+    /// body, so that requests which will be rejected anyway aren't
+    /// read. Returns the approved device.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::Unauthorized` for unknown devices and
+    /// stale timestamps and with `ErrorKind::Forbidden` for devices
+    /// that aren't approved.
+    pub fn check(&self, device_id: &str, timestamp: i64, now: i64) -> Result<Device, NasError> {
+        let device = self.devices.lock().map_err(|_| ErrorKind::FailedToAquireLock)?
+            .iter().find(|d| d.id == device_id).cloned()
+            .ok_or_else(|| NasError::new(ErrorKind::Unauthorized, "The device is not registered"))?;
+        if (now - timestamp).abs() > auth::MAX_CLOCK_SKEW_SECS {
+            return Err(NasError::new(ErrorKind::Unauthorized, "The signature has expired, check the clock of the client"));
+        }
+        match device.status {
+            DeviceStatus::Approved => Ok(device),
+            DeviceStatus::Pending => Err(NasError::new(ErrorKind::Forbidden, format!("The device {} has not been approved yet", device.id))),
+            DeviceStatus::Revoked => Err(NasError::new(ErrorKind::Forbidden, format!("The device {} has been revoked", device.id))),
+        }
+    }
+
+    /// Checks the signature of a request and returns the device that
+    /// signed it.
+    ///
+    /// # Errors
+    /// Fails like `check()` and with `ErrorKind::Unauthorized` for
+    /// malformed, reused or wrong signatures.
+    pub fn verify(&self, device_id: &str, timestamp: i64, signature: &str, message: &[u8], now: i64) -> Result<Device, NasError> {
+        let unauthorized = |reason: &str| NasError::new(ErrorKind::Unauthorized, reason.to_owned());
+        let device = self.check(device_id, timestamp, now)?;
+        let signature = BASE64.decode(signature).ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or_else(|| unauthorized("The signature is malformed"))?;
+        decode_public_key(&device.public_key)?.verify_strict(message, &signature)
+            .map_err(|_| unauthorized("The signature doesn't match the request"))?;
+
+        let mut seen = self.seen.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        seen.retain(|_, at| (now - *at).abs() <= auth::MAX_CLOCK_SKEW_SECS);
+        if seen.insert(BASE64.encode(signature.to_bytes()), timestamp).is_some() {
+            return Err(unauthorized("The request has already been made once"));
+        }
+        Ok(device)
+    }
+}
+
+fn needs_signature(req: &ServiceRequest) -> bool {
+    let method = req.method();
+    if OPEN_ROUTES.iter().any(|(m, path)| m == method && req.path() == *path) { return false; }
+    match *method {
+        Method::GET | Method::HEAD => MUTATING_GETS.contains(&req.path()),
+        Method::OPTIONS => false,
+        _ => true,
+    }
+}
+
+fn header<'a>(req: &'a ServiceRequest, name: &str) -> Result<&'a str, NasError> {
+    req.headers().get(name)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| NasError::new(ErrorKind::Unauthorized, format!("The request is not signed, the {} header is missing", name)))
+}
+
+/// Rejects requests that change something unless they are signed by
+/// an approved device, see the `auth` crate for the format. Reading
+/// requests stay open, so health checks and downloads keep working.
+///
+/// The body is only read once the device and the timestamp have been
+/// checked, to check the signature, and handed on to the route
+/// afterwards.
+pub async fn require_signature(mut req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if !needs_signature(&req) {
+        return next.call(req).await;
+    }
+    let registry = req.app_data::<web::Data<DeviceRegistry>>().cloned()
+        .ok_or_else(|| NasError::new(ErrorKind::Ignore, "There is no device registry"))?;
+    let device_id = header(&req, auth::DEVICE_HEADER)?.to_owned();
+    let timestamp: i64 = header(&req, auth::TIMESTAMP_HEADER)?.parse()
+        .map_err(|_| NasError::new(ErrorKind::Unauthorized, "The timestamp is not a number"))?;
+    let signature = header(&req, auth::SIGNATURE_HEADER)?.to_owned();
+    let now = Utc::now().timestamp();
+    if let Err(e) = registry.check(&device_id, timestamp, now) {
+        warn!("Rejected {} {} from {:?}: {}", req.method(), req.path(), req.peer_addr(), e);
+        return Err(e.into());
+    }
+
+    let mut body = web::BytesMut::new();
+    let mut payload = req.parts_mut().1.take();
+    while let Some(chunk) = payload.next().await {
+        body.extend_from_slice(&chunk?);
+        if body.len() > MAX_SIGNED_BODY {
+            return Err(NasError::new(ErrorKind::BadRequest, "The body of the request is too large").into());
+        }
+    }
+    let body = body.freeze();
+    let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/").to_owned();
+    let message = auth::message(req.method().as_str(), &path, timestamp, &body);
+    match registry.verify(&device_id, timestamp, &signature, &message, now) {
+        Ok(device) => { trace!("{} {} signed by {:?}", req.method(), path, device.name); },
+        Err(e) => {
+            warn!("Rejected {} {} from {:?}: {}", req.method(), path, req.peer_addr(), e);
+            return Err(e.into());
+        },
+    }
+    req.set_payload(Payload::from(body));
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App, HttpResponse, middleware::from_fn};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn signed(key: &SigningKey, method: Method, path: &str, body: &str, timestamp: i64) -> test::TestRequest {
+        let signature = key.sign(&auth::message(method.as_str(), path, timestamp, body.as_bytes()));
+        test::TestRequest::default().method(method).uri(path)
+            .insert_header((auth::DEVICE_HEADER, auth::device_id(&key.verifying_key())))
+            .insert_header((auth::TIMESTAMP_HEADER, timestamp.to_string()))
+            .insert_header((auth::SIGNATURE_HEADER, BASE64.encode(signature.to_bytes())))
+            .set_payload(body.to_owned())
+    }
+
+    #[actix_web::test]
+    async fn only_approved_devices_may_change_things() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = web::Data::new(DeviceRegistry::open(dir.path().join("devices.json")).unwrap());
+        let app = test::init_service(App::new()
+            .app_data(registry.clone())
+            .wrap(from_fn(require_signature))
+            .route("/", web::get().to(HttpResponse::Ok))
+            .route("/games", web::post().to(|body: String| async move { HttpResponse::Ok().body(body) }))).await;
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let device = registry.register("desktop", &BASE64.encode(key.verifying_key().as_bytes())).unwrap();
+        let now = Utc::now().timestamp();
+
+        let status = |req: test::TestRequest| {
+            let app = &app;
+            // rejected requests are errors, which the server turns into responses
+            async move {
+                match test::try_call_service(app, req.to_request()).await {
+                    Ok(res) => res.status().as_u16(),
+                    Err(e) => e.error_response().status().as_u16(),
+                }
+            }
+        };
+        assert_eq!(status(test::TestRequest::get().uri("/")).await, 200);
+        assert_eq!(status(test::TestRequest::post().uri("/games")).await, 401);
+        assert_eq!(status(signed(&key, Method::POST, "/games", "[]", now)).await, 403);
+
+        registry.set_status(&device.id, DeviceStatus::Approved).unwrap();
+        let res = test::call_service(&app, signed(&key, Method::POST, "/games", "[]", now + 1).to_request()).await;
+        assert_eq!(test::read_body(res).await, "[]");
+        // replayed, stale, tampered and foreign signatures
+        assert_eq!(status(signed(&key, Method::POST, "/games", "[]", now + 1)).await, 401);
+        assert_eq!(status(signed(&key, Method::POST, "/games", "[]", now - 3600)).await, 401);
+        let tampered = signed(&key, Method::POST, "/games", "[]", now + 2).set_payload("[{}]");
+        assert_eq!(status(tampered).await, 401);
+        assert_eq!(status(signed(&SigningKey::from_bytes(&[8; 32]), Method::POST, "/games", "[]", now)).await, 401);
+
+        // oversized bodies are refused
+        let large = "x".repeat(MAX_SIGNED_BODY + 1);
+        assert_eq!(status(signed(&key, Method::POST, "/games", &large, now + 3)).await, 400);
+
+        // the approval survives a restart
+        let reopened = DeviceRegistry::open(dir.path().join("devices.json")).unwrap();
+        assert_eq!(reopened.devices().unwrap()[0].status, DeviceStatus::Approved);
+    }
+
+    #[actix_web::test]
+    async fn limits_pending_devices() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = DeviceRegistry::open(dir.path().join("devices.json")).unwrap();
+        let public_key = |seed: u8| BASE64.encode(SigningKey::from_bytes(&[seed; 32]).verifying_key().as_bytes());
+        for seed in 0..MAX_PENDING_DEVICES as u8 {
+            registry.register("laptop", &public_key(seed)).unwrap();
+        }
+        assert_eq!(registry.register("laptop", &public_key(100)).unwrap_err().kind(), ErrorKind::Forbidden);
+        // known devices can still change their name
+        assert_eq!(registry.register("desktop", &public_key(0)).unwrap().name, "desktop");
+        assert_eq!(registry.register(&"x".repeat(MAX_DEVICE_NAME + 1), &public_key(0)).unwrap_err().kind(), ErrorKind::BadRequest);
+    }
+}
@@ -0,0 +1,218 @@
+//! This crate is for finding the servers on the local network, so
+//! that nobody has to type in an address. A client sends a probe over
+//! UDP broadcast, and to the loopback address for a server on the
+//! same machine, to `DISCOVERY_PORT`. Every server that hears it
+//! answers with an `Announcement` of its name, version and port, the
+//! address is the one the answer came from.
+//!
+//! The server answers from `nas-game server --start` unless
+//! `discovery` is off in its settings, the client searches with
+//! `nas-game client discover` and on its first start.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, error, logging::LoggingLevel, logging::logging_function};
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+pub const DISCOVERY_PORT: u16 = 53318;
+/// What a client sends, anything else is ignored by the server.
+pub const PROBE: &[u8] = b"nas-game discover 1";
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1500);
+/// Answers are a few hundred bytes, larger ones are cut off and fail
+/// to parse.
+const MAX_ANSWER_SIZE: usize = 4096;
+
+/// What a server answers to a probe.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    pub name: String,
+    pub version: String,
+    /// The port of the http api.
+    pub port: u16,
+    /// Whether the api speaks https.
+    #[serde(default)]
+    pub tls: bool,
+}
+
+/// A server that answered, together with where it can be reached.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredServer {
+    #[serde(flatten)]
+    pub announcement: Announcement,
+    pub address: IpAddr,
+    /// The `server_url` of the client settings that reaches the server.
+    pub url: String,
+}
+
+impl DiscoveredServer {
+    pub fn new(announcement: Announcement, address: IpAddr) -> Self {
+        let scheme = if announcement.tls { "https" } else { "http" };
+        let url = match address {
+            IpAddr::V4(ip) => format!("{}://{}:{}", scheme, ip, announcement.port),
+            IpAddr::V6(ip) => format!("{}://[{}]:{}", scheme, ip, announcement.port),
+        };
+        Self { announcement, address, url }
+    }
+}
+
+/// Where `discover()` sends its probe by default.
+pub fn default_targets() -> Vec<SocketAddr> {
+    vec![(Ipv4Addr::BROADCAST, DISCOVERY_PORT).into(), (Ipv4Addr::LOCALHOST, DISCOVERY_PORT).into()]
+}
+
+/// Answers every probe that arrives on `socket`, only returns once the
+/// socket fails.
+pub fn respond(socket: &UdpSocket, announcement: &Announcement) -> Result<(), NasError> {
+    let answer = serde_json::to_vec(announcement).context(ErrorKind::FailedToSerialize, "Failed to serialize the announcement")?;
+    let mut buffer = [0; 64];
+    loop {
+        let (len, from) = socket.recv_from(&mut buffer).context(ErrorKind::FailedToFetch, "Failed to receive a probe")?;
+        if &buffer[..len] != PROBE {
+            trace!("Ignoring {} bytes from {} on the discovery port", len, from);
+            continue;
+        }
+        trace!("Answering the probe of {}", from);
+        if let Err(e) = socket.send_to(&answer, from) {
+            warn!("Failed to answer the probe of {} with {}", from, e);
+        }
+    }
+}
+
+/// Where the server answers the probes for its `ip`.
+///
+/// A socket that is bound to a single address of the network doesn't
+/// hear broadcasts, so every other address is answered on all of them.
+/// The loopback address stays on its own, a server that only listens
+/// on 127.0.0.1 mustn't show up on the network.
+pub fn responder_address(ip: IpAddr, port: u16) -> SocketAddr {
+    match ip.is_loopback() {
+        true => (ip, port).into(),
+        false => (Ipv4Addr::UNSPECIFIED, port).into(),
+    }
+}
+
+/// Binds `address` and answers probes in a background thread.
+///
+/// # Errors
+/// Fails if the port can't be bound, e.g. because another server on
+/// the same machine already answers.
+pub fn spawn_responder(address: SocketAddr, announcement: Announcement) -> Result<JoinHandle<()>, NasError> {
+    let socket = UdpSocket::bind(address).context(ErrorKind::Ignore, format!("Failed to bind the discovery port {}", address))?;
+    info!("Announcing {:?} on {}", announcement.name, address);
+    thread::Builder::new()
+        .name("discovery".to_owned())
+        .spawn(move || {
+            if let Err(e) = respond(&socket, &announcement) {
+                error!("Stopped answering discovery probes: {}", e);
+            }
+        })
+        .context(ErrorKind::Ignore, "Failed to start the discovery thread")
+}
+
+/// Sends a probe to every target and collects the answers until
+/// `timeout` has passed.
+///
+/// A server that answers under several addresses is listed once,
+/// preferably with an address other machines can reach too.
+pub fn discover(targets: &[SocketAddr], timeout: Duration) -> Result<Vec<DiscoveredServer>, NasError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).context(ErrorKind::Ignore, "Failed to bind a socket for the discovery")?;
+    socket.set_broadcast(true).context(ErrorKind::Ignore, "Failed to allow broadcasts")?;
+    for target in targets {
+        // e.g. there is no route for broadcasts without a network
+        if let Err(e) = socket.send_to(PROBE, target) { trace!("Failed to probe {} with {}", target, e); }
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut servers: Vec<DiscoveredServer> = Vec::new();
+    let mut buffer = [0; MAX_ANSWER_SIZE];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+        socket.set_read_timeout(Some(left)).context(ErrorKind::Ignore, "Failed to set the timeout of the discovery")?;
+        let (len, from) = match socket.recv_from(&mut buffer) {
+            Ok(answer) => answer,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break,
+            // a target without a server, reported by some systems
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionRefused) => continue,
+            Err(e) => return Err(NasError::new(ErrorKind::FailedToFetch, "Failed to receive the answers of the discovery").with_source(e)),
+        };
+        let announcement: Announcement = match serde_json::from_slice(&buffer[..len]) {
+            Ok(announcement) => announcement,
+            Err(e) => {
+                trace!("Ignoring the answer of {} with {}", from, e);
+                continue;
+            },
+        };
+        let server = DiscoveredServer::new(announcement, from.ip());
+        let same = servers.iter_mut().find(|s| s.announcement.name == server.announcement.name && s.announcement.port == server.announcement.port);
+        match same {
+            Some(known) if known.address.is_loopback() && !server.address.is_loopback() => *known = server,
+            Some(_) => (),
+            None => servers.push(server),
+        }
+    }
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_servers_on_loopback() {
+        let announce = |name: &str, port| Announcement { name: name.to_owned(), version: "0.1.0".to_owned(), port, tls: port == 443 };
+        let mut targets = Vec::new();
+        for (name, port) in [("living room", 53317), ("attic", 443)] {
+            let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            targets.push(socket.local_addr().unwrap());
+            let announcement = announce(name, port);
+            thread::spawn(move || respond(&socket, &announcement));
+        }
+        // something that never answers doesn't hold up the others
+        let silent = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        targets.push(silent.local_addr().unwrap());
+
+        let mut servers = discover(&targets, Duration::from_millis(500)).unwrap();
+        servers.sort_by(|a, b| a.url.cmp(&b.url));
+        assert_eq!(servers.iter().map(|s| s.url.as_str()).collect::<Vec<_>>(), ["http://127.0.0.1:53317", "https://127.0.0.1:443"]);
+        assert_eq!(servers[0].announcement, announce("living room", 53317));
+    }
+
+    /// The responder of `answers_broadcasts_from_another_process`,
+    /// which runs it in a process of its own.
+    #[test]
+    #[ignore = "started by answers_broadcasts_from_another_process"]
+    fn responder_process() {
+        let (Ok(ip), Ok(port)) = (std::env::var("NAS_GAME_TEST_IP"), std::env::var("NAS_GAME_TEST_PORT")) else { return };
+        let address = responder_address(ip.parse().unwrap(), port.parse().unwrap());
+        let announcement = Announcement { name: "cellar".to_owned(), version: "0.1.0".to_owned(), port: 53317, tls: false };
+        respond(&UdpSocket::bind(address).unwrap(), &announcement).unwrap();
+    }
+
+    #[test]
+    fn answers_broadcasts_from_another_process() {
+        // the address of this machine on the network, no packet is sent
+        let probe = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let Some(ip) = probe.connect((Ipv4Addr::new(192, 0, 2, 1), 9)).and_then(|_| probe.local_addr()).ok().map(|a| a.ip()) else {
+            eprintln!("Skipping the discovery over the network, this machine has none");
+            return;
+        };
+        let port = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap().local_addr().unwrap().port();
+        let mut responder = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "discovery::tests::responder_process", "--ignored", "--test-threads=1"])
+            .env("NAS_GAME_TEST_IP", ip.to_string())
+            .env("NAS_GAME_TEST_PORT", port.to_string())
+            .stdout(std::process::Stdio::null())
+            .spawn().unwrap();
+
+        // the responder takes a moment to start
+        let target: SocketAddr = (Ipv4Addr::BROADCAST, port).into();
+        let servers = (0..10).map(|_| discover(&[target], Duration::from_millis(300)).unwrap())
+            .find(|servers| !servers.is_empty())
+            .unwrap_or_default();
+        responder.kill().unwrap();
+        responder.wait().unwrap();
+        assert_eq!(servers.iter().map(|s| s.announcement.name.as_str()).collect::<Vec<_>>(), ["cellar"]);
+        assert!(!servers[0].address.is_loopback());
+    }
+}
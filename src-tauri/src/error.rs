@@ -1,24 +1,223 @@
 //! This crate is for defining and implementing convenience
 //! functions for errors used throughout the program. The
-//! main error type is `NasError`. 
+//! main error type is `NasError`.
+use std::fmt;
+use actix_web::{HttpResponse, ResponseError, http::StatusCode};
+use serde_json::json;
+
+/// What went wrong, without any of the details.
+///
+/// The kind decides which HTTP status and which machine-readable
+/// code an error is reported with.
 #[allow(dead_code)]
-#[derive(Debug)]
-pub enum NasError {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
     FailedToReadFile,
     FailedToParse,
     FailedToSerialize,
     FailedToWrite,
     FailedToCreateFolder,
     FailedToEncode,
+    FailedToFetch,
     InvalidPath,
+    FailedToAquireLock,
+    FailedToMigrate,
+    /// A game or one of its hooks could not be started.
+    FailedToLaunch,
+    /// The requested resource does not exist.
+    NotFound,
+    /// The request itself is malformed.
+    BadRequest,
+    /// The request isn't signed by a known device.
+    Unauthorized,
+    /// The device is known but not allowed to do this (yet).
+    Forbidden,
+    /// The disk (or quota) the server writes to is full.
+    DiskFull,
+    PermissionDenied,
+    /// A background task of the server failed, e.g. it panicked.
+    Internal,
     Ignore,
-    
+}
+
+impl ErrorKind {
+    /// The machine-readable code that is sent to clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::FailedToReadFile => "failed_to_read_file",
+            ErrorKind::FailedToParse => "failed_to_parse",
+            ErrorKind::FailedToSerialize => "failed_to_serialize",
+            ErrorKind::FailedToWrite => "failed_to_write",
+            ErrorKind::FailedToCreateFolder => "failed_to_create_folder",
+            ErrorKind::FailedToEncode => "failed_to_encode",
+            ErrorKind::FailedToFetch => "failed_to_fetch",
+            ErrorKind::InvalidPath => "invalid_path",
+            ErrorKind::FailedToAquireLock => "failed_to_aquire_lock",
+            ErrorKind::FailedToMigrate => "failed_to_migrate",
+            ErrorKind::FailedToLaunch => "failed_to_launch",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::BadRequest => "bad_request",
+            ErrorKind::Unauthorized => "unauthorized",
+            ErrorKind::Forbidden => "forbidden",
+            ErrorKind::DiskFull => "disk_full",
+            ErrorKind::PermissionDenied => "permission_denied",
+            ErrorKind::Internal | ErrorKind::Ignore => "internal",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::BadRequest | ErrorKind::InvalidPath => StatusCode::BAD_REQUEST,
+            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorKind::Forbidden => StatusCode::FORBIDDEN,
+            ErrorKind::DiskFull => StatusCode::INSUFFICIENT_STORAGE,
+            ErrorKind::FailedToFetch => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// The error type used throughout the program.
+///
+/// It consists of the `ErrorKind`, a human readable context which
+/// describes what was being done and optionally the error that
+/// caused it.
+#[derive(Debug)]
+pub struct NasError {
+    kind: ErrorKind,
+    context: String,
+    source: Option<Box<dyn std::error::Error + Send + Sync + 'static>>,
+}
+
+impl NasError {
+    pub fn new(kind: ErrorKind, context: impl Into<String>) -> Self {
+        Self { kind, context: context.into(), source: None }
+    }
+
+    /// Attaches the error that caused this one.
+    ///
+    /// Should the source be an `std::io::Error` caused by a full disk
+    /// then the kind is changed to `ErrorKind::DiskFull`, since that
+    /// is far more useful to the caller than e.g. `FailedToWrite`.
+    pub fn with_source(mut self, source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        let source: Box<dyn std::error::Error + Send + Sync> = Box::new(source);
+        if let Some(io) = source.downcast_ref::<std::io::Error>() {
+            if is_disk_full(io) { self.kind = ErrorKind::DiskFull; }
+        }
+        self.source = Some(source);
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind { self.kind }
+}
+
+/// Checks for `ENOSPC` and `EDQUOT`.
+fn is_disk_full(e: &std::io::Error) -> bool {
+    e.kind() == std::io::ErrorKind::StorageFull || matches!(e.raw_os_error(), Some(28) | Some(122))
+}
+
+impl fmt::Display for NasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.context.is_empty(), &self.source) {
+            (true, None) => write!(f, "{}", self.kind.code()),
+            (true, Some(source)) => write!(f, "{}", source),
+            (false, None) => write!(f, "{}", self.context),
+            (false, Some(source)) => write!(f, "{}: {}", self.context, source),
+        }
+    }
+}
+
+impl std::error::Error for NasError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
+
+/// Every route can return a `NasError`, which is turned into a json
+/// body of the following form:
+///
+/// ```json
+/// { "error": { "code": "not_found", "message": "No game with the id 42", "status": 404 } }
+/// ```
+impl ResponseError for NasError {
+    fn status_code(&self) -> StatusCode { self.kind.status() }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        HttpResponse::build(status).json(json!({
+            "error": {
+                "code": self.kind.code(),
+                "message": self.to_string(),
+                "status": status.as_u16(),
+            }
+        }))
+    }
+}
+
+impl From<ErrorKind> for NasError {
+    fn from(kind: ErrorKind) -> Self { Self::new(kind, "") }
 }
 
 impl From<std::io::Error> for NasError {
-    fn from(_value: std::io::Error) -> Self { Self::Ignore }
+    fn from(value: std::io::Error) -> Self {
+        let kind = match value.kind() {
+            std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+            std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            _ => ErrorKind::Ignore,
+        };
+        Self::from(kind).with_source(value)
+    }
+}
+
+/// A closure passed to `web::block()` panicked or the thread pool is
+/// gone, so `web::block(...).await??` is all a route needs.
+impl From<actix_web::error::BlockingError> for NasError {
+    fn from(value: actix_web::error::BlockingError) -> Self { Self::from(ErrorKind::Internal).with_source(value) }
 }
 
 impl From<ron::error::SpannedError> for NasError {
-    fn from(_value: ron::error::SpannedError) -> Self { Self::Ignore }
+    fn from(value: ron::error::SpannedError) -> Self { Self::from(ErrorKind::FailedToParse).with_source(value) }
+}
+
+/// Convenience for turning any error into a `NasError`.
+///
+/// # Examples
+/// ```ignore
+/// fs::read_to_string(path).context(ErrorKind::FailedToReadFile, format!("reading {:?}", path))?;
+/// ```
+pub trait Context<T> {
+    fn context(self, kind: ErrorKind, context: impl Into<String>) -> Result<T, NasError>;
+}
+
+impl<T, E: std::error::Error + Send + Sync + 'static> Context<T> for Result<T, E> {
+    fn context(self, kind: ErrorKind, context: impl Into<String>) -> Result<T, NasError> {
+        self.map_err(|e| NasError::new(kind, context).with_source(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+
+    #[actix_web::test]
+    async fn renders_json_envelope() {
+        let err = NasError::new(ErrorKind::NotFound, "No game with the id 42");
+        let res = err.error_response();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body, json!({ "error": { "code": "not_found", "message": "No game with the id 42", "status": 404 } }));
+    }
+
+    #[test]
+    fn full_disks_are_reported_as_such() {
+        let io = std::io::Error::from_raw_os_error(28);
+        let err: Result<(), _> = Err(io);
+        let err = err.context(ErrorKind::FailedToWrite, "writing the library").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DiskFull);
+        assert_eq!(err.status_code(), StatusCode::INSUFFICIENT_STORAGE);
+        assert!(err.to_string().starts_with("writing the library: "));
+    }
 }
@@ -0,0 +1,28 @@
+//! This crate is for writing files that mustn't be left half
+//! written, e.g. by a crash or a full disk. It is used by both the
+//! server and the client.
+use crate::error::{NasError, ErrorKind, Context};
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Writes `contents` to a temporary file next to `path`, flushes it
+/// to the disk and then renames it over `path`.
+///
+/// Since the rename is atomic the file at `path` is always either
+/// the old or the new version, never a partially written one.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), NasError> {
+    // a name of its own, writers of the same file don't share it
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(format!(".{}.tmp", uuid::Uuid::new_v4()));
+    let tmp = PathBuf::from(tmp_name);
+
+    let context = || format!("Failed to write {:?}", path);
+    let result = fs::File::create(&tmp)
+        .and_then(|mut file| { file.write_all(contents)?; file.sync_all() })
+        .and_then(|_| fs::rename(&tmp, path))
+        .context(ErrorKind::FailedToWrite, context());
+    if result.is_err() { let _ = fs::remove_file(&tmp); }
+    result
+}
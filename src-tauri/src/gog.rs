@@ -0,0 +1,426 @@
+//! This crate is for reading the GOG games on the NAS. These are
+//! either installed games, which GOG Galaxy and the offline
+//! installers mark with a `goggame-<id>.info` file, or the offline
+//! installers themselves, e.g.
+//! `setup_celeste_1.4.0.0_(64bit)_(42346).exe` together with its
+//! `setup_celeste_1.4.0.0_(64bit)_(42346)-1.bin` parts.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, error, logging::LoggingLevel, logging::logging_function};
+use crate::types::{Game, Launcher};
+use crate::scanner::{DetectedGame, Detection};
+use crate::provider::{LauncherProvider, LaunchCommand, require_install_dir};
+use crate::heroic;
+
+use std::fs;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The name that is used for `Launcher.name`.
+pub const GOG_LAUNCHER: &str = "gog";
+
+/// How deep `find_games()` descends looking for GOG games.
+const MAX_DEPTH: usize = 4;
+
+/// The languages GOG puts into the name of localized installers.
+const LANGUAGES: &[&str] = &[
+    "english", "french", "german", "spanish", "italian", "polish", "russian", "portuguese", "brazilian",
+    "czech", "hungarian", "turkish", "japanese", "korean", "chinese", "dutch", "swedish", "danish",
+    "finnish", "norwegian", "ukrainian", "romanian", "greek", "arabic", "thai",
+];
+
+/// A GOG game, either installed or as an offline installer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GogGame {
+    /// The product id if it is known, otherwise the slug of the
+    /// installer name.
+    pub product_id: String,
+    pub name: String,
+    pub version: Option<String>,
+    pub build_id: Option<String>,
+    pub language: Option<String>,
+    pub install_dir: Option<PathBuf>,
+    pub installers: Vec<PathBuf>,
+}
+
+impl GogGame {
+    /// Builds the `Launcher` entry of this game.
+    pub fn launcher(&self) -> Launcher {
+        let mut launcher = Launcher::new(GOG_LAUNCHER.to_owned(), self.product_id.clone());
+        launcher.install_dir = self.install_dir.clone();
+        launcher.build_id = self.build_id.clone();
+        launcher.version = self.version.clone();
+        launcher.language = self.language.clone();
+        launcher.installers = self.installers.clone();
+        launcher
+    }
+
+    /// Builds the entry the scanner would have produced for this game.
+    pub fn to_detected(&self) -> DetectedGame {
+        DetectedGame { name: self.name.clone(), launcher: self.launcher() }
+    }
+}
+
+/// The entry of an offline installer in `game` that `found`, an
+/// installed game with its product id, takes over. Installers only
+/// know the slug of their name, e.g. `celeste`, so they are matched
+/// by the name of the game.
+pub fn installer_entry<'a>(game: &'a Game, found: &Launcher, found_name: &str) -> Option<&'a Launcher> {
+    if found.name != GOG_LAUNCHER || !is_product_id(&found.game_id) { return None; }
+    let name = comparable(found_name);
+    game.launchers().iter().find(|l| l.name == GOG_LAUNCHER && !is_product_id(&l.game_id)
+        && (comparable(&l.game_id) == name || comparable(game.name()) == name))
+}
+
+fn is_product_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_digit())
+}
+
+/// `Hollow Knight` and `hollow_knight` both become `hollowknight`.
+fn comparable(name: &str) -> String {
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+/// Parses a `goggame-<id>.info` file of an installed game, the game
+/// is installed in the directory the file is in.
+///
+/// # Errors
+/// Fails with `ErrorKind::FailedToParse` if the file isn't json or
+/// lacks the `gameId`.
+pub fn parse_info(path: &Path) -> Result<GogGame, NasError> {
+    let contents = fs::read_to_string(path).context(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path))?;
+    let info: serde_json::Value = serde_json::from_str(&contents).context(ErrorKind::FailedToParse, format!("Failed to parse {:?}", path))?;
+    let str_of = |key: &str| info.get(key).and_then(serde_json::Value::as_str).map(str::to_owned);
+
+    let product_id = str_of("gameId").ok_or_else(|| NasError::new(ErrorKind::FailedToParse, format!("{:?} has no \"gameId\"", path)))?;
+    let install_dir = path.parent().map(Path::to_path_buf);
+    Ok(GogGame {
+        name: str_of("name").unwrap_or_else(|| install_dir.as_deref().map(dir_name).unwrap_or_else(|| product_id.clone())),
+        // `version` is the version of the file format, not of the game
+        version: None,
+        build_id: str_of("buildId"),
+        language: str_of("language"),
+        install_dir,
+        installers: Vec::new(),
+        product_id,
+    })
+}
+
+/// What can be read from the file name of an offline installer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstallerName {
+    pub slug: String,
+    pub version: Option<String>,
+    pub build: Option<String>,
+    pub language: Option<String>,
+    /// The number of the `.bin` part, `None` for the `.exe`.
+    pub part: Option<u32>,
+}
+
+impl InstallerName {
+    /// Turns `the_witcher_3_wild_hunt` into `The Witcher 3 Wild Hunt`.
+    pub fn title(&self) -> String {
+        self.slug.split('_')
+            .filter(|w| !w.is_empty())
+            .map(|w| {
+                let mut chars = w.chars();
+                chars.next().map(|c| c.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The file name without the part number, which is the same for
+    /// every part of one installer.
+    fn group(file_name: &str) -> &str {
+        let stem = file_name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(file_name);
+        match stem.rsplit_once('-') {
+            Some((base, part)) if part.parse::<u32>().is_ok() => base,
+            _ => stem,
+        }
+    }
+}
+
+/// All parts of one offline installer.
+#[derive(Default)]
+struct InstallerGroup {
+    /// The parsed name of the `.exe`.
+    name: Option<InstallerName>,
+    parts: Vec<(Option<u32>, PathBuf)>,
+}
+
+/// Parses the file name of a Windows offline installer, which look
+/// like `setup_<slug>_<version>_(<extra>)_(<build>).exe` and
+/// `setup_<slug>_<version>_(<extra>)_(<build>)-<part>.bin`.
+///
+/// Returns `None` for anything that isn't such an installer.
+pub fn parse_installer_name(file_name: &str) -> Option<InstallerName> {
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    let rest = stem.strip_prefix("setup_")?;
+    let (rest, part) = match extension.to_ascii_lowercase().as_str() {
+        "exe" => (rest, None),
+        "bin" => {
+            let (base, part) = rest.rsplit_once('-')?;
+            (base, Some(part.parse().ok()?))
+        },
+        _ => return None,
+    };
+
+    let (mut words, mut extras) = (Vec::new(), Vec::new());
+    for token in rest.split('_').filter(|t| !t.is_empty()) {
+        match token.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+            Some(extra) => extras.push(extra),
+            None => words.push(token),
+        }
+    }
+    // the last word which looks like a version is the version, since
+    // the name itself may contain numbers as well
+    let is_version = |w: &&str| {
+        let w = w.strip_prefix('v').unwrap_or(w);
+        w.starts_with(|c: char| c.is_ascii_digit()) && w.chars().all(|c| c.is_ascii_alphanumeric() || c == '.')
+    };
+    let version_at = words.iter().rposition(is_version).filter(|i| *i > 0);
+    let (slug, version) = match version_at {
+        Some(i) => (words[..i].join("_"), Some(words[i..].join("_"))),
+        None => (words.join("_"), None),
+    };
+    if slug.is_empty() { return None; }
+
+    Some(InstallerName {
+        slug,
+        version,
+        build: extras.iter().rev().find(|e| e.chars().all(|c| c.is_ascii_digit())).map(|e| e.to_string()),
+        language: extras.iter().find(|e| LANGUAGES.contains(&e.to_ascii_lowercase().as_str())).map(|e| e.to_ascii_lowercase()),
+        part,
+    })
+}
+
+/// Finds the GOG games among the `files` of a single directory.
+///
+/// Every `goggame-<id>.info` is one installed game. The offline
+/// installers are grouped by their name, so that all parts of one
+/// installer end up in the same game. Should the directory also
+/// contain exactly one `.info` file then the installers are
+/// attached to it, since the product id is otherwise unknown.
+pub fn games_in_dir(files: &[PathBuf]) -> Vec<GogGame> {
+    let mut games: Vec<GogGame> = Vec::new();
+    let mut installers: BTreeMap<String, InstallerGroup> = BTreeMap::new();
+    for file in files {
+        let Some(file_name) = file.file_name().and_then(std::ffi::OsStr::to_str) else { continue };
+        if file_name.starts_with("goggame-") && file_name.ends_with(".info") {
+            match parse_info(file) {
+                Ok(game) => games.push(game),
+                Err(e) => { warn!("Skipping the GOG info {:?}: {}", file, e); },
+            }
+        } else if let Some(name) = parse_installer_name(file_name) {
+            let entry = installers.entry(InstallerName::group(file_name).to_owned()).or_default();
+            entry.parts.push((name.part, file.clone()));
+            if name.part.is_none() { entry.name = Some(name); }
+        }
+    }
+
+    let mut from_installers = Vec::new();
+    for (group, InstallerGroup { name, mut parts }) in installers {
+        // `.bin` parts without their `.exe` can't be installed
+        let Some(name) = name else {
+            warn!("The installer parts of {:?} have no setup executable", group);
+            continue;
+        };
+        parts.sort();
+        from_installers.push(GogGame {
+            product_id: name.slug.clone(),
+            name: name.title(),
+            version: name.version,
+            build_id: name.build,
+            language: name.language,
+            install_dir: None,
+            installers: parts.into_iter().map(|(_, path)| path).collect(),
+        });
+    }
+
+    match (games.as_mut_slice(), from_installers.as_slice()) {
+        ([installed], [installer]) => {
+            installed.installers = installer.installers.clone();
+            installed.version = installed.version.take().or(installer.version.clone());
+            installed.language = installed.language.take().or(installer.language.clone());
+        },
+        _ => games.extend(from_installers),
+    }
+    games
+}
+
+/// Finds every GOG game below `root`.
+pub fn find_games(root: &Path) -> Vec<GogGame> {
+    let mut found = Vec::new();
+    walk(root, 0, &mut found);
+    info!("Found {} GOG games in {:?}", found.len(), root);
+    found
+}
+
+fn walk(dir: &Path, depth: usize, found: &mut Vec<GogGame>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to read the directory {:?} with {:?}", dir, e);
+            return;
+        }
+    };
+    let (mut files, mut dirs): (Vec<PathBuf>, Vec<PathBuf>) = entries
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .partition(|p| !p.is_dir());
+    files.sort();
+    dirs.sort();
+
+    let games = games_in_dir(&files);
+    trace!("Found {} GOG games in {:?}", games.len(), dir);
+    // installed games are not searched any further
+    let installed = games.iter().any(|g| g.install_dir.is_some());
+    found.extend(games);
+    if installed || depth >= MAX_DEPTH { return; }
+    for sub in dirs {
+        walk(&sub, depth + 1, found);
+    }
+}
+
+/// Builds the command of the primary play task in the
+/// `goggame-<id>.info` of an installed game.
+///
+/// # Errors
+/// Fails with `ErrorKind::NotFound` if there is no primary file task.
+pub fn primary_play_task(install_dir: &Path, product_id: &str) -> Result<LaunchCommand, NasError> {
+    let path = install_dir.join(format!("goggame-{}.info", product_id));
+    let contents = fs::read_to_string(&path).context(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path))?;
+    let info: serde_json::Value = serde_json::from_str(&contents).context(ErrorKind::FailedToParse, format!("Failed to parse {:?}", path))?;
+    let task = info.get("playTasks").and_then(serde_json::Value::as_array).into_iter().flatten()
+        .find(|t| t.get("isPrimary").and_then(serde_json::Value::as_bool) == Some(true)
+            && t.get("type").and_then(serde_json::Value::as_str) == Some("FileTask"))
+        .ok_or_else(|| NasError::new(ErrorKind::NotFound, format!("{:?} has no primary play task", path)))?;
+    let str_of = |key: &str| task.get(key).and_then(serde_json::Value::as_str).filter(|s| !s.is_empty());
+
+    let program = install_dir.join(str_of("path").unwrap_or_default());
+    let mut command = LaunchCommand::new(program.to_string_lossy(), str_of("arguments").unwrap_or_default().split_whitespace());
+    command.working_dir = Some(str_of("workingDir").map(|dir| install_dir.join(dir)).unwrap_or_else(|| install_dir.to_path_buf()));
+    Ok(command)
+}
+
+/// Lists the GOG games in the library roots as well as the ones
+/// Heroic installed, and starts them through their play tasks.
+pub struct GogProvider {
+    roots: Vec<PathBuf>,
+    heroic_config: PathBuf,
+}
+
+impl GogProvider {
+    pub fn new(roots: Vec<PathBuf>, heroic_config: PathBuf) -> Self { Self { roots, heroic_config } }
+}
+
+impl LauncherProvider for GogProvider {
+    fn name(&self) -> &'static str { GOG_LAUNCHER }
+
+    fn list_games(&self) -> Result<Vec<DetectedGame>, NasError> {
+        let mut games: Vec<DetectedGame> = self.roots.iter().flat_map(|root| find_games(root)).map(|g| g.to_detected()).collect();
+        for game in heroic::gog_installed(&self.heroic_config)? {
+            if !games.iter().any(|g| g.launcher.is_same_as(&game.launcher)) { games.push(game); }
+        }
+        Ok(games)
+    }
+
+    fn detect(&self, _dir: &Path, files: &[PathBuf], _dirs: &[PathBuf]) -> Detection {
+        let games = games_in_dir(files);
+        Detection {
+            is_game_folder: games.iter().any(|g| g.install_dir.is_some()),
+            games: games.iter().map(GogGame::to_detected).collect(),
+            skip: Vec::new(),
+        }
+    }
+
+    fn launch_command(&self, launcher: &Launcher) -> Result<LaunchCommand, NasError> {
+        primary_play_task(require_install_dir(launcher)?, &launcher.game_id)
+    }
+}
+
+fn dir_name(dir: &Path) -> String {
+    dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn parses_installer_names() {
+        let name = parse_installer_name("setup_the_witcher_3_wild_hunt_goty_4.04_(french)_(79224)-2.bin").unwrap();
+        assert_eq!(name, InstallerName {
+            slug: "the_witcher_3_wild_hunt_goty".to_owned(),
+            version: Some("4.04".to_owned()),
+            build: Some("79224".to_owned()),
+            language: Some("french".to_owned()),
+            part: Some(2),
+        });
+        assert_eq!(name.title(), "The Witcher 3 Wild Hunt Goty");
+
+        let name = parse_installer_name("setup_celeste_1.4.0.0_(64bit)_(42346).exe").unwrap();
+        assert_eq!((name.slug.as_str(), name.version.as_deref(), name.part), ("celeste", Some("1.4.0.0"), None));
+        assert_eq!(parse_installer_name("setup_hades.exe").unwrap().version, None);
+        assert_eq!(parse_installer_name("setup.exe"), None);
+        assert_eq!(parse_installer_name("readme.txt"), None);
+    }
+
+    #[test]
+    fn groups_installer_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        let installers = dir.path().join("installers");
+        write(&installers.join("setup_celeste_1.4.0.0_(42346).exe"), "");
+        write(&installers.join("setup_celeste_1.4.0.0_(42346)-2.bin"), "");
+        write(&installers.join("setup_celeste_1.4.0.0_(42346)-1.bin"), "");
+        write(&installers.join("setup_hades_1.38_(english)_(5678).exe"), "");
+        write(&installers.join("setup_orphan_1.0-1.bin"), "");
+        write(&dir.path().join("Installed/Gwent/goggame-1971477531.info"), r#"{ "gameId": "1971477531", "name": "Gwent", "buildId": "5", "language": "English" }"#);
+
+        let mut games = find_games(dir.path());
+        games.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(games.iter().map(|g| g.name.as_str()).collect::<Vec<_>>(), ["Celeste", "Gwent", "Hades"]);
+
+        let launcher = games[0].launcher();
+        assert_eq!(launcher.name, GOG_LAUNCHER);
+        assert_eq!(launcher.version.as_deref(), Some("1.4.0.0"));
+        assert_eq!(launcher.installers, [
+            installers.join("setup_celeste_1.4.0.0_(42346).exe"),
+            installers.join("setup_celeste_1.4.0.0_(42346)-1.bin"),
+            installers.join("setup_celeste_1.4.0.0_(42346)-2.bin"),
+        ]);
+        assert_eq!(games[1].product_id, "1971477531");
+        assert_eq!(games[1].language.as_deref(), Some("English"));
+        assert_eq!(games[2].language.as_deref(), Some("english"));
+
+        // installing a game turns the entry of its installer into the installed one
+        let store = crate::store::JsonFileStore::open(dir.path().join("library.json"), 0);
+        crate::scanner::merge(&store, vec![games[0].to_detected()]).unwrap();
+        write(&dir.path().join("Installed/Celeste/goggame-1234.info"), r#"{ "gameId": "1234", "name": "Celeste" }"#);
+        let installed = find_games(&dir.path().join("Installed")).into_iter().find(|g| g.name == "Celeste").unwrap();
+        let report = crate::scanner::merge(&store, vec![installed.to_detected()]).unwrap();
+        assert_eq!((report.added.len(), report.updated.len()), (0, 1));
+        let library = crate::store::LibraryStore::games(&store).unwrap();
+        assert_eq!(library.len(), 1);
+        assert_eq!(library[0].launchers().iter().map(|l| l.game_id.as_str()).collect::<Vec<_>>(), ["1234"]);
+        assert_eq!(library[0].launchers()[0].installers.len(), 3);
+    }
+
+    #[test]
+    fn launches_the_primary_play_task() {
+        let dir = tempfile::tempdir().unwrap();
+        write(&dir.path().join("goggame-1.info"), r#"{ "gameId": "1", "playTasks": [
+            { "isPrimary": false, "type": "FileTask", "path": "tools.exe" },
+            { "isPrimary": true, "type": "FileTask", "path": "bin/game.exe", "arguments": "-fullscreen -nointro", "workingDir": "bin" }
+        ] }"#);
+        let command = primary_play_task(dir.path(), "1").unwrap();
+        assert_eq!(command.program, dir.path().join("bin/game.exe").to_string_lossy());
+        assert_eq!(command.args, ["-fullscreen", "-nointro"]);
+        assert_eq!(command.working_dir, Some(dir.path().join("bin")));
+    }
+}
@@ -0,0 +1,182 @@
+//! This crate is for reading the games installed through the Heroic
+//! Games Launcher. Heroic keeps json caches of the Epic games it
+//! installed through legendary and of the GOG games it installed
+//! itself, which are read here.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, logging::LoggingLevel, logging::logging_function};
+use crate::types::Launcher;
+use crate::scanner::{DetectedGame, Detection};
+use crate::provider::{LauncherProvider, LaunchCommand, require_install_dir};
+use crate::gog::GOG_LAUNCHER;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+
+/// The name that is used for `Launcher.name` of Epic games.
+pub const EPIC_LAUNCHER: &str = "epic";
+
+/// An entry of `legendaryConfig/legendary/installed.json`.
+#[derive(Debug, Deserialize)]
+struct LegendaryGame {
+    app_name: String,
+    title: String,
+    install_path: PathBuf,
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    install_size: Option<u64>,
+}
+
+/// An entry of `gog_store/installed.json`.
+#[derive(Debug, Deserialize)]
+struct HeroicGogGame {
+    #[serde(rename = "appName")]
+    app_name: String,
+    install_path: PathBuf,
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default, rename = "buildId")]
+    build_id: Option<String>,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    install_size: Option<serde_json::Value>,
+    #[serde(default)]
+    is_dlc: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct HeroicGogInstalled {
+    #[serde(default)]
+    installed: Vec<HeroicGogGame>,
+}
+
+/// The default location of Heroic's configuration on linux.
+pub fn default_config_dir() -> PathBuf {
+    crate::server::expand_tilde("~/.config/heroic")
+}
+
+/// Reads a json cache, a missing file means nothing is installed.
+fn read_cache<T: for<'de> Deserialize<'de> + Default>(path: &Path) -> Result<T, NasError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            trace!("There is no {:?}", path);
+            return Ok(T::default());
+        },
+        Err(e) => return Err(NasError::new(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path)).with_source(e)),
+    };
+    serde_json::from_str(&contents).context(ErrorKind::FailedToParse, format!("Failed to parse {:?}", path))
+}
+
+/// Lists the Epic games Heroic installed through legendary.
+pub fn epic_installed(config_dir: &Path) -> Result<Vec<DetectedGame>, NasError> {
+    let path = config_dir.join("legendaryConfig").join("legendary").join("installed.json");
+    let installed: std::collections::BTreeMap<String, LegendaryGame> = read_cache(&path)?;
+    Ok(installed.into_values().map(|game| {
+        let mut launcher = Launcher::new(EPIC_LAUNCHER.to_owned(), game.app_name);
+        launcher.install_dir = Some(game.install_path);
+        launcher.size_on_disk = game.install_size;
+        launcher.version = game.version;
+        DetectedGame { name: game.title, launcher }
+    }).collect())
+}
+
+/// Lists the GOG games Heroic installed. The cache has no titles, so
+/// the name of the install directory is used instead.
+pub fn gog_installed(config_dir: &Path) -> Result<Vec<DetectedGame>, NasError> {
+    let path = config_dir.join("gog_store").join("installed.json");
+    let installed: HeroicGogInstalled = read_cache(&path)?;
+    Ok(installed.installed.into_iter().filter(|game| !game.is_dlc).map(|game| {
+        let name = game.install_path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| game.app_name.clone());
+        let mut launcher = Launcher::new(GOG_LAUNCHER.to_owned(), game.app_name);
+        // heroic writes the size either as a number or as e.g. "1.2 GiB"
+        launcher.size_on_disk = game.install_size.as_ref().and_then(serde_json::Value::as_u64);
+        launcher.install_dir = Some(game.install_path);
+        launcher.version = game.version;
+        launcher.build_id = game.build_id;
+        launcher.language = game.language;
+        DetectedGame { name, launcher }
+    }).collect())
+}
+
+/// Heroic (through legendary) keeps an `.egstore` folder in every Epic
+/// game it installs. The folder name is the best name there is.
+pub fn detect_egstore(dir: &Path) -> Option<DetectedGame> {
+    let manifest = fs::read_dir(dir.join(".egstore")).ok()?
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .find(|p| p.extension().is_some_and(|e| e == "mancpn"))?;
+    let info: serde_json::Value = serde_json::from_str(&fs::read_to_string(manifest).ok()?).ok()?;
+    let app_name = info.get("AppName")?.as_str()?.to_owned();
+    let mut launcher = Launcher::new(EPIC_LAUNCHER.to_owned(), app_name);
+    launcher.install_dir = Some(dir.to_path_buf());
+    Some(DetectedGame { name: dir.file_name()?.to_string_lossy().into_owned(), launcher })
+}
+
+/// Lists the Epic games of Heroic and starts them through Heroic.
+pub struct HeroicProvider {
+    config_dir: PathBuf,
+}
+
+impl HeroicProvider {
+    pub fn new(config_dir: PathBuf) -> Self { Self { config_dir } }
+}
+
+impl LauncherProvider for HeroicProvider {
+    fn name(&self) -> &'static str { EPIC_LAUNCHER }
+
+    fn list_games(&self) -> Result<Vec<DetectedGame>, NasError> {
+        let games = epic_installed(&self.config_dir)?;
+        info!("Heroic has {} Epic games installed", games.len());
+        Ok(games)
+    }
+
+    fn detect(&self, dir: &Path, _files: &[PathBuf], dirs: &[PathBuf]) -> Detection {
+        if !dirs.iter().any(|d| d.file_name().is_some_and(|n| n == ".egstore")) { return Detection::default(); }
+        let games: Vec<DetectedGame> = detect_egstore(dir).into_iter().collect();
+        Detection { is_game_folder: !games.is_empty(), games, skip: Vec::new() }
+    }
+
+    fn launch_command(&self, launcher: &Launcher) -> Result<LaunchCommand, NasError> {
+        require_install_dir(launcher)?;
+        Ok(LaunchCommand::new("heroic", ["--no-gui".to_owned(), format!("heroic://launch?appName={}&runner=legendary", launcher.game_id)]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn reads_the_heroic_caches() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(epic_installed(dir.path()).unwrap().is_empty());
+
+        write(&dir.path().join("legendaryConfig/legendary/installed.json"), r#"{
+            "Fortnite": { "app_name": "Fortnite", "title": "Fortnite", "install_path": "/games/Fortnite", "version": "1.0", "install_size": 1024, "is_dlc": false }
+        }"#);
+        write(&dir.path().join("gog_store/installed.json"), r#"{ "installed": [
+            { "appName": "1207658924", "install_path": "/games/Unreal Gold", "version": "2.0", "install_size": "1.2 GiB", "language": "en-US", "is_dlc": false, "platform": "windows" }
+        ] }"#);
+
+        let epic = epic_installed(dir.path()).unwrap();
+        assert_eq!(epic[0].name, "Fortnite");
+        assert_eq!(epic[0].launcher.size_on_disk, Some(1024));
+
+        let gog = gog_installed(dir.path()).unwrap();
+        assert_eq!(gog[0].name, "Unreal Gold");
+        assert_eq!(gog[0].launcher.name, GOG_LAUNCHER);
+        assert_eq!(gog[0].launcher.language.as_deref(), Some("en-US"));
+        assert_eq!(gog[0].launcher.size_on_disk, None);
+
+        let command = HeroicProvider::new(dir.path().to_path_buf()).launch_command(&epic[0].launcher).unwrap();
+        assert_eq!(command.args[1], "heroic://launch?appName=Fortnite&runner=legendary");
+    }
+}
@@ -0,0 +1,502 @@
+//! This crate is for installing games from the server onto the
+//! client. The installer downloads every file of the game's
+//! manifest, resumes partial downloads through HTTP range requests
+//! and verifies every file against the SHA-256 of the manifest.
+//!
+//! Partial files are kept next to their target as `<name>.part`, so
+//! an install that was interrupted, e.g. by a flaky network, picks
+//! up where it stopped the next time it is started.
+//!
+//! Games that are already installed are updated with `update()`,
+//! which only downloads the chunks of the changed files that aren't
+//! on the client yet, see the `chunks` crate.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, logging::LoggingLevel, logging::logging_function};
+use crate::manifest::{self, FileEntry, Manifest};
+use crate::chunks::{self, Chunk, ChunkIndex};
+use crate::client::{self, Credentials};
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use futures::stream::{self, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+
+/// How often a single file is attempted before the install fails.
+const MAX_ATTEMPTS: u32 = 8;
+
+/// Progress is reported at most this often, plus once per file.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many chunks are requested at the same time during updates.
+const PARALLEL_CHUNKS: usize = 4;
+
+/// Sent to the UI as the `install-progress` event.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct InstallProgress {
+    pub game_id: String,
+    /// The file that is currently downloaded.
+    pub file: String,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub files_done: usize,
+    pub files_total: usize,
+}
+
+/// What `install()` did.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct InstallReport {
+    pub game_id: String,
+    pub dest: PathBuf,
+    /// Files that were already installed and intact.
+    pub skipped_files: usize,
+    /// Files that were downloaded or, for updates, put together again.
+    pub downloaded_files: usize,
+    pub downloaded_bytes: u64,
+    /// Bytes that were copied from the files on the client instead of
+    /// being downloaded, only used by updates.
+    pub reused_bytes: u64,
+}
+
+/// Where games are installed unless told otherwise.
+pub fn default_install_dir(game_id: &str) -> PathBuf {
+    client::client_dir().join("games").join(game_id)
+}
+
+/// Where the chunk indices of the installed games are cached.
+pub fn chunk_cache_dir() -> PathBuf {
+    client::client_dir().join("chunks")
+}
+
+fn game_url(server_url: &str, game_id: &str, rest: &[&str]) -> Result<reqwest::Url, NasError> {
+    let mut url = reqwest::Url::parse(server_url).context(ErrorKind::BadRequest, format!("{:?} is not a valid server url", server_url))?;
+    url.path_segments_mut()
+        .map_err(|_| NasError::new(ErrorKind::BadRequest, format!("{:?} is not a valid server url", server_url)))?
+        .pop_if_empty()
+        .extend(["games", game_id])
+        .extend(rest);
+    Ok(url)
+}
+
+/// A GET request to `url` as the user of `credentials`.
+fn get(http: &reqwest::Client, credentials: &Credentials, url: &reqwest::Url) -> reqwest::RequestBuilder {
+    credentials.apply(http.get(url.clone()), &reqwest::Method::GET, url, &[])
+}
+
+async fn get_json<T: DeserializeOwned>(http: &reqwest::Client, credentials: &Credentials, server_url: &str, game_id: &str, what: &str) -> Result<T, NasError> {
+    let url = game_url(server_url, game_id, &[what])?;
+    let res = get(http, credentials, &url).send().await.context(ErrorKind::FailedToFetch, format!("Failed to request {}", url))?;
+    let status = res.status();
+    let body = res.bytes().await.context(ErrorKind::FailedToFetch, format!("Failed to read the response of {}", url))?;
+    if !status.is_success() {
+        let kind = match status {
+            reqwest::StatusCode::NOT_FOUND => ErrorKind::NotFound,
+            reqwest::StatusCode::UNAUTHORIZED => ErrorKind::Unauthorized,
+            reqwest::StatusCode::FORBIDDEN => ErrorKind::Forbidden,
+            _ => ErrorKind::FailedToFetch,
+        };
+        return Err(NasError::new(kind, format!("The server has no files for {}: {}", game_id, String::from_utf8_lossy(&body))));
+    }
+    serde_json::from_slice(&body).context(ErrorKind::FailedToParse, format!("Failed to parse the {} of {}", what, game_id))
+}
+
+/// Fetches the manifest of a game from the server.
+pub async fn fetch_manifest(http: &reqwest::Client, credentials: &Credentials, server_url: &str, game_id: &str) -> Result<Manifest, NasError> {
+    get_json(http, credentials, server_url, game_id, "manifest").await
+}
+
+/// Fetches the chunk index of a game from the server.
+pub async fn fetch_chunk_index(http: &reqwest::Client, credentials: &Credentials, server_url: &str, game_id: &str) -> Result<ChunkIndex, NasError> {
+    get_json(http, credentials, server_url, game_id, "chunks").await
+}
+
+/// Downloads a chunk and checks its hash, retrying failed attempts
+/// with a growing delay.
+async fn fetch_chunk(http: &reqwest::Client, credentials: &Credentials, server_url: &str, game_id: &str, chunk: &Chunk) -> Result<Vec<u8>, NasError> {
+    let url = game_url(server_url, game_id, &["chunks", &chunk.sha256])?;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let result = async {
+            let res = get(http, credentials, &url).send().await.context(ErrorKind::FailedToFetch, format!("Failed to request {}", url))?;
+            if !res.status().is_success() {
+                return Err(NasError::new(ErrorKind::FailedToFetch, format!("{} failed with {}", url, res.status())));
+            }
+            let data = res.bytes().await.context(ErrorKind::FailedToFetch, format!("The download of {} was interrupted", url))?;
+            if chunks::hash_bytes(&data) != chunk.sha256 {
+                return Err(NasError::new(ErrorKind::FailedToFetch, format!("The chunk {} doesn't match its hash", chunk.sha256)));
+            }
+            Ok(data.to_vec())
+        }.await;
+        match result {
+            Ok(data) => return Ok(data),
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                warn!("Attempt {} of {} for the chunk {} failed: {}", attempt, MAX_ATTEMPTS, chunk.sha256, e);
+                actix_web::rt::time::sleep(Duration::from_millis(250 << attempt.min(6))).await;
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn part_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    target.with_file_name(name)
+}
+
+/// Downloads the rest of a file into its `.part` file.
+///
+/// Returns how many bytes were downloaded, `on_chunk` is called with
+/// the size of the `.part` file after every chunk. Should the server
+/// ignore the range then the file is downloaded from the start again.
+async fn download_part(http: &reqwest::Client, credentials: &Credentials, url: reqwest::Url, part: &Path, entry: &FileEntry, mut on_chunk: impl FnMut(u64)) -> Result<u64, NasError> {
+    let mut existing = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+    if existing > entry.size {
+        fs::remove_file(part)?;
+        existing = 0;
+    }
+    if existing == entry.size { return Ok(0); }
+
+    let mut req = get(http, credentials, &url);
+    if existing > 0 {
+        trace!("Resuming {} at {} of {} bytes", entry.path, existing, entry.size);
+        req = req.header(reqwest::header::RANGE, format!("bytes={}-", existing));
+    }
+    let mut res = req.send().await.context(ErrorKind::FailedToFetch, format!("Failed to request {}", url))?;
+    let append = match res.status() {
+        reqwest::StatusCode::PARTIAL_CONTENT => true,
+        reqwest::StatusCode::OK => false,
+        status => return Err(NasError::new(ErrorKind::FailedToFetch, format!("{} failed with {}", url, status))),
+    };
+    let mut written = if append { existing } else { 0 };
+
+    let mut file = fs::OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(part)
+        .context(ErrorKind::FailedToWrite, format!("Failed to open {:?}", part))?;
+    let mut downloaded = 0;
+    while let Some(chunk) = res.chunk().await.context(ErrorKind::FailedToFetch, format!("The download of {} was interrupted", entry.path))? {
+        file.write_all(&chunk).context(ErrorKind::FailedToWrite, format!("Failed to write {:?}", part))?;
+        downloaded += chunk.len() as u64;
+        written += chunk.len() as u64;
+        on_chunk(written);
+    }
+    file.sync_all().context(ErrorKind::FailedToWrite, format!("Failed to write {:?}", part))?;
+    Ok(downloaded)
+}
+
+/// Hashes a file on the thread pool, since games have files of many
+/// gigabytes.
+async fn hash_file(path: PathBuf) -> Result<String, NasError> {
+    actix_web::web::block(move || manifest::hash_file(&path)).await?
+}
+
+/// Installs every file of a game into `dest`.
+///
+/// Files that already exist with the right hash are skipped. Every
+/// other file is downloaded (or resumed) and only moved into place
+/// once its hash matches the manifest. Failed attempts are retried
+/// with a growing delay.
+///
+/// # Errors
+/// Fails with `ErrorKind::FailedToFetch` once a file failed
+/// `MAX_ATTEMPTS` times. Everything downloaded so far is kept. Fails
+/// with `ErrorKind::Unauthorized` if the server wants a user that
+/// `credentials` doesn't have.
+pub async fn install(server_url: &str, credentials: &Credentials, game_id: &str, dest: &Path, mut on_progress: impl FnMut(&InstallProgress) + Send) -> Result<InstallReport, NasError> {
+    let http = client::http()?;
+    let manifest = fetch_manifest(&http, credentials, server_url, game_id).await?;
+    info!("Installing {} with {} files ({} bytes) into {:?}", game_id, manifest.files.len(), manifest.total_size, dest);
+
+    let mut report = InstallReport { game_id: game_id.to_owned(), dest: dest.to_path_buf(), skipped_files: 0, downloaded_files: 0, downloaded_bytes: 0, reused_bytes: 0 };
+    let mut progress = InstallProgress {
+        game_id: game_id.to_owned(),
+        file: String::new(),
+        bytes_done: 0,
+        bytes_total: manifest.total_size,
+        files_done: 0,
+        files_total: manifest.files.len(),
+    };
+    let mut last_report = Instant::now();
+    // the bytes of every file before the current one
+    let mut bytes_before = 0;
+
+    for entry in &manifest.files {
+        let target = manifest::join(dest, &entry.path)?;
+        progress.file = entry.path.clone();
+        if target.exists() && fs::metadata(&target)?.len() == entry.size && hash_file(target.clone()).await? == entry.sha256 {
+            trace!("{} is already installed", entry.path);
+            report.skipped_files += 1;
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).context(ErrorKind::FailedToCreateFolder, format!("Failed to create {:?}", parent))?;
+            }
+            let part = part_path(&target);
+            let segments: Vec<&str> = std::iter::once("files").chain(entry.path.split('/')).collect();
+            let url = game_url(server_url, game_id, &segments)?;
+
+            let mut installed = false;
+            for attempt in 1..=MAX_ATTEMPTS {
+                let result = download_part(&http, credentials, url.clone(), &part, entry, |written| {
+                    progress.bytes_done = bytes_before + written;
+                    if last_report.elapsed() >= PROGRESS_INTERVAL {
+                        on_progress(&progress);
+                        last_report = Instant::now();
+                    }
+                }).await;
+                match result {
+                    Ok(downloaded) => report.downloaded_bytes += downloaded,
+                    Err(e) => {
+                        warn!("Attempt {} of {} for {} failed: {}", attempt, MAX_ATTEMPTS, entry.path, e);
+                        actix_web::rt::time::sleep(Duration::from_millis(250 << attempt.min(6))).await;
+                        continue;
+                    },
+                }
+                if hash_file(part.clone()).await? == entry.sha256 {
+                    fs::rename(&part, &target).context(ErrorKind::FailedToWrite, format!("Failed to move {:?} into place", part))?;
+                    installed = true;
+                    break;
+                }
+                warn!("{} doesn't match its hash, downloading it again", entry.path);
+                fs::remove_file(&part)?;
+            }
+            if !installed {
+                return Err(NasError::new(ErrorKind::FailedToFetch, format!("Giving up on {} after {} attempts", entry.path, MAX_ATTEMPTS)));
+            }
+            report.downloaded_files += 1;
+        }
+        bytes_before += entry.size;
+        progress.bytes_done = bytes_before;
+        progress.files_done += 1;
+        on_progress(&progress);
+        last_report = Instant::now();
+    }
+    info!("Installed {}: {} files downloaded, {} already present", game_id, report.downloaded_files, report.skipped_files);
+    Ok(report)
+}
+
+/// Updates a game that is installed in `dest` to the files that are
+/// on the server now.
+///
+/// Changed files are put together from their chunks. Chunks that are
+/// already part of any file in `dest` are copied from there, only the
+/// others are downloaded. The chunk index of `dest` is cached in
+/// `cache_dir`, so unchanged files are only read once. Files that are
+/// no longer part of the game are left alone, since they might be
+/// saves or settings.
+///
+/// # Errors
+/// Fails with `ErrorKind::FailedToFetch` once a chunk failed
+/// `MAX_ATTEMPTS` times. Files that were completed are kept. Fails
+/// with `ErrorKind::Unauthorized` if the server wants a user that
+/// `credentials` doesn't have.
+pub async fn update(server_url: &str, credentials: &Credentials, game_id: &str, dest: &Path, cache_dir: &Path, mut on_progress: impl FnMut(&InstallProgress) + Send) -> Result<InstallReport, NasError> {
+    let http = client::http()?;
+    let index = fetch_chunk_index(&http, credentials, server_url, game_id).await?;
+    fs::create_dir_all(dest).context(ErrorKind::FailedToCreateFolder, format!("Failed to create {:?}", dest))?;
+    // building the index reads every file that changed since the last update
+    let local = {
+        let (cache_dir, id, dest) = (cache_dir.to_path_buf(), game_id.to_owned(), dest.to_path_buf());
+        actix_web::web::block(move || chunks::load_or_build_index(&cache_dir, &id, &dest)).await??
+    };
+    let local_chunks = local.locations();
+    info!("Updating {} in {:?}, {} files ({} bytes) on the server, {} files on the client", game_id, dest, index.files.len(), index.total_size, local.files.len());
+
+    let mut report = InstallReport { game_id: game_id.to_owned(), dest: dest.to_path_buf(), skipped_files: 0, downloaded_files: 0, downloaded_bytes: 0, reused_bytes: 0 };
+    let mut progress = InstallProgress {
+        game_id: game_id.to_owned(),
+        file: String::new(),
+        bytes_done: 0,
+        bytes_total: index.total_size,
+        files_done: 0,
+        files_total: index.files.len(),
+    };
+    let mut last_report = Instant::now();
+    let mut bytes_before = 0;
+
+    for file in &index.files {
+        let target = manifest::join(dest, &file.path)?;
+        progress.file = file.path.clone();
+        if local.file(&file.path).is_some_and(|f| f.sha256 == file.sha256) {
+            trace!("{} is unchanged", file.path);
+            report.skipped_files += 1;
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).context(ErrorKind::FailedToCreateFolder, format!("Failed to create {:?}", parent))?;
+            }
+            // the old file stays in place until the new one is complete,
+            // so its chunks can still be copied
+            let part = part_path(&target);
+            let mut out = fs::File::create(&part).context(ErrorKind::FailedToWrite, format!("Failed to open {:?}", part))?;
+            let mut hasher = Sha256::new();
+            let mut written = 0;
+
+            let mut pieces = stream::iter(&file.chunks).map(|chunk| {
+                let local = local_chunks.get(chunk.sha256.as_str())
+                    .and_then(|(f, c)| manifest::join(dest, &f.path).ok().map(|path| (path, (*c).clone())));
+                let http = &http;
+                async move {
+                    // reading and hashing the local chunk mustn't hold up
+                    // the downloads, a chunk whose file has changed in the
+                    // meantime is downloaded
+                    if let Some((path, local)) = local {
+                        if let Ok(Ok(data)) = actix_web::web::block(move || chunks::read_chunk(&path, &local)).await {
+                            return Ok((data, true));
+                        }
+                    }
+                    fetch_chunk(http, credentials, server_url, game_id, chunk).await.map(|data| (data, false))
+                }
+            }).buffered(PARALLEL_CHUNKS);
+            while let Some(piece) = pieces.next().await {
+                let (data, reused) = piece?;
+                out.write_all(&data).context(ErrorKind::FailedToWrite, format!("Failed to write {:?}", part))?;
+                hasher.update(&data);
+                written += data.len() as u64;
+                if reused { report.reused_bytes += data.len() as u64 } else { report.downloaded_bytes += data.len() as u64 }
+                progress.bytes_done = bytes_before + written;
+                if last_report.elapsed() >= PROGRESS_INTERVAL {
+                    on_progress(&progress);
+                    last_report = Instant::now();
+                }
+            }
+            out.sync_all().context(ErrorKind::FailedToWrite, format!("Failed to write {:?}", part))?;
+
+            if format!("{:x}", hasher.finalize()) != file.sha256 {
+                fs::remove_file(&part)?;
+                return Err(NasError::new(ErrorKind::FailedToFetch, format!("{} doesn't match its hash after the update", file.path)));
+            }
+            fs::rename(&part, &target).context(ErrorKind::FailedToWrite, format!("Failed to move {:?} into place", part))?;
+            report.downloaded_files += 1;
+        }
+        bytes_before += file.size;
+        progress.bytes_done = bytes_before;
+        progress.files_done += 1;
+        on_progress(&progress);
+        last_report = Instant::now();
+    }
+    info!("Updated {}: {} files changed, {} bytes downloaded, {} bytes reused", game_id, report.downloaded_files, report.downloaded_bytes, report.reused_bytes);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpServer, HttpResponse};
+
+    async fn serve(games: web::Data<PathBuf>, path: web::Path<(String, String)>) -> actix_web::Result<actix_files::NamedFile> {
+        let (id, file) = path.into_inner();
+        Ok(actix_files::NamedFile::open(games.join(id).join(file))?)
+    }
+
+    async fn manifest(games: web::Data<PathBuf>, id: web::Path<String>) -> HttpResponse {
+        HttpResponse::Ok().json(manifest::build(&id, &games.join(id.as_str()), None).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn installs_resumes_and_repairs() {
+        let dir = tempfile::tempdir().unwrap();
+        let games = dir.path().join("server");
+        let source = games.join("7");
+        fs::create_dir_all(source.join("data files")).unwrap();
+        let big: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(source.join("data files/level.pak"), &big).unwrap();
+        fs::write(source.join("game.exe"), "game").unwrap();
+
+        let data = web::Data::new(games);
+        let server = HttpServer::new(move || App::new()
+                .app_data(data.clone())
+                .route("/games/{id}/manifest", web::get().to(manifest))
+                .route("/games/{id}/files/{path:.*}", web::get().to(serve)))
+            .bind(("127.0.0.1", 0)).unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        // half of the big file has been downloaded before
+        let dest = dir.path().join("client");
+        fs::create_dir_all(dest.join("data files")).unwrap();
+        fs::write(dest.join("data files/level.pak.part"), &big[..100_000]).unwrap();
+
+        let mut events = Vec::new();
+        let report = install(&url, &Credentials::default(), "7", &dest, |p| events.push(p.clone())).await.unwrap();
+        assert_eq!((report.downloaded_files, report.skipped_files, report.downloaded_bytes), (2, 0, 100_004));
+        assert_eq!(fs::read(dest.join("data files/level.pak")).unwrap(), big);
+        assert!(!dest.join("data files/level.pak.part").exists());
+        let last = events.last().unwrap();
+        assert_eq!((last.bytes_done, last.files_done), (200_004, 2));
+
+        // a corrupted file is downloaded again, intact ones are kept,
+        // and a broken partial download is thrown away
+        fs::write(dest.join("game.exe"), "gone").unwrap();
+        fs::write(dest.join("data files/level.pak"), "").unwrap();
+        fs::write(dest.join("data files/level.pak.part"), vec![0; 150_000]).unwrap();
+        let report = install(&url, &Credentials::default(), "7", &dest, |_| ()).await.unwrap();
+        assert_eq!((report.downloaded_files, report.skipped_files), (2, 0));
+        assert_eq!(fs::read(dest.join("game.exe")).unwrap(), b"game");
+        assert_eq!(fs::read(dest.join("data files/level.pak")).unwrap(), big);
+
+        let report = install(&url, &Credentials::default(), "7", &dest, |_| ()).await.unwrap();
+        assert_eq!((report.downloaded_files, report.skipped_files), (0, 2));
+    }
+
+    async fn chunk_index(cache: web::Data<chunks::ChunkCache>, games: web::Data<PathBuf>, id: web::Path<String>) -> HttpResponse {
+        HttpResponse::Ok().json(&cache.refresh(&id, &games.join(id.as_str())).unwrap().index)
+    }
+
+    async fn chunk(cache: web::Data<chunks::ChunkCache>, games: web::Data<PathBuf>, path: web::Path<(String, String)>) -> HttpResponse {
+        let (id, sha256) = path.into_inner();
+        match cache.read(&id, &games.join(&id), &sha256) {
+            Ok(data) => HttpResponse::Ok().body(data),
+            Err(_) => HttpResponse::NotFound().finish(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn updates_only_download_missing_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let games = dir.path().join("server");
+        let dest = dir.path().join("client");
+        let mut state = 7u64;
+        let archive: Vec<u8> = (0..6 * 1024 * 1024).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect();
+        for root in [games.join("7"), dest.clone()] {
+            fs::create_dir_all(&root).unwrap();
+            fs::write(root.join("data.pak"), &archive).unwrap();
+            fs::write(root.join("game.exe"), "v1").unwrap();
+            fs::write(root.join("save.dat"), "mine").unwrap();
+        }
+
+        // the patch changes a few bytes of the archive and the executable
+        let mut patched = archive.clone();
+        patched.splice(3_000_000..3_000_010, *b"patched!");
+        fs::write(games.join("7/data.pak"), &patched).unwrap();
+        fs::write(games.join("7/game.exe"), "v2").unwrap();
+        fs::remove_file(games.join("7/save.dat")).unwrap();
+
+        let cache = web::Data::new(chunks::ChunkCache::new(dir.path().join("server-cache")));
+        let data = web::Data::new(games);
+        let server = HttpServer::new(move || App::new()
+                .app_data(cache.clone())
+                .app_data(data.clone())
+                .route("/games/{id}/chunks", web::get().to(chunk_index))
+                .route("/games/{id}/chunks/{sha256}", web::get().to(chunk)))
+            .bind(("127.0.0.1", 0)).unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        let report = update(&url, &Credentials::default(), "7", &dest, &dir.path().join("client-cache"), |_| ()).await.unwrap();
+        assert_eq!((report.downloaded_files, report.skipped_files), (2, 0));
+        assert_eq!(fs::read(dest.join("data.pak")).unwrap(), patched);
+        assert_eq!(fs::read(dest.join("game.exe")).unwrap(), b"v2");
+        assert_eq!(fs::read(dest.join("save.dat")).unwrap(), b"mine");
+        assert!(report.downloaded_bytes < archive.len() as u64 / 2, "downloaded {} bytes", report.downloaded_bytes);
+        assert_eq!(report.downloaded_bytes + report.reused_bytes, patched.len() as u64 + 2);
+
+        let report = update(&url, &Credentials::default(), "7", &dest, &dir.path().join("client-cache"), |_| ()).await.unwrap();
+        assert_eq!((report.downloaded_files, report.skipped_files, report.downloaded_bytes), (0, 2, 0));
+    }
+}
@@ -0,0 +1,548 @@
+//! This crate is for the work that takes too long for a request, i.e.
+//! fetching and optimizing artwork. `POST /download_images` and
+//! `POST /optimize_images_server` only queue a `Job` and answer with
+//! its id, a worker runs the jobs one after the other.
+//!
+//! The progress of a job is at `GET /jobs/{id}` and is streamed as
+//! Server-Sent Events from `GET /jobs/{id}/events`, or for every job
+//! from `GET /jobs/events`. Jobs that fail because a fetch failed are
+//! tried again a few times, queued jobs can be cancelled and running
+//! ones stop after the item they are working on.
+//!
+//! The queue is kept in `jobs.json` of the data directory, jobs that
+//! were running when the server stopped are run again after a restart.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, error, logging::LoggingLevel, logging::logging_function};
+use crate::types::{ArtworkKind, Game, GamePatch, ServerSettings};
+use crate::data_dir::DataDir;
+use crate::reload::Live;
+use crate::store::LibraryStore;
+use crate::artwork::{ArtworkStore, FetchedImage};
+use crate::server::{fetch_artwork, has_artwork_source, optimize_artwork};
+use crate::files::write_atomic;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::{future, stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+/// How often a job is run before it counts as failed.
+pub const MAX_ATTEMPTS: u32 = 3;
+/// The wait before the second attempt, it doubles with every attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+/// The wait after the queue failed to start a job, it doubles up to
+/// `MAX_WORKER_BACKOFF` until a job starts again.
+const WORKER_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_WORKER_BACKOFF: Duration = Duration::from_secs(60);
+/// Finished jobs that are kept around so that their result can be
+/// looked up, older ones are forgotten.
+const FINISHED_COUNT: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobKind {
+    /// Fetches the artwork of `kinds` of the games that don't have it
+    /// yet, every kind if there are none.
+    DownloadImages {
+        games: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        kinds: Vec<ArtworkKind>,
+    },
+    /// Optimizes every image of the artwork with the profile of its
+    /// kind into `optimized_images_dir`. Images that are already
+    /// optimized are skipped unless `force` is set.
+    OptimizeImages {
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        force: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobStatus::Succeeded | JobStatus::Failed | JobStatus::Cancelled)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pub id: String,
+    #[serde(flatten)]
+    pub kind: JobKind,
+    pub status: JobStatus,
+    /// The items of the current attempt that are done, failed ones
+    /// included.
+    pub done: usize,
+    pub total: usize,
+    pub attempts: u32,
+    /// The items of the current attempt that failed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+    /// Why the last attempt failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// A queued job that failed before isn't run again until then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A job the worker took from the queue.
+#[derive(Debug, Clone)]
+pub struct Running {
+    pub job: Job,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Running {
+    pub fn is_cancelled(&self) -> bool { self.cancelled.load(Ordering::Relaxed) }
+}
+
+/// Whether another attempt might get further, e.g. a server that
+/// didn't answer. Images that don't exist or can't be decoded stay
+/// that way.
+fn is_transient(kind: ErrorKind) -> bool {
+    matches!(kind, ErrorKind::FailedToFetch | ErrorKind::Ignore)
+}
+
+/// Every job, mirrored into a json file, together with whoever waits
+/// for their progress.
+pub struct JobQueue {
+    path: PathBuf,
+    jobs: Mutex<Vec<Job>>,
+    /// The flags of the running jobs, set to cancel them.
+    cancel: Mutex<HashMap<String, Arc<AtomicBool>>>,
+    subscribers: Mutex<Vec<UnboundedSender<Job>>>,
+}
+
+impl JobQueue {
+    /// Opens the queue at `path`, a missing file is an empty queue.
+    /// Jobs that were running when the server stopped are queued again.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::FailedToParse` if the file is broken.
+    pub fn open(path: PathBuf) -> Result<Self, NasError> {
+        let mut jobs: Vec<Job> = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s).context(ErrorKind::FailedToParse, format!("Failed to parse {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(NasError::new(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path)).with_source(e)),
+        };
+        let interrupted = jobs.iter_mut().filter(|job| job.status == JobStatus::Running).map(|job| job.status = JobStatus::Queued).count();
+        if interrupted > 0 { info!("Running {} jobs again that were interrupted", interrupted); }
+        Ok(Self { path, jobs: Mutex::new(jobs), cancel: Mutex::new(HashMap::new()), subscribers: Mutex::new(Vec::new()) })
+    }
+
+    fn write(&self, jobs: &[Job]) -> Result<(), NasError> {
+        let json = serde_json::to_string_pretty(jobs).context(ErrorKind::FailedToSerialize, "Failed to serialize the jobs")?;
+        write_atomic(&self.path, json.as_bytes())
+    }
+
+    /// Tells every subscriber about the new state of `job`, the ones
+    /// that went away are dropped.
+    fn publish(&self, job: &Job) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|subscriber| subscriber.unbounded_send(job.clone()).is_ok());
+        }
+    }
+
+    /// Changes the job `id`, writes the queue if `persist` is set and
+    /// tells the subscribers.
+    fn update(&self, id: &str, persist: bool, change: impl FnOnce(&mut Job)) -> Result<Job, NasError> {
+        let mut jobs = self.jobs.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let job = jobs.iter_mut().find(|job| job.id == id).ok_or_else(|| job_not_found(id))?;
+        change(job);
+        job.updated_at = Utc::now();
+        let job = job.clone();
+        if persist { self.write(&jobs)?; }
+        drop(jobs);
+        self.publish(&job);
+        Ok(job)
+    }
+
+    pub fn jobs(&self) -> Result<Vec<Job>, NasError> {
+        Ok(self.jobs.lock().map_err(|_| ErrorKind::FailedToAquireLock)?.clone())
+    }
+
+    pub fn job(&self, id: &str) -> Result<Option<Job>, NasError> {
+        Ok(self.jobs.lock().map_err(|_| ErrorKind::FailedToAquireLock)?.iter().find(|job| job.id == id).cloned())
+    }
+
+    /// Every change of a job from now on.
+    pub fn subscribe(&self) -> Result<UnboundedReceiver<Job>, NasError> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.lock().map_err(|_| ErrorKind::FailedToAquireLock)?.push(sender);
+        Ok(receiver)
+    }
+
+    pub fn enqueue(&self, kind: JobKind) -> Result<Job, NasError> {
+        let now = Utc::now();
+        let job = Job {
+            id: uuid::Uuid::new_v4().to_string(),
+            kind,
+            status: JobStatus::Queued,
+            done: 0,
+            total: 0,
+            attempts: 0,
+            errors: Vec::new(),
+            error: None,
+            retry_at: None,
+            created_at: now,
+            updated_at: now,
+        };
+        let mut jobs = self.jobs.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        jobs.push(job.clone());
+        self.write(&jobs)?;
+        drop(jobs);
+        info!("Queued the job {} to {:?}", job.id, job.kind);
+        self.publish(&job);
+        Ok(job)
+    }
+
+    /// Cancels a queued job right away and a running one once it is
+    /// done with the current item.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::NotFound` for unknown jobs and with
+    /// `ErrorKind::BadRequest` for finished ones.
+    pub fn cancel(&self, id: &str) -> Result<Job, NasError> {
+        // held throughout, so that the worker can't start the job in between
+        let mut jobs = self.jobs.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let job = jobs.iter_mut().find(|job| job.id == id).ok_or_else(|| job_not_found(id))?;
+        match job.status {
+            JobStatus::Queued => {
+                job.status = JobStatus::Cancelled;
+                job.retry_at = None;
+                job.updated_at = Utc::now();
+                let job = job.clone();
+                self.write(&jobs)?;
+                drop(jobs);
+                self.publish(&job);
+                Ok(job)
+            },
+            JobStatus::Running => {
+                if let Some(flag) = self.cancel.lock().map_err(|_| ErrorKind::FailedToAquireLock)?.get(id) { flag.store(true, Ordering::Relaxed); }
+                info!("The job {} stops after its current item", id);
+                Ok(job.clone())
+            },
+            status => Err(NasError::new(ErrorKind::BadRequest, format!("The job {} is already {}", id, format!("{:?}", status).to_lowercase()))),
+        }
+    }
+
+    /// Takes the oldest job that is due and marks it as running.
+    pub fn start_next(&self) -> Result<Option<Running>, NasError> {
+        let now = Utc::now();
+        let mut jobs = self.jobs.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let Some(job) = jobs.iter_mut().find(|job| job.status == JobStatus::Queued && job.retry_at.is_none_or(|at| at <= now)) else {
+            return Ok(None);
+        };
+        job.status = JobStatus::Running;
+        job.attempts += 1;
+        (job.done, job.total) = (0, 0);
+        job.errors.clear();
+        job.retry_at = None;
+        job.updated_at = now;
+        let job = job.clone();
+        self.write(&jobs)?;
+        // before the jobs are let go, a running job can always be cancelled
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.cancel.lock().map_err(|_| ErrorKind::FailedToAquireLock)?.insert(job.id.clone(), cancelled.clone());
+        drop(jobs);
+        self.publish(&job);
+        Ok(Some(Running { job, cancelled }))
+    }
+
+    /// When the next job that failed before is due.
+    pub fn next_retry(&self) -> Result<Option<DateTime<Utc>>, NasError> {
+        let jobs = self.jobs.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        Ok(jobs.iter().filter(|job| job.status == JobStatus::Queued).filter_map(|job| job.retry_at).min())
+    }
+
+    pub fn set_total(&self, id: &str, total: usize) -> Result<Job, NasError> {
+        self.update(id, false, |job| job.total = total)
+    }
+
+    /// An item of the job is done, or failed with `error`.
+    pub fn step(&self, id: &str, error: Option<&NasError>) -> Result<Job, NasError> {
+        self.update(id, false, |job| {
+            job.done += 1;
+            if let Some(e) = error { job.errors.push(e.to_string()); }
+        })
+    }
+
+    /// Ends the attempt of a running job. A job that failed for a
+    /// transient reason is queued again, unless it ran out of attempts.
+    pub fn finish(&self, running: &Running, result: Result<(), NasError>) -> Result<Job, NasError> {
+        self.cancel.lock().map_err(|_| ErrorKind::FailedToAquireLock)?.remove(&running.job.id);
+        let job = self.update(&running.job.id, true, |job| {
+            job.error = result.as_ref().err().map(ToString::to_string);
+            job.status = match &result {
+                _ if running.is_cancelled() => JobStatus::Cancelled,
+                Ok(()) => JobStatus::Succeeded,
+                Err(e) if is_transient(e.kind()) && job.attempts < MAX_ATTEMPTS => {
+                    let delay = RETRY_DELAY * 2u32.pow(job.attempts - 1);
+                    job.retry_at = Some(Utc::now() + delay);
+                    JobStatus::Queued
+                },
+                Err(_) => JobStatus::Failed,
+            };
+        })?;
+        match (job.status, &job.error) {
+            (JobStatus::Queued, Some(e)) => { warn!("The job {} is tried again at {}: {}", job.id, job.retry_at.unwrap_or_default(), e); },
+            (JobStatus::Failed, Some(e)) => { error!("The job {} failed after {} attempts: {}", job.id, job.attempts, e); },
+            (status, _) => { info!("The job {} is {:?}", job.id, status); },
+        };
+        self.forget_finished()?;
+        Ok(job)
+    }
+
+    /// Drops the oldest finished jobs beyond `FINISHED_COUNT`.
+    fn forget_finished(&self) -> Result<(), NasError> {
+        let mut jobs = self.jobs.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let finished = jobs.iter().filter(|job| job.status.is_finished()).count();
+        if finished <= FINISHED_COUNT { return Ok(()); }
+        let mut excess = finished - FINISHED_COUNT;
+        jobs.retain(|job| {
+            let forget = excess > 0 && job.status.is_finished();
+            if forget { excess -= 1; }
+            !forget
+        });
+        self.write(&jobs)
+    }
+}
+
+/// The query of `POST /optimize_images_server`, e.g. `?force=true`.
+#[derive(Deserialize, Debug, Default)]
+pub struct OptimizeQuery {
+    #[serde(default)]
+    pub force: bool,
+}
+
+pub fn job_not_found(id: &str) -> NasError {
+    NasError::new(ErrorKind::NotFound, format!("There is no job with the id {}", id))
+}
+
+/// The games of the library that `games` names, by id or by name.
+/// Without any names every game is meant.
+fn resolve_games(library: Vec<Game>, games: &[String]) -> (Vec<Game>, Vec<String>) {
+    if games.is_empty() { return (library, Vec::new()); }
+    let (mut found, mut unknown): (Vec<Game>, Vec<String>) = (Vec::new(), Vec::new());
+    for wanted in games {
+        let game = library.iter().find(|game| game.id() == wanted || game.name() == wanted)
+            .or_else(|| library.iter().find(|game| game.name().eq_ignore_ascii_case(wanted)));
+        match game {
+            Some(game) if !found.iter().any(|known| known.id() == game.id()) => found.push(game.clone()),
+            Some(_) => (),
+            None => unknown.push(wanted.clone()),
+        }
+    }
+    (found, unknown)
+}
+
+async fn download_images(worker: &Worker, running: &Running, games: &[String], kinds: &[ArtworkKind], settings: &ServerSettings) -> Result<(), NasError> {
+    let (games, unknown) = resolve_games(worker.store.games()?, games);
+    let kinds = if kinds.is_empty() { &ArtworkKind::ALL[..] } else { kinds };
+    let mut missing = Vec::new();
+    for game in games {
+        for kind in kinds {
+            // nothing to fetch, the game would count as missing forever
+            if !has_artwork_source(&game, *kind) || worker.artwork.get(&game, *kind)?.is_some() { continue; }
+            // only grids were fetched before the manifest, adopting one moves its file
+            if *kind == ArtworkKind::Grids {
+                let (artwork, legacy) = (worker.artwork.clone(), game.clone());
+                if web::block(move || artwork.adopt_legacy(&legacy)).await??.is_some() { continue; }
+            }
+            missing.push((game.clone(), *kind));
+        }
+    }
+    info!("This artwork will be fetched: {:?}", missing.iter().map(|(game, kind)| format!("{} of {}", kind.as_str(), game.name())).collect::<Vec<_>>());
+    worker.queue.set_total(&running.job.id, missing.len() + unknown.len())?;
+
+    let mut failed = Vec::new();
+    for name in unknown {
+        let e = NasError::new(ErrorKind::NotFound, format!("There is no game called {:?} in the library", name));
+        failed.push(e.kind());
+        worker.queue.step(&running.job.id, Some(&e))?;
+    }
+    let failed = Mutex::new(failed);
+    stream::iter(missing)
+        .for_each_concurrent(Some(settings.download_concurrency), |(game, kind)| {
+            let failed = &failed;
+            async move {
+                if running.is_cancelled() { return; }
+                let result = match fetch_artwork(&game, kind).await {
+                    Ok(images) => {
+                        let (artwork, store, game) = (worker.artwork.clone(), worker.store.clone(), game.clone());
+                        // writing the images mustn't hold up the requests of this thread
+                        web::block(move || save_images(&artwork, &*store, &game, kind, images)).await
+                            .map_err(NasError::from)
+                            .and_then(|result| result)
+                    },
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = &result {
+                    error!("Failed to fetch the {} of {}: {}", kind.as_str(), game.name(), e);
+                    if let Ok(mut failed) = failed.lock() { failed.push(e.kind()); }
+                }
+                let _ = worker.queue.step(&running.job.id, result.as_ref().err());
+            }
+        }).await;
+
+    let failed = failed.into_inner().unwrap_or_default();
+    match failed.iter().find(|kind| is_transient(**kind)).or(failed.first()) {
+        Some(kind) => Err(NasError::new(*kind, format!("Failed to get the images of {} games", failed.len()))),
+        None => Ok(()),
+    }
+}
+
+/// Saves the artwork of `game` and remembers which SteamGridDB game it
+/// belongs to, so that the next fetch doesn't have to search again.
+fn save_images(artwork: &ArtworkStore, store: &dyn LibraryStore, game: &Game, kind: ArtworkKind, images: Vec<FetchedImage>) -> Result<(), NasError> {
+    let steam_grid_id = images.iter().find_map(|image| image.steam_grid_id.clone());
+    let entries = artwork.save(game.id(), kind, images)?;
+    info!("The {} of {:?} saved as {:?}", kind.as_str(), game.name(), entries.iter().map(|entry| &entry.file).collect::<Vec<_>>());
+    if let (None, Some(steam_grid_id)) = (game.steam_grid_id(), steam_grid_id) {
+        store.update_game(game.id(), GamePatch { steam_grid_id: Some(Some(steam_grid_id)), ..GamePatch::default() })?;
+    }
+    Ok(())
+}
+
+async fn optimize_images(worker: &Worker, running: &Running, force: bool, settings: &ServerSettings) -> Result<(), NasError> {
+    let (queue, dirs) = (&worker.queue, &worker.dirs);
+    let entries = worker.artwork.entries()?;
+    info!("Optimizing {} images at: {:?} -> {:?}", entries.len(), dirs.images(), dirs.optimized_images());
+    queue.set_total(&running.job.id, entries.len())?;
+
+    let mut failed = 0;
+    for entry in entries {
+        if running.is_cancelled() { break; }
+        let (artwork, settings, dir_out) = (worker.artwork.clone(), settings.clone(), dirs.optimized_images().to_path_buf());
+        // encoding takes a while, it mustn't hold up the requests of this thread
+        let result = web::block(move || optimize_artwork(&artwork, &entry, &settings, &dir_out, (None, None), force)).await
+            .map_err(NasError::from)
+            .and_then(|result| result);
+        if let Err(e) = &result {
+            error!("{}", e);
+            failed += 1;
+        }
+        queue.step(&running.job.id, result.as_ref().err())?;
+    }
+    match failed {
+        0 => Ok(()),
+        n => Err(NasError::new(ErrorKind::FailedToEncode, format!("Failed to optimize {} images", n))),
+    }
+}
+
+/// What the jobs work with.
+pub struct Worker {
+    pub queue: web::Data<JobQueue>,
+    pub settings: web::Data<Live<ServerSettings>>,
+    pub store: Arc<dyn LibraryStore>,
+    pub artwork: web::Data<ArtworkStore>,
+    pub dirs: DataDir,
+}
+
+impl Worker {
+    /// Runs the queued jobs one after the other on the current actix
+    /// runtime, for as long as the runtime lives.
+    pub fn spawn(self) -> Result<(), NasError> {
+        let (queue, settings) = (self.queue.clone(), self.settings.clone());
+        let mut changes = queue.subscribe()?;
+        actix_web::rt::spawn(async move {
+            let mut backoff = WORKER_BACKOFF;
+            loop {
+                let running = match queue.start_next() {
+                    Ok(running) => running,
+                    Err(e) => {
+                        // e.g. the disk is full for a moment, the queue is tried again
+                        error!("Failed to start the next job, trying again in {:?}: {}", backoff, e);
+                        actix_web::rt::time::sleep(backoff).await;
+                        backoff = (backoff * 2).min(MAX_WORKER_BACKOFF);
+                        continue;
+                    },
+                };
+                backoff = WORKER_BACKOFF;
+                if let Some(running) = running {
+                    trace!("Running the job {}, attempt {}", running.job.id, running.job.attempts);
+                    // the settings of the moment the attempt starts
+                    let settings = settings.get();
+                    let result = match &running.job.kind {
+                        JobKind::DownloadImages { games, kinds } => download_images(&self, &running, games, kinds, &settings).await,
+                        JobKind::OptimizeImages { force } => optimize_images(&self, &running, *force, &settings).await,
+                    };
+                    if let Err(e) = queue.finish(&running, result) { error!("Failed to finish the job {}: {}", running.job.id, e); }
+                    continue;
+                }
+
+                // wait for a new job, or for the next retry
+                let retry = queue.next_retry().ok().flatten().map(|at| (at - Utc::now()).to_std().unwrap_or_default());
+                let changed = match retry {
+                    Some(wait) => !matches!(future::select(changes.next(), Box::pin(actix_web::rt::time::sleep(wait))).await, future::Either::Left((None, _))),
+                    None => changes.next().await.is_some(),
+                };
+                if !changed { return; }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_are_retried_cancelled_and_resumed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("jobs.json");
+        let queue = JobQueue::open(path.clone()).unwrap();
+        let download = queue.enqueue(JobKind::DownloadImages { games: vec!["Celeste".to_owned()], kinds: Vec::new() }).unwrap();
+        let optimize = queue.enqueue(JobKind::OptimizeImages { force: false }).unwrap();
+        let mut changes = queue.subscribe().unwrap();
+
+        // a failed fetch is tried again later
+        let running = queue.start_next().unwrap().unwrap();
+        assert_eq!(running.job.id, download.id);
+        queue.set_total(&download.id, 1).unwrap();
+        let error = NasError::new(ErrorKind::FailedToFetch, "SteamGridDB didn't answer");
+        assert_eq!(queue.step(&download.id, Some(&error)).unwrap().errors.len(), 1);
+        let job = queue.finish(&running, Err(error)).unwrap();
+        assert_eq!((job.status, job.attempts), (JobStatus::Queued, 1));
+        assert!(job.retry_at.is_some() && queue.next_retry().unwrap() == job.retry_at);
+
+        // while it waits the next job runs, and is cancelled
+        let running = queue.start_next().unwrap().unwrap();
+        assert_eq!(running.job.id, optimize.id);
+        assert_eq!(queue.cancel(&optimize.id).unwrap().status, JobStatus::Running);
+        assert!(running.is_cancelled());
+        assert_eq!(queue.finish(&running, Ok(())).unwrap().status, JobStatus::Cancelled);
+        assert!(queue.cancel(&optimize.id).is_err());
+        assert!(queue.start_next().unwrap().is_none());
+
+        let seen: Vec<_> = std::iter::from_fn(|| changes.try_recv().ok()).map(|job| (job.id, job.status)).collect();
+        assert_eq!(seen.last(), Some(&(optimize.id.clone(), JobStatus::Cancelled)));
+        assert_eq!(seen.iter().filter(|(id, _)| *id == download.id).count(), 4);
+
+        // a job that was running when the server stopped runs again
+        queue.update(&download.id, true, |job| { job.status = JobStatus::Running; }).unwrap();
+        let reopened = JobQueue::open(path).unwrap();
+        assert_eq!(reopened.job(&download.id).unwrap().unwrap().status, JobStatus::Queued);
+        assert_eq!(reopened.job(&optimize.id).unwrap().unwrap().status, JobStatus::Cancelled);
+    }
+}
@@ -0,0 +1,552 @@
+//! This crate is for starting games on the client. Every game can
+//! have a launch config in `<client dir>/launch/<game id>.json`
+//! which overrides or extends the command the server suggests, e.g.
+//!
+//! ```json
+//! {
+//!     "executable": "/games/Hades/Hades.exe",
+//!     "args": ["-nointro"],
+//!     "env": { "DXVK_HUD": "fps" },
+//!     "pre_launch": ["mount /mnt/games"],
+//!     "post_exit": ["notify-send \"played $NAS_GAME_ID\""],
+//!     "compat": { "runner": "wine", "prefix": "/home/user/.wine-hades" }
+//! }
+//! ```
+//!
+//! Started games are supervised, once they exit their exit code and
+//! runtime are appended to `<client dir>/launch_history.jsonl` and
+//! sent to the server as a `PlaySession`. Sessions the server can't
+//! be reached for wait in `<client dir>/pending_sessions.jsonl`.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, error, logging::LoggingLevel, logging::logging_function};
+use crate::client::{self, ClientSettings};
+use crate::stats::PlaySession;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::Instant;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+const LAUNCH_CONFIG_DIR: &str = "launch";
+const LAUNCH_HISTORY_PATH: &str = "launch_history.jsonl";
+const PENDING_SESSIONS_PATH: &str = "pending_sessions.jsonl";
+
+/// How a single game is started on this client. Every field is
+/// optional, an empty config starts the game just like the server
+/// suggests.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct LaunchConfig {
+    /// Replaces the program the server suggests, together with its
+    /// arguments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executable: Option<PathBuf>,
+    /// Appended to the arguments of the program.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
+    /// Shell commands that are run before the game, should any of
+    /// them fail the game is not started.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_launch: Vec<String>,
+    /// Shell commands that are run after the game exited.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_exit: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compat: Option<CompatLayer>,
+}
+
+/// Runs Windows games through Wine or Proton.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "runner", rename_all = "lowercase")]
+pub enum CompatLayer {
+    Wine {
+        prefix: PathBuf,
+        /// The wine binary, defaults to `wine` in the `PATH`.
+        #[serde(default)]
+        binary: Option<PathBuf>,
+    },
+    Proton {
+        prefix: PathBuf,
+        /// The directory of the Proton version, which contains the
+        /// `proton` script.
+        proton: PathBuf,
+    },
+}
+
+/// The command the server suggests, as returned by
+/// `GET /games/{id}/launch_command`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ServerLaunchCommand {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub working_dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct ServerLaunchResponse { command: ServerLaunchCommand }
+
+/// Everything that is needed to start a game, see `plan()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchPlan {
+    pub game_id: String,
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub working_dir: Option<PathBuf>,
+    pub pre_launch: Vec<String>,
+    pub post_exit: Vec<String>,
+}
+
+/// One run of a game, as written to the launch history.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LaunchRecord {
+    pub game_id: String,
+    /// RFC 3339 timestamps.
+    pub started_at: String,
+    pub ended_at: String,
+    pub runtime_secs: u64,
+    /// `None` if the game was killed by a signal.
+    pub exit_code: Option<i32>,
+}
+
+impl LaunchRecord {
+    /// The run as a play session of this client.
+    pub fn to_session(&self, settings: &ClientSettings) -> Result<PlaySession, NasError> {
+        let parse = |time: &str| chrono::DateTime::parse_from_rfc3339(time)
+            .map(|time| time.with_timezone(&chrono::Utc))
+            .context(ErrorKind::FailedToParse, format!("{:?} is not a valid timestamp", time));
+        Ok(PlaySession {
+            id: String::new(),
+            game_id: self.game_id.clone(),
+            client_id: settings.client_id.clone(),
+            user: settings.user.clone(),
+            started_at: parse(&self.started_at)?,
+            ended_at: parse(&self.ended_at)?,
+            exit_status: self.exit_code,
+        })
+    }
+}
+
+/// A game that has been started but not yet waited for.
+pub struct RunningGame {
+    plan: LaunchPlan,
+    child: Child,
+    started_at: chrono::DateTime<chrono::Utc>,
+    started: Instant,
+    history: PathBuf,
+}
+
+fn config_path(dir: &Path, game_id: &str) -> PathBuf {
+    dir.join(LAUNCH_CONFIG_DIR).join(format!("{}.json", game_id))
+}
+
+/// Reads the launch config of a game from `dir`, a missing config
+/// is the same as an empty one.
+///
+/// # Errors
+/// Fails with `ErrorKind::FailedToParse` if the config is broken.
+pub fn load_config(dir: &Path, game_id: &str) -> Result<LaunchConfig, NasError> {
+    let path = config_path(dir, game_id);
+    match fs::read_to_string(&path) {
+        Ok(s) => serde_json::from_str(&s).context(ErrorKind::FailedToParse, format!("Failed to parse the launch config {:?}", path)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(LaunchConfig::default()),
+        Err(e) => Err(NasError::new(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path)).with_source(e)),
+    }
+}
+
+pub fn save_config(dir: &Path, game_id: &str, config: &LaunchConfig) -> Result<(), NasError> {
+    let path = config_path(dir, game_id);
+    fs::create_dir_all(dir.join(LAUNCH_CONFIG_DIR)).context(ErrorKind::FailedToCreateFolder, format!("Failed to create {:?}", dir))?;
+    let json = serde_json::to_string_pretty(config).context(ErrorKind::FailedToSerialize, "Failed to serialize the launch config")?;
+    fs::write(&path, json).context(ErrorKind::FailedToWrite, format!("Failed to write {:?}", path))
+}
+
+/// Asks the server how the game is started.
+pub async fn fetch_launch_command(server_url: &str, game_id: &str) -> Result<ServerLaunchCommand, NasError> {
+    let url = format!("{}/games/{}/launch_command", server_url.trim_end_matches('/'), game_id);
+    let res = client::request(&client::http()?, reqwest::Method::GET, &url, Vec::new())?.send().await
+        .context(ErrorKind::FailedToFetch, format!("Failed to request {}", url))?;
+    let status = res.status();
+    let body = res.text().await.context(ErrorKind::FailedToFetch, format!("Failed to read the response of {}", url))?;
+    if !status.is_success() {
+        let kind = match status {
+            reqwest::StatusCode::NOT_FOUND => ErrorKind::NotFound,
+            reqwest::StatusCode::UNAUTHORIZED => ErrorKind::Unauthorized,
+            reqwest::StatusCode::FORBIDDEN => ErrorKind::Forbidden,
+            _ => ErrorKind::FailedToFetch,
+        };
+        return Err(NasError::new(kind, format!("The server can't start {}: {}", game_id, body)));
+    }
+    let res: ServerLaunchResponse = serde_json::from_str(&body).context(ErrorKind::FailedToParse, format!("Failed to parse the response of {}", url))?;
+    Ok(res.command)
+}
+
+/// Combines the launch config with the command of the server.
+///
+/// # Errors
+/// Fails with `ErrorKind::NotFound` if neither the config nor the
+/// server name a program.
+pub fn plan(game_id: &str, config: &LaunchConfig, server: Option<ServerLaunchCommand>) -> Result<LaunchPlan, NasError> {
+    let (program, mut args, working_dir) = match (&config.executable, server) {
+        (Some(exe), _) => (exe.clone(), Vec::new(), exe.parent().filter(|p| !p.as_os_str().is_empty()).map(Path::to_path_buf)),
+        (None, Some(cmd)) => (PathBuf::from(cmd.program), cmd.args, cmd.working_dir),
+        (None, None) => return Err(NasError::new(ErrorKind::NotFound, format!("There is no executable to start {} with", game_id))),
+    };
+    args.extend(config.args.iter().cloned());
+
+    let mut env = config.env.clone();
+    env.insert("NAS_GAME_ID".to_owned(), game_id.to_owned());
+    let (program, args) = match &config.compat {
+        None => (program, args),
+        Some(CompatLayer::Wine { prefix, binary }) => {
+            env.entry("WINEPREFIX".to_owned()).or_insert_with(|| prefix.to_string_lossy().into_owned());
+            let wine = binary.clone().unwrap_or_else(|| PathBuf::from("wine"));
+            (wine, std::iter::once(program.to_string_lossy().into_owned()).chain(args).collect())
+        },
+        Some(CompatLayer::Proton { prefix, proton }) => {
+            env.entry("STEAM_COMPAT_DATA_PATH".to_owned()).or_insert_with(|| prefix.to_string_lossy().into_owned());
+            if let Ok(home) = std::env::var("HOME") {
+                env.entry("STEAM_COMPAT_CLIENT_INSTALL_PATH".to_owned()).or_insert_with(|| format!("{}/.steam/steam", home));
+            }
+            let run = ["run".to_owned(), program.to_string_lossy().into_owned()];
+            (proton.join("proton"), run.into_iter().chain(args).collect())
+        },
+    };
+
+    Ok(LaunchPlan {
+        game_id: game_id.to_owned(),
+        program,
+        args,
+        env,
+        working_dir: config.working_dir.clone().or(working_dir),
+        pre_launch: config.pre_launch.clone(),
+        post_exit: config.post_exit.clone(),
+    })
+}
+
+/// Loads the launch config of the game and asks the server for the
+/// command.
+///
+/// The server is asked even if the config names an executable, since
+/// it decides whether the user may play the game at all. Only when
+/// the server has no command of its own, e.g. because the game isn't
+/// installed there, the config is used on its own.
+pub async fn prepare(game_id: &str) -> Result<LaunchPlan, NasError> {
+    let config = load_config(&client::client_dir(), game_id)?;
+    let server = match fetch_launch_command(&client::get_client_settings()?.server_url, game_id).await {
+        Ok(command) => Some(command),
+        Err(e) if e.kind() == ErrorKind::NotFound && config.executable.is_some() => {
+            trace!("Starting {} as configured, the server has no command for it: {}", game_id, e);
+            None
+        },
+        Err(e) => return Err(e),
+    };
+    plan(game_id, &config, server)
+}
+
+fn shell(hook: &str) -> Command {
+    #[cfg(windows)]
+    { let mut cmd = Command::new("cmd"); cmd.args(["/C", hook]); cmd }
+    #[cfg(not(windows))]
+    { let mut cmd = Command::new("sh"); cmd.args(["-c", hook]); cmd }
+}
+
+/// Runs a hook with the environment of the game and waits for it.
+fn run_hook(hook: &str, plan: &LaunchPlan, extra_env: &[(&str, String)]) -> Result<(), NasError> {
+    trace!("Running the hook {:?} of {}", hook, plan.game_id);
+    let mut cmd = shell(hook);
+    cmd.envs(&plan.env).envs(extra_env.iter().map(|(k, v)| (*k, v)));
+    if let Some(dir) = &plan.working_dir { cmd.current_dir(dir); }
+    let status = cmd.status().context(ErrorKind::FailedToLaunch, format!("Failed to run the hook {:?}", hook))?;
+    if !status.success() {
+        return Err(NasError::new(ErrorKind::FailedToLaunch, format!("The hook {:?} failed with {}", hook, status)));
+    }
+    Ok(())
+}
+
+/// Runs the pre launch hooks and starts the game, with the launch
+/// history in `history`.
+///
+/// # Errors
+/// Fails with `ErrorKind::FailedToLaunch` if a hook fails or the game
+/// can't be started.
+pub fn start(plan: LaunchPlan, history: PathBuf) -> Result<RunningGame, NasError> {
+    for hook in &plan.pre_launch {
+        run_hook(hook, &plan, &[])?;
+    }
+    let mut cmd = Command::new(&plan.program);
+    cmd.args(&plan.args).envs(&plan.env);
+    if let Some(dir) = &plan.working_dir { cmd.current_dir(dir); }
+    let child = cmd.spawn().context(ErrorKind::FailedToLaunch, format!("Failed to start {:?}", plan.program))?;
+    info!("Started {} as {:?} with the pid {}", plan.game_id, plan.program, child.id());
+    Ok(RunningGame { plan, child, started_at: chrono::Utc::now(), started: Instant::now(), history })
+}
+
+impl RunningGame {
+    pub fn pid(&self) -> u32 { self.child.id() }
+
+    /// Waits for the game to exit, runs the post exit hooks and appends
+    /// the run to the launch history.
+    ///
+    /// # Errors
+    /// Failing hooks and a history that can't be written are only
+    /// logged, only waiting for the game itself can fail.
+    pub fn wait(mut self) -> Result<LaunchRecord, NasError> {
+        let status = self.child.wait().context(ErrorKind::FailedToLaunch, format!("Failed to wait for {}", self.plan.game_id))?;
+        let record = LaunchRecord {
+            game_id: self.plan.game_id.clone(),
+            started_at: self.started_at.to_rfc3339(),
+            ended_at: chrono::Utc::now().to_rfc3339(),
+            runtime_secs: self.started.elapsed().as_secs(),
+            exit_code: status.code(),
+        };
+        info!("{} exited with {} after {}s", record.game_id, status, record.runtime_secs);
+
+        let exit_code = record.exit_code.map(|c| c.to_string()).unwrap_or_default();
+        for hook in &self.plan.post_exit {
+            if let Err(e) = run_hook(hook, &self.plan, &[("NAS_GAME_EXIT_CODE", exit_code.clone())]) {
+                warn!("{}", e);
+            }
+        }
+        if let Err(e) = append_history(&self.history, &record) {
+            error!("Failed to record the run of {}: {}", record.game_id, e);
+        }
+        Ok(record)
+    }
+}
+
+fn append_history(path: &Path, record: &impl Serialize) -> Result<(), NasError> {
+    let line = serde_json::to_string(record).context(ErrorKind::FailedToSerialize, "Failed to serialize the launch record")?;
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)
+        .context(ErrorKind::FailedToWrite, format!("Failed to open {:?}", path))?;
+    writeln!(file, "{}", line).context(ErrorKind::FailedToWrite, format!("Failed to write {:?}", path))
+}
+
+fn read_lines<T: DeserializeOwned>(path: &Path) -> Vec<T> {
+    fs::read_to_string(path).unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Reads every recorded run, broken lines are skipped.
+pub fn read_history(path: &Path) -> Vec<LaunchRecord> {
+    read_lines(path)
+}
+
+/// Sends a play session to the server, after every session in
+/// `pending` that couldn't be sent before. Sessions the server
+/// rejects as invalid, e.g. because their game has been removed, are
+/// dropped.
+///
+/// Sessions are only ever appended to `pending`, the queue is taken
+/// over by renaming the file. Should another process send the queue
+/// at the same time, e.g. the window and `client launch`, then the
+/// session is left to that process and nothing is sent.
+///
+/// Returns how many sessions the server accepted.
+///
+/// # Errors
+/// Fails with `ErrorKind::FailedToFetch` if the server can't be
+/// reached or doesn't take the session for now, e.g. since the user
+/// has to log in again. The session is then kept in `pending` for the
+/// next time.
+pub async fn report_session(server_url: &str, session: PlaySession, pending: &Path) -> Result<usize, NasError> {
+    let http = client::http()?;
+    let url = format!("{}/sessions", server_url.trim_end_matches('/'));
+    append_history(pending, &session)?;
+    let mut taken = pending.as_os_str().to_owned();
+    taken.push(format!(".{}", uuid::Uuid::new_v4()));
+    let taken = PathBuf::from(taken);
+    match fs::rename(pending, &taken) {
+        Ok(()) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            trace!("Another process is sending the sessions of {:?}", pending);
+            return Ok(0);
+        },
+        Err(e) => return Err(NasError::new(ErrorKind::FailedToWrite, format!("Failed to take over {:?}", pending)).with_source(e)),
+    }
+    let queue: Vec<PlaySession> = read_lines(&taken);
+
+    let mut sent = 0;
+    let mut unsent = Vec::new();
+    let mut last_error = None;
+    for session in queue {
+        if last_error.is_some() {
+            unsent.push(session);
+            continue;
+        }
+        let body = serde_json::to_vec(&session).context(ErrorKind::FailedToSerialize, "Failed to serialize the play session")?;
+        let res = client::request(&http, reqwest::Method::POST, &url, body)?.send().await;
+        match res {
+            Ok(res) if res.status().is_success() => sent += 1,
+            // only sessions that will never be accepted are dropped, not
+            // those of a user who has to log in again or of a busy server
+            Ok(res) if matches!(res.status(), reqwest::StatusCode::BAD_REQUEST | reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::UNPROCESSABLE_ENTITY) => {
+                warn!("The server rejected the session of {} with {}, dropping it", session.game_id, res.status());
+            },
+            Ok(res) => {
+                last_error = Some(NasError::new(ErrorKind::FailedToFetch, format!("{} failed with {}", url, res.status())));
+                unsent.push(session);
+            },
+            Err(e) => {
+                last_error = Some(NasError::new(ErrorKind::FailedToFetch, format!("Failed to request {}", url)).with_source(e));
+                unsent.push(session);
+            },
+        }
+    }
+
+    for session in &unsent {
+        append_history(pending, session)?;
+    }
+    let _ = fs::remove_file(&taken);
+    match last_error {
+        Some(e) => Err(e),
+        None => Ok(sent),
+    }
+}
+
+/// Sends the run to the server with the settings of this client.
+/// Failures are only logged, see `report_session()`.
+pub fn report_blocking(record: &LaunchRecord) {
+    let report = client::get_client_settings().and_then(|mut settings| {
+        // the session has to name the client it was played on
+        if settings.client_id.is_empty() { client::save_client_settings(&mut settings)?; }
+        let session = record.to_session(&settings)?;
+        let pending = client::client_dir().join(PENDING_SESSIONS_PATH);
+        actix_web::rt::System::new().block_on(report_session(&settings.server_url, session, &pending))
+    });
+    match report {
+        Ok(sent) => { trace!("Sent {} play sessions to the server", sent); },
+        Err(e) => { warn!("Failed to send the play session of {} to the server: {}", record.game_id, e); },
+    }
+}
+
+/// The launch history of this client.
+pub fn history_path() -> PathBuf {
+    client::client_dir().join(LAUNCH_HISTORY_PATH)
+}
+
+/// Prepares, starts and waits for a game, which is what
+/// `nas-game client launch <id>` does.
+pub fn launch_blocking(game_id: &str) -> Result<LaunchRecord, NasError> {
+    let plan = actix_web::rt::System::new().block_on(prepare(game_id))?;
+    let record = start(plan, history_path())?.wait()?;
+    report_blocking(&record);
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_command() -> ServerLaunchCommand {
+        ServerLaunchCommand { program: "/games/Hades/Hades.exe".to_owned(), args: vec!["-a".to_owned()], working_dir: Some("/games/Hades".into()) }
+    }
+
+    #[test]
+    fn plans_with_and_without_compat_layers() {
+        let mut config = LaunchConfig { args: vec!["-b".to_owned()], ..LaunchConfig::default() };
+        let plan = plan("1", &config, Some(server_command())).unwrap();
+        assert_eq!((plan.program.as_path(), plan.args.as_slice()), (Path::new("/games/Hades/Hades.exe"), ["-a".to_owned(), "-b".to_owned()].as_slice()));
+        assert_eq!(plan.env.get("NAS_GAME_ID").map(String::as_str), Some("1"));
+
+        config.compat = Some(CompatLayer::Wine { prefix: "/prefix".into(), binary: None });
+        let plan = super::plan("1", &config, Some(server_command())).unwrap();
+        assert_eq!(plan.program, PathBuf::from("wine"));
+        assert_eq!(plan.args, ["/games/Hades/Hades.exe", "-a", "-b"]);
+        assert_eq!(plan.env.get("WINEPREFIX").map(String::as_str), Some("/prefix"));
+
+        config.compat = Some(CompatLayer::Proton { prefix: "/prefix".into(), proton: "/proton".into() });
+        config.executable = Some("/other/game.exe".into());
+        let plan = super::plan("1", &config, None).unwrap();
+        assert_eq!(plan.program, PathBuf::from("/proton/proton"));
+        assert_eq!(plan.args, ["run", "/other/game.exe", "-b"]);
+        assert_eq!(plan.working_dir, Some(PathBuf::from("/other")));
+
+        assert!(super::plan("1", &LaunchConfig::default(), None).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn supervises_games_and_runs_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = LaunchConfig {
+            executable: Some("sh".into()),
+            args: vec!["-c".to_owned(), "exit 3".to_owned()],
+            working_dir: Some(dir.path().to_path_buf()),
+            pre_launch: vec!["touch pre".to_owned()],
+            post_exit: vec!["echo $NAS_GAME_EXIT_CODE > post".to_owned()],
+            ..LaunchConfig::default()
+        };
+        save_config(dir.path(), "42", &config).unwrap();
+        assert_eq!(load_config(dir.path(), "42").unwrap(), config);
+
+        let history = dir.path().join(LAUNCH_HISTORY_PATH);
+        let plan = plan("42", &config, None).unwrap();
+        let record = start(plan, history.clone()).unwrap().wait().unwrap();
+        assert_eq!(record.exit_code, Some(3));
+        assert!(dir.path().join("pre").exists());
+        assert_eq!(fs::read_to_string(dir.path().join("post")).unwrap().trim(), "3");
+        assert_eq!(read_history(&history), [record]);
+
+        // a failing pre launch hook keeps the game from starting
+        let config = LaunchConfig { pre_launch: vec!["exit 1".to_owned()], ..config };
+        assert!(start(super::plan("42", &config, None).unwrap(), history).is_err());
+    }
+
+    #[actix_web::test]
+    async fn keeps_sessions_until_the_server_is_reachable() {
+        use actix_web::{web, App, HttpResponse, HttpServer};
+        use std::sync::{Mutex, atomic::{AtomicBool, Ordering}};
+
+        let dir = tempfile::tempdir().unwrap();
+        let pending = dir.path().join(PENDING_SESSIONS_PATH);
+        let settings = ClientSettings { client_id: "desktop".to_owned(), user: "anna".to_owned(), ..ClientSettings::default() };
+        let record = |game_id: &str| LaunchRecord {
+            game_id: game_id.to_owned(),
+            started_at: "2026-10-18T20:00:00+00:00".to_owned(),
+            ended_at: "2026-10-18T21:30:00+00:00".to_owned(),
+            runtime_secs: 5400,
+            exit_code: Some(0),
+        };
+
+        // nothing listens on port 9
+        assert!(report_session("http://127.0.0.1:9", record("1").to_session(&settings).unwrap(), &pending).await.is_err());
+        assert_eq!(read_lines::<PlaySession>(&pending).len(), 1);
+
+        let received = web::Data::new(Mutex::new(Vec::<PlaySession>::new()));
+        let logged_out = web::Data::new(AtomicBool::new(true));
+        let (data, logged) = (received.clone(), logged_out.clone());
+        let server = HttpServer::new(move || App::new().app_data(data.clone()).app_data(logged.clone()).route("/sessions", web::post().to(
+                |received: web::Data<Mutex<Vec<PlaySession>>>, logged_out: web::Data<AtomicBool>, session: web::Json<PlaySession>| async move {
+                    if logged_out.load(Ordering::SeqCst) { return HttpResponse::Unauthorized().finish(); }
+                    if session.game_id == "removed" { return HttpResponse::NotFound().finish(); }
+                    received.lock().unwrap().push(session.into_inner());
+                    HttpResponse::Created().finish()
+                })))
+            .bind(("127.0.0.1", 0)).unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        // the sessions wait for the user to log in again
+        append_history(&pending, &record("removed").to_session(&settings).unwrap()).unwrap();
+        assert!(report_session(&url, record("2").to_session(&settings).unwrap(), &pending).await.is_err());
+        assert_eq!(read_lines::<PlaySession>(&pending).len(), 3);
+
+        logged_out.store(false, Ordering::SeqCst);
+        assert_eq!(report_session(&url, record("3").to_session(&settings).unwrap(), &pending).await.unwrap(), 3);
+        assert!(!pending.exists());
+        let received = received.lock().unwrap();
+        assert_eq!(received.iter().map(|s| s.game_id.as_str()).collect::<Vec<_>>(), ["1", "2", "3"]);
+        assert_eq!((received[0].user.as_str(), received[0].duration().num_minutes()), ("anna", 90));
+    }
+}
@@ -1,13 +1,30 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 // use std::fs;
+pub mod error;
+pub mod logging;
+pub mod files;
+pub mod client;
+pub mod launch;
+pub mod manifest;
+pub mod chunks;
+pub mod stats;
+pub mod auth;
+pub mod pinning;
+pub mod discovery;
+pub mod install;
+
+use std::sync::Mutex;
 use tauri_plugin_fs;
+use tauri::Emitter;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let first_run = FirstRun(Mutex::new(client::is_first_run()));
     tauri::Builder::default()
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![greet])
+        .manage(first_run)
+        .invoke_handler(tauri::generate_handler![greet, first_run, discover_servers, select_server, login, launch_game, install_game, update_game])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -17,19 +34,101 @@ fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+/// Whether the client settings were missing when the window opened,
+/// until a server is picked.
+struct FirstRun(Mutex<bool>);
+
+/// Tells the window whether to show the servers on the network, which
+/// it does on the first start.
 #[tauri::command]
-fn save_games(name: &str) -> String {
-    format!("Hello, {}! You've been greeted from Rust!", name)
+fn first_run(state: tauri::State<FirstRun>) -> bool {
+    state.0.lock().map(|first_run| *first_run).unwrap_or(false)
 }
 
+/// Searches the local network for servers, see `discovery`.
 #[tauri::command]
-fn load_games(name: &str) -> String {
+async fn discover_servers() -> Result<Vec<discovery::DiscoveredServer>, String> {
+    tauri::async_runtime::spawn_blocking(|| discovery::discover(&discovery::default_targets(), discovery::DEFAULT_TIMEOUT))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
 
-    format!("Hello, {}! You've been greeted from Rust!", name)
+/// Makes `url` the server of the client.
+#[tauri::command]
+fn select_server(state: tauri::State<FirstRun>, url: String) -> Result<(), String> {
+    client::select_server(&url).map_err(|e| e.to_string())?;
+    // the overview stops sending the user to the server list
+    if let Ok(mut first_run) = state.0.lock() { *first_run = false; }
+    Ok(())
 }
 
+/// Logs in to the server, every request of the client is made as that
+/// user from then on.
+#[tauri::command]
+async fn login(name: String, password: String) -> Result<(), String> {
+    client::login(&name, &password).await.map(|_| ()).map_err(|e| e.to_string())
+}
 
+/// Starts a game and returns its pid.
+///
+/// The game is waited for in the background, once it exits the
+/// `LaunchRecord` is sent to the window as the `game-exited` event
+/// and to the server as a play session.
+#[tauri::command]
+async fn launch_game(app: tauri::AppHandle, id: String) -> Result<u32, String> {
+    let plan = launch::prepare(&id).await.map_err(|e| e.to_string())?;
+    let game = launch::start(plan, launch::history_path()).map_err(|e| e.to_string())?;
+    let pid = game.pid();
+    std::thread::spawn(move || match game.wait() {
+        Ok(record) => {
+            if let Err(e) = app.emit("game-exited", &record) {
+                logging::logging_function(logging::LoggingLevel::Error, &format!("Failed to emit game-exited with {}", e));
+            }
+            launch::report_blocking(&record);
+        },
+        Err(e) => logging::logging_function(logging::LoggingLevel::Error, &format!("Failed to wait for {} with {}", id, e)),
+    });
+    Ok(pid)
+}
 
+/// Installs a game from the server, by default into the games folder
+/// of the client. Progress is sent to the window as the
+/// `install-progress` event.
+#[tauri::command]
+async fn install_game(app: tauri::AppHandle, id: String, dest: Option<String>) -> Result<install::InstallReport, String> {
+    let settings = client::get_client_settings().map_err(|e| e.to_string())?;
+    let credentials = client::Credentials::load().map_err(|e| e.to_string())?;
+    let dest = dest.map(std::path::PathBuf::from).unwrap_or_else(|| install::default_install_dir(&id));
+    install::install(&settings.server_url, &credentials, &id, &dest, |progress| {
+        if let Err(e) = app.emit("install-progress", progress) {
+            logging::logging_function(logging::LoggingLevel::Warn, &format!("Failed to emit install-progress with {}", e));
+        }
+    }).await.map_err(|e| e.to_string())
+}
+
+/// Updates an installed game to the files on the server, downloading
+/// only the chunks that changed. Progress is sent to the window as the
+/// `install-progress` event.
+#[tauri::command]
+async fn update_game(app: tauri::AppHandle, id: String, dest: Option<String>) -> Result<install::InstallReport, String> {
+    let settings = client::get_client_settings().map_err(|e| e.to_string())?;
+    let credentials = client::Credentials::load().map_err(|e| e.to_string())?;
+    let dest = dest.map(std::path::PathBuf::from).unwrap_or_else(|| install::default_install_dir(&id));
+    install::update(&settings.server_url, &credentials, &id, &dest, &install::chunk_cache_dir(), |progress| {
+        if let Err(e) = app.emit("install-progress", progress) {
+            logging::logging_function(logging::LoggingLevel::Warn, &format!("Failed to emit install-progress with {}", e));
+        }
+    }).await.map_err(|e| e.to_string())
+}
 
+#[tauri::command]
+fn save_games(name: &str) -> String {
+    format!("Hello, {}! You've been greeted from Rust!", name)
+}
 
+#[tauri::command]
+fn load_games(name: &str) -> String {
 
+    format!("Hello, {}! You've been greeted from Rust!", name)
+}
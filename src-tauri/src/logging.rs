@@ -4,10 +4,20 @@
 // logging utils
 use colored::Colorize;
 use chrono;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
 
 /// This is used for changing the behaviour of the logging
 /// function.
+///
+/// The levels are ordered, see `set_level()`.
 #[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
 pub enum LoggingLevel {
     Trace,
     Info,
@@ -16,6 +26,51 @@ pub enum LoggingLevel {
     Fatal,
 }
 
+#[allow(dead_code)]
+impl LoggingLevel {
+    pub const ALL: [LoggingLevel; 5] = [LoggingLevel::Trace, LoggingLevel::Info, LoggingLevel::Warn, LoggingLevel::Error, LoggingLevel::Fatal];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoggingLevel::Trace => "trace",
+            LoggingLevel::Info => "info",
+            LoggingLevel::Warn => "warn",
+            LoggingLevel::Error => "error",
+            LoggingLevel::Fatal => "fatal",
+        }
+    }
+}
+
+impl std::str::FromStr for LoggingLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LoggingLevel::ALL.into_iter().find(|lvl| lvl.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("{:?} is not one of trace, info, warn, error or fatal", s))
+    }
+}
+
+/// Messages below this level are dropped, everything is printed
+/// until `set_level()` is called.
+static LEVEL: AtomicU8 = AtomicU8::new(LoggingLevel::Trace as u8);
+
+/// Drops every message below `lvl` from now on.
+#[allow(dead_code)]
+pub fn set_level(lvl: LoggingLevel) {
+    LEVEL.store(lvl as u8, Ordering::Relaxed);
+}
+
+/// Every message is appended to this file too, see `log_to_file()`.
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Appends every message to `path` from now on, next to printing it.
+#[allow(dead_code)]
+pub fn log_to_file(path: &Path) -> std::io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    *LOG_FILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    Ok(())
+}
+
 /// This function builds and prints the provided messages
 /// in accordance with the provided `LoggingLevel`.
 ///
@@ -31,6 +86,7 @@ pub enum LoggingLevel {
 /// of any of the wrapper macros.
 #[allow(unreachable_patterns)]
 pub fn logging_function(lvl: LoggingLevel, str: &str) {
+    if (lvl as u8) < LEVEL.load(Ordering::Relaxed) { return; }
     let time = chrono::offset::Local::now().to_string();
     let logging_level = match lvl {
         LoggingLevel::Trace => "TRACE".purple(),
@@ -41,6 +97,10 @@ pub fn logging_function(lvl: LoggingLevel, str: &str) {
         _ => "not yet implemented".white()
     };
     println!( "@ [{}] {} | {}", time, logging_level.to_string(), str);
+    if let Some(file) = LOG_FILE.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        // a full disk must not take down whoever logged
+        let _ = writeln!(file, "@ [{}] {:5} | {}", time, lvl.as_str().to_uppercase(), str);
+    }
 }
 
 #[macro_export]
@@ -0,0 +1,160 @@
+//! This crate is for reading the games installed through Lutris.
+//! Lutris keeps every game in its `pga.db` SQLite database and has
+//! one `<slug>-<id>.yml` config per game which points at the
+//! executable. The database is only read when the server was built
+//! with the `sqlite` feature, otherwise only the configs are used.
+use crate::error::NasError;
+#[allow(unused_imports)]
+use crate::{trace, info, warn, error, logging::LoggingLevel, logging::logging_function};
+use crate::types::Launcher;
+use crate::scanner::{DetectedGame, Detection};
+use crate::provider::{LauncherProvider, LaunchCommand};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The name that is used for `Launcher.name`.
+pub const LUTRIS_LAUNCHER: &str = "lutris";
+
+/// The default location of `pga.db` on linux.
+pub fn default_data_dir() -> PathBuf {
+    crate::server::expand_tilde("~/.local/share/lutris")
+}
+
+/// Older versions of Lutris keep the game configs in `~/.config`,
+/// newer ones next to the database.
+pub fn default_config_dirs() -> Vec<PathBuf> {
+    vec![crate::server::expand_tilde("~/.config/lutris/games"), default_data_dir().join("games")]
+}
+
+/// Reads a game config, which lists the executable under `game: exe:`.
+///
+/// The `Launcher.game_id` is the file stem, which Lutris calls the
+/// `configpath`.
+pub fn parse_game_config(file: &Path) -> Option<DetectedGame> {
+    let contents = fs::read_to_string(file).ok()?;
+    if !contents.lines().any(|l| l.trim_end() == "game:") { return None; }
+    let exe = contents.lines().find_map(|l| l.trim().strip_prefix("exe:").map(|v| v.trim().trim_matches(['"', '\'']).to_owned()))?;
+    let stem = file.file_stem()?.to_string_lossy().into_owned();
+    let name = slug_of(&stem).replace('-', " ");
+    let install_dir = PathBuf::from(exe).parent().map(Path::to_path_buf)?;
+    let mut launcher = Launcher::new(LUTRIS_LAUNCHER.to_owned(), stem);
+    launcher.install_dir = Some(install_dir);
+    Some(DetectedGame { name, launcher })
+}
+
+/// `celeste-1612345678` -> `celeste`
+fn slug_of(configpath: &str) -> &str {
+    match configpath.rsplit_once('-') {
+        Some((slug, id)) if id.chars().all(|c| c.is_ascii_digit()) => slug,
+        _ => configpath,
+    }
+}
+
+/// Lists the installed games of `pga.db`.
+#[cfg(feature = "sqlite")]
+pub fn games_in_db(db: &Path) -> Result<Vec<DetectedGame>, NasError> {
+    use crate::error::{Context, ErrorKind};
+    use rusqlite::{Connection, OpenFlags};
+
+    if !db.exists() { return Ok(Vec::new()); }
+    let conn = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .context(ErrorKind::FailedToReadFile, format!("Failed to open {:?}", db))?;
+    let mut stmt = conn.prepare(
+        "SELECT name, slug, configpath, directory FROM games WHERE installed = 1 ORDER BY name"
+    ).context(ErrorKind::FailedToParse, format!("{:?} is not a Lutris database", db))?;
+    let rows = stmt.query_map([], |row| {
+        let (name, slug): (String, String) = (row.get(0)?, row.get(1)?);
+        let configpath: Option<String> = row.get(2)?;
+        let mut launcher = Launcher::new(LUTRIS_LAUNCHER.to_owned(), configpath.filter(|c| !c.is_empty()).unwrap_or(slug));
+        launcher.install_dir = row.get::<_, Option<String>>(3)?.filter(|d| !d.is_empty()).map(PathBuf::from);
+        Ok(DetectedGame { name, launcher })
+    }).context(ErrorKind::FailedToReadFile, format!("Failed to query {:?}", db))?;
+    rows.collect::<Result<_, _>>().context(ErrorKind::FailedToReadFile, format!("Failed to query {:?}", db))
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub fn games_in_db(db: &Path) -> Result<Vec<DetectedGame>, NasError> {
+    trace!("Skipping {:?}, the server was built without sqlite", db);
+    Ok(Vec::new())
+}
+
+/// Lists the Lutris games and starts them through Lutris.
+pub struct LutrisProvider {
+    data_dir: PathBuf,
+    config_dirs: Vec<PathBuf>,
+}
+
+impl LutrisProvider {
+    pub fn new(data_dir: PathBuf, config_dirs: Vec<PathBuf>) -> Self { Self { data_dir, config_dirs } }
+}
+
+impl LauncherProvider for LutrisProvider {
+    fn name(&self) -> &'static str { LUTRIS_LAUNCHER }
+
+    /// The database knows the real names of the games, the configs
+    /// fill in the install location should the database lack it.
+    fn list_games(&self) -> Result<Vec<DetectedGame>, NasError> {
+        let mut games = games_in_db(&self.data_dir.join("pga.db"))?;
+        let configs = self.config_dirs.iter()
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .flat_map(|entries| entries.filter_map(Result::ok).map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "yml"))
+            .filter_map(|p| parse_game_config(&p));
+        for config in configs {
+            match games.iter_mut().find(|g| g.launcher.is_same_as(&config.launcher)) {
+                Some(game) => { game.launcher.install_dir = game.launcher.install_dir.take().or(config.launcher.install_dir); },
+                None => games.push(config),
+            }
+        }
+        Ok(games)
+    }
+
+    fn detect(&self, _dir: &Path, files: &[PathBuf], _dirs: &[PathBuf]) -> Detection {
+        let games = files.iter().filter(|f| f.extension().is_some_and(|e| e == "yml")).filter_map(|f| parse_game_config(f)).collect();
+        Detection { games, ..Detection::default() }
+    }
+
+    fn launch_command(&self, launcher: &Launcher) -> Result<LaunchCommand, NasError> {
+        Ok(LaunchCommand::new("lutris", [format!("lutris:rungame/{}", slug_of(&launcher.game_id))]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_database_and_configs() {
+        let dir = tempfile::tempdir().unwrap();
+        let configs = dir.path().join("games");
+        fs::create_dir_all(&configs).unwrap();
+        fs::write(configs.join("celeste-1612345678.yml"), "game:\n  exe: /games/Celeste/Celeste\n").unwrap();
+        fs::write(configs.join("system.yml"), "system:\n  env: {}\n").unwrap();
+
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = rusqlite::Connection::open(dir.path().join("pga.db")).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE games (id INTEGER PRIMARY KEY, name TEXT, slug TEXT, configpath TEXT, directory TEXT, installed INTEGER);
+                INSERT INTO games (name, slug, configpath, directory, installed) VALUES
+                    ('Celeste', 'celeste', 'celeste-1612345678', '', 1),
+                    ('Hades', 'hades', 'hades-1612345679', '/games/Hades', 1),
+                    ('Gone', 'gone', 'gone-1', '/games/Gone', 0);"
+            ).unwrap();
+        }
+
+        let provider = LutrisProvider::new(dir.path().to_path_buf(), vec![configs]);
+        let games = provider.list_games().unwrap();
+        let celeste = games.iter().find(|g| g.launcher.game_id == "celeste-1612345678").unwrap();
+        assert_eq!(celeste.launcher.install_dir, Some(PathBuf::from("/games/Celeste")));
+        #[cfg(feature = "sqlite")]
+        {
+            assert_eq!(games.len(), 2);
+            assert_eq!(celeste.name, "Celeste");
+        }
+
+        let command = provider.launch_command(&celeste.launcher).unwrap();
+        assert_eq!(command.args, ["lutris:rungame/celeste"]);
+    }
+}
@@ -1,9 +1,30 @@
 // #![allow(unused_imports)]
-mod error;
-mod logging;
+// the modules the client shares with the server come from the lib,
+// so that there is only one logging level and one error type
+use nas_game_lib::{error, logging, files, manifest, chunks, stats, auth};
+use nas_game_lib::{trace, info, warn};
 mod types;
 mod server;
 mod server_routes;
+mod store;
+mod scanner;
+mod vdf;
+mod steam;
+mod steam_api;
+mod gog;
+mod heroic;
+mod lutris;
+mod provider;
+mod devices;
+mod users;
+mod certs;
+mod config;
+mod reload;
+mod data_dir;
+mod jobs;
+mod artwork;
+#[cfg(feature = "sqlite")]
+mod sqlite_store;
 use clap::{Arg, ArgAction, Command};
 
 fn main() {
@@ -16,10 +37,113 @@ fn main() {
         .subcommand_required(true)
         .subcommand_value_name("APPLET")
         .subcommand_help_heading("APPLETS")
-        .subcommand(Command::new("client").about("starts the client")) // TODO: the client
+        .subcommand(
+            Command::new("client")
+                .about("starts the client")
+                .subcommand(
+                    Command::new("launch")
+                        .about("start a game and wait for it to exit")
+                        .arg(
+                            Arg::new("id")
+                                .required(true)
+                                .help("the id of the game in the game library")
+                        )
+                )
+                .subcommand(
+                    Command::new("discover")
+                        .about("search the local network for servers")
+                        .arg(
+                            Arg::new("save")
+                                .long("save")
+                                .action(ArgAction::SetTrue)
+                                .help("make the server the one of this client, if only one answers")
+                        )
+                )
+                .subcommand(
+                    Command::new("forget-server")
+                        .about("forget the pinned certificate of a server, e.g. after it got a new CA")
+                        .arg(Arg::new("host").required(true).help("the host name or ip of the server"))
+                )
+                .subcommand(
+                    Command::new("install")
+                        .about("download a game from the server, resuming an earlier install")
+                        .arg(
+                            Arg::new("id")
+                                .required(true)
+                                .help("the id of the game in the game library")
+                        )
+                        .arg(
+                            Arg::new("dest")
+                                .value_parser(clap::value_parser!(std::path::PathBuf))
+                                .help("where to install the game, defaults to the games folder of the client")
+                        )
+                )
+                .subcommand(
+                    Command::new("login")
+                        .about("log in to the server, the token is kept in the client settings")
+                        .arg(Arg::new("name").required(true).help("the name of the user"))
+                        .arg(Arg::new("password").help("the password, read from NAS_GAME_PASSWORD or stdin if missing"))
+                )
+                .subcommand(
+                    Command::new("register")
+                        .about("create the key of this client and register it with the server")
+                        .arg(
+                            Arg::new("name")
+                                .help("how the device is listed on the server, defaults to the user name")
+                        )
+                )
+                .subcommand(
+                    Command::new("request")
+                        .about("send a signed request to the server and print the response")
+                        .arg(Arg::new("method").required(true).help("e.g. POST"))
+                        .arg(Arg::new("path").required(true).help("e.g. /scan"))
+                        .arg(Arg::new("body").help("the json body of the request"))
+                )
+                .subcommand(
+                    Command::new("update")
+                        .about("update an installed game, downloading only the parts that changed")
+                        .arg(
+                            Arg::new("id")
+                                .required(true)
+                                .help("the id of the game in the game library")
+                        )
+                        .arg(
+                            Arg::new("dest")
+                                .value_parser(clap::value_parser!(std::path::PathBuf))
+                                .help("where the game is installed, defaults to the games folder of the client")
+                        )
+                )
+        )
         .subcommand(
             Command::new("server")
                 .about("the server")
+                .arg(
+                    Arg::new("config")
+                        .short('c')
+                        .long("config")
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .help("the settings file, defaults to server_settings.json in the data directory")
+                )
+                .arg(
+                    Arg::new("data-dir")
+                        .long("data-dir")
+                        .help("where the server keeps its files, e.g. /mnt/pool/nas-game")
+                )
+                .arg(Arg::new("ip").long("ip").help("the ip the server listens on"))
+                .arg(Arg::new("port").short('p').long("port").help("the port the server listens on"))
+                .arg(
+                    Arg::new("log-level")
+                        .long("log-level")
+                        .value_parser(["trace", "info", "warn", "error", "fatal"])
+                        .help("the least important messages that are still logged")
+                )
+                .arg(
+                    Arg::new("set")
+                        .long("set")
+                        .action(ArgAction::Append)
+                        .value_name("KEY=VALUE")
+                        .help("override any setting, e.g. --set webp_quality=90")
+                )
                 .arg(
                     Arg::new("info")
                         .short('i')
@@ -41,6 +165,12 @@ fn main() {
                         .action(ArgAction::SetTrue)
                         .help("generate default values for the server")
                 )
+                .arg(
+                    Arg::new("import-json")
+                        .long("import-json")
+                        .action(ArgAction::SetTrue)
+                        .help("import the json game library into the sqlite database")
+                )
                 .arg(
                     Arg::new("optimize-images")
                         .short('o')
@@ -48,16 +178,250 @@ fn main() {
                         .action(ArgAction::SetTrue)
                         .help("optimize images") // TODO: change it to a sub commmand for additional args
                 )
+                .subcommand(
+                    Command::new("config")
+                        .about("show, check or change the settings, see `server config show` for every key")
+                        .subcommand(Command::new("show").about("print every setting and where its value came from"))
+                        .subcommand(Command::new("validate").about("check the settings, exits with 1 if they are invalid"))
+                        .subcommand(
+                            Command::new("set")
+                                .about("change a setting in the settings file")
+                                .arg(Arg::new("key").required(true).help("e.g. data_dir"))
+                                .arg(Arg::new("value").required(true).help("e.g. /mnt/pool/nas-game, json unless the setting is text"))
+                        )
+                )
+                .subcommand(
+                    Command::new("import-steam")
+                        .about("import every game of a local Steam installation into the game library")
+                        .arg(
+                            Arg::new("steam-root")
+                                .value_parser(clap::value_parser!(std::path::PathBuf))
+                                .help("the Steam installation, defaults to ~/.local/share/Steam")
+                        )
+                )
+                .subcommand(
+                    Command::new("import-gog")
+                        .about("import the installed GOG games and GOG offline installers below a directory")
+                        .arg(
+                            Arg::new("dir")
+                                .num_args(0..)
+                                .value_parser(clap::value_parser!(std::path::PathBuf))
+                                .help("the directories to search, defaults to the configured library roots")
+                        )
+                )
+                .subcommand(
+                    Command::new("devices")
+                        .about("list the devices that registered with the server, or approve and revoke them")
+                        .subcommand(Command::new("list").about("list every device"))
+                        .subcommand(
+                            Command::new("approve")
+                                .about("allow a device to change the library")
+                                .arg(Arg::new("id").required(true).help("the id of the device"))
+                        )
+                        .subcommand(
+                            Command::new("revoke")
+                                .about("stop accepting requests of a device")
+                                .arg(Arg::new("id").required(true).help("the id of the device"))
+                        )
+                )
+                .subcommand(
+                    Command::new("cert")
+                        .about("manage the certificates the server speaks https with")
+                        .subcommand_required(true)
+                        .subcommand(
+                            Command::new("init")
+                                .about("create a local CA, unless there is one, and a new server certificate signed by it")
+                                .arg(
+                                    Arg::new("host")
+                                        .long("host")
+                                        .action(ArgAction::Append)
+                                        .help("another name or ip the server is reached under, e.g. nas.local")
+                                )
+                                .arg(
+                                    Arg::new("new-ca")
+                                        .long("new-ca")
+                                        .action(ArgAction::SetTrue)
+                                        .help("replace the CA, every client has to forget the server afterwards")
+                                )
+                        )
+                )
+                .subcommand(
+                    Command::new("users")
+                        .about("manage the accounts of the people sharing the server")
+                        .subcommand(Command::new("list").about("list every user"))
+                        .subcommand(
+                            Command::new("add")
+                                .about("add a user, who can log in with the password or a token")
+                                .arg(Arg::new("name").required(true).help("the name to log in with"))
+                                .arg(
+                                    Arg::new("role")
+                                        .long("role")
+                                        .default_value("player")
+                                        .value_parser(["admin", "player", "kid"])
+                                        .help("what the user may do")
+                                )
+                                .arg(Arg::new("password").long("password").help("the password, without one the user can only log in with a token"))
+                        )
+                        .subcommand(
+                            Command::new("remove")
+                                .about("remove a user")
+                                .arg(Arg::new("name").required(true).help("the name of the user"))
+                        )
+                        .subcommand(
+                            Command::new("token")
+                                .about("print a token that logs the user in and never expires")
+                                .arg(Arg::new("name").required(true).help("the name of the user"))
+                                .arg(Arg::new("label").long("label").default_value("cli").help("what the token is for"))
+                        )
+                )
+                .subcommand(
+                    Command::new("scan")
+                        .about("scan the library roots for installed games")
+                        .arg(
+                            Arg::new("root")
+                                .num_args(0..)
+                                .value_parser(clap::value_parser!(std::path::PathBuf))
+                                .help("additional directories to scan besides the configured library roots")
+                        )
+                )
         ).get_matches();
         
 
     match cmd.subcommand() {
-        #[allow(unused_variables)]
-        Some(("client", args)) => {
-            nas_game_lib::run();
+        Some(("client", args)) => match args.subcommand() {
+            Some(("launch", launch_args)) => {
+                let id = launch_args.get_one::<String>("id").expect("the id is required");
+                match nas_game_lib::launch::launch_blocking(id) {
+                    Ok(record) => println!("{} exited with {:?} after {}s", record.game_id, record.exit_code, record.runtime_secs),
+                    Err(e) => {
+                        eprintln!("Failed to launch {}: {}", id, e);
+                        std::process::exit(1);
+                    },
+                }
+            },
+            Some(("discover", discover_args)) => {
+                use nas_game_lib::{client, discovery};
+                let servers = match discovery::discover(&discovery::default_targets(), discovery::DEFAULT_TIMEOUT) {
+                    Ok(servers) => servers,
+                    Err(e) => {
+                        eprintln!("Failed to search for servers: {}", e);
+                        std::process::exit(1);
+                    },
+                };
+                for server in &servers {
+                    println!("{:<24}  {:<8}  {}", server.announcement.name, server.announcement.version, server.url);
+                }
+                match (servers.as_slice(), discover_args.get_flag("save")) {
+                    ([], _) => {
+                        eprintln!("No server answered");
+                        std::process::exit(1);
+                    },
+                    ([server], true) => if let Err(e) = client::select_server(&server.url) {
+                        eprintln!("Failed to save the server: {}", e);
+                        std::process::exit(1);
+                    },
+                    (_, true) => {
+                        eprintln!("Several servers answered, set the server_url of the client settings to one of them");
+                        std::process::exit(1);
+                    },
+                    _ => (),
+                }
+            },
+            Some(("forget-server", forget_args)) => {
+                use nas_game_lib::{client, pinning};
+                let host = forget_args.get_one::<String>("host").expect("the host is required");
+                match pinning::forget(&client::client_dir().join(pinning::KNOWN_SERVERS_PATH), host) {
+                    Ok(true) => println!("Forgot {}, its certificate is pinned again on the next request", host),
+                    Ok(false) => println!("No certificate of {} has been pinned", host),
+                    Err(e) => {
+                        eprintln!("Failed to forget {}: {}", host, e);
+                        std::process::exit(1);
+                    },
+                }
+            },
+            Some(("login", login_args)) => {
+                let name = login_args.get_one::<String>("name").expect("the name is required");
+                let password = login_args.get_one::<String>("password").cloned()
+                    .or_else(|| std::env::var("NAS_GAME_PASSWORD").ok())
+                    .unwrap_or_else(|| {
+                        eprint!("Password for {}: ", name);
+                        let mut line = String::new();
+                        let _ = std::io::stdin().read_line(&mut line);
+                        line.trim_end_matches(['\r', '\n']).to_owned()
+                    });
+                match actix_web::rt::System::new().block_on(nas_game_lib::client::login(name, &password)) {
+                    Ok(logged_in) => match logged_in.expires_at {
+                        Some(at) => println!("Logged in as {} until {}", name, at.format("%Y-%m-%d %H:%M")),
+                        None => println!("Logged in as {}", name),
+                    },
+                    Err(e) => {
+                        eprintln!("Failed to log in as {}: {}", name, e);
+                        std::process::exit(1);
+                    },
+                }
+            },
+            Some(("register", register_args)) => {
+                let result = nas_game_lib::client::get_client_settings().and_then(|settings| {
+                    let name = register_args.get_one::<String>("name").cloned().unwrap_or(settings.user);
+                    actix_web::rt::System::new().block_on(nas_game_lib::client::register_device(&settings.server_url, &name))
+                });
+                match result {
+                    Ok(device) => println!("Registered {:?} as {} ({}), approve it on the server with `nas-game server devices approve {}`", device.name, device.id, device.status, device.id),
+                    Err(e) => {
+                        eprintln!("Failed to register this client: {}", e);
+                        std::process::exit(1);
+                    },
+                }
+            },
+            Some(("request", request_args)) => {
+                let method = request_args.get_one::<String>("method").expect("the method is required");
+                let path = request_args.get_one::<String>("path").expect("the path is required");
+                let body = request_args.get_one::<String>("body").cloned().unwrap_or_default();
+                let result = nas_game_lib::client::get_client_settings().and_then(|settings| {
+                    actix_web::rt::System::new().block_on(nas_game_lib::client::send(&settings.server_url, method, path, body.into_bytes()))
+                });
+                match result {
+                    Ok((status, text)) => {
+                        println!("{}", text);
+                        if !status.is_success() {
+                            eprintln!("{}", status);
+                            std::process::exit(1);
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("Failed to send the request: {}", e);
+                        std::process::exit(1);
+                    },
+                }
+            },
+            Some((name @ ("install" | "update"), install_args)) => {
+                use nas_game_lib::install;
+                let id = install_args.get_one::<String>("id").expect("the id is required");
+                let dest = install_args.get_one::<std::path::PathBuf>("dest").cloned()
+                    .unwrap_or_else(|| install::default_install_dir(id));
+                let show = |p: &install::InstallProgress| eprint!("\r{}/{} files, {}/{} bytes", p.files_done, p.files_total, p.bytes_done, p.bytes_total);
+                let result = nas_game_lib::client::get_client_settings().and_then(|settings| {
+                    let credentials = nas_game_lib::client::Credentials::load()?;
+                    let system = actix_web::rt::System::new();
+                    match name {
+                        "install" => system.block_on(install::install(&settings.server_url, &credentials, id, &dest, show)),
+                        _ => system.block_on(install::update(&settings.server_url, &credentials, id, &dest, &install::chunk_cache_dir(), show)),
+                    }
+                });
+                eprintln!();
+                match result {
+                    Ok(report) => println!("Installed {} into {:?}, {} files downloaded ({} bytes, {} bytes reused), {} unchanged",
+                        id, report.dest, report.downloaded_files, report.downloaded_bytes, report.reused_bytes, report.skipped_files),
+                    Err(e) => {
+                        eprintln!("Failed to {} {}: {}", name, id, e);
+                        std::process::exit(1);
+                    },
+                }
+            },
+            _ => nas_game_lib::run(),
         },
         Some(("server", args)) => {
-            let _ = server::server(&args);
+            if server::server(args).is_err() { std::process::exit(1); }
         },
         _ => unreachable!("parser should ensure only valid subcommand names are used"),
     };
@@ -0,0 +1,193 @@
+//! This crate is for describing the files of an installed game, so
+//! that the client can download them from the server and verify
+//! them afterwards. It is used by both the server and the client.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, logging::LoggingLevel, logging::logging_function};
+
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// How much of a file is read at once while hashing it.
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// A single file of a game.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    /// The path relative to the install directory, always separated
+    /// by `/`.
+    pub path: String,
+    pub size: u64,
+    /// The lower case hex SHA-256 of the file.
+    pub sha256: String,
+    /// The modification time in seconds since the unix epoch. Only
+    /// used to tell whether the hash has to be computed again.
+    #[serde(default)]
+    pub modified: u64,
+}
+
+/// Every file of an installed game.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub game_id: String,
+    pub total_size: u64,
+    pub files: Vec<FileEntry>,
+}
+
+/// Computes the lower case hex SHA-256 of a file.
+pub fn hash_file(path: &Path) -> Result<String, NasError> {
+    let mut file = fs::File::open(path).context(ErrorKind::FailedToReadFile, format!("Failed to open {:?}", path))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+    loop {
+        let read = file.read(&mut buffer).context(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path))?;
+        if read == 0 { break; }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Joins a relative path of a manifest onto `root`.
+///
+/// # Errors
+/// Fails with `ErrorKind::InvalidPath` for absolute paths and paths
+/// that contain `..`, which would leave `root`.
+pub fn join(root: &Path, relative: &str) -> Result<PathBuf, NasError> {
+    let relative = Path::new(relative);
+    if relative.as_os_str().is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(NasError::new(ErrorKind::InvalidPath, format!("{:?} is not a relative path inside of the game", relative)));
+    }
+    Ok(root.join(relative))
+}
+
+/// Like `join()`, but the file must exist and must not leave `root`
+/// through a symlink either.
+pub fn resolve(root: &Path, relative: &str) -> Result<PathBuf, NasError> {
+    let path = join(root, relative)?;
+    let canonical = path.canonicalize()?;
+    if !canonical.starts_with(root.canonicalize()?) {
+        return Err(NasError::new(ErrorKind::InvalidPath, format!("{:?} points outside of the game", relative)));
+    }
+    Ok(canonical)
+}
+
+pub(crate) fn modified(metadata: &fs::Metadata) -> u64 {
+    metadata.modified().ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Collects every regular file below `dir`. Symlinks are skipped,
+/// they could point anywhere outside of `root`.
+pub(crate) fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) -> Result<(), NasError> {
+    for entry in fs::read_dir(dir).context(ErrorKind::FailedToReadFile, format!("Failed to read the directory {:?}", dir))? {
+        let path = entry?.path();
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_symlink() {
+            trace!("Skipping the symlink {:?}", path);
+        } else if file_type.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let relative = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+            files.push((relative, path));
+        }
+    }
+    Ok(())
+}
+
+/// Builds the manifest of every file below `root`.
+///
+/// Files whose size and modification time match the entry of the
+/// `previous` manifest keep their hash, every other file is hashed.
+pub fn build(game_id: &str, root: &Path, previous: Option<&Manifest>) -> Result<Manifest, NasError> {
+    let mut paths = Vec::new();
+    collect_files(root, root, &mut paths)?;
+    paths.sort();
+
+    let mut files = Vec::with_capacity(paths.len());
+    let mut hashed = 0;
+    for (relative, path) in paths {
+        let metadata = fs::metadata(&path)?;
+        let (size, modified) = (metadata.len(), modified(&metadata));
+        let known = previous.and_then(|p| p.files.iter().find(|f| f.path == relative && f.size == size && f.modified == modified));
+        let sha256 = match known {
+            Some(entry) => entry.sha256.clone(),
+            None => {
+                hashed += 1;
+                hash_file(&path)?
+            },
+        };
+        files.push(FileEntry { path: relative, size, sha256, modified });
+    }
+    trace!("Hashed {} of the {} files of {}", hashed, files.len(), game_id);
+    Ok(Manifest { game_id: game_id.to_owned(), total_size: files.iter().map(|f| f.size).sum(), files })
+}
+
+/// Builds the manifest of a game, reusing the hashes of the manifest
+/// that was cached in `cache_dir` the last time.
+pub fn load_or_build(cache_dir: &Path, game_id: &str, root: &Path) -> Result<Manifest, NasError> {
+    let cache = join(cache_dir, &format!("{}.json", game_id))?;
+    let previous: Option<Manifest> = fs::read_to_string(&cache).ok().and_then(|s| serde_json::from_str(&s).ok());
+    let manifest = build(game_id, root, previous.as_ref())?;
+    if previous.as_ref() != Some(&manifest) {
+        info!("The manifest of {} has changed, {} files with {} bytes", game_id, manifest.files.len(), manifest.total_size);
+        let json = serde_json::to_vec(&manifest).context(ErrorKind::FailedToSerialize, "Failed to serialize the manifest")?;
+        if let Err(e) = fs::create_dir_all(cache_dir).and_then(|_| fs::write(&cache, json)) {
+            warn!("Failed to cache the manifest of {} at {:?} with {}", game_id, cache, e);
+        }
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_and_reuses_manifests() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("game");
+        fs::create_dir_all(root.join("data")).unwrap();
+        fs::write(root.join("game.exe"), "abc").unwrap();
+        fs::write(root.join("data/level.pak"), "").unwrap();
+
+        let manifest = load_or_build(&dir.path().join("cache"), "1", &root).unwrap();
+        assert_eq!(manifest.total_size, 3);
+        assert_eq!(manifest.files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), ["data/level.pak", "game.exe"]);
+        assert_eq!(manifest.files[1].sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        // unchanged files are not hashed again, even if the hash is wrong
+        let mut previous = manifest.clone();
+        previous.files[1].sha256 = "cached".to_owned();
+        assert_eq!(build("1", &root, Some(&previous)).unwrap().files[1].sha256, "cached");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, outside) = (dir.path().join("game"), dir.path().join("outside"));
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(root.join("game.exe"), "abc").unwrap();
+        fs::write(outside.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("linked")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("secret.txt")).unwrap();
+
+        let manifest = build("1", &root, None).unwrap();
+        assert_eq!(manifest.files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), ["game.exe"]);
+    }
+
+    #[test]
+    fn rejects_paths_outside_of_the_game() {
+        let root = Path::new("/games/Hades");
+        assert_eq!(join(root, "a/b.txt").unwrap(), root.join("a/b.txt"));
+        for path in ["../etc/passwd", "/etc/passwd", "a/../../b", ""] {
+            assert_eq!(join(root, path).unwrap_err().kind(), ErrorKind::InvalidPath);
+        }
+    }
+}
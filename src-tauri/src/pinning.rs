@@ -0,0 +1,161 @@
+//! This crate is for trusting the certificate of a server that speaks
+//! https without a public CA, see `nas-game server cert init`.
+//!
+//! The first time the client talks to a server it pins the CA the
+//! server presents (trust on first use), from then on the server has
+//! to present a certificate that is signed by that very CA. A server
+//! that renews its certificate with the same CA keeps working, a
+//! server with a different CA is refused until its pin is forgotten
+//! with `nas-game client forget-server <host>`.
+//!
+//! The pins are kept in `known_servers.json` in the client directory.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{info, warn, logging::LoggingLevel, logging::logging_function};
+use crate::files::write_atomic;
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use chrono::{DateTime, Utc};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const KNOWN_SERVERS_PATH: &str = "known_servers.json";
+/// Every certificate of `server cert init` is valid for this name, so
+/// that a server stays reachable under an address its certificate
+/// doesn't list, the pin is what identifies it.
+pub const TLS_NAME: &str = "nas-game";
+
+/// Held while the pins are read and written again, so that two
+/// handshakes at the same time don't lose a pin.
+static KNOWN_SERVERS_LOCK: Mutex<()> = Mutex::new(());
+
+/// A server whose CA has been pinned.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KnownServer {
+    /// The host name or ip the server was reached under.
+    pub host: String,
+    /// See `fingerprint()`.
+    pub sha256: String,
+    /// The base64 encoded DER of the CA.
+    pub ca: String,
+    pub pinned_at: DateTime<Utc>,
+}
+
+/// The lower case hex SHA-256 of a DER encoded certificate, which is
+/// what `server cert init` prints.
+pub fn fingerprint(der: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(der))
+}
+
+/// Reads the pins, a missing file has none.
+pub fn known_servers(path: &std::path::Path) -> Result<Vec<KnownServer>, NasError> {
+    match fs::read_to_string(path) {
+        Ok(s) => serde_json::from_str(&s).context(ErrorKind::FailedToParse, format!("Failed to parse {:?}", path)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(NasError::new(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path)).with_source(e)),
+    }
+}
+
+fn write_known_servers(path: &std::path::Path, servers: &[KnownServer]) -> Result<(), NasError> {
+    let json = serde_json::to_string_pretty(servers).context(ErrorKind::FailedToSerialize, "Failed to serialize the known servers")?;
+    write_atomic(path, json.as_bytes())
+}
+
+/// Forgets the pin of `host`, `false` if there was none.
+pub fn forget(path: &std::path::Path, host: &str) -> Result<bool, NasError> {
+    let _lock = KNOWN_SERVERS_LOCK.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+    let mut servers = known_servers(path)?;
+    let count = servers.len();
+    servers.retain(|s| s.host != host);
+    if servers.len() == count { return Ok(false); }
+    write_known_servers(path, &servers)?;
+    info!("Forgot the certificate of {}", host);
+    Ok(true)
+}
+
+/// Checks the certificates of servers against the pinned CAs.
+#[derive(Debug)]
+pub struct PinningVerifier {
+    path: PathBuf,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinningVerifier {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, provider: Arc::new(crypto::ring::default_provider()) }
+    }
+
+    /// Verifies the chain against `ca`, first for the name the server
+    /// was reached under and then for `TLS_NAME`.
+    fn verify_with(&self, ca: CertificateDer<'_>, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>], server_name: &ServerName<'_>, ocsp_response: &[u8], now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        let mut roots = RootCertStore::empty();
+        roots.add(ca)?;
+        let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), self.provider.clone()).build()
+            .map_err(|e| rustls::Error::General(e.to_string()))?;
+        let tls_name = ServerName::try_from(TLS_NAME).expect("TLS_NAME is a valid dns name");
+        verifier.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+            .or_else(|_| verifier.verify_server_cert(end_entity, intermediates, &tls_name, ocsp_response, now))
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>], server_name: &ServerName<'_>, ocsp_response: &[u8], now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        let host = server_name.to_str().into_owned();
+        let fail = |reason: String| {
+            warn!("Refusing the certificate of {}: {}", host, reason);
+            rustls::Error::General(reason)
+        };
+        let _lock = KNOWN_SERVERS_LOCK.lock().map_err(|_| fail("Failed to lock the known servers".to_owned()))?;
+        let mut servers = known_servers(&self.path).map_err(|e| fail(e.to_string()))?;
+        if let Some(known) = servers.iter().find(|s| s.host == host) {
+            let ca = BASE64.decode(&known.ca).map_err(|_| fail(format!("The pin in {:?} is broken", self.path)))?;
+            return self.verify_with(CertificateDer::from(ca), end_entity, intermediates, server_name, ocsp_response, now)
+                .map_err(|e| fail(format!("{}, the certificate is not signed by the CA pinned on {}. If the server got a new CA run `nas-game client forget-server {}`",
+                    e, known.pinned_at.format("%Y-%m-%d"), host)));
+        }
+
+        // trust on first use, the last certificate of the chain is the CA
+        let ca = intermediates.last().ok_or_else(|| fail("The server sent no CA, create one with `nas-game server cert init`".to_owned()))?;
+        self.verify_with(ca.clone(), end_entity, intermediates, server_name, ocsp_response, now)
+            .map_err(|e| fail(e.to_string()))?;
+        let known = KnownServer { host: host.clone(), sha256: fingerprint(ca), ca: BASE64.encode(ca), pinned_at: Utc::now() };
+        info!("Pinned the CA of {} with the fingerprint {}, compare it with the one `nas-game server cert init` printed", host, known.sha256);
+        servers.push(known);
+        write_known_servers(&self.path, &servers).map_err(|e| fail(e.to_string()))?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// A http client that checks https servers against the pins in
+/// `known_servers`. Plain http is left untouched.
+pub fn http_client(known_servers: PathBuf) -> Result<reqwest::Client, NasError> {
+    let config = ClientConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .context(ErrorKind::Ignore, "Failed to configure tls")?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinningVerifier::new(known_servers)))
+        .with_no_client_auth();
+    reqwest::Client::builder()
+        .use_preconfigured_tls(config)
+        .build()
+        .context(ErrorKind::Ignore, "Failed to create the http client")
+}
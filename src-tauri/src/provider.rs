@@ -0,0 +1,271 @@
+//! This crate is for the launchers games can be installed through.
+//! Every launcher (Steam, GOG, Heroic, Lutris, ...) implements
+//! `LauncherProvider`, which knows how to list the games of that
+//! launcher and how to start them. The providers are looked up by
+//! `Launcher.name` through the `ProviderRegistry`, so supporting a
+//! new launcher only means implementing the trait and registering
+//! it in `ProviderRegistry::from_settings()`.
+use crate::error::{NasError, ErrorKind};
+use crate::{trace, info, warn, error, logging::LoggingLevel, logging::logging_function};
+use crate::types::{Launcher, ServerSettings};
+use crate::scanner::{self, DetectedGame, Detection};
+use crate::steam::{self, SteamProvider};
+use crate::gog::GogProvider;
+use crate::heroic::{self, HeroicProvider};
+use crate::lutris::{self, LutrisProvider};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+
+/// The name that is used for `Launcher.name` of games that are just
+/// an executable in a folder.
+pub const LOCAL_LAUNCHER: &str = "local";
+
+/// How a game is started.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LaunchCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
+}
+
+impl LaunchCommand {
+    pub fn new(program: impl Into<String>, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self { program: program.into(), args: args.into_iter().map(Into::into).collect(), working_dir: None }
+    }
+}
+
+pub trait LauncherProvider: Send + Sync {
+    /// The `Launcher.name` of the games this provider is responsible for.
+    fn name(&self) -> &'static str;
+
+    /// Lists every game that is installed through this launcher.
+    ///
+    /// # Errors
+    /// A launcher that isn't installed has no games, only launchers
+    /// whose files are broken should error.
+    fn list_games(&self) -> Result<Vec<DetectedGame>, NasError>;
+
+    /// Detects the games of this launcher among the `files` and `dirs`
+    /// of a directory the scanner walks through. The default detects
+    /// nothing, which suits launchers that only know their games
+    /// through their own files.
+    fn detect(&self, _dir: &Path, _files: &[PathBuf], _dirs: &[PathBuf]) -> Detection {
+        Detection::default()
+    }
+
+    /// Looks up what the launcher currently knows about one of its
+    /// games, e.g. to refresh the install location.
+    fn resolve(&self, launcher: &Launcher) -> Result<Option<DetectedGame>, NasError> {
+        Ok(self.list_games()?.into_iter().find(|game| game.launcher.is_same_as(launcher)))
+    }
+
+    /// Builds the command which starts the game.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::NotFound` if the game is not installed.
+    fn launch_command(&self, launcher: &Launcher) -> Result<LaunchCommand, NasError>;
+}
+
+/// Returns the install directory of the launcher or errors with
+/// `ErrorKind::NotFound`.
+pub fn require_install_dir(launcher: &Launcher) -> Result<&Path, NasError> {
+    launcher.install_dir.as_deref()
+        .ok_or_else(|| NasError::new(ErrorKind::NotFound, format!("{} {} is not installed", launcher.name, launcher.game_id)))
+}
+
+/// Games that are only a folder with an executable in one of the
+/// library roots.
+pub struct LocalProvider {
+    roots: Vec<PathBuf>,
+}
+
+impl LocalProvider {
+    pub fn new(roots: Vec<PathBuf>) -> Self { Self { roots } }
+}
+
+impl LauncherProvider for LocalProvider {
+    fn name(&self) -> &'static str { LOCAL_LAUNCHER }
+
+    /// Scans the roots with every built in provider, so that the
+    /// folders of other launchers aren't taken for local games.
+    fn list_games(&self) -> Result<Vec<DetectedGame>, NasError> {
+        let providers = ProviderRegistry::with_roots(self.roots.clone());
+        Ok(scanner::scan_roots(&providers, &self.roots).into_iter().filter(|game| game.launcher.name == LOCAL_LAUNCHER).collect())
+    }
+
+    /// Starts the first executable in the install directory whose name
+    /// resembles the folder name, or the first executable at all.
+    fn launch_command(&self, launcher: &Launcher) -> Result<LaunchCommand, NasError> {
+        let dir = require_install_dir(launcher)?;
+        let mut executables: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && scanner::is_executable(p))
+            .collect();
+        executables.sort();
+        let folder = launcher.game_id.to_lowercase().replace([' ', '_', '-'], "");
+        let resembles = |p: &&PathBuf| p.file_stem().is_some_and(|s| s.to_string_lossy().to_lowercase().replace([' ', '_', '-'], "") == folder);
+        let exe = executables.iter().find(resembles).or(executables.first())
+            .ok_or_else(|| NasError::new(ErrorKind::NotFound, format!("There is no executable in {:?}", dir)))?;
+
+        let mut command = LaunchCommand::new(exe.to_string_lossy(), Vec::<String>::new());
+        command.working_dir = Some(dir.to_path_buf());
+        Ok(command)
+    }
+}
+
+/// Every known `LauncherProvider`, looked up by its name.
+pub struct ProviderRegistry {
+    providers: Vec<Box<dyn LauncherProvider>>,
+}
+
+impl ProviderRegistry {
+    pub fn new() -> Self { Self { providers: Vec::new() } }
+
+    /// Adds a provider. Should there already be one with the same
+    /// name then it is replaced.
+    pub fn register(&mut self, provider: impl LauncherProvider + 'static) {
+        self.providers.retain(|p| p.name() != provider.name());
+        trace!("Registered the launcher provider {:?}", provider.name());
+        self.providers.push(Box::new(provider));
+    }
+
+    /// Registers the built in providers, using the default install
+    /// locations of every launcher and the configured library roots.
+    pub fn from_settings(settings: &ServerSettings) -> Self {
+        Self::with_roots(settings.library_roots.clone())
+    }
+
+    /// Like `from_settings()` but with the library `roots` given.
+    pub fn with_roots(roots: Vec<PathBuf>) -> Self {
+        let mut registry = Self::new();
+        registry.register(SteamProvider::new(steam::default_steam_root()));
+        registry.register(GogProvider::new(roots.clone(), heroic::default_config_dir()));
+        registry.register(HeroicProvider::new(heroic::default_config_dir()));
+        registry.register(LutrisProvider::new(lutris::default_data_dir(), lutris::default_config_dirs()));
+        registry.register(LocalProvider::new(roots));
+        registry
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn LauncherProvider> {
+        self.providers.iter().find(|p| p.name() == name).map(|p| p.as_ref())
+    }
+
+    /// Like `get()` but errors with `ErrorKind::NotFound`.
+    pub fn require(&self, name: &str) -> Result<&dyn LauncherProvider, NasError> {
+        self.get(name).ok_or_else(|| NasError::new(ErrorKind::NotFound, format!("There is no provider for the launcher {:?}", name)))
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.providers.iter().map(|p| p.name()).collect()
+    }
+
+    /// Asks every provider what it detects in a directory, see
+    /// `LauncherProvider::detect()`.
+    pub fn detect(&self, dir: &Path, files: &[PathBuf], dirs: &[PathBuf]) -> Detection {
+        let mut detection = Detection::default();
+        for provider in &self.providers {
+            let found = provider.detect(dir, files, dirs);
+            detection.games.extend(found.games);
+            detection.is_game_folder |= found.is_game_folder;
+            detection.skip.extend(found.skip);
+        }
+        detection
+    }
+
+    /// Builds the launch command of the first launcher of a game that
+    /// has a provider and is installed. Launchers without an install
+    /// location are resolved through their provider first, since the
+    /// game might have been installed since it was added.
+    pub fn launch_command(&self, launchers: &[Launcher]) -> Result<(Launcher, LaunchCommand), NasError> {
+        let mut last_error = None;
+        for launcher in launchers {
+            let Some(provider) = self.get(&launcher.name) else { continue };
+            let resolved = match launcher.install_dir {
+                Some(_) => None,
+                None => provider.resolve(launcher).unwrap_or_else(|e| {
+                    warn!("Failed to resolve {} {}: {}", launcher.name, launcher.game_id, e);
+                    None
+                }),
+            };
+            let launcher = resolved.as_ref().map(|game| &game.launcher).unwrap_or(launcher);
+            match provider.launch_command(launcher) {
+                Ok(command) => return Ok((launcher.clone(), command)),
+                Err(e) => {
+                    warn!("Can't launch {} {}: {}", launcher.name, launcher.game_id, e);
+                    last_error = Some(e);
+                },
+            }
+        }
+        Err(last_error.unwrap_or_else(|| NasError::new(ErrorKind::NotFound, "None of the launchers of the game can be started")))
+    }
+
+    /// Lists the games of the provider and logs how many there are.
+    pub fn list_games(&self, name: &str) -> Result<Vec<DetectedGame>, NasError> {
+        let games = self.require(name)?.list_games()
+            .inspect_err(|e| { error!("Failed to list the games of {} with {}", name, e); })?;
+        info!("{} lists {} games", name, games.len());
+        Ok(games)
+    }
+}
+
+impl Default for ProviderRegistry {
+    fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Dummy;
+
+    impl LauncherProvider for Dummy {
+        fn name(&self) -> &'static str { "dummy" }
+        fn list_games(&self) -> Result<Vec<DetectedGame>, NasError> {
+            let mut launcher = Launcher::new("dummy".to_owned(), "1".to_owned());
+            launcher.install_dir = Some("/games/dummy".into());
+            Ok(vec![DetectedGame { name: "Dummy".to_owned(), launcher }])
+        }
+        fn launch_command(&self, launcher: &Launcher) -> Result<LaunchCommand, NasError> {
+            Ok(LaunchCommand::new("dummy", [launcher.game_id.clone()]))
+        }
+    }
+
+    #[test]
+    fn looks_up_providers_by_launcher_name() {
+        let mut registry = ProviderRegistry::new();
+        registry.register(Dummy);
+        registry.register(LocalProvider::new(Vec::new()));
+        assert_eq!(registry.names(), ["dummy", LOCAL_LAUNCHER]);
+        assert!(registry.require("unknown").is_err());
+
+        let launcher = Launcher::new("dummy".to_owned(), "1".to_owned());
+        let resolved = registry.get("dummy").unwrap().resolve(&launcher).unwrap().unwrap();
+        assert_eq!(resolved.launcher.install_dir, Some(PathBuf::from("/games/dummy")));
+
+        // launchers without a provider are skipped
+        let launchers = [Launcher::new("unknown".to_owned(), "2".to_owned()), launcher];
+        let (used, command) = registry.launch_command(&launchers).unwrap();
+        assert_eq!(used.name, "dummy");
+        assert_eq!(command, LaunchCommand::new("dummy", ["1"]));
+    }
+
+    #[test]
+    fn launches_local_executables() {
+        let dir = tempfile::tempdir().unwrap();
+        let game = dir.path().join("Hades");
+        fs::create_dir_all(&game).unwrap();
+        for file in ["Hades.exe", "Crash.exe", "unins000.exe", "readme.txt"] {
+            fs::write(game.join(file), "").unwrap();
+        }
+        let mut launcher = Launcher::new(LOCAL_LAUNCHER.to_owned(), "Hades".to_owned());
+        launcher.install_dir = Some(game.clone());
+
+        let command = LocalProvider::new(vec![dir.path().to_path_buf()]).launch_command(&launcher).unwrap();
+        assert_eq!(command.program, game.join("Hades.exe").to_string_lossy());
+        assert_eq!(command.working_dir, Some(game));
+    }
+}
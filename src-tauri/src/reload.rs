@@ -0,0 +1,188 @@
+//! This crate is for picking up changes to `server_settings.json` and
+//! `game_library.json` while the server runs, so that editing either
+//! doesn't need a restart.
+//!
+//! Both files are watched with inotify (through `notify`). Changed
+//! settings are loaded with every layer of the `config` crate and
+//! swapped in at once, a change of the address or of https makes the
+//! server bind a new listener. Hand edits of the library are merged
+//! into the library of the server, see `store::merge_libraries()`.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, error, logging::LoggingLevel, logging::logging_function};
+use crate::config::{self, Layers};
+use crate::types::ServerSettings;
+use crate::store::LibraryStore;
+use crate::provider::ProviderRegistry;
+use crate::logging;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use actix_web::web;
+use futures::channel::mpsc::UnboundedSender;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Editors tend to write a file in several steps, the changes are
+/// acted upon once the file has been quiet for this long.
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+/// Settings that only take effect with a new listener.
+pub const REBIND_KEYS: &[&str] = &["ip", "port", "tls", "tls_cert", "tls_key", "require_auth"];
+/// Settings that are only read when the server starts.
+pub const RESTART_KEYS: &[&str] = &["data_dir", "cache_dir", "images_dir", "optimized_images_dir", "storage", "name", "discovery"];
+
+/// Takes the settings of the listener, see `REBIND_KEYS`, from
+/// `listener` and everything else from `settings`.
+pub fn keep_listener(mut settings: ServerSettings, listener: &ServerSettings) -> ServerSettings {
+    settings.ip = listener.ip.clone();
+    settings.port = listener.port;
+    settings.tls = listener.tls;
+    settings.tls_cert = listener.tls_cert.clone();
+    settings.tls_key = listener.tls_key.clone();
+    settings.require_auth = listener.require_auth;
+    settings
+}
+
+/// A value that is swapped out as a whole while others read it.
+///
+/// Readers get the value as of the moment they asked, a request never
+/// sees half of the old and half of the new settings.
+pub struct Live<T>(RwLock<Arc<T>>);
+
+impl<T> Live<T> {
+    pub fn new(value: T) -> Self { Self(RwLock::new(Arc::new(value))) }
+
+    pub fn get(&self) -> Arc<T> {
+        // a panicking writer can't leave the Arc half written
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Swaps in `value` and returns the old one.
+    pub fn replace(&self, value: T) -> Arc<T> {
+        std::mem::replace(&mut *self.0.write().unwrap_or_else(|e| e.into_inner()), Arc::new(value))
+    }
+}
+
+/// Applies the changes of the watched files.
+pub struct Reloader {
+    pub layers: Layers,
+    pub settings: web::Data<Live<ServerSettings>>,
+    pub providers: web::Data<Live<ProviderRegistry>>,
+    pub store: Arc<dyn LibraryStore>,
+    /// Gets the new settings whenever the server has to bind a new
+    /// listener, which swaps them in once it listens.
+    pub rebind: UnboundedSender<ServerSettings>,
+}
+
+impl Reloader {
+    /// Loads the settings again and swaps them in. Broken settings are
+    /// logged and the old ones are kept.
+    pub fn reload_settings(&self) {
+        let new = match config::load(&self.layers) {
+            Ok(loaded) => loaded.settings,
+            Err(e) => {
+                error!("Keeping the old server settings: {}", e);
+                return;
+            },
+        };
+        let old = self.settings.get();
+        let changes = config::diff(&old, &new);
+        if changes.is_empty() {
+            trace!("The server settings didn't change");
+            return;
+        }
+        for (key, old, new) in &changes { info!("Setting {} changed from {} to {}", key, old, new); }
+        let changed = |keys: &[&str]| changes.iter().any(|(key, _, _)| keys.contains(&key.as_str()));
+
+        logging::set_level(new.log_level);
+        if changed(&["library_roots"]) { self.providers.replace(ProviderRegistry::from_settings(&new)); }
+        if changed(RESTART_KEYS) { warn!("Changes to {} only take effect after a restart", RESTART_KEYS.join(", ")); }
+        if changed(&["port", "tls"]) && new.discovery { warn!("Clients that search the network see the old port and scheme until the server restarts"); }
+        if !changed(REBIND_KEYS) {
+            self.settings.replace(new);
+            return;
+        }
+        // until the new listener is up the old one is still in use
+        self.settings.replace(keep_listener(new.clone(), &old));
+        if self.rebind.unbounded_send(new).is_err() { warn!("The server is not running anymore, it can't bind the new address"); }
+    }
+
+    /// Merges the library file into the library and logs what changed.
+    pub fn reload_library(&self) {
+        let diff = match self.store.reload() {
+            Ok(diff) => diff,
+            Err(e) => {
+                // e.g. the file is saved halfway or has a typo, the next save is tried again
+                error!("Ignoring the change of the game library: {}", e);
+                return;
+            },
+        };
+        if diff.is_empty() {
+            trace!("The game library didn't change");
+            return;
+        }
+        for name in &diff.added { info!("Library: added {}", name); }
+        for name in &diff.removed { info!("Library: removed {}", name); }
+        for (name, fields) in &diff.changed { info!("Library: changed {} of {}", fields.join(", "), name); }
+        if diff.sessions_added + diff.sessions_removed > 0 { info!("Library: added {} and removed {} play sessions", diff.sessions_added, diff.sessions_removed); }
+        for conflict in &diff.conflicts { warn!("Library: kept the server's version of {}, the edited file is the newest backup", conflict); }
+    }
+
+    /// Watches the settings file and the file of the library, if the
+    /// store has one, and reloads them in a background thread. The
+    /// files are watched for as long as the watcher is kept.
+    ///
+    /// # Errors
+    /// Fails if inotify isn't available or the directories of the files
+    /// can't be watched.
+    pub fn spawn(self, settings_path: PathBuf) -> Result<RecommendedWatcher, NasError> {
+        let library_path = self.store.watched_path().map(Path::to_path_buf);
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).context(ErrorKind::Ignore, "Failed to start watching the files")?;
+        // the directories are watched since the files are replaced by a rename when they are saved
+        let dirs: HashSet<&Path> = [Some(&settings_path), library_path.as_ref()].into_iter().flatten().filter_map(|path| path.parent()).collect();
+        for dir in dirs {
+            watcher.watch(dir, RecursiveMode::NonRecursive).context(ErrorKind::Ignore, format!("Failed to watch {:?}", dir))?;
+            trace!("Watching {:?}", dir);
+        }
+
+        match &library_path {
+            Some(library) => { info!("Reloading {:?} and {:?} when they change", settings_path, library); },
+            None => { info!("Reloading {:?} when it changes", settings_path); },
+        };
+
+        thread::Builder::new()
+            .name("reload".to_owned())
+            .spawn(move || {
+                let (mut settings_changed, mut library_changed) = (false, false);
+                loop {
+                    let next = match settings_changed || library_changed {
+                        true => events.recv_timeout(SETTLE_TIME),
+                        false => events.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                    };
+                    let event = match next {
+                        Ok(Ok(event)) => event,
+                        Ok(Err(e)) => {
+                            warn!("Failed to watch the files: {}", e);
+                            continue;
+                        },
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            if std::mem::take(&mut settings_changed) { self.reload_settings(); }
+                            if std::mem::take(&mut library_changed) { self.reload_library(); }
+                            continue;
+                        },
+                        Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                    };
+                    if matches!(event.kind, EventKind::Access(_)) { continue; }
+                    let is = |path: &Path, watched: Option<&PathBuf>| watched.is_some_and(|watched| path.file_name() == watched.file_name());
+                    for path in &event.paths {
+                        settings_changed |= is(path, Some(&settings_path));
+                        library_changed |= is(path, library_path.as_ref());
+                    }
+                }
+            })
+            .context(ErrorKind::Ignore, "Failed to start the reload thread")?;
+        Ok(watcher)
+    }
+}
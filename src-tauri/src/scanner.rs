@@ -0,0 +1,284 @@
+//! This crate is for discovering the games that are installed on
+//! the NAS. It walks the configured library roots, lets every
+//! `LauncherProvider` detect the games of its launcher, falls back
+//! to a couple of heuristics and merges the results into the
+//! `LibraryStore`.
+use crate::error::NasError;
+use crate::{trace, info, warn, error, logging::LoggingLevel, logging::logging_function};
+use crate::types::{Game, GamePatch, Launcher};
+use crate::store::LibraryStore;
+use crate::provider::{ProviderRegistry, LOCAL_LAUNCHER};
+use crate::gog;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+
+/// How deep the scanner descends into a library root looking for
+/// game folders.
+const MAX_SCAN_DEPTH: usize = 4;
+
+/// Executables which are shipped next to games but are never the
+/// game itself.
+const IGNORED_EXECUTABLES: &[&str] = &["unins", "setup", "crashhandler", "crashreport", "vc_redist", "dxsetup", "uninstall"];
+
+/// A game that was found on the disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedGame {
+    pub name: String,
+    pub launcher: Launcher,
+}
+
+/// What a `LauncherProvider` detected in a single directory.
+#[derive(Debug, Default)]
+pub struct Detection {
+    pub games: Vec<DetectedGame>,
+    /// Set if the directory is the install folder of a game, which
+    /// stops the scanner from descending any further.
+    pub is_game_folder: bool,
+    /// Sub directories the scanner must not descend into.
+    pub skip: Vec<PathBuf>,
+}
+
+/// One entry of a `ScanReport`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanEntry {
+    pub id: String,
+    pub name: String,
+    pub path: Option<PathBuf>,
+}
+
+/// What a scan has changed in the library.
+///
+/// Missing games are only reported, they are never removed from the
+/// library since the NAS share might just not be mounted.
+#[derive(Debug, Default, Serialize)]
+pub struct ScanReport {
+    pub added: Vec<ScanEntry>,
+    pub updated: Vec<ScanEntry>,
+    pub missing: Vec<ScanEntry>,
+}
+
+/// Scans all `roots` with the `providers` and merges the detected
+/// games into the `store`.
+///
+/// See `merge()` for how the games are merged. Every game that is
+/// installed below one of the `roots` but no longer exists on disk
+/// is reported as missing.
+///
+/// # Errors
+/// Unreadable directories are only logged, only errors of the
+/// `store` are returned.
+pub fn scan(store: &dyn LibraryStore, providers: &ProviderRegistry, roots: &[PathBuf]) -> Result<ScanReport, NasError> {
+    let detected = scan_roots(providers, roots);
+    info!("Detected {} games in {:?}", detected.len(), roots);
+
+    let mut report = merge(store, detected)?;
+    for game in store.games()? {
+        for launcher in game.launchers() {
+            let Some(dir) = &launcher.install_dir else { continue };
+            if roots.iter().any(|root| dir.starts_with(root)) && !dir.exists() {
+                report.missing.push(ScanEntry { id: game.id().to_owned(), name: game.name().to_owned(), path: Some(dir.clone()) });
+            }
+        }
+    }
+    info!("Scan finished: {} added, {} updated, {} missing", report.added.len(), report.updated.len(), report.missing.len());
+    Ok(report)
+}
+
+/// Merges the detected games into the `store`.
+///
+/// A detected game is matched to a library entry by its launcher
+/// name and launcher game id. Matches get their launcher entry (and
+/// with that the install location) updated, everything else is added
+/// as a new game. New games are added all at once at the end. The
+/// returned report never contains missing games.
+pub fn merge(store: &dyn LibraryStore, detected: Vec<DetectedGame>) -> Result<ScanReport, NasError> {
+    let mut report = ScanReport::default();
+    let mut library = store.games()?;
+    let mut added: Vec<Game> = Vec::new();
+    for found in detected {
+        let path = found.launcher.install_dir.clone();
+        // found twice in this scan, e.g. by two providers
+        if let Some(game) = added.iter_mut().find(|game| game.launchers().iter().any(|l| l.is_same_as(&found.launcher))) {
+            game.set_launcher(found.launcher);
+            continue;
+        }
+        let index = library.iter().position(|game| game.launchers().iter().any(|l| l.is_same_as(&found.launcher)))
+            .or_else(|| library.iter().position(|game| gog::installer_entry(game, &found.launcher, &found.name).is_some()));
+        let existing = index.map(|i| &mut library[i]);
+        match existing {
+            Some(game) => {
+                let current = game.launchers().iter().find(|l| l.is_same_as(&found.launcher));
+                if current == Some(&found.launcher) && !game.name().is_empty() { continue; }
+
+                let mut launchers = game.launchers().to_vec();
+                let mut launcher = found.launcher;
+                match gog::installer_entry(game, &launcher, &found.name).cloned() {
+                    // the installed game takes over the entry its installer made
+                    Some(installer) if current.is_none() => {
+                        if launcher.installers.is_empty() { launcher.installers = installer.installers.clone(); }
+                        launchers.retain(|l| !l.is_same_as(&installer));
+                        launchers.push(launcher);
+                    },
+                    _ => match launchers.iter_mut().find(|l| l.is_same_as(&launcher)) {
+                        Some(existing) => *existing = launcher,
+                        None => launchers.push(launcher),
+                    },
+                }
+                let patch = GamePatch {
+                    name: game.name().is_empty().then(|| found.name.clone()),
+                    launcher: Some(launchers),
+                    ..GamePatch::default()
+                };
+                if let Some(game_after) = store.update_game(game.id(), patch)? {
+                    *game = game_after;
+                }
+                trace!("Updated {:?} at {:?}", game.name(), path);
+                report.updated.push(ScanEntry { id: game.id().to_owned(), name: game.name().to_owned(), path });
+            },
+            None => {
+                let mut game = Game::new();
+                game.set_name(found.name);
+                game.set_launcher(found.launcher);
+                // the id is known before the store sees the game
+                game.ensure_id();
+                trace!("Adding {:?} at {:?}", game.name(), path);
+                report.added.push(ScanEntry { id: game.id().to_owned(), name: game.name().to_owned(), path });
+                added.push(game);
+            },
+        }
+    }
+    if !added.is_empty() { store.add_games(added)?; }
+    Ok(report)
+}
+
+/// Walks every root and returns all of the games that were found.
+pub fn scan_roots(providers: &ProviderRegistry, roots: &[PathBuf]) -> Vec<DetectedGame> {
+    let mut found = Vec::new();
+    for root in roots {
+        if !root.is_dir() {
+            warn!("The library root {:?} is not a directory", root);
+            continue;
+        }
+        walk(providers, root, 0, &mut found);
+    }
+    found
+}
+
+/// Detects the games in `dir` and descends into its sub directories
+/// unless `dir` itself turned out to be a game folder.
+fn walk(providers: &ProviderRegistry, dir: &Path, depth: usize, found: &mut Vec<DetectedGame>) {
+    let (files, dirs) = match list_dir(dir) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to read the directory {:?} with {:?}", dir, e);
+            return;
+        }
+    };
+
+    let before = found.len();
+    let Detection { games, mut is_game_folder, skip } = providers.detect(dir, &files, &dirs);
+    found.extend(games);
+    // desktop entries and bare executables belong to no launcher
+    found.extend(files.iter().filter(|f| f.extension().is_some_and(|e| e == "desktop")).filter_map(|f| detect_desktop_entry(f)));
+    if !is_game_folder && found.len() == before && depth > 0 && files.iter().any(|f| is_executable(f)) {
+        found.push(detected(dir_name(dir), LOCAL_LAUNCHER, dir_name(dir), dir));
+        is_game_folder = true;
+    }
+
+    if is_game_folder || depth >= MAX_SCAN_DEPTH { return; }
+    for sub in dirs {
+        if skip.contains(&sub) || sub.file_name().and_then(std::ffi::OsStr::to_str).is_some_and(|n| n.starts_with('.')) { continue; }
+        walk(providers, &sub, depth + 1, found);
+    }
+}
+
+fn list_dir(dir: &Path) -> std::io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let (mut files, mut dirs) = (Vec::new(), Vec::new());
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() { dirs.push(path) } else { files.push(path) }
+    }
+    files.sort();
+    dirs.sort();
+    Ok((files, dirs))
+}
+
+fn dir_name(dir: &Path) -> String {
+    dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+fn detected(name: String, launcher: &str, game_id: String, install_dir: &Path) -> DetectedGame {
+    let mut launcher = Launcher::new(launcher.to_owned(), game_id);
+    launcher.install_dir = Some(install_dir.to_path_buf());
+    DetectedGame { name, launcher }
+}
+
+/// Checks if a file looks like the main executable of a game.
+pub(crate) fn is_executable(path: &Path) -> bool {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+    if IGNORED_EXECUTABLES.iter().any(|ignored| name.starts_with(ignored)) { return false; }
+    match path.extension().and_then(std::ffi::OsStr::to_str) {
+        Some("exe" | "x86_64" | "x86" | "AppImage") => true,
+        Some(_) => false,
+        None => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+            }
+            #[cfg(not(unix))]
+            { false }
+        }
+    }
+}
+
+/// Reads `Name=` and `Exec=` (or `Path=`) of a freedesktop entry.
+fn detect_desktop_entry(file: &Path) -> Option<DetectedGame> {
+    let contents = fs::read_to_string(file).ok()?;
+    let value = |key: &str| contents.lines().find_map(|l| l.strip_prefix(key).map(|v| v.trim().to_owned()));
+    if !contents.lines().any(|l| l.trim() == "[Desktop Entry]") { return None; }
+    let name = value("Name=")?;
+    let stem = file.file_stem()?.to_string_lossy().into_owned();
+    let install_dir = value("Path=").map(PathBuf::from).unwrap_or_else(|| file.parent().unwrap_or(file).to_path_buf());
+    Some(detected(name, "desktop", stem, &install_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::JsonFileStore;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn detects_and_merges_games() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("games");
+        write(&root.join("steamapps/appmanifest_620.acf"), "\"AppState\"\n{\n\t\"appid\"\t\t\"620\"\n\t\"name\"\t\t\"Portal 2\"\n\t\"installdir\"\t\t\"Portal 2\"\n}\n");
+        write(&root.join("steamapps/common/Portal 2/portal2.exe"), "");
+        write(&root.join("GOG/Celeste/goggame-1234.info"), r#"{ "gameId": "1234", "name": "Celeste" }"#);
+        write(&root.join("Other/Hades/Hades.exe"), "");
+        write(&root.join("Other/Hades/unins000.exe"), "");
+
+        let store = JsonFileStore::open(dir.path().join("game_library.json"), 0);
+        let providers = ProviderRegistry::with_roots(vec![root.clone()]);
+        let report = scan(&store, &providers, std::slice::from_ref(&root)).unwrap();
+        let mut added: Vec<_> = report.added.iter().map(|e| e.name.as_str()).collect();
+        added.sort();
+        assert_eq!(added, ["Celeste", "Hades", "Portal 2"]);
+
+        // a second scan finds the same games and changes nothing
+        let report = scan(&store, &providers, std::slice::from_ref(&root)).unwrap();
+        assert!(report.added.is_empty() && report.updated.is_empty() && report.missing.is_empty());
+
+        fs::remove_dir_all(root.join("Other/Hades")).unwrap();
+        let report = scan(&store, &providers, &[root]).unwrap();
+        assert_eq!(report.missing.len(), 1);
+        assert_eq!(report.missing[0].name, "Hades");
+    }
+}
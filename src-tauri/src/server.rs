@@ -1,28 +1,55 @@
 //! This crate is for handling the server side code of the application.
 //! This means that this crate orchestrates which functions should be called,
 //! it also defines the API. 
-use crate::error::NasError;
+use crate::error::{NasError, ErrorKind, Context};
 use crate::{trace, info, warn, error, logging::LoggingLevel, logging::logging_function};
-use crate::types::{Launcher, Game, GameLibrary, GameNameRequest, ServerSettings};
+use crate::types::{ArtworkKind, Game, ImageProfile, OutputFormat, ResizePolicy, ServerSettings, StorageBackend};
 use crate::server_routes::*;
+use crate::store::{LibraryStore, JsonFileStore, DEFAULT_BACKUP_COUNT};
+use crate::files::write_atomic;
+use crate::scanner;
+use crate::steam::{self, SteamApp, STEAM_LAUNCHER};
+use crate::steam_api::SteamApiClient;
+use crate::gog;
+use crate::provider::ProviderRegistry;
+use crate::chunks::ChunkCache;
+use crate::devices::{self, DeviceRegistry, DeviceStatus};
+use crate::users::{NewUser, Role, UserRegistry};
+use crate::certs;
+use crate::config::{self, Layers};
+use crate::data_dir::DataDir;
+use crate::jobs::{JobQueue, Worker};
+use crate::artwork::{self, ArtworkEntry, ArtworkStore, FetchedImage};
+use crate::reload::{self, Live, Reloader};
+use crate::logging;
+use nas_game_lib::discovery::{self, Announcement};
 
 use clap::ArgMatches;
 use std::{fs, env};
-use std::sync::Mutex;
+use std::io::Cursor;
+use std::sync::Arc;
 use std::path::{Path, PathBuf};
-use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder, http::StatusCode};
+use std::net::IpAddr;
+use actix_web::{web, App, HttpServer, dev::Server, middleware::{from_fn, Condition}};
+use futures::{future::{self, Either}, StreamExt};
 use serde_json;
 use image::*;
 use webp::*;
-use steamgriddb_api::{Client, query_parameters::QueryType::Grid};
-use steamgriddb_api::query_parameters::{AnimtionType, GridDimentions, GridQueryParameters, Humor, Nsfw};
+use steamgriddb_api::{Client, query_parameters::QueryType::{Grid, Hero, Icon, Logo}};
+use steamgriddb_api::query_parameters::{AnimtionType, GridDimentions, GridQueryParameters, HeroQueryParameters, Humor, IconQueryParameters, LogoQueryParameters, Nsfw};
 use reqwest;
-use futures::stream::{self, StreamExt};
 
-const DEFAULT_GAME_LIB_PATH: &str = "game_library.json";
-const DEFAULT_SERVER_SETTINGS_PATH: &str = "server_settings.json";
-const DEFAULT_GAME_LIBRARY_CONTENTS: &str = "[]";
 
+/// How much time the AVIF encoder spends on an image, from 1 (the
+/// smallest files) to 10 (the fastest).
+const AVIF_SPEED: u8 = 6;
+
+/// The name of the machine the server runs on, if it has one.
+fn hostname() -> Option<String> {
+    env::var("HOSTNAME").or_else(|_| fs::read_to_string("/etc/hostname")).ok()
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+}
 
 /// Expands the `~/` expression for relative paths on linux-like systems
 ///
@@ -37,7 +64,7 @@ const DEFAULT_GAME_LIBRARY_CONTENTS: &str = "[]";
 /// "example/" -> "example"
 /// "example" -> "example"
 /// ```
-fn expand_tilde(path: &str) -> PathBuf {
+pub fn expand_tilde(path: &str) -> PathBuf {
     if path.starts_with("~/") {
         if let Ok(home) = env::var("HOME") {
             return PathBuf::from(home).join(&path[2..]);
@@ -46,36 +73,9 @@ fn expand_tilde(path: &str) -> PathBuf {
     PathBuf::from(path)
 }
 
-/// Sets up the default working directory for the server.
-///
-/// It first generates where the working directory should be.
-/// Then it checks if the path exists and if it doesn't it
-/// creates all the paths recursively to avoid any errors.
-/// It will print an error if the path creating fails. This
-/// is because the `create_dir_all()` function will error if
-/// the path already exists. This of course shouldn't happen
-/// since it checks for the path prior thus the error will
-/// likely be of a different kind.
-///
-/// # Path
-///
-/// Resolves to: let path = expand_tilde("~/.local/share/nas-game/server");
-pub fn default_cwd() -> PathBuf {
-    let path = expand_tilde("~/.local/share/nas-game/server");
-    if !path.exists() {
-        if let Err(e) = std::fs::create_dir_all(&path) {
-            error!("Failed to create the default directory {:?} with {:?}", std::env::current_dir().unwrap().join(&path),  e);
-            return path;
-        };
-        info!("Folder {:?} was created since it was missing", &path);
-    }
-    path
-}
-
-/// Creates a dir in the working directory 
+/// Creates a dir, and its parents
 ///
-/// Creates a dir in the current working directory.
-/// Should `std::fs::create_dir()` fail then the error will
+/// Should `std::fs::create_dir_all()` fail then the error will
 /// be logged using trace. It is not uncommon for the function
 /// to error since it will often be called in duplicate
 /// contexts.
@@ -88,25 +88,10 @@ pub fn default_cwd() -> PathBuf {
 /// `std::fs::create_dif()` returns.
 pub fn prepare_folder<P: AsRef<Path>>(path: P) -> P {
     // just issue a warning but don't error 
-    let _ = std::fs::create_dir(&path).map_err(|e| { trace!("Failed to create the directory {:?} with {:?}", std::env::current_dir().unwrap().join(&path),  e); });
+    let _ = std::fs::create_dir_all(&path).map_err(|e| { trace!("Failed to create the directory {:?} with {:?}", path.as_ref(), e); });
     path
 } 
 
-/// Read the server settings from the specified file
-///
-/// This reads the file contents and then tries to parse the
-/// results into a `ServerSettings` struct.
-///
-/// # Errors
-/// This fuction can error both at the file read and the parsing
-/// part. Should the `fs::read_to_sting()` fail then this function
-/// will map the error to be `NasError::Ignore`. The parsing error
-/// will be mapped to `NasError::FailedToReadFile`.
-pub fn get_server_settings(path: &Path) -> Result<ServerSettings, NasError> {
-    let file = fs::read_to_string(path).map_err(|_| NasError::FailedToReadFile)?;
-    serde_json::from_str::<ServerSettings>(&file).map_err(|_| NasError::FailedToParse)
-}
-
 /// Write server settings to a file
 ///
 /// This will write the server settings if provided to the
@@ -115,45 +100,45 @@ pub fn get_server_settings(path: &Path) -> Result<ServerSettings, NasError> {
 ///
 /// # Errors
 /// It can only error at the serialization or at the file writing.
-/// As such the errors can only be `NasError::FailedToSerialize` or
-/// `NasError::FailedToWrite`
+/// As such the errors can only be `ErrorKind::FailedToSerialize` or
+/// `ErrorKind::FailedToWrite` (`ErrorKind::DiskFull` if the disk is full)
 pub fn write_server_settings(path: &Path, settings: Option<ServerSettings>) -> Result<(), NasError> {
     let settings = settings.unwrap_or_default();
-    let settings_serialized = serde_json::to_string(&settings).map_err(|_| NasError::FailedToSerialize)?;
-    fs::write(path, settings_serialized).map_err(|_| NasError::FailedToWrite)?;
-    Ok(())
+    let settings_serialized = serde_json::to_string(&settings).context(ErrorKind::FailedToSerialize, "Failed to serialize the server settings")?;
+    write_atomic(path, settings_serialized.as_bytes())
+}
+
+/// Whether `fetch_artwork()` has anywhere to get `kind` of `game`
+/// from, only Steam games have screenshots.
+pub fn has_artwork_source(game: &Game, kind: ArtworkKind) -> bool {
+    kind != ArtworkKind::Screenshots || game.launchers().iter().any(|l| l.name == STEAM_LAUNCHER)
 }
 
-/// Fetch the image from the steam grid api based on the input
+/// Fetches the artwork of `kind` of `game`.
 ///
-/// This function will attempt to fetch and save an image from
-/// the steam grid api. It uses the `search_for` parameter to
-/// filter the results. The first result from the results vec
-/// is used to then fetch the image and save it to the `path_out`
-/// directory
+/// Grids, heroes, logos and icons come from SteamGridDB, by the
+/// `steam_grid_id` of the game or else the first result of searching
+/// for its name. Screenshots come from the Steam store page of the
+/// game, games without a Steam launcher have none.
 ///
 /// # Errors
-/// This fucition has many points of failure. So many that it
-/// might be best to split this function into several smaller
-/// ones to keep things simpler.
-///
-/// First and foremost the search itself can error due to
-/// network issues.
-///
-/// The second point of failure is that the search might not
-/// return any results.
-///
-/// Additional points of failure either fall into similar
-/// categories as the ones above or are results of calling
-/// `unwrap()` on the `extension()` and `to_str()` operands.
-///
-/// In other words this function returns a wide range of
-/// errors which are not being logged or handled properly.
-///
-/// # TODO
-///
-/// Improve the error handling in this function
-pub async fn fetch_image(search_for: &str, path_out: &Path) -> Result<(), Box<dyn std::error::Error>> {
+/// Any network issue, be it with the search, the image query or
+/// the download itself, is reported as `ErrorKind::FailedToFetch`.
+/// Should the search not return any results, or the game not have
+/// any images, then this errors with `ErrorKind::NotFound`.
+pub async fn fetch_artwork(game: &Game, kind: ArtworkKind) -> Result<Vec<FetchedImage>, NasError> {
+    if kind == ArtworkKind::Screenshots {
+        let Some(launcher) = game.launchers().iter().find(|l| l.name == STEAM_LAUNCHER) else {
+            trace!("{:?} is not a Steam game and has no screenshots", game.name());
+            return Ok(Vec::new());
+        };
+        let mut images = Vec::new();
+        for url in SteamApiClient::store_from_env().screenshots(&launcher.game_id).await? {
+            let (extension, bytes) = download_image(&url).await?;
+            images.push(FetchedImage { steam_grid_id: None, source_url: url, extension, bytes });
+        }
+        return Ok(images);
+    }
 
     // get api key for steam grid
     let key_env = "STEAM_GRID_API_KEY";
@@ -163,131 +148,355 @@ pub async fn fetch_image(search_for: &str, path_out: &Path) -> Result<(), Box<dy
     });
     trace!("STEAM_GRID_API_KEY is {:?}", key);
     
-    // stearch for the game
+    // stearch for the game, unless it is known already
     let client = Client::new(key);
-    let games = client.search(search_for).await?;
-    let first_game = games.iter().next().ok_or("No games found")?;
-
-    let grid_query = GridQueryParameters {
-        types: Some(&[AnimtionType::Static]),
-        dimentions: Some(&[GridDimentions::D600x900]),
-        nsfw: Some(&Nsfw::Any),
-        humor: Some(&Humor::False),
-        ..Default::default()
+    let steam_grid_id: usize = match game.steam_grid_id() {
+        Some(id) => id.parse().context(ErrorKind::BadRequest, format!("The steam_grid_id {:?} of {:?} is not a number", id, game.name()))?,
+        None => {
+            let games = client.search(game.name()).await.map_err(|e| {
+                NasError::new(ErrorKind::FailedToFetch, format!("Failed to search SteamGridDB for {:?}: {}", game.name(), e))
+            })?;
+            let first_game = games.first()
+                .ok_or_else(|| NasError::new(ErrorKind::NotFound, format!("No games found for {:?}", game.name())))?;
+            info!("Found {:?} ({}) on SteamGridDB for {:?}", first_game.name, first_game.id, game.name());
+            first_game.id
+        },
+    };
+
+    let types: &[AnimtionType] = &[AnimtionType::Static];
+    let query = match kind {
+        ArtworkKind::Grids => Grid(Some(GridQueryParameters {
+            types: Some(types),
+            dimentions: Some(&[GridDimentions::D600x900]),
+            nsfw: Some(&Nsfw::Any),
+            humor: Some(&Humor::False),
+            ..Default::default()
+        })),
+        ArtworkKind::Heroes => Hero(Some(HeroQueryParameters { types: Some(types), nsfw: Some(&Nsfw::Any), humor: Some(&Humor::False), ..Default::default() })),
+        ArtworkKind::Logos => Logo(Some(LogoQueryParameters { types: Some(types), nsfw: Some(&Nsfw::Any), humor: Some(&Humor::False), ..Default::default() })),
+        ArtworkKind::Icons => Icon(Some(IconQueryParameters { types: Some(types), nsfw: Some(&Nsfw::Any), humor: Some(&Humor::False), ..Default::default() })),
+        ArtworkKind::Screenshots => unreachable!("screenshots don't come from SteamGridDB"),
     };
 
     // get the image list based on the game
-    let images = client.get_images_for_id(first_game.id, &Grid(Some(grid_query))).await?;
-    // client.get_official_steam_images(steam_app_id)
-
-    // get get the file extensions in a scuffed manner
-    let temp = PathBuf::from(&images[0].url);
-    let extension = temp.extension().unwrap();
-    info!("The file extension is: {:?}", &extension);
-    let complete_path = path_out.join(first_game.name.to_owned() + "." + extension.to_str().unwrap());
-    trace!("The complete path is: {:?}", complete_path);
-
-    // get the image
-    let response = reqwest::get(&images[0].url).await?;
-    if response.status().is_success() {
-
-        let mut dest = fs::File::create(complete_path)?;
-        let bytes = response.bytes().await?;
-        let mut content = bytes.as_ref();
-
-        std::io::copy(&mut content, &mut dest)?;
-        info!("Image saved as {}.{:?}", first_game.name, extension);
-    } else {
-        error!("Failed to fetch image: {}", response.status());
-    }
-    Ok(())
+    let images = client.get_images_for_id(steam_grid_id, &query).await.map_err(|e| {
+        NasError::new(ErrorKind::FailedToFetch, format!("Failed to query the {} of {:?}: {}", kind.as_str(), game.name(), e))
+    })?;
+    let image = images.first()
+        .ok_or_else(|| NasError::new(ErrorKind::NotFound, format!("There are no {} for {:?}", kind.as_str(), game.name())))?;
+    let (extension, bytes) = download_image(&image.url).await?;
+    Ok(vec![FetchedImage { steam_grid_id: Some(steam_grid_id.to_string()), source_url: image.url.clone(), extension, bytes }])
+}
+
+/// Downloads the image at `url`, together with the extension of its
+/// file name.
+async fn download_image(url: &str) -> Result<(String, Vec<u8>), NasError> {
+    let parsed = reqwest::Url::parse(url).context(ErrorKind::FailedToFetch, format!("{:?} is not a url", url))?;
+    let extension = Path::new(parsed.path()).extension().and_then(std::ffi::OsStr::to_str)
+        .ok_or_else(|| NasError::new(ErrorKind::FailedToFetch, format!("The image url {:?} has no file extension", url)))?
+        .to_owned();
+    trace!("The file extension is: {:?}", &extension);
+
+    let response = reqwest::get(url).await
+        .and_then(|response| response.error_for_status())
+        .context(ErrorKind::FailedToFetch, format!("Failed to fetch the image {:?}", url))?;
+    let bytes = response.bytes().await.context(ErrorKind::FailedToFetch, format!("Failed to fetch the image {:?}", url))?;
+    Ok((extension, bytes.to_vec()))
 }
 
-/// Optimizes images from one directory into another
+/// Optimizes an image into another file
 ///
-/// This function takes in a file and a target
-/// directory. It will  optimize and save it to
-/// the `dir_out`. The images are resized to the target
-/// dimension if provided, otherwise the dimensions are
-/// preserverd.
+/// This function takes in a file and the path of the
+/// optimized image. The image is resized into the size of
+/// `profile` by its `ResizePolicy`, which never stretches
+/// it, and is encoded as its `OutputFormat`. The quality of
+/// the lossy formats goes from 0 to 100.
 ///
 /// # Errors
 ///
 /// This function can only error with the following errors:
-/// `NasError::FailedToReadFile`, `NasError::FailedToEncode`
-/// or `NasError::FailedToWrite`. These errors are explanatory
-pub fn optimize_image(file: &Path, dir_out: &Path, target_dimension: &Option<(u32, u32)>) -> Result<(), NasError> {
-
-    let img = image::open(file).map_err(|e| {
-        error!("Failed to read image at {:?} with {:?}", file, e);
-        NasError::FailedToReadFile
-    })?;
-    
-    let (w, h) = target_dimension.unwrap_or_else(|| img.dimensions());
-    let size_factor = 1.0;
-
-    let img: DynamicImage = image::DynamicImage::ImageRgba8(imageops::resize(
-        &img,
-        (w as f64 * size_factor) as u32,
-        (h as f64 * size_factor) as u32,
-        imageops::FilterType::Triangle,
-    ));
-
-    // webp encoder
-    let encoder: Encoder = Encoder::from_image(&img).map_err(|e| {
-        error!("Failed to encode the image at {:?} with {}", file, e);
-        NasError::FailedToEncode
-    })?;
-    let webp: WebPMemory = encoder.encode(85f32); // quality as f32
-
-    // let file_name = file.file_name().unwrap_or(std::ffi::OsStr::new("fail.webp"));
-    // let file_name = file.file_stem().unwrap().join();
-    let stem = file.file_stem().unwrap_or(std::ffi::OsStr::new("fail"));
-    let file_name = std::ffi::OsString::from(format!("{}.webp", stem.to_string_lossy()));
+/// `ErrorKind::FailedToReadFile`, `ErrorKind::FailedToEncode`
+/// or `ErrorKind::FailedToWrite`. These errors are explanatory
+pub fn optimize_image(file: &Path, out_path: &Path, profile: &ImageProfile) -> Result<(), NasError> {
+
+    let img = image::open(file).context(ErrorKind::FailedToReadFile, format!("Failed to read image at {:?}", file))?;
+
+    let (w, h) = (profile.width, profile.height);
+    let filter = imageops::FilterType::Lanczos3;
+    let img = match profile.resize {
+        ResizePolicy::Fit if img.width() <= w && img.height() <= h => img,
+        ResizePolicy::Fit => img.resize(w, h, filter),
+        ResizePolicy::Fill => img.resize_to_fill(w, h, filter),
+        ResizePolicy::Pad => {
+            let fitted = img.resize(w, h, filter);
+            let mut padded = RgbaImage::new(w, h);
+            imageops::overlay(&mut padded, &fitted, ((w - fitted.width()) / 2) as i64, ((h - fitted.height()) / 2) as i64);
+            DynamicImage::ImageRgba8(padded)
+        },
+    };
 
-    let out_path = dir_out.join(file_name);
+    let encoding_failed = |e: &dyn std::fmt::Display| NasError::new(ErrorKind::FailedToEncode, format!("Failed to encode the image at {:?}: {}", file, e));
+    // the encoders only take 8 bit colors
+    let rgba = DynamicImage::ImageRgba8(img.to_rgba8());
+    let quality = profile.quality.clamp(1.0, 100.0);
+    let mut bytes = Cursor::new(Vec::new());
+    match profile.format {
+        OutputFormat::Webp | OutputFormat::WebpLossless => {
+            let encoder: Encoder = Encoder::from_image(&rgba).map_err(|e| encoding_failed(&e))?;
+            let webp: WebPMemory = match profile.format {
+                OutputFormat::WebpLossless => encoder.encode_lossless(),
+                _ => encoder.encode(quality),
+            };
+            bytes.get_mut().extend_from_slice(&webp);
+        },
+        OutputFormat::Avif => {
+            let encoder = codecs::avif::AvifEncoder::new_with_speed_quality(&mut bytes, AVIF_SPEED, quality as u8);
+            rgba.write_with_encoder(encoder).map_err(|e| encoding_failed(&e))?;
+        },
+        OutputFormat::Jpeg => {
+            let encoder = codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, quality as u8);
+            DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder).map_err(|e| encoding_failed(&e))?;
+        },
+        OutputFormat::Png => rgba.write_to(&mut bytes, image::ImageFormat::Png).map_err(|e| encoding_failed(&e))?,
+    }
 
-    std::fs::write(&out_path, &*webp).map_err(|e| {
-        error!("Failed to write imgage to file at {:?} with {:?}", out_path, e);
-        NasError::FailedToWrite
-    })?;
+    // the file may be served while it is written
+    write_atomic(out_path, bytes.get_ref())?;
     Ok(())
 }
 
+/// Optimizes one image of the artwork into `dir_out`, with the profile
+/// of `artwork::optimized_profile()`, and returns where it was written,
+/// see `artwork::optimized_path()`. Images that are newer than the
+/// artwork they were optimized from, with the same profile, are kept
+/// unless `force` is set.
+///
+/// # Errors
+/// Those of `optimize_image()`, or `ErrorKind::FailedToCreateFolder`.
+pub fn optimize_artwork(artwork: &ArtworkStore, entry: &ArtworkEntry, settings: &ServerSettings, dir_out: &Path, bounds: (Option<u32>, Option<u32>), force: bool) -> Result<PathBuf, NasError> {
+    let (profile, path) = artwork::optimized_target(entry, settings, dir_out, bounds);
+    let original = artwork.path(entry);
+    if !force && !artwork::is_outdated(&original, &path, &profile) {
+        trace!("{:?} is up to date", path);
+        return Ok(path);
+    }
+    let parent = path.parent().unwrap_or(dir_out);
+    fs::create_dir_all(parent).context(ErrorKind::FailedToCreateFolder, format!("Failed to create {:?}", parent))?;
+    optimize_image(&original, &path, &profile)?;
+    artwork::write_fingerprint(&path, &profile)?;
+    if bounds != (None, None) { artwork::prune_sized_copies(entry, dir_out); }
+    Ok(path)
+}
+
+/// Opens the `LibraryStore` that was selected in the settings.
+///
+/// Should the sqlite backend be selected in a build without the
+/// `sqlite` feature, then this falls back to the json file.
+///
+/// # Errors
+/// Those of `SqliteStore::open()`. A database that fails to open is
+/// never replaced by the json file, which is usually out of date.
+pub fn open_library_store(settings: &ServerSettings, dirs: &DataDir) -> Result<Arc<dyn LibraryStore>, NasError> {
+    let json_path = dirs.library();
+    match settings.storage {
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite => {
+            let db_path = dirs.library_db();
+            let store = crate::sqlite_store::SqliteStore::open(&db_path)?;
+            info!("Using the sqlite game library at {:?}", db_path);
+            return Ok(Arc::new(store));
+        },
+        #[cfg(not(feature = "sqlite"))]
+        StorageBackend::Sqlite => { warn!("This build does not support the sqlite backend"); },
+        StorageBackend::Json => (),
+    };
+    info!("Using the json game library at {:?}", json_path);
+    Ok(Arc::new(JsonFileStore::open(json_path, DEFAULT_BACKUP_COUNT)))
+}
 
-/// Iterate over all images in one directory and output the
-/// optimized images to another
+/// Imports `game_library.json` into `game_library.db`.
 ///
-/// This iterates over all files in a given directory `dir_in`,
-/// filters them and then optimizes and saves them to a target
-/// directory
-/// 
 /// # Errors
 ///
 /// This will not error and it instead only log any issues.
-pub fn optimize_images(dir_in: &Path, dir_out: &Path) {
-    let entries = match fs::read_dir(&dir_in) {
-        Ok(entries) => entries,
-        Err(e) => {
-            error!("Failed to read input directory {:?} with {:?}", &dir_in, e);
-            return;
-        }
+#[cfg(feature = "sqlite")]
+fn import_json_library(dirs: &DataDir) {
+    let (json_path, db_path) = (dirs.library(), dirs.library_db());
+    match crate::sqlite_store::SqliteStore::open(&db_path).and_then(|db| db.import_json(&json_path)) {
+        Ok(n) => { info!("Imported {} games from {:?} into {:?}", n, json_path, db_path); },
+        Err(e) => { error!("Failed to import {:?} into {:?} with {:?}", json_path, db_path, e); },
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn import_json_library(_dirs: &DataDir) {
+    error!("This build does not support the sqlite backend, there is nothing to import into");
+}
+
+/// The `--config`, `--data-dir`, `--ip`, `--port`, `--log-level` and
+/// `--set` flags as a layer of the settings.
+///
+/// # Errors
+/// Fails with `ErrorKind::BadRequest` if `--set` isn't `key=value`.
+fn config_layers(args: &ArgMatches) -> Result<Layers, NasError> {
+    let mut cli = Vec::new();
+    for (flag, key) in [("data-dir", "data_dir"), ("ip", "ip"), ("port", "port"), ("log-level", "log_level")] {
+        if let Some(value) = args.get_one::<String>(flag) { cli.push((key.to_owned(), value.clone())); }
+    }
+    for pair in args.get_many::<String>("set").into_iter().flatten() {
+        let (key, value) = pair.split_once('=')
+            .ok_or_else(|| NasError::new(ErrorKind::BadRequest, format!("--set {:?} is not key=value", pair)))?;
+        cli.push((key.trim().to_owned(), value.to_owned()));
+    }
+    Ok(Layers::from_process(args.get_one::<PathBuf>("config").cloned(), cli))
+}
+
+/// `nas-game server config show|validate|set`
+fn config_command(layers: &Layers, args: &ArgMatches) -> std::io::Result<()> {
+    let result = match args.subcommand() {
+        Some(("set", set_args)) => {
+            let key = set_args.get_one::<String>("key").expect("the key is required");
+            let value = set_args.get_one::<String>("value").expect("the value is required");
+            let path = layers.settings_path();
+            config::set(&path, key, value).map(|_| {
+                println!("Set {} in {:?}", key, path);
+                if layers.env.iter().any(|(var, _)| *var == config::env_var(key)) {
+                    warn!("{} is set, it overrides the settings file", config::env_var(key));
+                }
+            })
+        },
+        Some(("validate", _)) => config::merge(layers).and_then(|loaded| {
+            let problems = config::validate(&loaded.settings);
+            for problem in &problems { println!("{}", problem); }
+            match problems.len() {
+                0 => {
+                    println!("The settings of {:?} are valid", loaded.path);
+                    Ok(())
+                },
+                n => Err(NasError::new(ErrorKind::BadRequest, format!("Found {} problems in the settings of {:?}", n, loaded.path))),
+            }
+        }),
+        _ => config::merge(layers).map(|loaded| {
+            println!("# {:?}", loaded.path);
+            for (key, value, source) in config::describe(&loaded) {
+                println!("{:<22} {:<40} ({})", key, value, source);
+            }
+        }),
     };
-    for entry in entries {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        match path.extension().and_then(std::ffi::OsStr::to_str) {
-            Some("png" | "jpg" | "webp") => {
-                // ignore the errors
-                // limit the size of the image since it likely won't
-                // exeed an image size of 308x461 ± x% on a 1440p monitor
-                let _ = optimize_image(&path, &dir_out, &Some((308,461)));
-            },
-            _ => { warn!("No images were found with the correct file extension"); }
-        }
+    result.map_err(|e| {
+        error!("{}", e);
+        std::io::Error::other(e.to_string())
+    })
+}
+
+/// Everything the routes get as app data, shared by every listener.
+#[derive(Clone)]
+struct AppState {
+    store: web::Data<dyn LibraryStore>,
+    settings: web::Data<Live<ServerSettings>>,
+    providers: web::Data<Live<ProviderRegistry>>,
+    chunks: web::Data<ChunkCache>,
+    devices: web::Data<DeviceRegistry>,
+    users: web::Data<UserRegistry>,
+    dirs: web::Data<DataDir>,
+    jobs: web::Data<JobQueue>,
+    artwork: web::Data<ArtworkStore>,
+}
+
+/// Loads the certificate of the listener, if it serves https.
+///
+/// # Errors
+/// Fails if the certificate or its key can't be loaded.
+fn tls_config(state: &AppState, settings: &ServerSettings) -> std::io::Result<Option<rustls::ServerConfig>> {
+    match settings.tls {
+        true => Ok(Some(certs::server_config(&state.dirs.resolve(&settings.tls_cert), &state.dirs.resolve(&settings.tls_key))
+            .map_err(|e| std::io::Error::other(e.to_string()))?)),
+        false => Ok(None),
     }
 }
 
+/// Binds a listener on the address of `settings`, speaking https if
+/// there is a `tls` config, see `tls_config()`. It only accepts
+/// connections once it is awaited.
+///
+/// # Errors
+/// Fails if the address can't be bound.
+fn bind(state: AppState, settings: &ServerSettings, tls: Option<rustls::ServerConfig>) -> std::io::Result<Server> {
+    let require_auth = settings.require_auth;
+    if !require_auth { warn!("require_auth is off, anyone on the network can change the library"); }
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(state.store.clone())
+            .app_data(state.settings.clone())
+            .app_data(state.providers.clone())
+            .app_data(state.chunks.clone())
+            .app_data(state.devices.clone())
+            .app_data(state.users.clone())
+            .app_data(state.dirs.clone())
+            .app_data(state.jobs.clone())
+            .app_data(state.artwork.clone())
+            .wrap(Condition::new(require_auth, from_fn(devices::require_signature)))
+            .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+            .app_data(web::PathConfig::default().error_handler(path_error_handler))
+            .service(route_hello)
+            .service(route_echo)
+            .service(route_add_dummy_get)
+            .service(route_add_to_games)
+            .service(route_get_games)
+            .service(route_get_game)
+            .service(route_patch_game)
+            .service(route_delete_game)
+            .service(route_save_library)
+            .service(route_scan)
+            .service(route_steam_import)
+            .service(route_steam_profile)
+            .service(route_get_launchers)
+            .service(route_import_launcher)
+            .service(route_get_launch_command)
+            .service(route_get_manifest)
+            .service(route_get_game_file)
+            .service(route_get_chunk_index)
+            .service(route_get_chunk)
+            .service(route_register_device)
+            .service(route_get_devices)
+            .service(route_login)
+            .service(route_logout)
+            .service(route_get_me)
+            .service(route_get_my_games)
+            .service(route_patch_my_game)
+            .service(route_get_users)
+            .service(route_add_user)
+            .service(route_patch_user)
+            .service(route_delete_user)
+            .service(route_add_session)
+            .service(route_get_stats)
+            .service(route_get_game_stats)
+            .service(route_get_user_stats)
+            .service(route_get_artwork)
+            .service(route_get_game_artwork)
+            .service(route_get_optimized_artwork)
+            .service(route_download_images)
+            .service(route_optimize_images_server)
+            .service(route_get_jobs)
+            .service(route_all_job_events)
+            .service(route_get_job)
+            .service(route_job_events)
+            .service(route_cancel_job)
+    });
+    let address = (settings.ip.as_str(), settings.port);
+    let server = match tls {
+        Some(config) => {
+            info!("Serving https on {}:{}", address.0, address.1);
+            server.bind_rustls_0_23(address, config)?
+        },
+        None => {
+            info!("Serving http on {}:{}", address.0, address.1);
+            server.bind(address)?
+        },
+    };
+    Ok(server.run())
+}
+
 /// Starts the server. Will change behaviour based on the flags.
 ///
 /// This function sets up the working directories and starts
@@ -303,28 +512,49 @@ pub fn optimize_images(dir_in: &Path, dir_out: &Path) {
 /// Improve this, add additional flags and functions.
 #[actix_web::main]
 pub async fn server(args: &ArgMatches)  -> std::io::Result<()> {
-    // set and get the default cwd
-    let cwd = default_cwd();
-    std::env::set_current_dir(&cwd).unwrap();
-    info!("CWD is: {:?}", std::env::current_dir().unwrap());
-
-    // get server settings     
-    let server_settings_path = std::env::current_dir().unwrap().join(DEFAULT_SERVER_SETTINGS_PATH);
-    // info!("Server settings path is {:?}", server_settings_path);
-    let server_settings : ServerSettings = get_server_settings(&server_settings_path).unwrap_or_else( |_| {
-        warn!("Server settings could not be found at {:?}", server_settings_path);
-        ServerSettings::default()
-    });
+    // get server settings, see the config crate for the layers
+    let layers = match config_layers(args) {
+        Ok(layers) => layers,
+        Err(e) => {
+            error!("{}", e);
+            return Err(std::io::Error::other(e.to_string()));
+        },
+    };
+    // show, check or change the settings and exit, before they are
+    // validated so that broken settings can be fixed
+    if let Some(("config", config_args)) = args.subcommand() {
+        return config_command(&layers, config_args);
+    };
+    let loaded = match config::load(&layers) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("{}, check them with `nas-game server config validate`", e);
+            return Err(std::io::Error::other(e.to_string()));
+        },
+    };
+    let (server_settings, server_settings_path) = (loaded.settings, loaded.path);
+    logging::set_level(server_settings.log_level);
+
+    // every path of the server is in here, the cwd is never used
+    let dirs = DataDir::from_settings(&server_settings).with_settings_file(server_settings_path);
+    if let Err(e) = dirs.create() {
+        error!("{}", e);
+        return Err(std::io::Error::other(e.to_string()));
+    };
+    if let Err(e) = logging::log_to_file(&dirs.log_file()) {
+        warn!("Only logging to the console, failed to open {:?}: {}", dirs.log_file(), e);
+    }
+    info!("Data directory is {:?}, cache directory is {:?}", dirs.root(), dirs.cache());
 
     // gen default server settings
     if args.get_flag("default") {
-        let _ = write_server_settings(&server_settings_path, None).unwrap_or_else(|e| {
+        let _ = write_server_settings(dirs.settings(), None).unwrap_or_else(|e| {
             error!("Failed to override settings with {:?}", e); // TODO: add an early escape if this fails
         });
     }
     // vomit out data about the server
     if args.get_flag("info") {
-        info!("Server config location at {:?}", std::env::current_dir().unwrap().join(DEFAULT_SERVER_SETTINGS_PATH));
+        info!("Server config location at {:?}", dirs.settings());
         info!("Server settings are: {:?}", server_settings);
     };
 
@@ -333,141 +563,299 @@ pub async fn server(args: &ArgMatches)  -> std::io::Result<()> {
     if args.get_flag("optimize-images") {
 
         // create folders
-        prepare_folder("images");
-        std::env::set_current_dir("images").unwrap();
-        info!("CWD is: {:?}", std::env::current_dir().unwrap());
-        prepare_folder("non-optimized");
-        prepare_folder("optimized");
-
-        let (path_in, path_out) = (std::env::current_dir().unwrap().join("non-optimized"), std::env::current_dir().unwrap().join("optimized"));
+        let path_in = prepare_folder(dirs.images());
+        let path_out = prepare_folder(dirs.optimized_images());
         info!("Optimizing images at: {:?} -> {:?}", &path_in, &path_out);
-        
-        optimize_images(&path_in, &path_out);
-        std::env::set_current_dir(&cwd).unwrap();
-        info!("CWD is now {:?}", std::env::current_dir().unwrap());
+
+        match ArtworkStore::open(path_in.to_path_buf()) {
+            Ok(artwork) => {
+                let entries = artwork.entries().unwrap_or_default();
+                if entries.is_empty() { warn!("There is no artwork in {:?}", path_in); }
+                for entry in entries {
+                    // only log the errors
+                    if let Err(e) = optimize_artwork(&artwork, &entry, &server_settings, path_out, (None, None), false) { error!("{}", e); }
+                }
+            },
+            Err(e) => { error!("{}", e); },
+        }
     };
-    if args.get_flag("start") {
-        info!("Server started");
-        let game_library_path = PathBuf::from(DEFAULT_GAME_LIB_PATH);
-        let game_library_file = match fs::read_to_string(&game_library_path) {
-            Ok(s) => s,
+    // list, approve or revoke devices and exit
+    if let Some(("devices", devices_args)) = args.subcommand() {
+        let registry = match DeviceRegistry::open(dirs.devices()) {
+            Ok(registry) => registry,
             Err(e) => {
-                error!("Failed to read game library file with: {:?}", e);
-                info!("Falling back to default value with {:?}", DEFAULT_GAME_LIBRARY_CONTENTS);
-                DEFAULT_GAME_LIBRARY_CONTENTS.to_owned()
+                error!("Failed to open the devices with {}", e);
+                return Err(std::io::Error::other(e.to_string()));
             },
         };
-        let raw_gamelib: GameLibrary = match serde_json::from_str::<GameLibrary>(&game_library_file) {
-            Ok(s) => s,
-            Err(_) => {
-                error!("Failed to deserialize the game library from: {:?}", game_library_path);
-                info!("Falling back to default game library");
-                GameLibrary::default()
-            }
+        let result = match devices_args.subcommand() {
+            Some((action @ ("approve" | "revoke"), id_args)) => {
+                let id = id_args.get_one::<String>("id").expect("the id is required");
+                let status = if action == "approve" { DeviceStatus::Approved } else { DeviceStatus::Revoked };
+                registry.set_status(id, status).map(|_| ())
+            },
+            _ => registry.devices().map(|devices| {
+                for device in devices {
+                    println!("{}  {:<8}  {}  (registered {})", device.id, format!("{:?}", device.status).to_lowercase(), device.name, device.registered_at.format("%Y-%m-%d %H:%M"));
+                }
+            }),
         };
-        let gamelib = web::Data::new(Mutex::new(raw_gamelib)); 
-        let filelocation = web::Data::new(PathBuf::from(DEFAULT_GAME_LIB_PATH));
-        return HttpServer::new(move || {
-            App::new()
-                .app_data(gamelib.clone())
-                .app_data(filelocation.clone())
-                .service(hello)
-                .service(echo)
-                .service(add_dummy_get)
-                .service(add_to_games)
-                .service(save_library)
-                .service(download_images)
-                .service(optimize_images_server)
-        })
-        .bind((server_settings.ip, server_settings.port))?
-        .run()
-        .await;              
+        return result.map_err(|e| {
+            error!("{}", e);
+            std::io::Error::other(e.to_string())
+        });
     };
-    Ok(())
-}
-
-#[get("/")]
-async fn hello() -> impl Responder {
-    HttpResponse::Ok().body("Is Alive")
-}
-#[post("/echo")]
-async fn echo(req_body: String) -> impl Responder {
-    HttpResponse::Ok().body(req_body)
-}
-#[get("/add_dummy")]
-async fn add_dummy_get(data: web::Data<Mutex<GameLibrary>>) -> impl Responder {
-    let lib = &mut data.lock().unwrap().collection;
-    lib.push(Game::new());
-    format!("{:?}", lib)
-}
-
-#[post("/games")]
-async fn add_to_games(data: web::Data<Mutex<GameLibrary>>, games: web::Json<Vec<Game>>) -> impl Responder {
-    let lib = &mut data.lock().unwrap().collection;
-    let mut counter = 0;
-    // if I were to rewirte this for loop with the filter() method then it would
-    // allow for duplicate entries to be made.
-    for item in games.into_inner() {
-        if !lib.contains(&item) {
-            lib.push(item);
-            counter += 1;
+    // create the certificates for https and exit
+    if let Some(("cert", cert_args)) = args.subcommand() {
+        if let Some(("init", init_args)) = cert_args.subcommand() {
+            let mut names: Vec<String> = init_args.get_many::<String>("host").into_iter().flatten().cloned().collect();
+            if !["", "0.0.0.0"].contains(&server_settings.ip.as_str()) { names.push(server_settings.ip.clone()); }
+            names.extend(hostname());
+            match certs::init(&dirs.tls(), &names, init_args.get_flag("new-ca")) {
+                Ok(report) => {
+                    println!("CA:          {:?}{}", report.ca_cert, if report.new_ca { " (new)" } else { "" });
+                    println!("fingerprint: {}", report.ca_sha256);
+                    println!("certificate: {:?} for {}", report.server_cert, report.names.join(", "));
+                    println!("key:         {:?}", report.server_key);
+                    if !server_settings.tls { println!("Set \"tls\" to true in {:?} to serve https", dirs.settings()); }
+                },
+                Err(e) => {
+                    error!("Failed to create the certificates with {}", e);
+                    return Err(std::io::Error::other(e.to_string()));
+                },
+            };
         }
-    }
-    info!("Added {} to in-memory game library", &counter);
-    HttpResponse::build(StatusCode::OK).body(format!("{} games have been added", &counter))
-}
-
-#[post("/save_library")]
-async fn save_library(filelocation: web::Data<PathBuf>, data: web::Data<Mutex<GameLibrary>>) -> impl Responder {
-    let lib = &mut match data.lock() {
-        Ok(s) => s.collection.clone(),
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to aquire lock on game library")
+        return Ok(());
+    };
+    // manage the user accounts and exit
+    if let Some(("users", users_args)) = args.subcommand() {
+        let registry = match UserRegistry::open(dirs.users()) {
+            Ok(registry) => registry,
+            Err(e) => {
+                error!("Failed to open the users with {}", e);
+                return Err(std::io::Error::other(e.to_string()));
+            },
+        };
+        let result = match users_args.subcommand() {
+            Some(("add", add_args)) => {
+                let role: Result<Role, NasError> = add_args.get_one::<String>("role").expect("the role has a default").parse();
+                role.and_then(|role| registry.add(NewUser {
+                    name: add_args.get_one::<String>("name").expect("the name is required").clone(),
+                    role,
+                    password: add_args.get_one::<String>("password").cloned(),
+                })).map(|_| ())
+            },
+            Some(("remove", remove_args)) => {
+                let name = remove_args.get_one::<String>("name").expect("the name is required");
+                registry.remove(name).and_then(|removed| match removed {
+                    true => Ok(()),
+                    false => Err(NasError::new(ErrorKind::NotFound, format!("There is no user called {}", name))),
+                })
+            },
+            Some(("token", token_args)) => {
+                let name = token_args.get_one::<String>("name").expect("the name is required");
+                let label = token_args.get_one::<String>("label").expect("the label has a default");
+                registry.create_token(name, label, None).map(|(token, _)| println!("{}", token))
+            },
+            _ => registry.users().map(|users| {
+                for user in users {
+                    println!("{:<16}  {:<6}  {} favourites, {} hidden", user.name, format!("{:?}", user.role).to_lowercase(), user.favourites.len(), user.hidden.len());
+                }
+            }),
+        };
+        return result.map_err(|e| {
+            error!("{}", e);
+            std::io::Error::other(e.to_string())
+        });
     };
-    // the Vec<Game> is used because Mutexes are not serializable
-    let game_lib = match serde_json::to_string::<Vec<Game>>(&lib) {
-        Ok(s) => s,
-        Err(_) => return HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR).body("Failed to serialize")
+    // scan the library roots and exit
+    if let Some(("scan", scan_args)) = args.subcommand() {
+        let mut roots = server_settings.library_roots.clone();
+        roots.extend(scan_args.get_many::<PathBuf>("root").into_iter().flatten().cloned());
+        let store = open_library_store(&server_settings, &dirs).map_err(|e| {
+            error!("Failed to open the game library: {}", e);
+            std::io::Error::other(e.to_string())
+        })?;
+        match scanner::scan(&*store, &ProviderRegistry::with_roots(roots.clone()), &roots) {
+            Ok(report) => {
+                for entry in &report.added { info!("added   {} {:?}", entry.name, entry.path); }
+                for entry in &report.updated { info!("updated {} {:?}", entry.name, entry.path); }
+                for entry in &report.missing { warn!("missing {} {:?}", entry.name, entry.path); }
+            },
+            Err(e) => { error!("Failed to scan {:?} with {}", roots, e); },
+        };
+        return Ok(());
     };
-    match fs::write(&**filelocation, game_lib) {
-        Ok(_) => (),
-        Err(_) => return HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR).body("Failed to write to file")
-    }
-    info!("Saved in-memory library to {:?}", filelocation.as_path());
-    HttpResponse::build(StatusCode::OK).body("library has been saved")
-}
 
-fn image_exists(dir: &Path, name: &str) -> bool {
-    let extensions = ["webp", "jpg", "jpeg", "png"];
-    extensions.iter().any(|ext| { dir.join(format!("{}.{}", name, ext)).exists() })
-}
+    // import a local steam library and exit
+    if let Some(("import-steam", import_args)) = args.subcommand() {
+        let steam_root = import_args.get_one::<PathBuf>("steam-root").cloned().unwrap_or_else(steam::default_steam_root);
+        let store = open_library_store(&server_settings, &dirs).map_err(|e| {
+            error!("Failed to open the game library: {}", e);
+            std::io::Error::other(e.to_string())
+        })?;
+        match steam::installed_apps(&steam_root).and_then(|apps| scanner::merge(&*store, apps.iter().map(SteamApp::to_detected).collect())) {
+            Ok(report) => { info!("Imported {} new and updated {} games from {:?}", report.added.len(), report.updated.len(), steam_root); },
+            Err(e) => { error!("Failed to import the Steam library at {:?} with {}", steam_root, e); },
+        };
+        return Ok(());
+    };
 
-#[post("/download_images")]
-async fn download_images(data: web::Json<GameNameRequest>) -> impl Responder {
-
-    let path = default_cwd().join("images").join("non-optimized");
-    let missing_games: Vec<_> = data.games.iter()
-        .filter(|game| !image_exists(&path, game))
-        .cloned()
-        .collect();
-    info!("The images for these games will be fetched: {:?}", missing_games);
-
-    stream::iter(missing_games)
-        .for_each_concurrent(Some(5), |game_name| {
-            let path = path.clone();
-            async move {
-                if let Err(e) = fetch_image(&game_name, &path).await {
-                    error!("Failed to fetch image for {}: {}", game_name, e);
-                }
+    // import the gog games of the nas share and exit
+    if let Some(("import-gog", import_args)) = args.subcommand() {
+        let game_dirs: Vec<PathBuf> = match import_args.get_many::<PathBuf>("dir") {
+            Some(dirs) => dirs.cloned().collect(),
+            None => server_settings.library_roots.clone(),
+        };
+        let store = open_library_store(&server_settings, &dirs).map_err(|e| {
+            error!("Failed to open the game library: {}", e);
+            std::io::Error::other(e.to_string())
+        })?;
+        let detected = game_dirs.iter().flat_map(|dir| gog::find_games(dir)).map(|game| game.to_detected()).collect();
+        match scanner::merge(&*store, detected) {
+            Ok(report) => { info!("Imported {} new and updated {} GOG games from {:?}", report.added.len(), report.updated.len(), game_dirs); },
+            Err(e) => { error!("Failed to import the GOG games from {:?} with {}", game_dirs, e); },
+        };
+        return Ok(());
+    };
+
+    // one-shot import of the json library into the database
+    if args.get_flag("import-json") {
+        import_json_library(&dirs);
+    };
+    if args.get_flag("start") {
+        info!("Server started");
+        let store = open_library_store(&server_settings, &dirs).map_err(|e| {
+            error!("Failed to open the game library: {}", e);
+            std::io::Error::other(e.to_string())
+        })?;
+        let devices = match DeviceRegistry::open(dirs.devices()) {
+            Ok(registry) => web::Data::new(registry),
+            Err(e) => {
+                error!("Refusing to start without the devices: {}", e);
+                return Err(std::io::Error::other(e.to_string()));
+            },
+        };
+        let users = match UserRegistry::open(dirs.users()) {
+            Ok(registry) => web::Data::new(registry),
+            Err(e) => {
+                error!("Refusing to start without the users: {}", e);
+                return Err(std::io::Error::other(e.to_string()));
+            },
+        };
+        if users.is_empty().unwrap_or(false) { warn!("There are no users yet, every request is made as an admin until one is added with `nas-game server users add`"); }
+        let jobs = match JobQueue::open(dirs.jobs()) {
+            Ok(queue) => web::Data::new(queue),
+            Err(e) => {
+                error!("Refusing to start without the job queue: {}", e);
+                return Err(std::io::Error::other(e.to_string()));
+            },
+        };
+        let artwork = match ArtworkStore::open(dirs.images().to_path_buf()) {
+            Ok(artwork) => web::Data::new(artwork),
+            Err(e) => {
+                error!("Refusing to start without the artwork: {}", e);
+                return Err(std::io::Error::other(e.to_string()));
+            },
+        };
+        let state = AppState {
+            store: web::Data::from(store.clone()),
+            settings: web::Data::new(Live::new(server_settings.clone())),
+            providers: web::Data::new(Live::new(ProviderRegistry::from_settings(&server_settings))),
+            chunks: web::Data::new(ChunkCache::new(dirs.chunks())),
+            devices,
+            users,
+            dirs: web::Data::new(dirs.clone()),
+            jobs,
+            artwork,
+        };
+        let worker = Worker {
+            queue: state.jobs.clone(),
+            settings: state.settings.clone(),
+            store: store.clone(),
+            artwork: state.artwork.clone(),
+            dirs: dirs.clone(),
+        };
+        if let Err(e) = worker.spawn() {
+            error!("Refusing to start without the job worker: {}", e);
+            return Err(std::io::Error::other(e.to_string()));
+        };
+        if server_settings.discovery {
+            let announcement = Announcement {
+                name: Some(server_settings.name.clone()).filter(|n| !n.is_empty()).or_else(hostname).unwrap_or_else(|| "nas-game".to_owned()),
+                version: env!("CARGO_PKG_VERSION").to_owned(),
+                port: server_settings.port,
+                tls: server_settings.tls,
+            };
+            // not on the network for 127.0.0.1, see `discovery::responder_address()`
+            let responder = server_settings.ip.parse::<IpAddr>()
+                .context(ErrorKind::FailedToParse, format!("{:?} is not an ip address", server_settings.ip))
+                .and_then(|ip| discovery::spawn_responder(discovery::responder_address(ip, discovery::DISCOVERY_PORT), announcement));
+            if let Err(e) = responder {
+                warn!("Clients won't find the server on their own: {}", e);
             }
-        }).await;
-    HttpResponse::build(StatusCode::OK).body("Images have been downloaded")
-}
+        }
 
-#[post("/optimize_images_server")]
-async fn optimize_images_server() -> impl Responder {
-    info!("Attempting to optimize images");
-    let dir_in = default_cwd().join("images").join("non-optimized");
-    let dir_out = default_cwd().join("images").join("optimized");
-    optimize_images(&dir_in, &dir_out);
-    HttpResponse::build(StatusCode::OK).body("library has been saved")
+        // pick up edits of the settings and the library for as long as the watcher lives
+        let (rebind, mut rebinds) = futures::channel::mpsc::unbounded();
+        let reloader = Reloader { layers, settings: state.settings.clone(), providers: state.providers.clone(), store, rebind };
+        let _watcher = reloader.spawn(dirs.settings().to_path_buf())
+            .map_err(|e| { warn!("Changes to the settings and the library need a restart: {}", e); })
+            .ok();
+
+        let mut current = ServerSettings::clone(&server_settings);
+        let tls = tls_config(&state, &current).inspect_err(|e| { error!("Refusing to start: {}", e); })?;
+        let mut running = bind(state.clone(), &current, tls).inspect_err(|e| { error!("Refusing to start: {}", e); })?;
+        loop {
+            let handle = running.handle();
+            running = match future::select(running, rebinds.next()).await {
+                Either::Left((result, _)) => return result,
+                Either::Right((None, old)) => return old.await,
+                Either::Right((Some(settings), old)) => {
+                    // whatever can fail is tried while the old listener keeps serving
+                    let tls = match tls_config(&state, &settings) {
+                        Ok(tls) => tls,
+                        Err(e) => {
+                            error!("Still listening on {}:{}: {}", current.ip, current.port, e);
+                            running = old;
+                            continue;
+                        },
+                    };
+                    let new = if (settings.ip.as_str(), settings.port) == (current.ip.as_str(), current.port) {
+                        // the old listener has to let go of the address first
+                        let (_, result) = futures::join!(handle.stop(true), old);
+                        result?;
+                        match bind(state.clone(), &settings, tls) {
+                            Ok(new) => new,
+                            Err(e) => {
+                                error!("Failed to listen again, going back to the old listener settings: {}", e);
+                                let tls = tls_config(&state, &current).inspect_err(|e| { error!("Failed to listen again: {}", e); })?;
+                                running = bind(state.clone(), &current, tls).inspect_err(|e| { error!("Failed to listen again: {}", e); })?;
+                                continue;
+                            },
+                        }
+                    } else {
+                        match bind(state.clone(), &settings, tls) {
+                            Ok(new) => {
+                                // requests that are underway are finished by the old listener
+                                let (_, result) = futures::join!(handle.stop(true), old);
+                                result?;
+                                new
+                            },
+                            Err(e) => {
+                                error!("Still listening on {}:{}: {}", current.ip, current.port, e);
+                                running = old;
+                                continue;
+                            },
+                        }
+                    };
+                    // only now the settings of the new listener are the ones in use
+                    state.settings.replace(reload::keep_listener(ServerSettings::clone(&state.settings.get()), &settings));
+                    current = settings;
+                    new
+                },
+            };
+        }
+    };
+    Ok(())
 }
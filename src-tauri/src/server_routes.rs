@@ -1,98 +1,599 @@
 #[allow(unused_imports)]
 use crate::{trace, info, warn, error, logging::LoggingLevel, logging::logging_function};
 #[allow(unused_imports)]
-use crate::types::{Launcher, Game, GameLibrary, GameNameRequest};
-use crate::server::{default_cwd, optimize_images,fetch_image};
+use crate::types::{ArtworkKind, Launcher, Game, GameLibrary, GameNameRequest, GamePatch, ServerSettings};
+use crate::store::LibraryStore;
+use crate::scanner;
+use crate::provider::ProviderRegistry;
+use crate::reload::Live;
+use crate::data_dir::DataDir;
+use crate::jobs::{job_not_found, Job, JobKind, JobQueue, OptimizeQuery};
+use crate::artwork::{ArtworkEntry, ArtworkInfo, ArtworkQuery, ArtworkStore, ARTWORK_CACHE_CONTROL};
+use crate::server::optimize_artwork;
+use crate::steam_api::{self, SteamApiClient, SteamImportRequest};
+use crate::manifest;
+use crate::chunks::ChunkCache;
+use crate::stats::{self, PlaySession, StatsQuery};
+use crate::devices::{DeviceRegistry, RegisterRequest};
+use crate::users::{self, CurrentUser, GameFlags, LoginRequest, NewUser, Role, UserPatch, UserRegistry, ViewQuery};
+use crate::error::{NasError, ErrorKind, Context};
 
-use std::fs;
-use std::sync::Mutex;
 use std::path::{Path, PathBuf};
-use actix_web::{get, post, web, HttpResponse, Responder, http::StatusCode};
-use serde_json;
-use futures::stream::{self, StreamExt};
+use actix_web::{get, post, patch, delete, web, CustomizeResponder, HttpRequest, HttpResponse, Responder, http::StatusCode};
+use actix_web::http::header::{CACHE_CONTROL, LOCATION};
+use actix_web::error::{JsonPayloadError, PathError};
+use futures::{future, stream::{self, StreamExt}};
 
 #[get("/")]
-async fn route_hello() -> impl Responder {
+pub async fn route_hello() -> impl Responder {
     HttpResponse::Ok().body("Is Alive")
 }
 #[post("/echo")]
-async fn route_echo(req_body: String) -> impl Responder {
+pub async fn route_echo(req_body: String) -> impl Responder {
     HttpResponse::Ok().body(req_body)
 }
 #[get("/add_dummy")]
-async fn route_add_dummy_get(data: web::Data<Mutex<GameLibrary>>) -> impl Responder {
-    let lib = &mut data.lock().unwrap().collection;
-    lib.push(Game::new());
-    format!("{:?}", lib)
+pub async fn route_add_dummy_get(store: web::Data<dyn LibraryStore>, user: CurrentUser) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    store.add_games(vec![Game::new()])?;
+    Ok(HttpResponse::Ok().body(format!("{:?}", store.games()?)))
 }
 
 #[post("/games")]
-async fn route_add_to_games(data: web::Data<Mutex<GameLibrary>>, games: web::Json<Vec<Game>>) -> impl Responder {
-    let lib = &mut data.lock().unwrap().collection;
-    let mut counter = 0;
-    // if I were to rewirte this for loop with the filter() method then it would
-    // allow for duplicate entries to be made.
-    for item in games.into_inner() {
-        if !lib.contains(&item) {
-            lib.push(item);
-            counter += 1;
-        }
-    }
-    info!("Added {} to in-memory game library", &counter);
-    HttpResponse::build(StatusCode::OK).body(format!("{} games have been added", &counter))
+pub async fn route_add_to_games(store: web::Data<dyn LibraryStore>, user: CurrentUser, games: web::Json<Vec<Game>>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    let counter = store.add_games(games.into_inner())?;
+    info!("Added {} to the game library", &counter);
+    Ok(HttpResponse::build(StatusCode::OK).body(format!("{} games have been added", &counter)))
+}
+
+/// Lists every game, kids only see the games they are allowed to.
+#[get("/games")]
+pub async fn route_get_games(store: web::Data<dyn LibraryStore>, user: CurrentUser) -> Result<HttpResponse, NasError> {
+    let mut games = store.games()?;
+    games.retain(|game| user.require_game(game.id()).is_ok());
+    Ok(HttpResponse::Ok().json(games))
 }
 
+#[get("/games/{id}")]
+pub async fn route_get_game(store: web::Data<dyn LibraryStore>, user: CurrentUser, id: web::Path<String>) -> Result<HttpResponse, NasError> {
+    user.require_game(&id)?;
+    let game = store.game(&id)?.ok_or_else(|| game_not_found(&id))?;
+    Ok(HttpResponse::Ok().json(game))
+}
+
+#[patch("/games/{id}")]
+pub async fn route_patch_game(store: web::Data<dyn LibraryStore>, user: CurrentUser, id: web::Path<String>, patch: web::Json<GamePatch>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    let game = store.update_game(&id, patch.into_inner())?.ok_or_else(|| game_not_found(&id))?;
+    info!("Updated the game {}", id);
+    Ok(HttpResponse::Ok().json(game))
+}
+
+#[delete("/games/{id}")]
+pub async fn route_delete_game(store: web::Data<dyn LibraryStore>, user: CurrentUser, id: web::Path<String>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    if !store.remove_game(&id)? { return Err(game_not_found(&id)); }
+    info!("Removed the game {}", id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn game_not_found(id: &str) -> NasError {
+    NasError::new(ErrorKind::NotFound, format!("No game with the id {}", id))
+}
+
+/// Forces the game library to be written to disk.
+///
+/// The store already persists every change on its own, this route
+/// is kept around for scripts that still call it.
 #[post("/save_library")]
-async fn route_save_library(filelocation: web::Data<PathBuf>, data: web::Data<Mutex<GameLibrary>>) -> impl Responder {
-    let lib = &mut match data.lock() {
-        Ok(s) => s.collection.clone(),
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to aquire lock on game library")
-    };
-    // the Vec<Game> is used because Mutexes are not serializable
-    let game_lib = match serde_json::to_string::<Vec<Game>>(&lib) {
-        Ok(s) => s,
-        Err(_) => return HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR).body("Failed to serialize")
-    };
-    match fs::write(&**filelocation, game_lib) {
-        Ok(_) => (),
-        Err(_) => return HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR).body("Failed to write to file")
+pub async fn route_save_library(store: web::Data<dyn LibraryStore>, user: CurrentUser) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    store.persist()?;
+    info!("Saved the game library");
+    Ok(HttpResponse::build(StatusCode::OK).body("library has been saved"))
+}
+
+/// Scans the configured library roots for installed games.
+///
+/// Returns a `ScanReport` of the games that have been added, updated
+/// or that have gone missing.
+#[post("/scan")]
+pub async fn route_scan(store: web::Data<dyn LibraryStore>, settings: web::Data<Live<ServerSettings>>, providers: web::Data<Live<ProviderRegistry>>, user: CurrentUser) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    let (store, providers) = (store.into_inner(), providers.get());
+    let roots = settings.get().library_roots.clone();
+    let report = web::block(move || scanner::scan(&*store, &providers, &roots)).await??;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Lists the names of every launcher the server knows how to read.
+#[get("/launchers")]
+pub async fn route_get_launchers(providers: web::Data<Live<ProviderRegistry>>) -> impl Responder {
+    HttpResponse::Ok().json(providers.get().names())
+}
+
+/// Imports every game of a launcher, see `scanner::merge()`.
+///
+/// Returns a `ScanReport` without any missing games.
+#[post("/launchers/{name}/import")]
+pub async fn route_import_launcher(store: web::Data<dyn LibraryStore>, providers: web::Data<Live<ProviderRegistry>>, user: CurrentUser, name: web::Path<String>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    let (store, providers) = (store.into_inner(), providers.get());
+    providers.require(&name)?;
+    let report = web::block(move || scanner::merge(&*store, providers.list_games(&name)?)).await??;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Returns the command which starts the game, together with the
+/// launcher it was built for.
+///
+/// Kids are refused games they aren't allowed to play and games after
+/// their time of the day is used up.
+#[get("/games/{id}/launch_command")]
+pub async fn route_get_launch_command(store: web::Data<dyn LibraryStore>, providers: web::Data<Live<ProviderRegistry>>, user: CurrentUser, id: web::Path<String>) -> Result<HttpResponse, NasError> {
+    user.require_game(&id)?;
+    let game = store.game(&id)?.ok_or_else(|| game_not_found(&id))?;
+    if let Some(restrictions) = user.0.restrictions() {
+        let midnight = chrono::Local::now().date_naive().and_time(chrono::NaiveTime::MIN).and_local_timezone(chrono::Local).earliest()
+            .map(|t| t.with_timezone(&chrono::Utc))
+            .unwrap_or_else(chrono::Utc::now);
+        let played_today = store.sessions()?.iter()
+            .filter(|s| s.user == user.0.name && s.ended_at > midnight)
+            .map(|s| s.ended_at - s.started_at.max(midnight))
+            .sum();
+        restrictions.check(&id, played_today)?;
     }
-    info!("Saved in-memory library to {:?}", filelocation.as_path());
-    HttpResponse::build(StatusCode::OK).body("library has been saved")
+    let (launcher, command) = providers.get().launch_command(game.launchers())?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "launcher": launcher, "command": command })))
 }
 
-fn image_exists(dir: &Path, name: &str) -> bool {
-    let extensions = ["webp", "jpg", "jpeg", "png"];
-    extensions.iter().any(|ext| { dir.join(format!("{}.{}", name, ext)).exists() })
+/// The first install location of a game that exists on the server.
+fn game_root(game: &Game) -> Result<PathBuf, NasError> {
+    game.launchers().iter()
+        .filter_map(|l| l.install_dir.clone())
+        .find(|dir| dir.is_dir())
+        .ok_or_else(|| NasError::new(ErrorKind::NotFound, format!("The files of the game {} are not on the server", game.id())))
 }
 
-#[post("/download_images")]
-async fn route_download_images(data: web::Json<GameNameRequest>) -> impl Responder {
+/// Returns the `Manifest` of every file of a game, which the client
+/// downloads and verifies the game with.
+///
+/// The hashes are cached in `manifests/` of the cache directory, so only files that changed
+/// since the last request are hashed again.
+#[get("/games/{id}/manifest")]
+pub async fn route_get_manifest(store: web::Data<dyn LibraryStore>, dirs: web::Data<DataDir>, user: CurrentUser, id: web::Path<String>) -> Result<HttpResponse, NasError> {
+    user.require_game(&id)?;
+    let game = store.game(&id)?.ok_or_else(|| game_not_found(&id))?;
+    let root = game_root(&game)?;
+    let (id, manifests) = (id.into_inner(), dirs.manifests());
+    let manifest = web::block(move || manifest::load_or_build(&manifests, &id, &root)).await??;
+    Ok(HttpResponse::Ok().json(manifest))
+}
+
+/// Serves a single file of a game. Range requests are supported, so
+/// that interrupted downloads can be resumed.
+#[get("/games/{id}/files/{path:.*}")]
+pub async fn route_get_game_file(store: web::Data<dyn LibraryStore>, user: CurrentUser, path: web::Path<(String, String)>) -> Result<actix_files::NamedFile, NasError> {
+    let (id, file) = path.into_inner();
+    user.require_game(&id)?;
+    let game = store.game(&id)?.ok_or_else(|| game_not_found(&id))?;
+    let path = manifest::resolve(&game_root(&game)?, &file)?;
+    trace!("Serving {:?} of {}", path, id);
+    Ok(actix_files::NamedFile::open_async(&path).await?)
+}
+
+/// Returns the `ChunkIndex` of a game, which the client updates its
+/// install with by only downloading the chunks it is missing.
+#[get("/games/{id}/chunks")]
+pub async fn route_get_chunk_index(store: web::Data<dyn LibraryStore>, chunks: web::Data<ChunkCache>, user: CurrentUser, id: web::Path<String>) -> Result<HttpResponse, NasError> {
+    user.require_game(&id)?;
+    let game = store.game(&id)?.ok_or_else(|| game_not_found(&id))?;
+    let root = game_root(&game)?;
+    let chunks = chunks.into_inner();
+    let source = web::block(move || chunks.refresh(&id, &root)).await??;
+    Ok(HttpResponse::Ok().json(&source.index))
+}
+
+/// Serves a single chunk of a game by its SHA-256.
+#[get("/games/{id}/chunks/{sha256}")]
+pub async fn route_get_chunk(store: web::Data<dyn LibraryStore>, chunks: web::Data<ChunkCache>, user: CurrentUser, path: web::Path<(String, String)>) -> Result<HttpResponse, NasError> {
+    let (id, sha256) = path.into_inner();
+    user.require_game(&id)?;
+    let game = store.game(&id)?.ok_or_else(|| game_not_found(&id))?;
+    let root = game_root(&game)?;
+    let chunks = chunks.into_inner();
+    let data = web::block(move || chunks.read(&id, &root, &sha256)).await??;
+    Ok(HttpResponse::Ok().content_type("application/octet-stream").body(data))
+}
+
+/// Registers the public key of a client, which can't sign anything
+/// until an admin approved it with `nas-game server devices approve`.
+///
+/// Returns the `Device` with its id.
+#[post("/devices/register")]
+pub async fn route_register_device(devices: web::Data<DeviceRegistry>, req: web::Json<RegisterRequest>) -> Result<HttpResponse, NasError> {
+    let device = devices.register(&req.name, &req.public_key)?;
+    Ok(HttpResponse::Accepted().json(device))
+}
+
+/// Lists every registered device and whether it has been approved.
+#[get("/devices")]
+pub async fn route_get_devices(devices: web::Data<DeviceRegistry>, user: CurrentUser) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    Ok(HttpResponse::Ok().json(devices.devices()?))
+}
+
+/// Logs a user in with their password.
+///
+/// Returns a `LoginResponse` with the token for the `Authorization`
+/// header.
+#[post("/login")]
+pub async fn route_login(users: web::Data<UserRegistry>, req: web::Json<LoginRequest>) -> Result<HttpResponse, NasError> {
+    let users = users.into_inner();
+    let req = req.into_inner();
+    // hashing the password takes a while, so it's kept off the workers
+    let login = web::block(move || users.login(&req.name, &req.password)).await??;
+    Ok(HttpResponse::Ok().json(login))
+}
+
+/// Forgets the token the request was made with.
+#[post("/logout")]
+pub async fn route_logout(users: web::Data<UserRegistry>, req: HttpRequest) -> Result<HttpResponse, NasError> {
+    let token = users::bearer_token(&req).ok_or_else(|| NasError::new(ErrorKind::BadRequest, "The request has no bearer token"))?;
+    if !users.logout(token)? {
+        return Err(NasError::new(ErrorKind::Unauthorized, "The token is unknown"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Returns the `Profile` of the user that made the request.
+#[get("/me")]
+pub async fn route_get_me(user: CurrentUser) -> Result<HttpResponse, NasError> {
+    Ok(HttpResponse::Ok().json(user.0))
+}
+
+/// Returns the library as the user sees it, see `users::library_view()`.
+/// Hidden games are left out unless asked for with `?hidden=true`.
+#[get("/me/games")]
+pub async fn route_get_my_games(store: web::Data<dyn LibraryStore>, user: CurrentUser, query: web::Query<ViewQuery>) -> Result<HttpResponse, NasError> {
+    let view = users::library_view(&user.0, store.games()?, &store.sessions()?, query.hidden);
+    Ok(HttpResponse::Ok().json(view))
+}
+
+/// Marks a game as a favourite of the user or hides it, e.g. with
+/// `{ "favourite": true }`.
+#[patch("/me/games/{id}")]
+pub async fn route_patch_my_game(store: web::Data<dyn LibraryStore>, users: web::Data<UserRegistry>, user: CurrentUser, id: web::Path<String>, flags: web::Json<GameFlags>) -> Result<HttpResponse, NasError> {
+    user.require_game(&id)?;
+    let name = user.name().ok_or_else(|| NasError::new(ErrorKind::BadRequest, "There are no users yet, add one first"))?;
+    store.game(&id)?.ok_or_else(|| game_not_found(&id))?;
+    Ok(HttpResponse::Ok().json(users.set_game_flags(name, &id, flags.into_inner())?))
+}
+
+/// Lists every user.
+#[get("/users")]
+pub async fn route_get_users(users: web::Data<UserRegistry>, user: CurrentUser) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    Ok(HttpResponse::Ok().json(users.users()?))
+}
+
+/// Adds a user, the first one has to be an admin so that nobody is
+/// locked out.
+#[post("/users")]
+pub async fn route_add_user(users: web::Data<UserRegistry>, user: CurrentUser, new: web::Json<NewUser>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    if user.name().is_none() && new.role != Role::Admin {
+        return Err(NasError::new(ErrorKind::BadRequest, "The first user has to be an admin"));
+    }
+    let users = users.into_inner();
+    let profile = web::block(move || users.add(new.into_inner())).await??;
+    Ok(HttpResponse::Created().json(profile))
+}
+
+/// Changes the role, password or restrictions of a user, see
+/// `UserPatch`.
+#[patch("/users/{name}")]
+pub async fn route_patch_user(users: web::Data<UserRegistry>, user: CurrentUser, name: web::Path<String>, patch: web::Json<UserPatch>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    let users = users.into_inner();
+    let profile = web::block(move || users.update(&name, patch.into_inner())).await??;
+    Ok(HttpResponse::Ok().json(profile))
+}
+
+#[delete("/users/{name}")]
+pub async fn route_delete_user(users: web::Data<UserRegistry>, user: CurrentUser, name: web::Path<String>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    if user.name() == Some(name.as_str()) {
+        return Err(NasError::new(ErrorKind::BadRequest, "You can't remove yourself"));
+    }
+    if !users.remove(&name)? {
+        return Err(NasError::new(ErrorKind::NotFound, format!("There is no user called {}", name)));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Records a play session that a client sent once the game exited.
+/// The session always belongs to the user that sent it.
+///
+/// Returns the stored `PlaySession` together with its id.
+#[post("/sessions")]
+pub async fn route_add_session(store: web::Data<dyn LibraryStore>, user: CurrentUser, session: web::Json<PlaySession>) -> Result<HttpResponse, NasError> {
+    let mut session = session.into_inner();
+    if let Some(name) = user.name() { session.user = name.to_owned(); }
+    if session.ended_at < session.started_at {
+        return Err(NasError::new(ErrorKind::BadRequest, "A session can't end before it started"));
+    }
+    let session = store.add_session(session)?;
+    info!("{} played {} for {}s on {}", session.user, session.game_id, session.duration().num_seconds(), session.client_id);
+    Ok(HttpResponse::Created().json(session))
+}
+
+/// Returns the `Stats` of every session of every user, kids only of
+/// the games they are allowed to see.
+#[get("/stats")]
+pub async fn route_get_stats(store: web::Data<dyn LibraryStore>, user: CurrentUser, query: web::Query<StatsQuery>) -> Result<HttpResponse, NasError> {
+    let sessions = store.sessions()?;
+    let sessions = sessions.iter().filter(|s| user.require_game(&s.game_id).is_ok());
+    Ok(HttpResponse::Ok().json(stats::summarize(sessions, query.weeks(), query.limit(), chrono::Utc::now())))
+}
+
+/// Returns the `Stats` of a single game.
+#[get("/stats/games/{id}")]
+pub async fn route_get_game_stats(store: web::Data<dyn LibraryStore>, user: CurrentUser, id: web::Path<String>, query: web::Query<StatsQuery>) -> Result<HttpResponse, NasError> {
+    user.require_game(&id)?;
+    store.game(&id)?.ok_or_else(|| game_not_found(&id))?;
+    let sessions = store.sessions()?;
+    let sessions = sessions.iter().filter(|s| s.game_id == *id);
+    Ok(HttpResponse::Ok().json(stats::summarize(sessions, query.weeks(), query.limit(), chrono::Utc::now())))
+}
+
+/// Returns the `Stats` of a single user, whose `most_played` are the
+/// games that user played the longest.
+#[get("/stats/users/{user}")]
+pub async fn route_get_user_stats(store: web::Data<dyn LibraryStore>, user: CurrentUser, name: web::Path<String>, query: web::Query<StatsQuery>) -> Result<HttpResponse, NasError> {
+    let sessions = store.sessions()?;
+    let sessions = sessions.iter().filter(|s| s.user == *name && user.require_game(&s.game_id).is_ok());
+    Ok(HttpResponse::Ok().json(stats::summarize(sessions, query.weeks(), query.limit(), chrono::Utc::now())))
+}
+
+/// Imports the games owned by a Steam user, together with their
+/// playtime and optionally their achievement progress.
+///
+/// Returns a `SteamImportReport`.
+#[post("/steam/import")]
+pub async fn route_steam_import(store: web::Data<dyn LibraryStore>, user: CurrentUser, req: web::Json<SteamImportRequest>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    let client = SteamApiClient::from_env();
+    let report = steam_api::import_owned_games(&client, store.into_inner(), &req.steam_id, req.achievements).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
 
-    let path = default_cwd().join("images").join("non-optimized");
-    let missing_games: Vec<_> = data.games.iter()
-        .filter(|game| !image_exists(&path, game))
-        .cloned()
+/// Returns the profile of a Steam user and the games they played in
+/// the last two weeks. Only for users, the requests are made with the
+/// key of the server.
+#[get("/steam/profile/{steam_id}")]
+pub async fn route_steam_profile(user: CurrentUser, steam_id: web::Path<String>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Player)?;
+    let client = SteamApiClient::from_env();
+    let player = client.player_summaries(&[&steam_id]).await?.into_iter().next()
+        .ok_or_else(|| NasError::new(ErrorKind::NotFound, format!("No Steam user with the id {}", steam_id)))?;
+    let recently_played = client.recently_played_games(&steam_id).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "player": player, "recently_played": recently_played })))
+}
+
+/// Turns the errors of the json extractor into a `NasError`, so that
+/// malformed bodies are reported like every other error.
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    NasError::new(ErrorKind::BadRequest, err.to_string()).into()
+}
+
+/// Turns the errors of the path extractor into a `NasError`.
+pub fn path_error_handler(err: PathError, _req: &HttpRequest) -> actix_web::Error {
+    NasError::new(ErrorKind::BadRequest, err.to_string()).into()
+}
+
+/// Answers a queued job with where its progress is.
+fn job_accepted(job: Job) -> HttpResponse {
+    HttpResponse::Accepted().insert_header((LOCATION, format!("/jobs/{}", job.id))).json(job)
+}
+
+/// The artwork of every game, with where it is served. Kids only get
+/// the artwork of the games they are allowed to see.
+#[get("/artwork")]
+pub async fn route_get_artwork(artwork: web::Data<ArtworkStore>, user: CurrentUser) -> Result<HttpResponse, NasError> {
+    let entries: Vec<ArtworkInfo> = artwork.entries()?.into_iter()
+        .filter(|entry| user.require_game(&entry.game_id).is_ok())
+        .map(ArtworkInfo::from)
         .collect();
-    info!("The images for these games will be fetched: {:?}", missing_games);
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+/// The image of `kind` of the game `id` that `query` picks.
+fn find_artwork(store: &dyn LibraryStore, artwork: &ArtworkStore, id: &str, kind: ArtworkKind, query: &ArtworkQuery) -> Result<ArtworkEntry, NasError> {
+    let game = store.game(id)?.ok_or_else(|| game_not_found(id))?;
+    let index = query.index.unwrap_or(0);
+    artwork.get_all(&game, kind)?.into_iter().find(|entry| entry.index == index)
+        .ok_or_else(|| NasError::new(ErrorKind::NotFound, format!("There are no {} for {:?} yet", kind.as_str(), game.name())))
+}
+
+/// Serves an image with its ETag and Last-Modified, which answer
+/// conditional requests with 304 Not Modified.
+async fn artwork_file(path: &Path) -> Result<CustomizeResponder<actix_files::NamedFile>, NasError> {
+    let file = actix_files::NamedFile::open_async(path).await?;
+    Ok(file.customize().insert_header((CACHE_CONTROL, ARTWORK_CACHE_CONTROL)))
+}
 
-    stream::iter(missing_games)
-        .for_each_concurrent(Some(5), |game_name| {
-            let path = path.clone();
-            async move {
-                if let Err(e) = fetch_image(&game_name, &path).await {
-                    error!("Failed to fetch image for {}: {}", game_name, e);
-                }
-            }
-        }).await;
-    HttpResponse::build(StatusCode::OK).body("Images have been downloaded")
+/// One image of a kind of artwork of a game, screenshots after the
+/// first are picked with `?index=`.
+#[get("/games/{id}/artwork/{kind}")]
+pub async fn route_get_game_artwork(store: web::Data<dyn LibraryStore>, artwork: web::Data<ArtworkStore>, user: CurrentUser, path: web::Path<(String, ArtworkKind)>, query: web::Query<ArtworkQuery>) -> Result<impl Responder, NasError> {
+    let (id, kind) = path.into_inner();
+    user.require_game(&id)?;
+    let entry = find_artwork(&**store, &artwork, &id, kind, &query)?;
+    artwork_file(&artwork.path(&entry)).await
 }
 
+/// The optimized copy of an image, in the size and format of the
+/// profile of its kind or so that it fits into `?w=&h=`. Missing or
+/// outdated copies are optimized first and then kept for the next
+/// request.
+#[get("/games/{id}/artwork/{kind}/optimized")]
+pub async fn route_get_optimized_artwork(
+    store: web::Data<dyn LibraryStore>,
+    artwork: web::Data<ArtworkStore>,
+    settings: web::Data<Live<ServerSettings>>,
+    dirs: web::Data<DataDir>,
+    user: CurrentUser,
+    path: web::Path<(String, ArtworkKind)>,
+    query: web::Query<ArtworkQuery>,
+) -> Result<impl Responder, NasError> {
+    let (id, kind) = path.into_inner();
+    user.require_game(&id)?;
+    let entry = find_artwork(&**store, &artwork, &id, kind, &query)?;
+    let bounds = query.bounds()?;
+    let (settings, dir_out) = (settings.get(), dirs.optimized_images().to_path_buf());
+    // encoding takes a while, it mustn't hold up the requests of this thread
+    let optimized = web::block(move || optimize_artwork(&artwork, &entry, &settings, &dir_out, bounds, false)).await??;
+    artwork_file(&optimized).await
+}
+
+/// Queues the download of the artwork of the games that don't have it
+/// yet. The games are named by id or by name, without any every game
+/// of the library is meant.
+#[post("/download_images")]
+pub async fn route_download_images(jobs: web::Data<JobQueue>, user: CurrentUser, data: web::Json<GameNameRequest>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    let GameNameRequest { games, kinds } = data.into_inner();
+    let job = jobs.enqueue(JobKind::DownloadImages { games, kinds })?;
+    Ok(job_accepted(job))
+}
+
+/// Queues the optimization of the artwork, `?force=true` redoes the
+/// images that are up to date as well.
 #[post("/optimize_images_server")]
-async fn route_optimize_images_server() -> impl Responder {
-    info!("Attempting to optimize images");
-    let dir_in = default_cwd().join("images").join("non-optimized");
-    let dir_out = default_cwd().join("images").join("optimized");
-    optimize_images(&dir_in, &dir_out);
-    HttpResponse::build(StatusCode::OK).body("library has been saved")
+pub async fn route_optimize_images_server(jobs: web::Data<JobQueue>, user: CurrentUser, query: web::Query<OptimizeQuery>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    let job = jobs.enqueue(JobKind::OptimizeImages { force: query.force })?;
+    Ok(job_accepted(job))
+}
+
+/// Every job, kids can't see them since they name games.
+#[get("/jobs")]
+pub async fn route_get_jobs(jobs: web::Data<JobQueue>, user: CurrentUser) -> Result<HttpResponse, NasError> {
+    user.require(Role::Player)?;
+    Ok(HttpResponse::Ok().json(jobs.jobs()?))
+}
+
+/// Streams `jobs` as Server-Sent Events, one `job` event per change.
+fn job_events(jobs: impl futures::Stream<Item = Job> + 'static) -> HttpResponse {
+    let events = jobs.map(|job| {
+        let json = serde_json::to_string(&job).context(ErrorKind::FailedToSerialize, "Failed to serialize the job")?;
+        Ok::<_, NasError>(web::Bytes::from(format!("event: job\ndata: {}\n\n", json)))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(events)
+}
+
+/// Every change of every job, for as long as the client listens.
+#[get("/jobs/events")]
+pub async fn route_all_job_events(jobs: web::Data<JobQueue>, user: CurrentUser) -> Result<HttpResponse, NasError> {
+    user.require(Role::Player)?;
+    Ok(job_events(jobs.subscribe()?))
+}
+
+#[get("/jobs/{id}")]
+pub async fn route_get_job(jobs: web::Data<JobQueue>, user: CurrentUser, id: web::Path<String>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Player)?;
+    let job = jobs.job(&id)?.ok_or_else(|| job_not_found(&id))?;
+    Ok(HttpResponse::Ok().json(job))
+}
+
+/// The job as it is now and then every change of it, the stream ends
+/// once the job is finished.
+#[get("/jobs/{id}/events")]
+pub async fn route_job_events(jobs: web::Data<JobQueue>, user: CurrentUser, id: web::Path<String>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Player)?;
+    // subscribed first so that no change between the two gets lost
+    let changes = jobs.subscribe()?;
+    let job = jobs.job(&id)?.ok_or_else(|| job_not_found(&id))?;
+    let id = id.into_inner();
+    let updates = stream::unfold(Some((Some(job), changes)), move |state| {
+        let id = id.clone();
+        async move {
+            let (first, mut changes) = state?;
+            let job = match first {
+                Some(job) => job,
+                None => changes.by_ref().filter(|job| future::ready(job.id == id)).next().await?,
+            };
+            let state = (!job.status.is_finished()).then_some((None, changes));
+            Some((job, state))
+        }
+    });
+    Ok(job_events(updates))
+}
+
+#[post("/jobs/{id}/cancel")]
+pub async fn route_cancel_job(jobs: web::Data<JobQueue>, user: CurrentUser, id: web::Path<String>) -> Result<HttpResponse, NasError> {
+    user.require(Role::Admin)?;
+    Ok(HttpResponse::Ok().json(jobs.cancel(&id)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::JsonFileStore;
+    use crate::types::Launcher;
+    use nas_game_lib::{client::Credentials, install};
+    use actix_web::{App, HttpServer};
+    use std::{fs, sync::Arc};
+
+    #[actix_web::test]
+    async fn installs_as_the_logged_in_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("games/seven");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("game.exe"), "v1").unwrap();
+
+        let store: Arc<dyn LibraryStore> = Arc::new(JsonFileStore::open(dir.path().join("library.json"), 0));
+        let mut game = Game::with_id("7".to_owned(), "Seven".to_owned());
+        game.set_launcher(Launcher { install_dir: Some(source.clone()), ..Launcher::new("steam".to_owned(), "70".to_owned()) });
+        store.add_games(vec![game]).unwrap();
+        let users = web::Data::new(UserRegistry::open(dir.path().join("users.json")).unwrap());
+        users.add(NewUser { name: "mum".to_owned(), role: Role::Admin, password: Some("secret".to_owned()) }).unwrap();
+        users.add(NewUser { name: "tim".to_owned(), role: Role::Player, password: None }).unwrap();
+        let (token, _) = users.create_token("tim", "laptop", None).unwrap();
+        let settings = ServerSettings { data_dir: dir.path().join("server"), ..ServerSettings::default() };
+        let dirs = web::Data::new(DataDir::from_settings(&settings));
+        let chunks = web::Data::new(ChunkCache::new(dirs.chunks()));
+
+        let store = web::Data::from(store);
+        let server = HttpServer::new(move || App::new()
+                .app_data(store.clone())
+                .app_data(users.clone())
+                .app_data(dirs.clone())
+                .app_data(chunks.clone())
+                .service(route_get_manifest)
+                .service(route_get_game_file)
+                .service(route_get_chunk_index)
+                .service(route_get_chunk))
+            .bind(("127.0.0.1", 0)).unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        let dest = dir.path().join("client");
+        let anonymous = Credentials::default();
+        assert_eq!(install::install(&url, &anonymous, "7", &dest, |_| ()).await.unwrap_err().kind(), ErrorKind::Unauthorized);
+        let tim = Credentials { token: Some(token), device_key: None };
+        assert_eq!(install::install(&url, &tim, "7", &dest, |_| ()).await.unwrap().downloaded_files, 1);
+        assert_eq!(fs::read(dest.join("game.exe")).unwrap(), b"v1");
+
+        fs::write(source.join("game.exe"), "v2").unwrap();
+        let cache = dir.path().join("client-cache");
+        assert_eq!(install::update(&url, &anonymous, "7", &dest, &cache, |_| ()).await.unwrap_err().kind(), ErrorKind::Unauthorized);
+        assert_eq!(install::update(&url, &tim, "7", &dest, &cache, |_| ()).await.unwrap().downloaded_files, 1);
+        assert_eq!(fs::read(dest.join("game.exe")).unwrap(), b"v2");
+    }
 }
@@ -0,0 +1,459 @@
+//! This crate implements a `LibraryStore` on top of an embedded
+//! SQLite database. Unlike the `JsonFileStore` every change only
+//! touches the rows it concerns instead of rewriting the whole
+//! library.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, logging::LoggingLevel, logging::logging_function};
+use crate::types::{AchievementProgress, Game, GamePatch, Launcher};
+use crate::store::{LibraryStore, read_library};
+use crate::stats::PlaySession;
+
+use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+/// The schema migrations in the order they have to be applied.
+///
+/// The index of a migration plus one is the schema version it
+/// upgrades the database to, the version itself is tracked with
+/// `PRAGMA user_version`. Migrations must never be edited once
+/// they have been released, add a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // v1: initial schema
+    "CREATE TABLE games (
+        id            INTEGER PRIMARY KEY AUTOINCREMENT,
+        steam_grid_id TEXT
+    );
+    CREATE TABLE launchers (
+        game_id          INTEGER NOT NULL REFERENCES games(id) ON DELETE CASCADE,
+        name             TEXT NOT NULL,
+        launcher_game_id TEXT NOT NULL,
+        position         INTEGER NOT NULL,
+        UNIQUE (game_id, name, launcher_game_id)
+    );
+    CREATE INDEX launchers_lookup ON launchers (name, launcher_game_id);
+    CREATE TABLE artwork (
+        game_id    INTEGER NOT NULL REFERENCES games(id) ON DELETE CASCADE,
+        kind       TEXT NOT NULL,
+        path       TEXT NOT NULL,
+        source_url TEXT,
+        sha256     TEXT,
+        width      INTEGER,
+        height     INTEGER,
+        fetched_at TEXT,
+        PRIMARY KEY (game_id, kind)
+    );
+    CREATE TABLE play_sessions (
+        id          INTEGER PRIMARY KEY AUTOINCREMENT,
+        game_id     INTEGER NOT NULL REFERENCES games(id) ON DELETE CASCADE,
+        client_id   TEXT NOT NULL,
+        started_at  TEXT NOT NULL,
+        ended_at    TEXT,
+        exit_status INTEGER
+    );
+    CREATE INDEX play_sessions_game ON play_sessions (game_id);",
+    // v2: stable game ids and display names, the ids of existing rows
+    // are filled in by `assign_ids()`
+    "ALTER TABLE games ADD COLUMN uuid TEXT;
+    ALTER TABLE games ADD COLUMN name TEXT NOT NULL DEFAULT '';
+    CREATE UNIQUE INDEX games_uuid ON games (uuid);",
+    // v3: install locations of launchers
+    "ALTER TABLE launchers ADD COLUMN install_dir TEXT;",
+    // v4: details of installed games
+    "ALTER TABLE launchers ADD COLUMN size_on_disk INTEGER;
+    ALTER TABLE launchers ADD COLUMN build_id TEXT;",
+    // v5: playtime and achievements from the Steam Web API
+    "ALTER TABLE games ADD COLUMN playtime_minutes INTEGER;
+    ALTER TABLE games ADD COLUMN achievements_unlocked INTEGER;
+    ALTER TABLE games ADD COLUMN achievements_total INTEGER;",
+    // v6: GOG versions, languages and offline installers, the latter
+    // as a json array of paths
+    "ALTER TABLE launchers ADD COLUMN version TEXT;
+    ALTER TABLE launchers ADD COLUMN language TEXT;
+    ALTER TABLE launchers ADD COLUMN installers TEXT;",
+    // v7: who played a session
+    "ALTER TABLE play_sessions ADD COLUMN user TEXT NOT NULL DEFAULT '';",
+    // v8: artwork stays in the manifest of the `artwork` crate whatever
+    // the backend, the table of v1 was never used
+    "DROP TABLE artwork;",
+];
+
+/// A `LibraryStore` backed by a SQLite database file.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at `path` and brings its
+    /// schema up to date.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::FailedToReadFile` should the database
+    /// not be openable and with `ErrorKind::FailedToMigrate` should
+    /// one of the migrations fail.
+    pub fn open(path: &Path) -> Result<Self, NasError> {
+        let mut conn = Connection::open(path)
+            .context(ErrorKind::FailedToReadFile, format!("Failed to open the database at {:?}", path))?;
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;")
+            .context(ErrorKind::FailedToReadFile, format!("Failed to configure the database at {:?}", path))?;
+        migrate(&mut conn)?;
+        assign_ids(&conn)?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    /// Imports every game of a json game library into the database.
+    ///
+    /// Games which are already present are skipped, so importing the
+    /// same file twice is harmless. Returns how many games have been
+    /// added.
+    pub fn import_json(&self, json_path: &Path) -> Result<usize, NasError> {
+        let library = read_library(json_path)?;
+        info!("Importing {} games from {:?}", library.collection.len(), json_path);
+        self.add_games(library.collection)
+    }
+}
+
+/// Applies every migration which is newer than the schema version
+/// of the database. Each migration runs in its own transaction.
+fn migrate(conn: &mut Connection) -> Result<(), NasError> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .context(ErrorKind::FailedToMigrate, "Failed to read the schema version")?;
+    if version > MIGRATIONS.len() {
+        warn!("The database schema v{} is newer than this build knows about (v{})", version, MIGRATIONS.len());
+        return Ok(());
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction().context(ErrorKind::FailedToMigrate, "Failed to start a transaction")?;
+        tx.execute_batch(migration)
+            .and_then(|_| tx.pragma_update(None, "user_version", i + 1))
+            .and_then(|_| tx.commit())
+            .context(ErrorKind::FailedToMigrate, format!("Failed to migrate the database to v{}", i + 1))?;
+        info!("Migrated the database to schema v{}", i + 1);
+    }
+    Ok(())
+}
+
+/// Hands out ids to every row that lacks one, which are the rows
+/// that have been created before the v2 migration.
+fn assign_ids(conn: &Connection) -> Result<(), NasError> {
+    let assign = || -> rusqlite::Result<usize> {
+        let mut stmt = conn.prepare("SELECT id FROM games WHERE uuid IS NULL")?;
+        let rows = stmt.query_map([], |row| row.get::<_, i64>(0))?.collect::<rusqlite::Result<Vec<_>>>()?;
+        for row in &rows {
+            conn.execute("UPDATE games SET uuid = ?1 WHERE id = ?2", params![uuid::Uuid::new_v4().to_string(), row])?;
+        }
+        Ok(rows.len())
+    };
+    match assign() {
+        Ok(0) => Ok(()),
+        Ok(n) => { info!("Assigned ids to {} games", n); Ok(()) },
+        Err(e) => Err(NasError::new(ErrorKind::FailedToWrite, "Failed to assign game ids").with_source(e)),
+    }
+}
+
+/// Reads the games matching the `filter`, which is appended to the
+/// select statement as is.
+fn query_games(conn: &Connection, filter: &str, params: impl rusqlite::Params) -> rusqlite::Result<Vec<Game>> {
+    let mut games_stmt = conn.prepare_cached(&format!(
+        "SELECT id, uuid, name, steam_grid_id, playtime_minutes, achievements_unlocked, achievements_total
+        FROM games {} ORDER BY id",
+        filter
+    ))?;
+    let mut launcher_stmt = conn.prepare_cached(
+        "SELECT name, launcher_game_id, install_dir, size_on_disk, build_id, version, language, installers
+        FROM launchers WHERE game_id = ?1 ORDER BY position"
+    )?;
+    let rows = games_stmt.query_map(params, |row| {
+        let mut game = Game::with_id(row.get(1)?, row.get(2)?);
+        game.set_steam_grid_id(row.get(3)?);
+        let achievements = match (row.get(5)?, row.get(6)?) {
+            (Some(unlocked), Some(total)) => Some(AchievementProgress { unlocked, total }),
+            _ => None,
+        };
+        game.apply(GamePatch { playtime_minutes: row.get(4)?, achievements, ..GamePatch::default() });
+        Ok((row.get::<_, i64>(0)?, game))
+    })?;
+
+    let mut games = Vec::new();
+    for row in rows {
+        let (row_id, mut game) = row?;
+        let launchers = launcher_stmt.query_map([row_id], |row| {
+            let mut launcher = Launcher::new(row.get(0)?, row.get(1)?);
+            launcher.install_dir = row.get::<_, Option<String>>(2)?.map(PathBuf::from);
+            launcher.size_on_disk = row.get(3)?;
+            launcher.build_id = row.get(4)?;
+            launcher.version = row.get(5)?;
+            launcher.language = row.get(6)?;
+            if let Some(installers) = row.get::<_, Option<String>>(7)? {
+                launcher.installers = serde_json::from_str(&installers)
+                    .map_err(|e| rusqlite::Error::FromSqlConversionFailure(7, rusqlite::types::Type::Text, Box::new(e)))?;
+            }
+            Ok(launcher)
+        })?;
+        for launcher in launchers {
+            game.set_launcher(launcher?);
+        }
+        games.push(game);
+    }
+    Ok(games)
+}
+
+fn parse_time(index: usize, value: &str) -> rusqlite::Result<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&chrono::Utc))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+fn query_sessions(conn: &Connection) -> rusqlite::Result<Vec<PlaySession>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT s.id, g.uuid, s.client_id, s.user, s.started_at, s.ended_at, s.exit_status
+        FROM play_sessions s JOIN games g ON g.id = s.game_id ORDER BY s.started_at, s.id"
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let started_at = parse_time(4, &row.get::<_, String>(4)?)?;
+        Ok(PlaySession {
+            id: row.get::<_, i64>(0)?.to_string(),
+            game_id: row.get(1)?,
+            client_id: row.get(2)?,
+            user: row.get(3)?,
+            started_at,
+            ended_at: match row.get::<_, Option<String>>(5)? {
+                Some(ended_at) => parse_time(5, &ended_at)?,
+                None => started_at,
+            },
+            exit_status: row.get(6)?,
+        })
+    })?;
+    rows.collect()
+}
+
+fn insert_launchers(tx: &Transaction, row_id: i64, game: &Game) -> rusqlite::Result<()> {
+    for (position, launcher) in game.launchers().iter().enumerate() {
+        tx.execute(
+            "INSERT INTO launchers (game_id, name, launcher_game_id, position, install_dir, size_on_disk, build_id, version, language, installers)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                row_id, launcher.name, launcher.game_id, position,
+                launcher.install_dir.as_ref().map(|p| p.to_string_lossy()), launcher.size_on_disk, launcher.build_id,
+                launcher.version, launcher.language,
+                (!launcher.installers.is_empty()).then(|| serde_json::to_string(&launcher.installers).unwrap_or_default()),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_game(tx: &Transaction, game: &Game) -> rusqlite::Result<i64> {
+    tx.execute(
+        "INSERT INTO games (uuid, name, steam_grid_id, playtime_minutes, achievements_unlocked, achievements_total)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            game.id(), game.name(), game.steam_grid_id(), game.playtime_minutes(),
+            game.achievements().map(|a| a.unlocked), game.achievements().map(|a| a.total),
+        ],
+    )?;
+    let row_id = tx.last_insert_rowid();
+    insert_launchers(tx, row_id, game)?;
+    Ok(row_id)
+}
+
+/// Checks if a duplicate of the `game` is stored already. Only the
+/// rows found through the id or the first launcher are compared, so
+/// this never has to read the whole library.
+fn is_stored(conn: &Connection, game: &Game) -> rusqlite::Result<bool> {
+    if !game.id().is_empty() {
+        return conn.query_row("SELECT 1 FROM games WHERE uuid = ?1", [game.id()], |_| Ok(())).optional().map(|row| row.is_some());
+    }
+    let candidates = match game.launchers().first() {
+        Some(launcher) => query_games(
+            conn,
+            "WHERE id IN (SELECT game_id FROM launchers WHERE name = ?1 AND launcher_game_id = ?2)",
+            [&launcher.name, &launcher.game_id],
+        )?,
+        None => query_games(conn, "WHERE name = ?1", [game.name()])?,
+    };
+    Ok(candidates.iter().any(|candidate| candidate.is_duplicate_of(game)))
+}
+
+impl LibraryStore for SqliteStore {
+    fn games(&self) -> Result<Vec<Game>, NasError> {
+        let conn = self.conn.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        query_games(&conn, "", []).context(ErrorKind::FailedToReadFile, "Failed to query the games")
+    }
+
+    fn game(&self, id: &str) -> Result<Option<Game>, NasError> {
+        let conn = self.conn.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let games = query_games(&conn, "WHERE uuid = ?1", [id])
+            .context(ErrorKind::FailedToReadFile, format!("Failed to query the game {}", id))?;
+        Ok(games.into_iter().next())
+    }
+
+    fn add_games(&self, games: Vec<Game>) -> Result<usize, NasError> {
+        let mut conn = self.conn.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let tx = conn.transaction().context(ErrorKind::FailedToWrite, "Failed to start a transaction")?;
+        let mut counter = 0;
+        for mut item in games {
+            let duplicate = is_stored(&tx, &item).context(ErrorKind::FailedToReadFile, format!("Failed to look up {:?}", item))?;
+            if duplicate { continue; }
+            item.ensure_id();
+            let id = insert_game(&tx, &item).context(ErrorKind::FailedToWrite, format!("Failed to insert {:?}", item))?;
+            trace!("Inserted game with row id {}", id);
+            counter += 1;
+        }
+        tx.commit().context(ErrorKind::FailedToWrite, "Failed to commit the transaction")?;
+        Ok(counter)
+    }
+
+    fn update_game(&self, id: &str, patch: GamePatch) -> Result<Option<Game>, NasError> {
+        let mut conn = self.conn.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let tx = conn.transaction().context(ErrorKind::FailedToWrite, "Failed to start a transaction")?;
+        let update = || -> rusqlite::Result<Option<Game>> {
+            let Some(mut game) = query_games(&tx, "WHERE uuid = ?1", [id])?.into_iter().next() else { return Ok(None) };
+            let row_id: i64 = tx.query_row("SELECT id FROM games WHERE uuid = ?1", [id], |row| row.get(0))?;
+            let replace_launchers = patch.launcher.is_some();
+            game.apply(patch);
+            tx.execute(
+                "UPDATE games SET name = ?1, steam_grid_id = ?2, playtime_minutes = ?3,
+                achievements_unlocked = ?4, achievements_total = ?5 WHERE id = ?6",
+                params![
+                    game.name(), game.steam_grid_id(), game.playtime_minutes(),
+                    game.achievements().map(|a| a.unlocked), game.achievements().map(|a| a.total), row_id,
+                ],
+            )?;
+            if replace_launchers {
+                tx.execute("DELETE FROM launchers WHERE game_id = ?1", [row_id])?;
+                insert_launchers(&tx, row_id, &game)?;
+            }
+            Ok(Some(game))
+        };
+        let game = update().context(ErrorKind::FailedToWrite, format!("Failed to update the game {}", id))?;
+        tx.commit().context(ErrorKind::FailedToWrite, "Failed to commit the transaction")?;
+        Ok(game)
+    }
+
+    fn remove_game(&self, id: &str) -> Result<bool, NasError> {
+        let conn = self.conn.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let removed = conn.execute("DELETE FROM games WHERE uuid = ?1", [id])
+            .context(ErrorKind::FailedToWrite, format!("Failed to remove the game {}", id))?;
+        Ok(removed > 0)
+    }
+
+    /// Every statement is already committed on its own, so this only
+    /// folds the write-ahead log back into the database file.
+    fn persist(&self) -> Result<(), NasError> {
+        let conn = self.conn.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+            .optional()
+            .context(ErrorKind::FailedToWrite, "Failed to checkpoint the database")?;
+        Ok(())
+    }
+
+    fn add_session(&self, mut session: PlaySession) -> Result<PlaySession, NasError> {
+        let conn = self.conn.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let row_id: Option<i64> = conn.query_row("SELECT id FROM games WHERE uuid = ?1", [&session.game_id], |row| row.get(0))
+            .optional()
+            .context(ErrorKind::FailedToReadFile, format!("Failed to query the game {}", session.game_id))?;
+        let row_id = row_id.ok_or_else(|| NasError::new(ErrorKind::NotFound, format!("No game with the id {}", session.game_id)))?;
+        conn.execute(
+            "INSERT INTO play_sessions (game_id, client_id, user, started_at, ended_at, exit_status) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![row_id, session.client_id, session.user, session.started_at.to_rfc3339(), session.ended_at.to_rfc3339(), session.exit_status],
+        ).context(ErrorKind::FailedToWrite, format!("Failed to record a session of {}", session.game_id))?;
+        session.id = conn.last_insert_rowid().to_string();
+        Ok(session)
+    }
+
+    fn sessions(&self) -> Result<Vec<PlaySession>, NasError> {
+        let conn = self.conn.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        query_sessions(&conn).context(ErrorKind::FailedToReadFile, "Failed to query the play sessions")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::JsonFileStore;
+
+    fn game(id: &str) -> Game {
+        let mut game = Game::new();
+        game.set_launcher(Launcher::new("steam".to_owned(), id.to_owned()));
+        game.set_launcher(Launcher::new("gog".to_owned(), id.to_owned()));
+        game.set_steam_grid_id(Some(id.to_owned()));
+        game
+    }
+
+    #[test]
+    fn migrates_and_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game_library.db");
+
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.add_games(vec![game("1"), game("2"), game("1")]).unwrap(), 2);
+        drop(store);
+
+        // re-opening must not re-run the migrations
+        let store = SqliteStore::open(&path).unwrap();
+        let games = store.games().unwrap();
+        assert_eq!(games.len(), 2);
+        assert!(games[0].is_duplicate_of(&game("1")) && games[1].is_duplicate_of(&game("2")));
+        assert_eq!(store.game(games[1].id()).unwrap().as_ref(), Some(&games[1]));
+        assert_eq!(store.add_games(games).unwrap(), 0);
+    }
+
+    #[test]
+    fn updates_and_removes_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(&dir.path().join("game_library.db")).unwrap();
+        store.add_games(vec![game("1")]).unwrap();
+        let id = store.games().unwrap()[0].id().to_owned();
+
+        let patch: GamePatch = serde_json::from_str(r#"{ "name": "Hades", "launcher": [{ "name": "epic", "game_id": "x" }] }"#).unwrap();
+        let updated = store.update_game(&id, patch).unwrap().unwrap();
+        assert_eq!(store.game(&id).unwrap(), Some(updated.clone()));
+        assert_eq!((updated.name(), updated.launchers().len(), updated.steam_grid_id()), ("Hades", 1, Some("1")));
+
+        assert!(store.remove_game(&id).unwrap());
+        assert!(!store.remove_game(&id).unwrap());
+        assert!(store.games().unwrap().is_empty());
+    }
+
+    #[test]
+    fn records_sessions_of_known_games() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(&dir.path().join("game_library.db")).unwrap();
+        store.add_games(vec![game("1")]).unwrap();
+        let id = store.games().unwrap()[0].id().to_owned();
+
+        let started_at = chrono::DateTime::parse_from_rfc3339("2026-10-18T20:00:00+02:00").unwrap().with_timezone(&chrono::Utc);
+        let session = PlaySession {
+            id: String::new(),
+            game_id: id.clone(),
+            client_id: "desktop".to_owned(),
+            user: "anna".to_owned(),
+            started_at,
+            ended_at: started_at + chrono::Duration::minutes(90),
+            exit_status: None,
+        };
+        let stored = store.add_session(session.clone()).unwrap();
+        assert!(!stored.id.is_empty());
+        assert_eq!(store.sessions().unwrap(), [stored]);
+
+        let unknown = PlaySession { game_id: "unknown".to_owned(), ..session };
+        assert_eq!(store.add_session(unknown).unwrap_err().kind(), ErrorKind::NotFound);
+
+        // the sessions go away together with their game
+        store.remove_game(&id).unwrap();
+        assert!(store.sessions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn imports_json_library_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_path = dir.path().join("game_library.json");
+        JsonFileStore::open(json_path.clone(), 0).add_games(vec![game("1"), game("2")]).unwrap();
+
+        let store = SqliteStore::open(&dir.path().join("game_library.db")).unwrap();
+        assert_eq!(store.import_json(&json_path).unwrap(), 2);
+        assert_eq!(store.import_json(&json_path).unwrap(), 0);
+        assert_eq!(store.games().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,178 @@
+//! This crate is for play sessions and the statistics derived from
+//! them. The client records a `PlaySession` every time a game exits
+//! and sends it to the server, which keeps the sessions next to the
+//! game library, so every client sees the same numbers.
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How many weeks the histogram covers unless asked otherwise.
+pub const DEFAULT_WEEKS: usize = 12;
+/// Histograms never reach back further than this many weeks.
+pub const MAX_WEEKS: usize = 520;
+/// How many games `most_played` lists unless asked otherwise.
+pub const DEFAULT_LIMIT: usize = 10;
+
+/// The query of the `GET /stats` routes, e.g. `?weeks=4&limit=5`.
+#[derive(Deserialize, Debug, Default)]
+pub struct StatsQuery {
+    pub weeks: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl StatsQuery {
+    pub fn weeks(&self) -> usize { self.weeks.unwrap_or(DEFAULT_WEEKS).min(MAX_WEEKS) }
+    pub fn limit(&self) -> usize { self.limit.unwrap_or(DEFAULT_LIMIT) }
+}
+
+/// One run of a game on one of the clients.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PlaySession {
+    /// Handed out by the server, leave it empty when sending a new
+    /// session.
+    #[serde(default)]
+    pub id: String,
+    pub game_id: String,
+    /// The client the game ran on, see `ClientSettings.client_id`.
+    pub client_id: String,
+    /// Who played, see `ClientSettings.user`.
+    #[serde(default)]
+    pub user: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    /// The exit code of the game, `None` if it was killed by a signal.
+    #[serde(default)]
+    pub exit_status: Option<i32>,
+}
+
+impl PlaySession {
+    /// How long the game ran, never negative.
+    pub fn duration(&self) -> Duration {
+        (self.ended_at - self.started_at).max(Duration::zero())
+    }
+}
+
+/// The aggregated sessions of a single game.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct GameStats {
+    pub game_id: String,
+    pub sessions: usize,
+    pub total_secs: i64,
+    pub last_played: Option<DateTime<Utc>>,
+}
+
+/// The playtime of one week, starting on monday.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct WeekStats {
+    pub week_start: NaiveDate,
+    pub total_secs: i64,
+}
+
+/// Everything the stats routes return for a game or a user.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub sessions: usize,
+    pub total_secs: i64,
+    pub last_played: Option<DateTime<Utc>>,
+    /// The last weeks up to and including the current one, oldest
+    /// first.
+    pub weeks: Vec<WeekStats>,
+    /// The games with the most playtime, most played first.
+    pub most_played: Vec<GameStats>,
+}
+
+fn week_start(time: DateTime<Utc>) -> NaiveDate {
+    let date = time.date_naive();
+    date - Duration::days(date.weekday().num_days_from_monday().into())
+}
+
+/// Sums up the sessions per game, ordered by their total playtime and
+/// then by when they were played last.
+pub fn per_game<'a>(sessions: impl IntoIterator<Item = &'a PlaySession>) -> Vec<GameStats> {
+    let mut games: HashMap<&str, GameStats> = HashMap::new();
+    for session in sessions {
+        let stats = games.entry(&session.game_id).or_insert_with(|| GameStats {
+            game_id: session.game_id.clone(),
+            sessions: 0,
+            total_secs: 0,
+            last_played: None,
+        });
+        stats.sessions += 1;
+        stats.total_secs += session.duration().num_seconds();
+        stats.last_played = stats.last_played.max(Some(session.ended_at));
+    }
+    let mut games: Vec<GameStats> = games.into_values().collect();
+    games.sort_by(|a, b| b.total_secs.cmp(&a.total_secs).then(b.last_played.cmp(&a.last_played)).then(a.game_id.cmp(&b.game_id)));
+    games
+}
+
+/// The playtime of the last `weeks` weeks up to the week of `now`.
+/// Sessions count towards the week they were started in.
+pub fn weekly<'a>(sessions: impl IntoIterator<Item = &'a PlaySession>, weeks: usize, now: DateTime<Utc>) -> Vec<WeekStats> {
+    let current = week_start(now);
+    let mut histogram: Vec<WeekStats> = (0..weeks as i64).rev()
+        .map(|n| WeekStats { week_start: current - Duration::weeks(n), total_secs: 0 })
+        .collect();
+    for session in sessions {
+        let start = week_start(session.started_at);
+        if let Some(week) = histogram.iter_mut().find(|w| w.week_start == start) {
+            week.total_secs += session.duration().num_seconds();
+        }
+    }
+    histogram
+}
+
+/// Aggregates the sessions into `Stats`, with `most_played` cut off
+/// after `limit` games.
+pub fn summarize<'a>(sessions: impl IntoIterator<Item = &'a PlaySession> + Clone, weeks: usize, limit: usize, now: DateTime<Utc>) -> Stats {
+    let mut games = per_game(sessions.clone());
+    let stats = Stats {
+        sessions: games.iter().map(|g| g.sessions).sum(),
+        total_secs: games.iter().map(|g| g.total_secs).sum(),
+        last_played: games.iter().filter_map(|g| g.last_played).max(),
+        weeks: weekly(sessions, weeks, now),
+        most_played: Vec::new(),
+    };
+    games.truncate(limit);
+    Stats { most_played: games, ..stats }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(game_id: &str, user: &str, start: &str, minutes: i64) -> PlaySession {
+        let started_at = DateTime::parse_from_rfc3339(start).unwrap().with_timezone(&Utc);
+        PlaySession {
+            id: String::new(),
+            game_id: game_id.to_owned(),
+            client_id: "desktop".to_owned(),
+            user: user.to_owned(),
+            started_at,
+            ended_at: started_at + Duration::minutes(minutes),
+            exit_status: Some(0),
+        }
+    }
+
+    #[test]
+    fn aggregates_playtime_per_game_and_week() {
+        let sessions = [
+            session("hades", "anna", "2026-10-05T20:00:00Z", 60),
+            session("celeste", "anna", "2026-10-12T18:00:00Z", 30),
+            session("hades", "anna", "2026-10-15T21:00:00Z", 90),
+            session("hades", "ben", "2026-10-16T21:00:00Z", 10),
+            // before the histogram starts, but still part of the totals
+            session("celeste", "anna", "2026-01-01T12:00:00Z", 120),
+        ];
+        let now = DateTime::parse_from_rfc3339("2026-10-18T12:00:00Z").unwrap().with_timezone(&Utc);
+        let anna: Vec<_> = sessions.iter().filter(|s| s.user == "anna").collect();
+        let stats = summarize(anna.iter().copied(), 3, 1, now);
+
+        assert_eq!((stats.sessions, stats.total_secs), (4, 300 * 60));
+        assert_eq!(stats.last_played, Some(sessions[2].ended_at));
+        assert_eq!(stats.weeks.iter().map(|w| (w.week_start.to_string(), w.total_secs / 60)).collect::<Vec<_>>(),
+            [("2026-09-28".to_owned(), 0), ("2026-10-05".to_owned(), 60), ("2026-10-12".to_owned(), 120)]);
+        assert_eq!(stats.most_played.len(), 1);
+        assert_eq!((stats.most_played[0].game_id.as_str(), stats.most_played[0].sessions), ("hades", 2));
+    }
+}
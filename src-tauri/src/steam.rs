@@ -0,0 +1,238 @@
+//! This crate is for reading the games that are installed through a
+//! local Steam installation. It reads `libraryfolders.vdf` to find
+//! every library folder and then the `appmanifest_*.acf` of every
+//! installed app.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, error, logging::LoggingLevel, logging::logging_function};
+use crate::types::Launcher;
+use crate::vdf;
+use crate::scanner::{DetectedGame, Detection};
+use crate::provider::{LauncherProvider, LaunchCommand, require_install_dir};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The name that is used for `Launcher.name`.
+pub const STEAM_LAUNCHER: &str = "steam";
+
+/// An installed Steam app as described by its app manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SteamApp {
+    pub app_id: String,
+    pub name: String,
+    /// The absolute path of `steamapps/common/<installdir>`.
+    pub install_dir: PathBuf,
+    pub size_on_disk: Option<u64>,
+    pub build_id: Option<String>,
+}
+
+impl SteamApp {
+    /// Builds the `Launcher` entry of this app.
+    pub fn launcher(&self) -> Launcher {
+        let mut launcher = Launcher::new(STEAM_LAUNCHER.to_owned(), self.app_id.clone());
+        launcher.install_dir = Some(self.install_dir.clone());
+        launcher.size_on_disk = self.size_on_disk;
+        launcher.build_id = self.build_id.clone();
+        launcher
+    }
+
+    /// Builds the entry the scanner would have produced for this app.
+    pub fn to_detected(&self) -> DetectedGame {
+        DetectedGame { name: self.name.clone(), launcher: self.launcher() }
+    }
+}
+
+/// Returns the `steamapps` directories of every library folder of
+/// the Steam installation at `steam_root`, including its own.
+///
+/// Both the current (`"libraryfolders" { "0" { "path" ... } }`) and
+/// the legacy (`"LibraryFolders" { "1" "D:\\Steam" }`) layout of
+/// `libraryfolders.vdf` are understood.
+pub fn library_folders(steam_root: &Path) -> Result<Vec<PathBuf>, NasError> {
+    let steamapps = steam_root.join("steamapps");
+    let vdf_path = steamapps.join("libraryfolders.vdf");
+    let mut folders = vec![steamapps];
+
+    let contents = match fs::read_to_string(&vdf_path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!("There is no {:?}, only the default library folder is used", vdf_path);
+            return Ok(folders);
+        },
+        Err(e) => return Err(NasError::new(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", vdf_path)).with_source(e)),
+    };
+    let (_, root) = vdf::parse(&contents)?;
+    for (key, value) in root.entries() {
+        // only the numbered entries are library folders
+        if key.parse::<u32>().is_err() { continue; }
+        let path = match value {
+            vdf::Vdf::Value(path) => path.as_str(),
+            object => match object.get_str("path") {
+                Some(path) => path,
+                None => continue,
+            },
+        };
+        let folder = PathBuf::from(path).join("steamapps");
+        if !folders.contains(&folder) { folders.push(folder); }
+    }
+    Ok(folders)
+}
+
+/// Parses a single `appmanifest_*.acf`.
+///
+/// `steamapps` is the directory the manifest is in, the install
+/// directory of the app is relative to it.
+///
+/// # Errors
+/// Fails with `ErrorKind::FailedToParse` if the manifest lacks an
+/// `appid` or an `installdir`.
+pub fn parse_app_manifest(path: &Path, steamapps: &Path) -> Result<SteamApp, NasError> {
+    let contents = fs::read_to_string(path).context(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path))?;
+    let (_, state) = vdf::parse(&contents)?;
+    let missing = |key: &str| NasError::new(ErrorKind::FailedToParse, format!("{:?} has no {:?}", path, key));
+
+    let app_id = state.get_str("appid").ok_or_else(|| missing("appid"))?.to_owned();
+    let install_dir = state.get_str("installdir").ok_or_else(|| missing("installdir"))?;
+    Ok(SteamApp {
+        name: state.get_str("name").unwrap_or(&app_id).to_owned(),
+        install_dir: steamapps.join("common").join(install_dir),
+        size_on_disk: state.get_str("SizeOnDisk").and_then(|s| s.parse().ok()),
+        build_id: state.get_str("buildid").map(str::to_owned),
+        app_id,
+    })
+}
+
+/// Parses every app manifest in a `steamapps` directory.
+///
+/// # Errors
+/// This will not error and it instead only log any issues, broken
+/// manifests are skipped.
+pub fn apps_in_folder(steamapps: &Path) -> Vec<SteamApp> {
+    let entries = match fs::read_dir(steamapps) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to read the library folder {:?} with {:?}", steamapps, e);
+            return Vec::new();
+        }
+    };
+    let mut apps: Vec<SteamApp> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().and_then(std::ffi::OsStr::to_str)
+            .is_some_and(|n| n.starts_with("appmanifest_") && n.ends_with(".acf")))
+        .filter_map(|path| parse_app_manifest(&path, steamapps)
+            .map_err(|e| { warn!("Skipping the app manifest {:?}: {}", path, e); })
+            .ok())
+        .collect();
+    apps.sort_by(|a, b| a.app_id.cmp(&b.app_id));
+    trace!("Found {} apps in {:?}", apps.len(), steamapps);
+    apps
+}
+
+/// Returns every app that is installed in any library folder of the
+/// Steam installation at `steam_root`.
+pub fn installed_apps(steam_root: &Path) -> Result<Vec<SteamApp>, NasError> {
+    let apps: Vec<SteamApp> = library_folders(steam_root)?.iter()
+        .flat_map(|folder| apps_in_folder(folder))
+        .collect();
+    info!("Found {} installed Steam apps in {:?}", apps.len(), steam_root);
+    Ok(apps)
+}
+
+/// The default location of the Steam installation on linux.
+pub fn default_steam_root() -> PathBuf {
+    crate::server::expand_tilde("~/.local/share/Steam")
+}
+
+/// Lists the games of a local Steam installation and starts them
+/// through the Steam client.
+pub struct SteamProvider {
+    steam_root: PathBuf,
+}
+
+impl SteamProvider {
+    pub fn new(steam_root: PathBuf) -> Self { Self { steam_root } }
+}
+
+impl LauncherProvider for SteamProvider {
+    fn name(&self) -> &'static str { STEAM_LAUNCHER }
+
+    fn list_games(&self) -> Result<Vec<DetectedGame>, NasError> {
+        if !self.steam_root.is_dir() { return Ok(Vec::new()); }
+        Ok(installed_apps(&self.steam_root)?.iter().map(SteamApp::to_detected).collect())
+    }
+
+    /// Every app manifest in a `steamapps` folder is a game, which
+    /// lives in `common/` and must not be picked up a second time.
+    fn detect(&self, dir: &Path, files: &[PathBuf], _dirs: &[PathBuf]) -> Detection {
+        let mut detection = Detection::default();
+        for file in files {
+            let Some(file_name) = file.file_name().and_then(std::ffi::OsStr::to_str) else { continue };
+            if !(file_name.starts_with("appmanifest_") && file_name.ends_with(".acf")) { continue; }
+            match parse_app_manifest(file, dir) {
+                Ok(app) => detection.games.push(app.to_detected()),
+                Err(e) => { warn!("Skipping the app manifest {:?}: {}", file, e); },
+            }
+        }
+        if !detection.games.is_empty() { detection.skip.push(dir.join("common")); }
+        detection
+    }
+
+    fn launch_command(&self, launcher: &Launcher) -> Result<LaunchCommand, NasError> {
+        require_install_dir(launcher)?;
+        Ok(LaunchCommand::new("steam", [format!("steam://rungameid/{}", launcher.game_id)]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn manifest(app_id: &str, name: &str) -> String {
+        format!(
+            "\"AppState\"\n{{\n\t\"appid\"\t\t\"{0}\"\n\t\"name\"\t\t\"{1}\"\n\t\"buildid\"\t\t\"{0}00\"\n\t\"installdir\"\t\t\"{1}\"\n\t\"SizeOnDisk\"\t\t\"1024\"\n}}\n",
+            app_id, name
+        )
+    }
+
+    #[test]
+    fn reads_every_library_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, extra) = (dir.path().join("Steam"), dir.path().join("SteamLibrary"));
+        write(&root.join("steamapps/libraryfolders.vdf"), &format!(
+            "\"libraryfolders\"\n{{\n\t\"0\"\n\t{{\n\t\t\"path\"\t\t\"{}\"\n\t}}\n\t\"1\"\n\t{{\n\t\t\"path\"\t\t\"{}\"\n\t\t\"apps\" {{ \"620\" \"1024\" }}\n\t}}\n}}\n",
+            root.display(), extra.display()
+        ));
+        write(&root.join("steamapps/appmanifest_400.acf"), &manifest("400", "Portal"));
+        write(&extra.join("steamapps/appmanifest_620.acf"), &manifest("620", "Portal 2"));
+        write(&extra.join("steamapps/appmanifest_1.acf"), "broken");
+
+        let apps = installed_apps(&root).unwrap();
+        assert_eq!(apps.len(), 2);
+        assert_eq!(apps[1], SteamApp {
+            app_id: "620".to_owned(),
+            name: "Portal 2".to_owned(),
+            install_dir: extra.join("steamapps/common/Portal 2"),
+            size_on_disk: Some(1024),
+            build_id: Some("62000".to_owned()),
+        });
+
+        let game = apps[1].to_detected();
+        assert_eq!(game.name, "Portal 2");
+        assert_eq!(game.launcher.game_id, "620");
+        assert_eq!(game.launcher.build_id.as_deref(), Some("62000"));
+    }
+
+    #[test]
+    fn reads_legacy_library_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Steam");
+        write(&root.join("steamapps/libraryfolders.vdf"), "\"LibraryFolders\"\n{\n\t\"TimeNextStatsReport\"\t\t\"1\"\n\t\"1\"\t\t\"/mnt/games\"\n}\n");
+        assert_eq!(library_folders(&root).unwrap(), [root.join("steamapps"), PathBuf::from("/mnt/games/steamapps")]);
+    }
+}
@@ -0,0 +1,411 @@
+//! This crate is for talking to the Steam Web API. It wraps the
+//! endpoints listed in the README in a typed client and imports the
+//! owned games of a user, together with their playtime and
+//! achievement progress, into the game library.
+//!
+//! See https://developer.valvesoftware.com/wiki/Steam_Web_API
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, logging::LoggingLevel, logging::logging_function};
+use crate::types::{AchievementProgress, Game, GamePatch, Launcher};
+use crate::store::LibraryStore;
+use crate::steam::STEAM_LAUNCHER;
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use actix_web::web;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
+pub const DEFAULT_BASE_URL: &str = "https://api.steampowered.com";
+/// The storefront API, which isn't part of the Web API and needs no key.
+pub const DEFAULT_STORE_URL: &str = "https://store.steampowered.com";
+
+/// How many screenshots of a game are kept.
+pub const MAX_SCREENSHOTS: usize = 8;
+
+/// How many achievement requests are sent at the same time.
+const CONCURRENT_REQUESTS: usize = 5;
+
+/// A game as returned by `GetOwnedGames` and `GetRecentlyPlayedGames`.
+///
+/// Playtimes are in minutes.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct OwnedGame {
+    pub appid: u64,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub playtime_forever: u64,
+    #[serde(default)]
+    pub playtime_2weeks: Option<u64>,
+    #[serde(default)]
+    pub img_icon_url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Achievement {
+    pub apiname: String,
+    pub achieved: u8,
+    #[serde(default)]
+    pub unlocktime: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PlayerSummary {
+    pub steamid: String,
+    pub personaname: String,
+    #[serde(default)]
+    pub profileurl: Option<String>,
+    #[serde(default)]
+    pub avatarfull: Option<String>,
+    #[serde(default)]
+    pub personastate: u8,
+}
+
+#[derive(Deserialize)]
+struct Response<T> { response: T }
+
+#[derive(Deserialize)]
+struct GamesResponse {
+    // private profiles return an empty response
+    #[serde(default)]
+    games: Vec<OwnedGame>,
+}
+
+#[derive(Deserialize)]
+struct PlayerStats {
+    playerstats: PlayerStatsInner,
+}
+
+#[derive(Deserialize)]
+struct PlayerStatsInner {
+    #[serde(default)]
+    achievements: Vec<Achievement>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PlayersResponse { players: Vec<PlayerSummary> }
+
+/// An app of the response of `api/appdetails`, which is keyed by app id.
+#[derive(Deserialize)]
+struct AppDetails {
+    success: bool,
+    #[serde(default)]
+    data: Option<AppData>,
+}
+
+#[derive(Deserialize)]
+struct AppData {
+    #[serde(default)]
+    screenshots: Vec<Screenshot>,
+}
+
+#[derive(Deserialize)]
+struct Screenshot { path_full: String }
+
+/// What `import_owned_games` did to the game library.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SteamImportReport {
+    pub added: usize,
+    pub updated: usize,
+}
+
+/// The request body of `POST /steam/import`.
+#[derive(Debug, Deserialize)]
+pub struct SteamImportRequest {
+    pub steam_id: String,
+    /// Whether the achievements of every played game are fetched
+    /// as well, which takes one request per game.
+    #[serde(default)]
+    pub achievements: bool,
+}
+
+pub struct SteamApiClient {
+    http: reqwest::Client,
+    key: String,
+    base_url: String,
+    store_url: String,
+}
+
+impl SteamApiClient {
+    pub fn new(key: impl Into<String>) -> Self {
+        Self { http: reqwest::Client::new(), key: key.into(), base_url: DEFAULT_BASE_URL.to_owned(), store_url: DEFAULT_STORE_URL.to_owned() }
+    }
+
+    /// Sends every request to `base_url` instead of the real API,
+    /// e.g. a local mock server.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Sends the storefront requests to `store_url` instead.
+    pub fn with_store_url(mut self, store_url: impl Into<String>) -> Self {
+        self.store_url = store_url.into().trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Builds a client from `STEAM_API_KEY` and the optional
+    /// `STEAM_API_URL` and `STEAM_STORE_URL`.
+    pub fn from_env() -> Self {
+        let key = env::var("STEAM_API_KEY").unwrap_or_else(|_| {
+            warn!("STEAM_API_KEY is not set, requests to the Steam Web API will be rejected");
+            String::new()
+        });
+        Self::new(key).with_env_urls()
+    }
+
+    /// Like `from_env()`, but without a key, which the storefront, e.g.
+    /// `screenshots()`, doesn't need.
+    pub fn store_from_env() -> Self {
+        Self::new(String::new()).with_env_urls()
+    }
+
+    fn with_env_urls(self) -> Self {
+        let client = match env::var("STEAM_API_URL") {
+            Ok(url) => self.with_base_url(url),
+            Err(_) => self,
+        };
+        match env::var("STEAM_STORE_URL") {
+            Ok(url) => client.with_store_url(url),
+            Err(_) => client,
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, method: &str, query: &[(&str, &str)]) -> Result<T, NasError> {
+        let url = format!("{}/{}/", self.base_url, method);
+        trace!("Requesting {}", url);
+        let res = self.http.get(&url)
+            .query(&[("key", self.key.as_str()), ("format", "json")])
+            .query(query)
+            .send().await
+            .context(ErrorKind::FailedToFetch, format!("Failed to request {}", method))?;
+
+        let status = res.status();
+        let body = res.bytes().await.context(ErrorKind::FailedToFetch, format!("Failed to read the response of {}", method))?;
+        if !status.is_success() {
+            // GetPlayerAchievements explains itself even on errors
+            let reason = serde_json::from_slice::<PlayerStats>(&body).ok()
+                .and_then(|s| s.playerstats.error)
+                .unwrap_or_else(|| status.to_string());
+            return Err(NasError::new(ErrorKind::FailedToFetch, format!("{} failed with {}", method, reason)));
+        }
+        serde_json::from_slice(&body).context(ErrorKind::FailedToParse, format!("Failed to parse the response of {}", method))
+    }
+
+    /// `IPlayerService/GetOwnedGames`, including the names of the
+    /// games. Private profiles have no games.
+    pub async fn owned_games(&self, steam_id: &str) -> Result<Vec<OwnedGame>, NasError> {
+        let res: Response<GamesResponse> = self.get(
+            "IPlayerService/GetOwnedGames/v0001",
+            &[("steamid", steam_id), ("include_appinfo", "1"), ("include_played_free_games", "1")],
+        ).await?;
+        Ok(res.response.games)
+    }
+
+    /// `IPlayerService/GetRecentlyPlayedGames`, the games played in
+    /// the last two weeks.
+    pub async fn recently_played_games(&self, steam_id: &str) -> Result<Vec<OwnedGame>, NasError> {
+        let res: Response<GamesResponse> = self.get("IPlayerService/GetRecentlyPlayedGames/v0001", &[("steamid", steam_id)]).await?;
+        Ok(res.response.games)
+    }
+
+    /// `ISteamUserStats/GetPlayerAchievements` for a single game.
+    ///
+    /// # Errors
+    /// Games without stats are answered with an error by Steam, which
+    /// becomes `ErrorKind::FailedToFetch`.
+    pub async fn player_achievements(&self, steam_id: &str, app_id: u64) -> Result<Vec<Achievement>, NasError> {
+        let app_id = app_id.to_string();
+        let res: PlayerStats = self.get("ISteamUserStats/GetPlayerAchievements/v0001", &[("steamid", steam_id), ("appid", &app_id)]).await?;
+        Ok(res.playerstats.achievements)
+    }
+
+    /// `ISteamUser/GetPlayerSummaries` for up to 100 Steam ids.
+    pub async fn player_summaries(&self, steam_ids: &[&str]) -> Result<Vec<PlayerSummary>, NasError> {
+        let ids = steam_ids.join(",");
+        let res: Response<PlayersResponse> = self.get("ISteamUser/GetPlayerSummaries/v0002", &[("steamids", &ids)]).await?;
+        Ok(res.response.players)
+    }
+
+    /// The urls of the first `MAX_SCREENSHOTS` screenshots on the store
+    /// page of `app_id`, from `api/appdetails`.
+    ///
+    /// # Errors
+    /// Apps that aren't on the store are `ErrorKind::NotFound`.
+    pub async fn screenshots(&self, app_id: &str) -> Result<Vec<String>, NasError> {
+        let url = format!("{}/api/appdetails", self.store_url);
+        trace!("Requesting {} for {}", url, app_id);
+        let body = self.http.get(&url)
+            .query(&[("appids", app_id), ("filters", "screenshots")])
+            .send().await
+            .and_then(|res| res.error_for_status())
+            .context(ErrorKind::FailedToFetch, format!("Failed to request the store page of {}", app_id))?
+            .bytes().await
+            .context(ErrorKind::FailedToFetch, format!("Failed to read the store page of {}", app_id))?;
+        let mut res: HashMap<String, AppDetails> = serde_json::from_slice(&body)
+            .context(ErrorKind::FailedToParse, format!("Failed to parse the store page of {}", app_id))?;
+        match res.remove(app_id) {
+            Some(AppDetails { success: true, data }) => Ok(data.map(|data| data.screenshots).unwrap_or_default()
+                .into_iter().take(MAX_SCREENSHOTS).map(|s| s.path_full).collect()),
+            _ => Err(NasError::new(ErrorKind::NotFound, format!("{} is not on the Steam store", app_id))),
+        }
+    }
+}
+
+/// Imports every game owned by `steam_id` into the store.
+///
+/// Games that already have a Steam launcher with the same app id get
+/// their playtime and achievements updated, everything else about
+/// them (e.g. the install directory) is kept. The others are added
+/// with only a Steam launcher.
+///
+/// The library is read and written off the async thread, with one
+/// write for all of the games.
+///
+/// # Errors
+/// Only fetching the owned games and writing to the store can fail,
+/// games whose achievements can't be fetched are logged and skipped.
+pub async fn import_owned_games(client: &SteamApiClient, store: Arc<dyn LibraryStore>, steam_id: &str, with_achievements: bool) -> Result<SteamImportReport, NasError> {
+    let owned = client.owned_games(steam_id).await?;
+    info!("{} owns {} games on Steam", steam_id, owned.len());
+
+    let achievements: Vec<(u64, AchievementProgress)> = if with_achievements {
+        stream::iter(owned.iter().filter(|g| g.playtime_forever > 0))
+            .map(|game| async move {
+                match client.player_achievements(steam_id, game.appid).await {
+                    Ok(list) if !list.is_empty() => Some((game.appid, progress(&list))),
+                    Ok(_) => None,
+                    Err(e) => {
+                        trace!("No achievements for {}: {}", game.appid, e);
+                        None
+                    },
+                }
+            })
+            .buffer_unordered(CONCURRENT_REQUESTS)
+            .filter_map(|x| async move { x })
+            .collect().await
+    } else {
+        Vec::new()
+    };
+
+    let existing = { let store = store.clone(); web::block(move || store.games()).await?? };
+    let mut patches = Vec::new();
+    let mut new_games = Vec::new();
+    for owned_game in owned {
+        let app_id = owned_game.appid.to_string();
+        let patch = GamePatch {
+            playtime_minutes: Some(owned_game.playtime_forever),
+            achievements: achievements.iter().find(|(id, _)| *id == owned_game.appid).map(|(_, p)| *p),
+            ..GamePatch::default()
+        };
+        let known = existing.iter().find(|game| game.launchers().iter()
+            .any(|l| l.name == STEAM_LAUNCHER && l.game_id == app_id));
+        match known {
+            Some(game) => patches.push((game.id().to_owned(), patch)),
+            None => {
+                let mut game = Game::new();
+                game.set_name(owned_game.name.unwrap_or_else(|| app_id.clone()));
+                game.set_launcher(Launcher::new(STEAM_LAUNCHER.to_owned(), app_id));
+                game.apply(patch);
+                new_games.push(game);
+            },
+        }
+    }
+    let (updated, added) = web::block(move || Ok::<_, NasError>((store.update_games(patches)?, store.add_games(new_games)?))).await??;
+    let report = SteamImportReport { added, updated };
+    info!("Imported the Steam library of {}: {} added, {} updated", steam_id, report.added, report.updated);
+    Ok(report)
+}
+
+fn progress(achievements: &[Achievement]) -> AchievementProgress {
+    AchievementProgress {
+        unlocked: achievements.iter().filter(|a| a.achieved != 0).count() as u32,
+        total: achievements.len() as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::JsonFileStore;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use serde_json::json;
+
+    async fn owned_games() -> HttpResponse {
+        HttpResponse::Ok().json(json!({ "response": { "game_count": 2, "games": [
+            { "appid": 620, "name": "Portal 2", "playtime_forever": 600 },
+            { "appid": 70, "name": "Half-Life", "playtime_forever": 0 },
+        ] } }))
+    }
+
+    async fn achievements(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+        match query.get("appid").map(String::as_str) {
+            Some("620") => HttpResponse::Ok().json(json!({ "playerstats": { "success": true, "achievements": [
+                { "apiname": "a", "achieved": 1, "unlocktime": 1 },
+                { "apiname": "b", "achieved": 0, "unlocktime": 0 },
+            ] } })),
+            _ => HttpResponse::BadRequest().json(json!({ "playerstats": { "error": "Requested app has no stats", "success": false } })),
+        }
+    }
+
+    async fn app_details(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+        let screenshots: Vec<_> = (0..10).map(|i| json!({ "id": i, "path_full": format!("https://cdn/ss_{}.jpg", i) })).collect();
+        match query.get("appids").map(String::as_str) {
+            Some("620") => HttpResponse::Ok().json(json!({ "620": { "success": true, "data": { "screenshots": screenshots } } })),
+            Some(id) => HttpResponse::Ok().json(json!({ id: { "success": false } })),
+            None => HttpResponse::BadRequest().finish(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn imports_owned_games_from_a_mock() {
+        let server = HttpServer::new(|| App::new()
+                .route("/IPlayerService/GetOwnedGames/v0001/", web::get().to(owned_games))
+                .route("/ISteamUserStats/GetPlayerAchievements/v0001/", web::get().to(achievements)))
+            .bind(("127.0.0.1", 0)).unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn LibraryStore> = Arc::new(JsonFileStore::open(dir.path().join("library.json"), 0));
+        let mut portal = Game::new();
+        portal.set_name("Portal 2".to_owned());
+        let mut launcher = Launcher::new(STEAM_LAUNCHER.to_owned(), "620".to_owned());
+        launcher.install_dir = Some("/games/Portal 2".into());
+        portal.set_launcher(launcher);
+        store.add_games(vec![portal]).unwrap();
+
+        let client = SteamApiClient::new("key").with_base_url(format!("http://{}", addr)).with_store_url(format!("http://{}", addr));
+        let report = import_owned_games(&client, store.clone(), "1", true).await.unwrap();
+        assert_eq!(report, SteamImportReport { added: 1, updated: 1 });
+
+        let games = store.games().unwrap();
+        let portal = games.iter().find(|g| g.name() == "Portal 2").unwrap();
+        assert_eq!(portal.playtime_minutes(), Some(600));
+        assert_eq!(portal.achievements(), Some(AchievementProgress { unlocked: 1, total: 2 }));
+        assert!(portal.launchers()[0].install_dir.is_some());
+        let half_life = games.iter().find(|g| g.name() == "Half-Life").unwrap();
+        assert_eq!(half_life.achievements(), None);
+
+        let err = client.player_achievements("1", 70).await.unwrap_err();
+        assert!(err.to_string().contains("Requested app has no stats"));
+    }
+
+    #[actix_web::test]
+    async fn fetches_screenshots_from_a_mock_store() {
+        let server = HttpServer::new(|| App::new().route("/api/appdetails", web::get().to(app_details)))
+            .bind(("127.0.0.1", 0)).unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let client = SteamApiClient::new("").with_store_url(format!("http://{}", addr));
+        let screenshots = client.screenshots("620").await.unwrap();
+        assert_eq!((screenshots.len(), screenshots[0].as_str()), (MAX_SCREENSHOTS, "https://cdn/ss_0.jpg"));
+        assert_eq!(client.screenshots("70").await.unwrap_err().kind(), ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,581 @@
+//! This crate is for persisting the `GameLibrary`. Routes should
+//! only ever talk to a `LibraryStore` and never to the file that
+//! backs it, so that every change is written to disk right away.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{trace, info, warn, error, logging::LoggingLevel, logging::logging_function};
+use crate::types::{Game, GameLibrary, GamePatch};
+use crate::stats::PlaySession;
+use crate::files::write_atomic;
+
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use serde_json::{Map, Value};
+
+/// The amount of old library files that are kept around.
+pub const DEFAULT_BACKUP_COUNT: usize = 5;
+/// The amount of versions of the library file that are remembered to
+/// merge hand edits against, see `JsonFileStore::reload()`.
+const HISTORY_COUNT: usize = 16;
+
+/// Storage layer for the `GameLibrary`.
+///
+/// Every method that changes the library is expected to persist
+/// the change before it returns. As such a crash can at most lose
+/// the change that was being made while it happened.
+pub trait LibraryStore: Send + Sync {
+    /// Returns a copy of every game in the library.
+    fn games(&self) -> Result<Vec<Game>, NasError>;
+    /// Returns the game with the given id, if there is one.
+    fn game(&self, id: &str) -> Result<Option<Game>, NasError>;
+    /// Adds the games that are not already present and returns how
+    /// many have been added. Games without an id are given one.
+    fn add_games(&self, games: Vec<Game>) -> Result<usize, NasError>;
+    /// Applies the `patch` to the game with the given id and returns
+    /// the updated game, or `None` if there is no such game.
+    fn update_game(&self, id: &str, patch: GamePatch) -> Result<Option<Game>, NasError>;
+    /// Applies every patch to the game of its id at once and returns
+    /// how many games there were. Unknown ids are skipped.
+    fn update_games(&self, patches: Vec<(String, GamePatch)>) -> Result<usize, NasError> {
+        let mut updated = 0;
+        for (id, patch) in patches {
+            if self.update_game(&id, patch)?.is_some() { updated += 1; }
+        }
+        Ok(updated)
+    }
+    /// Removes the game with the given id. Returns `false` if there
+    /// was no such game.
+    fn remove_game(&self, id: &str) -> Result<bool, NasError>;
+    /// Forces the library to be written to disk.
+    fn persist(&self) -> Result<(), NasError>;
+    /// Records a finished play session and returns it together with
+    /// the id it has been given.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::NotFound` if there is no game with the
+    /// `game_id` of the session.
+    fn add_session(&self, session: PlaySession) -> Result<PlaySession, NasError>;
+    /// Returns every play session, oldest first. The sessions of a
+    /// game are removed together with the game.
+    fn sessions(&self) -> Result<Vec<PlaySession>, NasError>;
+    /// The file that is edited by hand, if the library is kept in one.
+    fn watched_path(&self) -> Option<&Path> { None }
+    /// Picks up changes that were made to `watched_path()` behind the
+    /// back of the store, see `merge_libraries()`.
+    fn reload(&self) -> Result<LibraryDiff, NasError> { Ok(LibraryDiff::default()) }
+}
+
+/// What `LibraryStore::reload()` picked up from the file. Games are
+/// listed by their name, or their id if they have none.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LibraryDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// A game and the fields of it that changed.
+    pub changed: Vec<(String, Vec<String>)>,
+    /// Changes of the file that were dropped because the server
+    /// changed the same thing since, e.g. `"Hades: name"`.
+    pub conflicts: Vec<String>,
+    pub sessions_added: usize,
+    pub sessions_removed: usize,
+}
+
+impl LibraryDiff {
+    pub fn is_empty(&self) -> bool { *self == Self::default() }
+}
+
+fn label(game: &Game) -> String {
+    match game.name() {
+        "" => game.id().to_owned(),
+        name => name.to_owned(),
+    }
+}
+
+fn to_map(game: &Game) -> Result<Map<String, Value>, NasError> {
+    match serde_json::to_value(game).context(ErrorKind::FailedToSerialize, format!("Failed to serialize {}", label(game)))? {
+        Value::Object(map) => Ok(map),
+        _ => unreachable!("a game serializes to an object"),
+    }
+}
+
+/// Merges the fields of one game, returns the merged game together
+/// with the fields that were taken from `theirs` and those that
+/// conflict.
+fn merge_game(base: Option<&Game>, ours: &Game, theirs: &Game) -> Result<(Game, Vec<String>, Vec<String>), NasError> {
+    let base = base.map(to_map).transpose()?.unwrap_or_default();
+    let (mut merged, theirs_map) = (to_map(ours)?, to_map(theirs)?);
+    let ours = merged.clone();
+    let (mut changed, mut conflicts) = (Vec::new(), Vec::new());
+    for key in ours.keys().chain(theirs_map.keys()).collect::<BTreeSet<_>>() {
+        let (b, o, t) = (base.get(key), ours.get(key), theirs_map.get(key));
+        // nothing new in the file
+        if t == o || t == b { continue; }
+        if o != b {
+            conflicts.push(key.clone());
+            continue;
+        }
+        match t {
+            Some(value) => merged.insert(key.clone(), value.clone()),
+            None => merged.remove(key),
+        };
+        changed.push(key.clone());
+    }
+    let merged = serde_json::from_value(Value::Object(merged)).context(ErrorKind::FailedToParse, format!("Failed to merge {}", label(theirs)))?;
+    Ok((merged, changed, conflicts))
+}
+
+/// How many fields of the games differ between two libraries, a game
+/// that only one of them has counts with every field. Sessions count
+/// one each.
+fn distance(a: &GameLibrary, b: &GameLibrary) -> usize {
+    let fields = |game: &Game| to_map(game).unwrap_or_default();
+    let mut others: Vec<&Game> = b.collection.iter().collect();
+    let mut distance = 0;
+    for game in &a.collection {
+        let ours = fields(game);
+        match others.iter().position(|other| other.id() == game.id()) {
+            Some(index) => {
+                let theirs = fields(others.swap_remove(index));
+                distance += ours.keys().chain(theirs.keys()).collect::<BTreeSet<_>>().into_iter()
+                    .filter(|key| ours.get(*key) != theirs.get(*key))
+                    .count();
+            },
+            None => distance += ours.len(),
+        }
+    }
+    distance += others.into_iter().map(|game| fields(game).len()).sum::<usize>();
+    let sessions = |lib: &GameLibrary| lib.sessions.iter().map(|session| session.id.clone()).collect::<HashSet<_>>();
+    distance + sessions(a).symmetric_difference(&sessions(b)).count()
+}
+
+/// Merges a library file that was edited by hand (`theirs`) into the
+/// one of the server (`ours`). `base` is the library both of them
+/// started from.
+///
+/// Games and their fields that only changed on one side take that
+/// side. Should both sides have changed the same field differently,
+/// or should one side have removed a game the other changed, then the
+/// server's version is kept and the change of the file is reported as
+/// a conflict. Sessions are merged by their id.
+///
+/// # Errors
+/// Fails with `ErrorKind::FailedToParse` if a merged game isn't
+/// valid anymore.
+pub fn merge_libraries(base: &GameLibrary, ours: &GameLibrary, theirs: GameLibrary) -> Result<(GameLibrary, LibraryDiff), NasError> {
+    let mut diff = LibraryDiff::default();
+    let base_games: HashMap<&str, &Game> = base.collection.iter().map(|game| (game.id(), game)).collect();
+    let GameLibrary { collection: mut their_games, sessions: their_sessions } = theirs;
+
+    let mut collection = Vec::new();
+    for game in &ours.collection {
+        let base_game = base_games.get(game.id()).copied();
+        match their_games.iter().position(|theirs| theirs.id() == game.id()) {
+            Some(index) => {
+                let theirs = their_games.remove(index);
+                let (merged, changed, conflicts) = merge_game(base_game, game, &theirs)?;
+                if !changed.is_empty() { diff.changed.push((label(&merged), changed)); }
+                if !conflicts.is_empty() { diff.conflicts.push(format!("{}: {}", label(&merged), conflicts.join(", "))); }
+                collection.push(merged);
+            },
+            // removed from the file
+            None => match base_game {
+                Some(base_game) if base_game == game => diff.removed.push(label(game)),
+                Some(_) => {
+                    diff.conflicts.push(format!("{}: removed from the file but changed by the server", label(game)));
+                    collection.push(game.clone());
+                },
+                // added by the server since
+                None => collection.push(game.clone()),
+            },
+        }
+    }
+    for theirs in their_games {
+        match base_games.get(theirs.id()) {
+            // removed by the server since
+            Some(base_game) if **base_game == theirs => (),
+            Some(_) => diff.conflicts.push(format!("{}: changed in the file but removed by the server", label(&theirs))),
+            None => {
+                diff.added.push(label(&theirs));
+                collection.push(theirs);
+            },
+        }
+    }
+
+    let base_sessions: HashSet<&str> = base.sessions.iter().map(|session| session.id.as_str()).collect();
+    let kept_sessions: HashSet<&str> = their_sessions.iter().map(|session| session.id.as_str()).collect();
+    let our_sessions: HashSet<&str> = ours.sessions.iter().map(|session| session.id.as_str()).collect();
+    let mut sessions: Vec<PlaySession> = ours.sessions.iter()
+        .filter(|session| {
+            let removed = base_sessions.contains(session.id.as_str()) && !kept_sessions.contains(session.id.as_str());
+            if removed { diff.sessions_removed += 1; }
+            !removed
+        })
+        .cloned()
+        .collect();
+    for session in &their_sessions {
+        if !our_sessions.contains(session.id.as_str()) && !base_sessions.contains(session.id.as_str()) {
+            diff.sessions_added += 1;
+            sessions.push(session.clone());
+        }
+    }
+    sessions.retain(|session| collection.iter().any(|game| game.id() == session.game_id));
+    Ok((GameLibrary { collection, sessions }, diff))
+}
+
+/// A `LibraryStore` which keeps the library in memory and mirrors
+/// it into a single json file.
+///
+/// Writes are atomic: the library is written to a temporary file
+/// which is then renamed over the real one. Before that happens
+/// the old file is rotated into `{path}.1` ... `{path}.{backups}`.
+pub struct JsonFileStore {
+    path: PathBuf,
+    backups: usize,
+    library: Mutex<GameLibrary>,
+    /// The versions of the file since the last `reload()`, newest
+    /// last, one of which a hand edit started from.
+    history: Mutex<VecDeque<GameLibrary>>,
+}
+
+impl JsonFileStore {
+    /// Opens the library at `path`.
+    ///
+    /// Should the file be missing then an empty library is used.
+    /// Should the file be unreadable then the backups are tried
+    /// in order, starting with the newest one.
+    pub fn open(path: PathBuf, backups: usize) -> Self {
+        let mut library = match read_library(&path) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Failed to load the game library from {:?} with {:?}", &path, e);
+                (1..=backups)
+                    .map(|n| backup_path(&path, n))
+                    .find_map(|backup| {
+                        let lib = read_library(&backup).ok()?;
+                        warn!("Recovered the game library from the backup at {:?}", backup);
+                        Some(lib)
+                    })
+                    .unwrap_or_else(|| {
+                        info!("Falling back to default game library");
+                        GameLibrary::default()
+                    })
+            }
+        };
+        // libraries from before games had ids are upgraded right away
+        // so that the ids stay the same across restarts
+        let assigned = library.assign_ids();
+        let store = Self { path, backups, history: Mutex::new(VecDeque::from([library.clone()])), library: Mutex::new(library) };
+        if assigned > 0 {
+            info!("Assigned ids to {} games", assigned);
+            let _ = store.persist().map_err(|e| { error!("Failed to save the assigned ids with {:?}", e); });
+        }
+        store
+    }
+
+    /// Applies `f` to a copy of the library, writes the copy to disk
+    /// and only then swaps it in, all while holding the lock. A failed
+    /// write leaves the library as it is on disk.
+    fn modify<T>(&self, f: impl FnOnce(&mut GameLibrary) -> T) -> Result<T, NasError> {
+        let mut lib = self.library.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let mut changed = lib.clone();
+        let result = f(&mut changed);
+        self.write(&changed)?;
+        *lib = changed;
+        Ok(result)
+    }
+
+    fn write(&self, lib: &GameLibrary) -> Result<(), NasError> {
+        let serialized = serde_json::to_string_pretty(lib).context(ErrorKind::FailedToSerialize, "Failed to serialize the game library")?;
+        rotate_backups(&self.path, self.backups);
+        write_atomic(&self.path, serialized.as_bytes())?;
+        trace!("Saved the game library to {:?}", &self.path);
+        if let Ok(mut history) = self.history.lock() {
+            if history.len() == HISTORY_COUNT { history.pop_front(); }
+            history.push_back(lib.clone());
+        }
+        Ok(())
+    }
+}
+
+impl LibraryStore for JsonFileStore {
+    fn games(&self) -> Result<Vec<Game>, NasError> {
+        let lib = self.library.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        Ok(lib.collection.clone())
+    }
+
+    fn game(&self, id: &str) -> Result<Option<Game>, NasError> {
+        let lib = self.library.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        Ok(lib.collection.iter().find(|game| game.id() == id).cloned())
+    }
+
+    fn add_games(&self, games: Vec<Game>) -> Result<usize, NasError> {
+        self.modify(|lib| {
+            let mut counter = 0;
+            // if I were to rewirte this for loop with the filter() method then it would
+            // allow for duplicate entries to be made.
+            for mut item in games {
+                if !lib.collection.iter().any(|game| game.is_duplicate_of(&item)) {
+                    item.ensure_id();
+                    lib.collection.push(item);
+                    counter += 1;
+                }
+            }
+            counter
+        })
+    }
+
+    fn update_game(&self, id: &str, patch: GamePatch) -> Result<Option<Game>, NasError> {
+        if self.game(id)?.is_none() { return Ok(None); }
+        self.modify(|lib| {
+            let game = lib.collection.iter_mut().find(|game| game.id() == id)?;
+            game.apply(patch);
+            Some(game.clone())
+        })
+    }
+
+    fn update_games(&self, patches: Vec<(String, GamePatch)>) -> Result<usize, NasError> {
+        // a single write, however many games there are
+        self.modify(|lib| {
+            let mut updated = 0;
+            for (id, patch) in patches {
+                let Some(game) = lib.collection.iter_mut().find(|game| game.id() == id) else { continue };
+                game.apply(patch);
+                updated += 1;
+            }
+            updated
+        })
+    }
+
+    fn remove_game(&self, id: &str) -> Result<bool, NasError> {
+        if self.game(id)?.is_none() { return Ok(false); }
+        self.modify(|lib| {
+            let Some(index) = lib.collection.iter().position(|game| game.id() == id) else { return false };
+            lib.collection.remove(index);
+            lib.sessions.retain(|session| session.game_id != id);
+            true
+        })
+    }
+
+    fn persist(&self) -> Result<(), NasError> {
+        self.modify(|_| ())
+    }
+
+    fn add_session(&self, mut session: PlaySession) -> Result<PlaySession, NasError> {
+        if self.game(&session.game_id)?.is_none() {
+            return Err(NasError::new(ErrorKind::NotFound, format!("No game with the id {}", session.game_id)));
+        }
+        session.id = uuid::Uuid::new_v4().to_string();
+        self.modify(|lib| lib.sessions.push(session.clone()))?;
+        Ok(session)
+    }
+
+    /// The sessions are kept in the order they were reported in, which
+    /// isn't the order they were played in.
+    fn sessions(&self) -> Result<Vec<PlaySession>, NasError> {
+        let lib = self.library.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let mut sessions = lib.sessions.clone();
+        sessions.sort_by_key(|session| session.started_at);
+        Ok(sessions)
+    }
+
+    fn watched_path(&self) -> Option<&Path> { Some(&self.path) }
+
+    /// Merges the file into the library and writes the result back, so
+    /// that the file matches the library again. The edit that was
+    /// replaced ends up in the newest backup.
+    ///
+    /// The edit is merged against the version of the file it differs
+    /// the least from, since that is most likely the one the editor
+    /// opened. Ties go to the newest version.
+    fn reload(&self) -> Result<LibraryDiff, NasError> {
+        let theirs = read_library(&self.path)?;
+        let mut lib = self.library.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        // every write of the store shows up as a change as well
+        if *lib == theirs { return Ok(LibraryDiff::default()); }
+        let base = {
+            let history = self.history.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+            history.iter().rev().min_by_key(|version| distance(version, &theirs)).cloned().unwrap_or_else(|| lib.clone())
+        };
+        let (mut merged, diff) = merge_libraries(&base, &lib, theirs.clone())?;
+        merged.assign_ids();
+        if merged != theirs { self.write(&merged)?; }
+        let mut history = self.history.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        *history = VecDeque::from([merged.clone()]);
+        *lib = merged;
+        Ok(diff)
+    }
+}
+
+/// Reads a library file.
+///
+/// Older versions of the server saved the bare `Vec<Game>` instead
+/// of the `GameLibrary`, both layouts are accepted.
+pub(crate) fn read_library(path: &Path) -> Result<GameLibrary, NasError> {
+    let file = fs::read_to_string(path).context(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path))?;
+    serde_json::from_str::<GameLibrary>(&file)
+        .or_else(|_| serde_json::from_str::<Vec<Game>>(&file).map(|collection| GameLibrary { collection, sessions: Vec::new() }))
+        .context(ErrorKind::FailedToParse, format!("Failed to parse the game library at {:?}", path))
+}
+
+fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Shifts `{path}.1` into `{path}.2` and so on, then copies the
+/// current file into `{path}.1`. The oldest backup is dropped.
+///
+/// # Errors
+/// This will not error and it instead only log any issues. A failed
+/// backup should never prevent the library itself from being saved.
+fn rotate_backups(path: &Path, backups: usize) {
+    if backups == 0 || !path.exists() { return; }
+    for n in (1..backups).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            let _ = fs::rename(&from, backup_path(path, n + 1)).map_err(|e| {
+                warn!("Failed to rotate the backup {:?} with {:?}", from, e);
+            });
+        }
+    }
+    let _ = fs::copy(path, backup_path(path, 1)).map_err(|e| {
+        warn!("Failed to back up {:?} with {:?}", path, e);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Launcher;
+
+    fn game(id: &str) -> Game {
+        let mut game = Game::new();
+        game.set_launcher(Launcher::new("steam".to_owned(), id.to_owned()));
+        game
+    }
+
+    fn launcher_ids(games: Vec<Game>) -> Vec<String> {
+        games.iter().map(|game| game.launchers()[0].game_id.clone()).collect()
+    }
+
+    #[test]
+    fn persists_every_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game_library.json");
+
+        let store = JsonFileStore::open(path.clone(), 2);
+        assert_eq!(store.add_games(vec![game("1"), game("1"), game("2")]).unwrap(), 2);
+
+        let games = store.games().unwrap();
+        let reopened = JsonFileStore::open(path, 2);
+        assert_eq!(reopened.games().unwrap(), games);
+        assert_eq!(launcher_ids(games), ["1", "2"]);
+    }
+
+    #[test]
+    fn failed_writes_change_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = JsonFileStore::open(dir.path().join("library/game_library.json"), 0);
+        assert!(store.add_games(vec![game("1")]).is_err());
+        assert!(store.games().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rotates_and_recovers_from_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game_library.json");
+
+        let store = JsonFileStore::open(path.clone(), 2);
+        store.add_games(vec![game("1")]).unwrap();
+        store.add_games(vec![game("2")]).unwrap();
+        store.add_games(vec![game("3")]).unwrap();
+        assert!(backup_path(&path, 2).exists());
+        assert!(!backup_path(&path, 3).exists());
+
+        fs::write(&path, "not json").unwrap();
+        let recovered = JsonFileStore::open(path, 2);
+        assert_eq!(launcher_ids(recovered.games().unwrap()), ["1", "2"]);
+    }
+
+    #[test]
+    fn reads_legacy_bare_arrays() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game_library.json");
+        fs::write(&path, serde_json::to_string(&vec![game("1")]).unwrap()).unwrap();
+
+        let store = JsonFileStore::open(path.clone(), 0);
+        let games = store.games().unwrap();
+        assert_eq!(launcher_ids(games.clone()), ["1"]);
+        assert!(!games[0].id().is_empty());
+
+        // the assigned id has to survive a restart
+        assert_eq!(JsonFileStore::open(path, 0).games().unwrap(), games);
+    }
+
+    #[test]
+    fn merges_hand_edits_of_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game_library.json");
+        let store = JsonFileStore::open(path.clone(), 1);
+        store.add_games(vec![game("1"), game("2"), game("3")]).unwrap();
+        let ids: Vec<String> = store.games().unwrap().iter().map(|game| game.id().to_owned()).collect();
+        let patch = |json: &str| -> GamePatch { serde_json::from_str(json).unwrap() };
+
+        // the file is opened in an editor before the server changes it
+        let mut edited = read_library(&path).unwrap();
+        store.update_game(&ids[0], patch(r#"{ "steam_grid_id": "42" }"#)).unwrap();
+        store.update_game(&ids[2], patch(r#"{ "name": "Server" }"#)).unwrap();
+        assert!(store.reload().unwrap().is_empty());
+
+        edited.collection[0].apply(patch(r#"{ "name": "Hades" }"#));
+        edited.collection[2].apply(patch(r#"{ "name": "Editor" }"#));
+        edited.collection.remove(1);
+        let mut added = game("4");
+        added.apply(patch(r#"{ "name": "Celeste" }"#));
+        edited.collection.push(added);
+        fs::write(&path, serde_json::to_string(&edited).unwrap()).unwrap();
+
+        let diff = store.reload().unwrap();
+        assert_eq!(diff.added, ["Celeste"]);
+        assert_eq!(diff.removed, [ids[1].clone()]);
+        assert_eq!(diff.changed, [("Hades".to_owned(), vec!["name".to_owned()])]);
+        assert_eq!(diff.conflicts, ["Server: name"]);
+
+        let hades = store.game(&ids[0]).unwrap().unwrap();
+        assert_eq!((hades.name(), hades.steam_grid_id()), ("Hades", Some("42")));
+        assert_eq!(store.game(&ids[2]).unwrap().unwrap().name(), "Server");
+        assert_eq!(read_library(&path).unwrap().collection, store.games().unwrap());
+        assert_eq!(read_library(&backup_path(&path, 1)).unwrap(), edited);
+    }
+
+    #[test]
+    fn updates_and_removes_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = JsonFileStore::open(dir.path().join("game_library.json"), 0);
+        store.add_games(vec![game("1"), game("2")]).unwrap();
+        let id = store.games().unwrap()[0].id().to_owned();
+
+        let patch: GamePatch = serde_json::from_str(r#"{ "name": "Hades", "steam_grid_id": "42" }"#).unwrap();
+        let updated = store.update_game(&id, patch).unwrap().unwrap();
+        assert_eq!((updated.id(), updated.name(), updated.steam_grid_id()), (id.as_str(), "Hades", Some("42")));
+
+        let patch: GamePatch = serde_json::from_str(r#"{ "steam_grid_id": null }"#).unwrap();
+        assert_eq!(store.update_game(&id, patch).unwrap().unwrap().steam_grid_id(), None);
+
+        let now = chrono::Utc::now();
+        let session = PlaySession { id: String::new(), game_id: id.clone(), client_id: "desktop".to_owned(), user: String::new(), started_at: now, ended_at: now, exit_status: None };
+        assert!(!store.add_session(session.clone()).unwrap().id.is_empty());
+        // a session that was reported late still comes first
+        let earlier = chrono::Duration::hours(2);
+        store.add_session(PlaySession { started_at: now - earlier, ended_at: now - earlier, ..session }).unwrap();
+        assert_eq!(store.sessions().unwrap().iter().map(|s| s.started_at).collect::<Vec<_>>(), [now - earlier, now]);
+
+        assert!(store.remove_game(&id).unwrap());
+        assert!(!store.remove_game(&id).unwrap());
+        assert_eq!(store.game(&id).unwrap(), None);
+        assert!(store.sessions().unwrap().is_empty());
+        assert!(store.update_game(&id, GamePatch::default()).unwrap().is_none());
+    }
+}
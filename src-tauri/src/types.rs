@@ -1,7 +1,10 @@
 //! This crate is for defining and implementing convenience
 //! functions for types used throughout the program. 
 use serde::{Serialize, Deserialize};
-use std::sync::Mutex;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use crate::stats::PlaySession;
+use crate::logging::LoggingLevel;
 
 
 const DEFAULT_IP_ADDR: &str = "127.0.0.1";
@@ -14,18 +17,234 @@ const DEFAULT_IP_PORT: u16 = 53317;
 /// # Arguments
 /// `ìp` - This is the IP the server will be listening on
 /// `port` - This is the PORT the server will be listening on
+/// `storage` - Where the game library is kept, see `StorageBackend`
+/// `library_roots` - The directories that are scanned for installed games
+/// `require_auth` - Whether changes have to be signed by an approved device
+/// `tls` - Whether the server speaks https, see `nas-game server cert init`
+/// `tls_cert` - The certificate chain of the server, relative to the server directory
+/// `tls_key` - The private key of `tls_cert`
+/// `name` - How the server is listed on the clients, defaults to the host name
+/// `discovery` - Whether the server answers clients that search the network for it
+/// `data_dir` - Where the server keeps its files, the other paths are relative to it, empty for `$XDG_DATA_HOME/nas-game/server`
+/// `cache_dir` - Where the server keeps files that it can rebuild, empty for `$XDG_CACHE_HOME/nas-game/server`
+/// `images_dir` - Where the downloaded artwork is saved
+/// `optimized_images_dir` - Where the optimized artwork is written to
+/// `image_width`, `image_height` - The size artwork is resized to when optimized
+/// `webp_quality` - The quality of the optimized artwork, from 0 to 100
+/// `image_profiles` - How every kind of artwork is resized and encoded, see `ImageProfile`
+/// `download_concurrency` - How many images are downloaded at once
+/// `log_level` - The least important messages that are still logged
+///
+/// The settings are loaded in layers, see the `config` crate.
 /// # IPv4 vs IPv6
 /// The server doesn't handle IPv6 just yet as such the struct does
 /// not support it
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ServerSettings {
     #[serde(default)]
     pub ip: String,
     pub port: u16,
+    #[serde(default)]
+    pub storage: StorageBackend,
+    #[serde(default)]
+    pub library_roots: Vec<PathBuf>,
+    #[serde(default = "default_require_auth")]
+    pub require_auth: bool,
+    #[serde(default)]
+    pub tls: bool,
+    #[serde(default = "default_tls_cert")]
+    pub tls_cert: PathBuf,
+    #[serde(default = "default_tls_key")]
+    pub tls_key: PathBuf,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_discovery")]
+    pub discovery: bool,
+    #[serde(default)]
+    pub data_dir: PathBuf,
+    #[serde(default)]
+    pub cache_dir: PathBuf,
+    #[serde(default = "default_images_dir")]
+    pub images_dir: PathBuf,
+    #[serde(default = "default_optimized_images_dir")]
+    pub optimized_images_dir: PathBuf,
+    #[serde(default = "default_image_width")]
+    pub image_width: u32,
+    #[serde(default = "default_image_height")]
+    pub image_height: u32,
+    #[serde(default = "default_webp_quality")]
+    pub webp_quality: f32,
+    #[serde(default)]
+    pub image_profiles: BTreeMap<ArtworkKind, ImageProfile>,
+    #[serde(default = "default_download_concurrency")]
+    pub download_concurrency: usize,
+    #[serde(default = "default_log_level")]
+    pub log_level: LoggingLevel,
 }
 
+fn default_require_auth() -> bool { true }
+fn default_tls_cert() -> PathBuf { PathBuf::from("tls/server.crt") }
+fn default_tls_key() -> PathBuf { PathBuf::from("tls/server.key") }
+fn default_discovery() -> bool { true }
+fn default_images_dir() -> PathBuf { PathBuf::from("images/non-optimized") }
+fn default_optimized_images_dir() -> PathBuf { PathBuf::from("images/optimized") }
+// artwork likely won't exceed 308x461 ± x% on a 1440p monitor
+fn default_image_width() -> u32 { 308 }
+fn default_image_height() -> u32 { 461 }
+fn default_webp_quality() -> f32 { 85.0 }
+fn default_download_concurrency() -> usize { 5 }
+fn default_log_level() -> LoggingLevel { LoggingLevel::Info }
+
 impl Default for ServerSettings {
-    fn default() -> Self { Self { ip: DEFAULT_IP_ADDR.to_owned(), port: DEFAULT_IP_PORT} }
+    fn default() -> Self {
+        Self {
+            ip: DEFAULT_IP_ADDR.to_owned(),
+            port: DEFAULT_IP_PORT,
+            storage: StorageBackend::default(),
+            library_roots: Vec::new(),
+            require_auth: default_require_auth(),
+            tls: false,
+            tls_cert: default_tls_cert(),
+            tls_key: default_tls_key(),
+            name: String::new(),
+            discovery: default_discovery(),
+            data_dir: PathBuf::new(),
+            cache_dir: PathBuf::new(),
+            images_dir: default_images_dir(),
+            optimized_images_dir: default_optimized_images_dir(),
+            image_width: default_image_width(),
+            image_height: default_image_height(),
+            webp_quality: default_webp_quality(),
+            image_profiles: BTreeMap::new(),
+            download_concurrency: default_download_concurrency(),
+            log_level: default_log_level(),
+        }
+    }
+}
+
+impl ServerSettings {
+    /// The size artwork is resized to.
+    pub fn image_size(&self) -> (u32, u32) {
+        (self.image_width, self.image_height)
+    }
+
+    /// How artwork of `kind` is optimized, grids default to
+    /// `image_width`, `image_height` and `webp_quality`.
+    pub fn image_profile(&self, kind: ArtworkKind) -> ImageProfile {
+        if let Some(profile) = self.image_profiles.get(&kind) { return *profile; }
+        let (width, height) = match kind {
+            ArtworkKind::Grids => self.image_size(),
+            // the banner above the details of a game
+            ArtworkKind::Heroes => (1280, 414),
+            ArtworkKind::Logos => (640, 360),
+            ArtworkKind::Icons => (128, 128),
+            ArtworkKind::Screenshots => (1280, 720),
+        };
+        let (resize, format) = match kind {
+            // covers are shown side by side and have to be the same size
+            ArtworkKind::Grids | ArtworkKind::Heroes => (ResizePolicy::Fill, OutputFormat::Webp),
+            // small enough that lossy artifacts show
+            ArtworkKind::Icons => (ResizePolicy::Fit, OutputFormat::WebpLossless),
+            ArtworkKind::Logos | ArtworkKind::Screenshots => (ResizePolicy::Fit, OutputFormat::Webp),
+        };
+        ImageProfile { width, height, quality: self.webp_quality, resize, format }
+    }
+}
+
+/// The kinds of artwork of a game. Screenshots come from the Steam
+/// store, so only games with a Steam launcher have them, everything
+/// else comes from SteamGridDB.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtworkKind {
+    /// The cover of a game in the library.
+    #[default]
+    Grids,
+    Heroes,
+    Logos,
+    Icons,
+    Screenshots,
+}
+
+impl ArtworkKind {
+    pub const ALL: [ArtworkKind; 5] = [ArtworkKind::Grids, ArtworkKind::Heroes, ArtworkKind::Logos, ArtworkKind::Icons, ArtworkKind::Screenshots];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ArtworkKind::Grids => "grids",
+            ArtworkKind::Heroes => "heroes",
+            ArtworkKind::Logos => "logos",
+            ArtworkKind::Icons => "icons",
+            ArtworkKind::Screenshots => "screenshots",
+        }
+    }
+}
+
+/// How artwork is optimized, it is resized into `width` x `height` by
+/// `resize` and encoded as `format`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ImageProfile {
+    pub width: u32,
+    pub height: u32,
+    /// The quality of the lossy formats, from 0 to 100.
+    pub quality: f32,
+    #[serde(default)]
+    pub resize: ResizePolicy,
+    #[serde(default)]
+    pub format: OutputFormat,
+}
+
+/// How an image is made to fit into the size of its `ImageProfile`,
+/// none of them stretch the image.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizePolicy {
+    /// Scales the image down until it fits, the result may be smaller
+    /// in one direction.
+    #[default]
+    Fit,
+    /// Scales the image until it covers the size and crops what is
+    /// left over on either side.
+    Fill,
+    /// Fits the image and fills the rest with transparency, or black
+    /// for formats without it.
+    Pad,
+}
+
+/// What optimized artwork is encoded as.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Webp,
+    WebpLossless,
+    Avif,
+    Jpeg,
+    Png,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Webp | OutputFormat::WebpLossless => "webp",
+            OutputFormat::Avif => "avif",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Png => "png",
+        }
+    }
+}
+
+/// The backend the game library is stored in.
+///
+/// `Json` keeps the whole library in a single json file while
+/// `Sqlite` uses an embedded database. The latter is only available
+/// when the server was built with the `sqlite` feature.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Json,
+    Sqlite,
 }
 
 /// This struct represents one instance of a launcher and its game_id.
@@ -41,10 +260,37 @@ pub struct Launcher {
     pub name: String,
     /// The game id that is associated with this game and launcher
     pub game_id: String,
+    /// Where the game is installed on the NAS, if it is installed at all.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install_dir: Option<PathBuf>,
+    /// How much space the installed game takes up in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_on_disk: Option<u64>,
+    /// The build (or version) of the installed game as reported by the launcher.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_id: Option<String>,
+    /// The human readable version, e.g. of a GOG installer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The language the game was installed (or downloaded) in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Offline installers of the game on the NAS. Installers that are
+    /// split into several parts list every part, the `.exe` first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub installers: Vec<PathBuf>,
 }
 
 impl Launcher {
-    pub fn new(launcher: String, game_id: String) -> Self { Self { name: launcher, game_id } }
+    pub fn new(launcher: String, game_id: String) -> Self {
+        Self {
+            name: launcher, game_id, install_dir: None, size_on_disk: None, build_id: None,
+            version: None, language: None, installers: Vec::new(),
+        }
+    }
+    /// Checks if both launchers refer to the same game in the same
+    /// launcher, regardless of where it is installed.
+    pub fn is_same_as(&self, other: &Launcher) -> bool { self.name == other.name && self.game_id == other.game_id }
 }
 
 /// This struct represents a game and its many potential launchers.
@@ -54,8 +300,18 @@ impl Launcher {
 /// be added to the library without having a valid launcher, making such
 /// entries effectively just place holders. The `steam_grid_id` is used to
 /// reteive details about the game from the steam grid api.
+///
+/// Every game has a stable `id` which is handed out by the
+/// `LibraryStore` the first time it sees the game. Games that have
+/// not been stored yet have an empty `id`.
 #[derive(Debug, PartialEq, Eq, Clone, Deserialize, Serialize)]
 pub struct Game {
+    /// The stable identifier of the game (a UUID).
+    #[serde(default)]
+    id: String,
+    /// The name that is displayed in the library.
+    #[serde(default)]
+    name: String,
     launcher: Vec<Launcher>,
     /// The steam_grid_db id.
     ///
@@ -63,38 +319,137 @@ pub struct Game {
     ///
     /// This is NOT the steam id of the game.
     steam_grid_id: Option<String>,
+    /// The total playtime in minutes as reported by the Steam Web API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    playtime_minutes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    achievements: Option<AchievementProgress>,
+}
+
+/// How many of the achievements of a game have been unlocked.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+pub struct AchievementProgress {
+    pub unlocked: u32,
+    pub total: u32,
 }
 
 impl Game {
     /// Initialize a new instance without any data.
-    pub fn new() -> Self { Self { launcher: Vec::new(), steam_grid_id: None } }
+    pub fn new() -> Self {
+        Self { id: String::new(), name: String::new(), launcher: Vec::new(), steam_grid_id: None, playtime_minutes: None, achievements: None }
+    }
+    /// Initialize an instance of a game that has already been stored.
+    pub fn with_id(id: String, name: String) -> Self { Self { id, name, ..Self::new() } }
+    /// Adds the launcher, replacing the entry for the same launcher and
+    /// launcher game id if there already is one.
     pub fn set_launcher(&mut self, launcher: Launcher) {
-        if !self.launcher.contains(&launcher) { self.launcher.push(launcher); }
+        match self.launcher.iter_mut().find(|l| l.is_same_as(&launcher)) {
+            Some(existing) => *existing = launcher,
+            None => self.launcher.push(launcher),
+        }
     }
     pub fn set_steam_grid_id(&mut self, id: Option<String>) {
         self.steam_grid_id = id;
     }
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+    pub fn id(&self) -> &str { &self.id }
+    pub fn name(&self) -> &str { &self.name }
+    pub fn launchers(&self) -> &[Launcher] { &self.launcher }
+    pub fn steam_grid_id(&self) -> Option<&str> { self.steam_grid_id.as_deref() }
+    pub fn playtime_minutes(&self) -> Option<u64> { self.playtime_minutes }
+    pub fn achievements(&self) -> Option<AchievementProgress> { self.achievements }
+
+    /// Hands out a new id unless the game already has one.
+    ///
+    /// Returns `true` if an id has been assigned.
+    pub fn ensure_id(&mut self) -> bool {
+        if !self.id.is_empty() { return false; }
+        self.id = uuid::Uuid::new_v4().to_string();
+        true
+    }
+
+    /// Checks if both games describe the same entry.
+    ///
+    /// Two games with an id are the same if their ids match. Should
+    /// either one lack an id then every other field is compared
+    /// instead.
+    pub fn is_duplicate_of(&self, other: &Game) -> bool {
+        if !self.id.is_empty() && !other.id.is_empty() { return self.id == other.id; }
+        self.name == other.name && self.launcher == other.launcher && self.steam_grid_id == other.steam_grid_id
+    }
+
+    /// Applies all of the fields that are set in the `patch`. The id
+    /// of a game can never be changed.
+    pub fn apply(&mut self, patch: GamePatch) {
+        if let Some(name) = patch.name { self.name = name; }
+        if let Some(launcher) = patch.launcher { self.launcher = launcher; }
+        if let Some(steam_grid_id) = patch.steam_grid_id { self.steam_grid_id = steam_grid_id; }
+        if let Some(playtime_minutes) = patch.playtime_minutes { self.playtime_minutes = Some(playtime_minutes); }
+        if let Some(achievements) = patch.achievements { self.achievements = Some(achievements); }
+    }
+}
+
+/// A partial update of a `Game` as sent to `PATCH /games/{id}`.
+///
+/// Fields that are missing are left untouched. Since the
+/// `steam_grid_id` is optional itself, `null` clears it while leaving
+/// it out keeps the old value.
+#[derive(Debug, Default, Deserialize)]
+pub struct GamePatch {
+    pub name: Option<String>,
+    pub launcher: Option<Vec<Launcher>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub steam_grid_id: Option<Option<String>>,
+    pub playtime_minutes: Option<u64>,
+    pub achievements: Option<AchievementProgress>,
+}
+
+/// Wraps any present value in `Some` so that an explicit `null` can
+/// be told apart from a missing field.
+fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 /// This struct represents all of the games the server has saved.
 ///
 /// Since this contais a `Vec` this means that the game libarary can be empty.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct GameLibrary {
     pub collection: Vec<Game>, // TODO: explore if a hashset is a better choice
+    /// Every play session of every client, in the order they were
+    /// reported. `LibraryStore::sessions()` sorts them by their start.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sessions: Vec<PlaySession>,
 }
 
 impl Default for GameLibrary {
-    fn default() -> Self { Self { collection: Vec::new() } }
+    fn default() -> Self { Self { collection: Vec::new(), sessions: Vec::new() } }
 }
 
 impl GameLibrary {
     #[allow(dead_code)]
-    pub fn new() -> Self { Self { collection: Vec::new() } }
+    pub fn new() -> Self { Self::default() }
+
+    /// Hands out ids to every game which lacks one and returns how
+    /// many have been assigned.
+    pub fn assign_ids(&mut self) -> usize {
+        self.collection.iter_mut().filter_map(|game| game.ensure_id().then_some(())).count()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GameNameRequest {
+    /// The ids or the names of the games.
+    #[serde(default)]
     pub games: Vec<String>,
+    /// Every kind if there are none.
+    #[serde(default)]
+    pub kinds: Vec<ArtworkKind>,
 }
 
@@ -0,0 +1,594 @@
+//! This crate is for the accounts of the people that share the server.
+//! The game library is the same for everyone, but every user keeps
+//! their own favourites and hidden games, and the playtime of the
+//! `PlaySession`s is counted per user.
+//!
+//! There are three roles: admins may change the library and manage the
+//! other accounts, players may play and look after their own view, and
+//! kids are players whose `Restrictions` are enforced when they start a
+//! game.
+//!
+//! A user logs in with `POST /login` and sends the returned token as
+//! `Authorization: Bearer <token>` from then on. Admins can also hand
+//! out tokens that don't expire with `nas-game server users token`.
+//! As long as there is no account at all every request is treated as
+//! coming from an admin, so that a fresh server can be set up.
+//!
+//! The users are kept in `users.json` next to the game library, only
+//! the hashes of the passwords and tokens are stored.
+use crate::error::{NasError, ErrorKind, Context};
+use crate::{info, logging::LoggingLevel, logging::logging_function};
+use crate::types::Game;
+use crate::stats::PlaySession;
+use crate::files::write_atomic;
+
+use std::fs;
+use std::future::{ready, Ready};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use actix_web::{web, dev::Payload, http::header, FromRequest, HttpRequest};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// How long the token of `POST /login` is valid.
+pub const LOGIN_TOKEN_DAYS: i64 = 30;
+
+/// Ordered by what a role may do, so `role >= Role::Player` holds for
+/// players and admins.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Kid,
+    Player,
+    Admin,
+}
+
+impl std::str::FromStr for Role {
+    type Err = NasError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kid" => Ok(Role::Kid),
+            "player" => Ok(Role::Player),
+            "admin" => Ok(Role::Admin),
+            _ => Err(NasError::new(ErrorKind::BadRequest, format!("{:?} is not a role, use admin, player or kid", s))),
+        }
+    }
+}
+
+/// The parental restrictions of a kid, ignored for the other roles.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Restrictions {
+    /// The ids of the games the kid may see and play, every game if
+    /// `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_games: Option<Vec<String>>,
+    /// How many minutes may be played per day, counted from midnight
+    /// of the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_minutes: Option<u32>,
+}
+
+impl Restrictions {
+    pub fn allows(&self, game_id: &str) -> bool {
+        self.allowed_games.as_ref().is_none_or(|games| games.iter().any(|id| id == game_id))
+    }
+
+    /// Checks whether the files of a game may be seen at all, the time
+    /// of the day only limits starting it.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::Forbidden` if the game isn't allowed.
+    pub fn check_game(&self, game_id: &str) -> Result<(), NasError> {
+        if !self.allows(game_id) {
+            return Err(NasError::new(ErrorKind::Forbidden, format!("The game {} is not allowed", game_id)));
+        }
+        Ok(())
+    }
+
+    /// Checks whether a game may be started after `played_today` of
+    /// playtime.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::Forbidden` if the game isn't allowed or
+    /// the time of the day is used up.
+    pub fn check(&self, game_id: &str, played_today: Duration) -> Result<(), NasError> {
+        self.check_game(game_id)?;
+        if let Some(minutes) = self.daily_minutes {
+            if played_today >= Duration::minutes(minutes.into()) {
+                return Err(NasError::new(ErrorKind::Forbidden, format!("The {} minutes of today have been played", minutes)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Token {
+    /// The lower case hex SHA-256 of the token.
+    sha256: String,
+    label: String,
+    created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct User {
+    #[serde(flatten)]
+    profile: Profile,
+    /// The argon2 hash of the password, users without one can only log
+    /// in with a token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_hash: Option<String>,
+    #[serde(default)]
+    tokens: Vec<Token>,
+}
+
+/// Everything about a user that may be shown, i.e. without the
+/// password and the tokens.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub role: Role,
+    /// The ids of the favourite games.
+    #[serde(default)]
+    pub favourites: Vec<String>,
+    /// The ids of the games the user doesn't want to see.
+    #[serde(default)]
+    pub hidden: Vec<String>,
+    #[serde(default)]
+    pub restrictions: Restrictions,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Profile {
+    /// The restrictions that apply to the user, `None` unless it is a
+    /// kid.
+    pub fn restrictions(&self) -> Option<&Restrictions> {
+        (self.role == Role::Kid).then_some(&self.restrictions)
+    }
+}
+
+/// What an admin sends to `POST /users`.
+#[derive(Deserialize, Debug)]
+pub struct NewUser {
+    pub name: String,
+    pub role: Role,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// What an admin sends to `PATCH /users/{name}`, missing fields stay
+/// as they are.
+#[derive(Deserialize, Debug, Default)]
+pub struct UserPatch {
+    pub role: Option<Role>,
+    pub password: Option<String>,
+    pub restrictions: Option<Restrictions>,
+}
+
+/// What a user sends to `PATCH /me/games/{id}`.
+#[derive(Deserialize, Debug, Default)]
+pub struct GameFlags {
+    pub favourite: Option<bool>,
+    pub hidden: Option<bool>,
+}
+
+/// What a user sends to `POST /login`.
+#[derive(Deserialize, Debug)]
+pub struct LoginRequest {
+    pub name: String,
+    pub password: String,
+}
+
+/// The answer to a login, the token is only ever shown this once.
+#[derive(Serialize, Debug)]
+pub struct LoginResponse {
+    pub token: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub user: Profile,
+}
+
+/// The query of `GET /me/games`, e.g. `?hidden=true`.
+#[derive(Deserialize, Debug, Default)]
+pub struct ViewQuery {
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+/// A game of the library as a single user sees it.
+#[derive(Serialize, Debug)]
+pub struct UserGame {
+    #[serde(flatten)]
+    pub game: Game,
+    pub favourite: bool,
+    pub hidden: bool,
+    pub playtime_secs: i64,
+    pub last_played: Option<DateTime<Utc>>,
+}
+
+/// The games a user sees, favourites first. Hidden games are only
+/// part of it with `include_hidden`, games a kid isn't allowed to play
+/// never are.
+pub fn library_view(profile: &Profile, games: Vec<Game>, sessions: &[PlaySession], include_hidden: bool) -> Vec<UserGame> {
+    let mut view: Vec<UserGame> = games.into_iter()
+        .filter(|game| profile.restrictions().is_none_or(|r| r.allows(game.id())))
+        .map(|game| {
+            let played = sessions.iter().filter(|s| s.user == profile.name && s.game_id == game.id());
+            let (playtime_secs, last_played) = played.fold((0, None), |(secs, last), s| (secs + s.duration().num_seconds(), last.max(Some(s.ended_at))));
+            UserGame {
+                favourite: profile.favourites.iter().any(|id| id == game.id()),
+                hidden: profile.hidden.iter().any(|id| id == game.id()),
+                game,
+                playtime_secs,
+                last_played,
+            }
+        })
+        .filter(|game| include_hidden || !game.hidden)
+        .collect();
+    view.sort_by_key(|game| !game.favourite);
+    view
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn hash_password(password: &str) -> Result<String, NasError> {
+    if password.is_empty() {
+        return Err(NasError::new(ErrorKind::BadRequest, "The password must not be empty"));
+    }
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).map_err(|e| NasError::new(ErrorKind::Ignore, format!("Failed to generate a salt: {}", e)))?;
+    let salt = SaltString::encode_b64(&bytes).map_err(|e| NasError::new(ErrorKind::FailedToEncode, format!("Failed to encode the salt: {}", e)))?;
+    Argon2::default().hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| NasError::new(ErrorKind::FailedToEncode, format!("Failed to hash the password: {}", e)))
+}
+
+/// Logins of unknown users are checked against this hash, so that
+/// they take as long as the ones of known users.
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password("nas-game").unwrap_or_default())
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+fn is_last_admin(users: &[User], name: &str) -> bool {
+    let mut admins = users.iter().filter(|u| u.profile.role == Role::Admin);
+    admins.clone().count() == 1 && admins.any(|u| u.profile.name == name)
+}
+
+fn user_not_found(name: &str) -> NasError {
+    NasError::new(ErrorKind::NotFound, format!("There is no user called {}", name))
+}
+
+/// Every account, mirrored into a json file.
+pub struct UserRegistry {
+    path: PathBuf,
+    users: Mutex<Vec<User>>,
+}
+
+impl UserRegistry {
+    /// Opens the registry at `path`, a missing file is an empty
+    /// registry.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::FailedToParse` if the file is broken,
+    /// since an empty registry would let everyone in as an admin.
+    pub fn open(path: PathBuf) -> Result<Self, NasError> {
+        let users = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s).context(ErrorKind::FailedToParse, format!("Failed to parse {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(NasError::new(ErrorKind::FailedToReadFile, format!("Failed to read {:?}", path)).with_source(e)),
+        };
+        Ok(Self { path, users: Mutex::new(users) })
+    }
+
+    fn write(&self, users: &[User]) -> Result<(), NasError> {
+        let json = serde_json::to_string_pretty(users).context(ErrorKind::FailedToSerialize, "Failed to serialize the users")?;
+        write_atomic(&self.path, json.as_bytes())
+    }
+
+    /// Runs `change` on the user called `name` and saves the result.
+    fn change<T>(&self, name: &str, change: impl FnOnce(&mut User) -> Result<T, NasError>) -> Result<T, NasError> {
+        let mut users = self.users.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let user = users.iter_mut().find(|u| u.profile.name == name).ok_or_else(|| user_not_found(name))?;
+        let result = change(user)?;
+        self.write(&users)?;
+        Ok(result)
+    }
+
+    pub fn is_empty(&self) -> Result<bool, NasError> {
+        Ok(self.users.lock().map_err(|_| ErrorKind::FailedToAquireLock)?.is_empty())
+    }
+
+    pub fn users(&self) -> Result<Vec<Profile>, NasError> {
+        Ok(self.users.lock().map_err(|_| ErrorKind::FailedToAquireLock)?.iter().map(|u| u.profile.clone()).collect())
+    }
+
+    pub fn user(&self, name: &str) -> Result<Profile, NasError> {
+        self.users.lock().map_err(|_| ErrorKind::FailedToAquireLock)?
+            .iter().find(|u| u.profile.name == name)
+            .map(|u| u.profile.clone())
+            .ok_or_else(|| user_not_found(name))
+    }
+
+    /// Creates an account.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::BadRequest` if the name is empty or taken.
+    pub fn add(&self, new: NewUser) -> Result<Profile, NasError> {
+        let name = new.name.trim();
+        if name.is_empty() {
+            return Err(NasError::new(ErrorKind::BadRequest, "The name must not be empty"));
+        }
+        let password_hash = new.password.as_deref().map(hash_password).transpose()?;
+        let mut users = self.users.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        if users.iter().any(|u| u.profile.name == name) {
+            return Err(NasError::new(ErrorKind::BadRequest, format!("There already is a user called {}", name)));
+        }
+        let profile = Profile {
+            name: name.to_owned(),
+            role: new.role,
+            favourites: Vec::new(),
+            hidden: Vec::new(),
+            restrictions: Restrictions::default(),
+            created_at: Utc::now(),
+        };
+        users.push(User { profile: profile.clone(), password_hash, tokens: Vec::new() });
+        self.write(&users)?;
+        info!("Added the {:?} {}", profile.role, profile.name);
+        Ok(profile)
+    }
+
+    /// Changes the role, password or restrictions of a user.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::NotFound` for unknown users and with
+    /// `ErrorKind::BadRequest` for taking the role of the last admin.
+    pub fn update(&self, name: &str, patch: UserPatch) -> Result<Profile, NasError> {
+        let password_hash = patch.password.as_deref().map(hash_password).transpose()?;
+        let demoted = patch.role.is_some_and(|role| role != Role::Admin);
+        // checked under the same lock as the change, like in `remove()`
+        let mut users = self.users.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        if demoted && is_last_admin(&users, name) {
+            return Err(NasError::new(ErrorKind::BadRequest, format!("{} is the last admin, add another admin first", name)));
+        }
+        let user = users.iter_mut().find(|u| u.profile.name == name).ok_or_else(|| user_not_found(name))?;
+        if let Some(role) = patch.role { user.profile.role = role; }
+        if let Some(restrictions) = patch.restrictions { user.profile.restrictions = restrictions; }
+        if password_hash.is_some() {
+            user.password_hash = password_hash;
+            // a new password logs out every session, but keeps the tokens of the admin
+            user.tokens.retain(|t| t.expires_at.is_none());
+        }
+        let profile = user.profile.clone();
+        self.write(&users)?;
+        Ok(profile)
+    }
+
+    /// Removes an account, `false` if there was none.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::BadRequest` for the last admin, without
+    /// one the server would be open to everyone again.
+    pub fn remove(&self, name: &str) -> Result<bool, NasError> {
+        let mut users = self.users.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        if is_last_admin(&users, name) {
+            return Err(NasError::new(ErrorKind::BadRequest, format!("{} is the last admin, add another admin first", name)));
+        }
+        let count = users.len();
+        users.retain(|u| u.profile.name != name);
+        if users.len() == count { return Ok(false); }
+        self.write(&users)?;
+        info!("Removed the user {}", name);
+        Ok(true)
+    }
+
+    /// Marks a game as a favourite or hides it for a single user.
+    pub fn set_game_flags(&self, name: &str, game_id: &str, flags: GameFlags) -> Result<Profile, NasError> {
+        fn set(ids: &mut Vec<String>, id: &str, on: Option<bool>) {
+            match on {
+                Some(true) if !ids.iter().any(|i| i == id) => ids.push(id.to_owned()),
+                Some(false) => ids.retain(|i| i != id),
+                _ => (),
+            }
+        }
+        self.change(name, |user| {
+            set(&mut user.profile.favourites, game_id, flags.favourite);
+            set(&mut user.profile.hidden, game_id, flags.hidden);
+            Ok(user.profile.clone())
+        })
+    }
+
+    /// Creates a new token for a user, which expires after
+    /// `valid_for` or never.
+    pub fn create_token(&self, name: &str, label: &str, valid_for: Option<Duration>) -> Result<(String, Option<DateTime<Utc>>), NasError> {
+        let mut bytes = [0; 32];
+        getrandom::getrandom(&mut bytes).map_err(|e| NasError::new(ErrorKind::Ignore, format!("Failed to generate a token: {}", e)))?;
+        let token = BASE64_URL.encode(bytes);
+        let now = Utc::now();
+        let expires_at = valid_for.map(|d| now + d);
+        self.change(name, |user| {
+            user.tokens.retain(|t| t.expires_at.is_none_or(|at| at > now));
+            user.tokens.push(Token { sha256: hash_token(&token), label: label.to_owned(), created_at: now, expires_at });
+            Ok(())
+        })?;
+        Ok((token, expires_at))
+    }
+
+    /// Checks the password of a user and hands out a token for
+    /// `LOGIN_TOKEN_DAYS`.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::Unauthorized` for unknown users and wrong
+    /// passwords alike.
+    pub fn login(&self, name: &str, password: &str) -> Result<LoginResponse, NasError> {
+        let hash = self.users.lock().map_err(|_| ErrorKind::FailedToAquireLock)?
+            .iter().find(|u| u.profile.name == name)
+            .and_then(|u| u.password_hash.clone());
+        let matches = verify_password(password, hash.as_deref().unwrap_or_else(|| dummy_hash()));
+        if hash.is_none() || !matches {
+            return Err(NasError::new(ErrorKind::Unauthorized, "Wrong name or password"));
+        }
+        let (token, expires_at) = self.create_token(name, "login", Some(Duration::days(LOGIN_TOKEN_DAYS)))?;
+        info!("{} logged in", name);
+        Ok(LoginResponse { token, expires_at, user: self.user(name)? })
+    }
+
+    /// Forgets a token, `false` if it didn't belong to anyone.
+    pub fn logout(&self, token: &str) -> Result<bool, NasError> {
+        let sha256 = hash_token(token);
+        let mut users = self.users.lock().map_err(|_| ErrorKind::FailedToAquireLock)?;
+        let Some(user) = users.iter_mut().find(|u| u.tokens.iter().any(|t| t.sha256 == sha256)) else { return Ok(false) };
+        user.tokens.retain(|t| t.sha256 != sha256);
+        self.write(&users)?;
+        Ok(true)
+    }
+
+    /// Returns the user a token belongs to.
+    ///
+    /// # Errors
+    /// Fails with `ErrorKind::Unauthorized` for unknown and expired
+    /// tokens.
+    pub fn authenticate(&self, token: &str, now: DateTime<Utc>) -> Result<Profile, NasError> {
+        let sha256 = hash_token(token);
+        self.users.lock().map_err(|_| ErrorKind::FailedToAquireLock)?
+            .iter().find(|u| u.tokens.iter().any(|t| t.sha256 == sha256 && t.expires_at.is_none_or(|at| at > now)))
+            .map(|u| u.profile.clone())
+            .ok_or_else(|| NasError::new(ErrorKind::Unauthorized, "The token is unknown or has expired, log in again"))
+    }
+}
+
+/// The bearer token of a request, if there is one.
+pub fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// The user that made a request, taken from its bearer token.
+///
+/// While there are no accounts at all every request is an admin
+/// without a name, see the crate documentation.
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub Profile);
+
+impl CurrentUser {
+    /// The name of the user, `None` while the server is set up.
+    pub fn name(&self) -> Option<&str> {
+        (!self.0.name.is_empty()).then_some(self.0.name.as_str())
+    }
+
+    /// # Errors
+    /// Fails with `ErrorKind::Forbidden` unless the user has at least
+    /// `role`.
+    pub fn require(&self, role: Role) -> Result<&Profile, NasError> {
+        if self.0.role < role {
+            return Err(NasError::new(ErrorKind::Forbidden, format!("Only a {:?} may do this", role).to_lowercase()));
+        }
+        Ok(&self.0)
+    }
+
+    /// # Errors
+    /// Fails with `ErrorKind::Forbidden` if the user is a kid who isn't
+    /// allowed to see the game.
+    pub fn require_game(&self, game_id: &str) -> Result<(), NasError> {
+        self.0.restrictions().map_or(Ok(()), |restrictions| restrictions.check_game(game_id))
+    }
+
+    fn from_request(req: &HttpRequest) -> Result<Self, NasError> {
+        let registry = req.app_data::<web::Data<UserRegistry>>()
+            .ok_or_else(|| NasError::new(ErrorKind::Ignore, "There is no user registry"))?;
+        match bearer_token(req) {
+            Some(token) => Ok(Self(registry.authenticate(token, Utc::now())?)),
+            None if registry.is_empty()? => Ok(Self(Profile {
+                name: String::new(),
+                role: Role::Admin,
+                favourites: Vec::new(),
+                hidden: Vec::new(),
+                restrictions: Restrictions::default(),
+                created_at: Utc::now(),
+            })),
+            None => Err(NasError::new(ErrorKind::Unauthorized, "Log in first, the request has no bearer token")),
+        }
+    }
+}
+
+impl FromRequest for CurrentUser {
+    type Error = NasError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(CurrentUser::from_request(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn logs_in_and_applies_the_view_of_each_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = web::Data::new(UserRegistry::open(dir.path().join("users.json")).unwrap());
+        let anonymous = TestRequest::default().app_data(registry.clone()).to_http_request();
+        // a server without accounts is open until the first one exists
+        assert_eq!(CurrentUser::from_request(&anonymous).unwrap().require(Role::Admin).unwrap().role, Role::Admin);
+
+        registry.add(NewUser { name: "mum".to_owned(), role: Role::Admin, password: Some("secret".to_owned()) }).unwrap();
+        registry.add(NewUser { name: "tim".to_owned(), role: Role::Kid, password: None }).unwrap();
+        assert_eq!(CurrentUser::from_request(&anonymous).unwrap_err().kind(), ErrorKind::Unauthorized);
+        assert_eq!(registry.login("mum", "wrong").unwrap_err().kind(), ErrorKind::Unauthorized);
+        assert_eq!(registry.login("tim", "").unwrap_err().kind(), ErrorKind::Unauthorized);
+
+        let login = registry.login("mum", "secret").unwrap();
+        let (kid_token, _) = registry.create_token("tim", "tablet", None).unwrap();
+        let as_user = |token: &str| {
+            let req = TestRequest::default().app_data(registry.clone())
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", token))).to_http_request();
+            CurrentUser::from_request(&req)
+        };
+        assert_eq!(as_user(&login.token).unwrap().name(), Some("mum"));
+        let kid = as_user(&kid_token).unwrap();
+        assert_eq!(kid.require(Role::Admin).unwrap_err().kind(), ErrorKind::Forbidden);
+        assert!(kid.require(Role::Kid).is_ok());
+
+        let restrictions = Restrictions { allowed_games: Some(vec!["1".to_owned(), "2".to_owned()]), daily_minutes: Some(60) };
+        registry.update("tim", UserPatch { restrictions: Some(restrictions), ..UserPatch::default() }).unwrap();
+        registry.set_game_flags("tim", "2", GameFlags { favourite: Some(true), hidden: None }).unwrap();
+        let tim = registry.set_game_flags("tim", "1", GameFlags { favourite: None, hidden: Some(true) }).unwrap();
+        let games: Vec<Game> = ["1", "2", "3"].iter().map(|id| Game::with_id(id.to_string(), format!("Game {}", id))).collect();
+        let view = library_view(&tim, games.clone(), &[], false);
+        assert_eq!(view.iter().map(|g| (g.game.id(), g.favourite)).collect::<Vec<_>>(), [("2", true)]);
+        assert_eq!(library_view(&tim, games, &[], true).len(), 2);
+        let restrictions = tim.restrictions().unwrap();
+        assert!(restrictions.check("2", Duration::minutes(59)).is_ok());
+        assert_eq!(restrictions.check("2", Duration::minutes(60)).unwrap_err().kind(), ErrorKind::Forbidden);
+        assert_eq!(restrictions.check("3", Duration::zero()).unwrap_err().kind(), ErrorKind::Forbidden);
+
+        // the accounts survive a restart, without the plain tokens
+        let reopened = UserRegistry::open(dir.path().join("users.json")).unwrap();
+        assert_eq!(reopened.authenticate(&kid_token, Utc::now()).unwrap().name, "tim");
+        assert!(!fs::read_to_string(dir.path().join("users.json")).unwrap().contains(&kid_token));
+        assert!(reopened.logout(&login.token).unwrap());
+        assert_eq!(reopened.authenticate(&login.token, Utc::now()).unwrap_err().kind(), ErrorKind::Unauthorized);
+        assert_eq!(reopened.login("nobody", "secret").unwrap_err().kind(), ErrorKind::Unauthorized);
+
+        // the server never goes back to having no admin
+        assert_eq!(reopened.remove("mum").unwrap_err().kind(), ErrorKind::BadRequest);
+        let demote = UserPatch { role: Some(Role::Player), ..UserPatch::default() };
+        assert_eq!(reopened.update("mum", demote).unwrap_err().kind(), ErrorKind::BadRequest);
+        reopened.update("tim", UserPatch { role: Some(Role::Admin), ..UserPatch::default() }).unwrap();
+        assert!(reopened.remove("mum").unwrap());
+    }
+}
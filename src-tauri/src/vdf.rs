@@ -0,0 +1,186 @@
+//! This crate is for parsing Valve's KeyValues text format (VDF),
+//! which Steam uses for `libraryfolders.vdf` and the
+//! `appmanifest_*.acf` files.
+//!
+//! Only the text format is supported. Conditionals such as
+//! `[$WIN32]` are accepted but ignored.
+use crate::error::{NasError, ErrorKind};
+
+/// A value inside of a VDF document.
+///
+/// Objects keep their entries in order and may contain the same key
+/// more than once, which is why they are not a map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Vdf {
+    Value(String),
+    Object(Vec<(String, Vdf)>),
+}
+
+impl Vdf {
+    /// Returns the first entry with the given key. Keys are compared
+    /// case insensitively, just like Steam does.
+    pub fn get(&self, key: &str) -> Option<&Vdf> {
+        match self {
+            Vdf::Object(entries) => entries.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v),
+            Vdf::Value(_) => None,
+        }
+    }
+
+    /// Shorthand for `get(key)` followed by `as_str()`.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(Vdf::as_str)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Vdf::Value(s) => Some(s),
+            Vdf::Object(_) => None,
+        }
+    }
+
+    /// Returns all entries of an object, or nothing for a value.
+    pub fn entries(&self) -> &[(String, Vdf)] {
+        match self {
+            Vdf::Object(entries) => entries,
+            Vdf::Value(_) => &[],
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Str(String),
+    Open,
+    Close,
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+}
+
+impl Lexer<'_> {
+    fn next_token(&mut self) -> Result<Option<Token>, NasError> {
+        loop {
+            let Some(c) = self.chars.next() else { return Ok(None) };
+            match c {
+                '\n' => self.line += 1,
+                c if c.is_whitespace() => (),
+                '{' => return Ok(Some(Token::Open)),
+                '}' => return Ok(Some(Token::Close)),
+                '/' if self.chars.peek() == Some(&'/') => {
+                    while self.chars.next_if(|c| *c != '\n').is_some() {}
+                },
+                '[' => {
+                    // conditionals like [$WIN32] are ignored
+                    while self.chars.next_if(|c| *c != ']').is_some() {}
+                    self.chars.next();
+                },
+                '"' => return self.quoted().map(|s| Some(Token::Str(s))),
+                c => {
+                    let mut s = String::from(c);
+                    while let Some(c) = self.chars.next_if(|c| !c.is_whitespace() && !matches!(c, '"' | '{' | '}')) {
+                        s.push(c);
+                    }
+                    return Ok(Some(Token::Str(s)));
+                },
+            }
+        }
+    }
+
+    fn quoted(&mut self) -> Result<String, NasError> {
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some(c) => s.push(c),
+                    None => break,
+                },
+                Some(c) => {
+                    if c == '\n' { self.line += 1; }
+                    s.push(c);
+                },
+                None => break,
+            }
+        }
+        Err(NasError::new(ErrorKind::FailedToParse, format!("Unterminated string in line {}", self.line)))
+    }
+}
+
+/// Parses a whole document and returns its root key and value.
+///
+/// # Errors
+/// Fails with `ErrorKind::FailedToParse` if the document is not
+/// valid KeyValues text.
+pub fn parse(input: &str) -> Result<(String, Vdf), NasError> {
+    let mut lexer = Lexer { chars: input.chars().peekable(), line: 1 };
+    let entries = parse_object(&mut lexer, false)?;
+    entries.into_iter().next()
+        .ok_or_else(|| NasError::new(ErrorKind::FailedToParse, "The document is empty"))
+}
+
+fn parse_object(lexer: &mut Lexer, nested: bool) -> Result<Vec<(String, Vdf)>, NasError> {
+    let mut entries = Vec::new();
+    loop {
+        let key = match lexer.next_token()? {
+            Some(Token::Str(key)) => key,
+            Some(Token::Close) if nested => return Ok(entries),
+            None if !nested => return Ok(entries),
+            token => return Err(unexpected(lexer, token)),
+        };
+        let value = match lexer.next_token()? {
+            Some(Token::Str(value)) => Vdf::Value(value),
+            Some(Token::Open) => Vdf::Object(parse_object(lexer, true)?),
+            token => return Err(unexpected(lexer, token)),
+        };
+        entries.push((key, value));
+    }
+}
+
+fn unexpected(lexer: &Lexer, token: Option<Token>) -> NasError {
+    let found = match token {
+        Some(Token::Str(s)) => format!("{:?}", s),
+        Some(Token::Open) => "'{'".to_owned(),
+        Some(Token::Close) => "'}'".to_owned(),
+        None => "the end of the document".to_owned(),
+    };
+    NasError::new(ErrorKind::FailedToParse, format!("Unexpected {} in line {}", found, lexer.line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nested_documents() {
+        let input = r#"
+            // comment
+            "AppState"
+            {
+                "appid"     "620"
+                "name"      "Portal \"2\""
+                UserConfig
+                {
+                    "language"  "english" [$WIN32]
+                }
+            }
+        "#;
+        let (key, root) = parse(input).unwrap();
+        assert_eq!(key, "AppState");
+        assert_eq!(root.get_str("AppID"), Some("620"));
+        assert_eq!(root.get_str("name"), Some("Portal \"2\""));
+        assert_eq!(root.get("userconfig").and_then(|c| c.get_str("language")), Some("english"));
+        assert_eq!(root.entries().len(), 3);
+    }
+
+    #[test]
+    fn rejects_broken_documents() {
+        assert!(parse(r#""a" { "b" "c" "#).is_err());
+        assert!(parse(r#""a" "b"#).is_err());
+        assert!(parse("").is_err());
+        assert!(parse(r#""a" }"#).is_err());
+    }
+}
@@ -1,11 +1,20 @@
 /* @refresh reload */
-import { createSignal } from "solid-js";
+import { createSignal, onMount } from "solid-js";
+import { useNavigate } from "@solidjs/router";
 import logo from "./assets/logo.svg";
 import { invoke } from "@tauri-apps/api/core";
 import "./App.css";
 import TopMenu from "./components/TopMenu.jsx";
 
 function App() {
+  const navigate = useNavigate();
+
+  // the first start shows the servers on the network to pick from
+  onMount(async () => {
+    if (await invoke("first_run")) {
+      navigate("/settings");
+    }
+  });
 
   return (
     <main class="container">
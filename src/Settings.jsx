@@ -1,16 +1,59 @@
 /* @refresh reload */
-import { createSignal } from "solid-js";
+import { createSignal, onMount, For, Show } from "solid-js";
 import logo from "./assets/logo.svg";
 import { invoke } from "@tauri-apps/api/core";
 import "./App.css";
 import TopMenu from "./components/TopMenu.jsx";
 
 function App() {
+  const [servers, setServers] = createSignal([]);
+  const [searching, setSearching] = createSignal(false);
+  const [message, setMessage] = createSignal("");
+
+  async function discover() {
+    setSearching(true);
+    try {
+      setServers(await invoke("discover_servers"));
+      setMessage("");
+    } catch (err) {
+      setMessage(`Failed to search for servers: ${err}`);
+    }
+    setSearching(false);
+  }
+
+  async function select(server) {
+    try {
+      await invoke("select_server", { url: server.url });
+      setMessage(`Now using ${server.name} at ${server.url}`);
+    } catch (err) {
+      setMessage(`Failed to use ${server.url}: ${err}`);
+    }
+  }
+
+  onMount(discover);
 
   return (
     <main class="container">
       <TopMenu/>
       This is the settings page
+      <section class="servers">
+        <h2>Servers on the network</h2>
+        <button onClick={discover} disabled={searching()}>{searching() ? "Searching..." : "Search again"}</button>
+        <Show when={!searching() && servers().length === 0}>
+          <p>No server answered, is it running with discovery enabled?</p>
+        </Show>
+        <ul>
+          <For each={servers()}>
+            {(server) => (
+              <li>
+                {server.name} ({server.version}) at {server.url}
+                <button onClick={() => select(server)}>Use</button>
+              </li>
+            )}
+          </For>
+        </ul>
+        <p>{message()}</p>
+      </section>
     </main>
   );
 }